@@ -1,42 +1,92 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use routing::{
-    chassis::{Chassis, LinkLayerId, NicHandle},
+    application::{
+        bgp::router::BgpConfig, dhcp::server::DhcpServerConfig, ospf::router::OspfConfig,
+        rip::router::RipConfig, vrrp::router::VrrpConfig,
+    },
+    chassis::{switch::Switch, Chassis, LinkLayerId, NicHandle},
+    link::{capture::Capture, ethernet::lacp::Lacp},
     network::{
         arp::GenericArpHandle,
         ipv4::{addr::IpV4Addr, config::IpV4Config},
+        ipv6::{addr::IpV6Addr, config::IpV6Config},
+        ndp::GenericNdpHandle,
     },
     process::ProcessManager,
-    transport::{icmp::IcmpApi, udp::UdpHandleGeneric},
+    transport::{
+        icmp::IcmpApi, ospf::OspfHandle, tcp::TcpHandleGeneric, udp::UdpHandleGeneric,
+        vrrp::VrrpHandle,
+    },
 };
 use tokio::sync::RwLock;
 
 pub struct ChassisData {
     pub c: Chassis,
     pub ip_v4_conf: IpV4Config,
+    pub ip_v6_conf: IpV6Config,
     pub nics: HashMap<LinkLayerId, NicHandle>,
+    /// LACP of the bonds, by bond id
+    pub bonds: HashMap<u16, Arc<RwLock<Lacp>>>,
+    /// Switches, by id
+    pub switches: HashMap<u16, Switch>,
+    /// Running packet captures, by file
+    pub captures: HashMap<PathBuf, Arc<Capture>>,
     pub ip_v4_arp_handle: GenericArpHandle,
+    pub ndp_handle: GenericNdpHandle,
     pub icmp: IcmpApi,
-    pub udp_handles: (UdpHandleGeneric<IpV4Addr>,),
+    pub udp_handles: (UdpHandleGeneric<IpV4Addr>, UdpHandleGeneric<IpV6Addr>),
+    pub udp_broadcast_handle: UdpHandleGeneric<LinkLayerId>,
+    pub tcp_handles: (TcpHandleGeneric<IpV4Addr>, TcpHandleGeneric<IpV6Addr>),
+    pub ospf_handle: OspfHandle,
+    pub vrrp_handle: VrrpHandle,
     pub processes: ProcessManager,
+    pub dhcp_server_conf: DhcpServerConfig,
+    pub rip_conf: RipConfig,
+    pub ospf_conf: OspfConfig,
+    pub bgp_conf: BgpConfig,
+    pub vrrp_conf: VrrpConfig,
 }
 
 impl ChassisData {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         c: Chassis,
         ip_v4_conf: IpV4Config,
+        ip_v6_conf: IpV6Config,
         ip_v4_arp_handle: GenericArpHandle,
+        ndp_handle: GenericNdpHandle,
         icmp: IcmpApi,
         ip_v4_udp_handle: UdpHandleGeneric<IpV4Addr>,
+        ip_v6_udp_handle: UdpHandleGeneric<IpV6Addr>,
+        udp_broadcast_handle: UdpHandleGeneric<LinkLayerId>,
+        ip_v4_tcp_handle: TcpHandleGeneric<IpV4Addr>,
+        ip_v6_tcp_handle: TcpHandleGeneric<IpV6Addr>,
+        ospf_handle: OspfHandle,
+        vrrp_handle: VrrpHandle,
     ) -> Self {
         Self {
             c,
             ip_v4_conf,
+            ip_v6_conf,
             nics: Default::default(),
+            bonds: Default::default(),
+            switches: Default::default(),
+            captures: Default::default(),
             ip_v4_arp_handle,
+            ndp_handle,
             icmp,
-            udp_handles: (ip_v4_udp_handle,),
+            udp_handles: (ip_v4_udp_handle, ip_v6_udp_handle),
+            udp_broadcast_handle,
+            tcp_handles: (ip_v4_tcp_handle, ip_v6_tcp_handle),
+            ospf_handle,
+            vrrp_handle,
             processes: Default::default(),
+            dhcp_server_conf: Default::default(),
+            rip_conf: Default::default(),
+            ospf_conf: Default::default(),
+            bgp_conf: Default::default(),
+            vrrp_conf: Default::default(),
         }
     }
 }
@@ -9,8 +9,17 @@ use crate::{
 
 use super::ParsedCommand;
 pub mod arp;
+pub mod bgp;
+pub mod capture;
+pub mod dhcp;
 pub mod ip_v4;
+pub mod ip_v6;
 pub mod link;
+pub mod ndp;
+pub mod ospf;
+pub mod rip;
+pub mod switch;
+pub mod vrrp;
 
 #[async_trait::async_trait]
 pub trait ParsedChassisCommand<Args> {
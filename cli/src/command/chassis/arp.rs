@@ -1,12 +1,24 @@
 use tracing::info;
 
-use crate::{chassis::ChassisData, ctrlc::CtrlC};
+use crate::{chassis::ChassisData, ctrlc::CtrlC, IfaceId, LinkType};
 
 use super::ParsedChassisCommandRead;
 
 #[derive(Debug, clap::Parser)]
 pub enum Arp {
     IpV4List,
+    /// Answers the requests on the interface for the addresses routed through other interfaces
+    Proxy {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Sends gratuitous ARP for the addresses of the interface
+    Announce {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
 }
 
 pub struct ArpCommand;
@@ -17,9 +29,11 @@ impl ParsedChassisCommandRead<Arp> for ArpCommand {
         &mut self,
         cmd: Arp,
         _: &CtrlC,
-        _: String,
+        name: String,
         ChassisData {
-            ip_v4_arp_handle, ..
+            ip_v4_arp_handle,
+            ip_v4_conf,
+            ..
         }: &ChassisData,
     ) -> bool {
         match cmd {
@@ -40,6 +54,25 @@ impl ParsedChassisCommandRead<Arp> for ArpCommand {
                     info!("ARP IPv4 list:\n{table}");
                 }
             }
+            Arp::Proxy {
+                iface_type,
+                iface_id,
+                enabled,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                let proxy_arp = &mut ip_v4_conf.write().await.proxy_arp;
+                if enabled {
+                    info!("Chassis {name} {iface} proxies ARP");
+                    proxy_arp.insert(iface);
+                } else {
+                    info!("Chassis {name} {iface} doesn't proxy ARP");
+                    proxy_arp.remove(&iface);
+                }
+            }
+            Arp::Announce {
+                iface_type,
+                iface_id,
+            } => ip_v4_arp_handle.announce(iface_type.iface(iface_id)).await,
         }
         false
     }
@@ -0,0 +1,261 @@
+use routing::{
+    application::bgp::{
+        policy::{Action, Rule, Set},
+        router::{BgpPeer, BgpRouter},
+    },
+    network::ipv4::addr::{IpV4Addr, IpV4Mask},
+};
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, ctrlc::CtrlC};
+
+use super::ParsedChassisCommandRead;
+
+#[derive(Debug, clap::Parser)]
+pub enum Bgp {
+    /// Local AS number, has to be set before starting
+    As {
+        #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+        asn: u16,
+    },
+    /// Used by the speakers started afterwards, the highest interface address otherwise
+    RouterId {
+        id: IpV4Addr,
+    },
+    /// Hold time offered to the peers, and delay before connecting again
+    Timers {
+        hold_secs: u16,
+        connect_retry_secs: u16,
+    },
+    #[command(subcommand)]
+    Neighbor(NeighborCmd),
+    /// Prefixes originated by this AS
+    #[command(subcommand)]
+    Network(NetworkCmd),
+    /// Adds a rule to a neighbor's filter, the first matching rule applies and the routes no rule
+    /// matches are permitted
+    Filter {
+        addr: IpV4Addr,
+        direction: Direction,
+        action: FilterAction,
+        network: IpV4Addr,
+        mask: u8,
+        /// Also matches the longer prefixes inside it
+        #[arg(long, short)]
+        or_longer: bool,
+        #[arg(long)]
+        local_pref: Option<u32>,
+        #[arg(long)]
+        med: Option<u32>,
+        /// Times the first AS of the path is repeated
+        #[arg(long, default_value_t = 0)]
+        prepend: u8,
+    },
+    /// Removes the rules of a neighbor's filter
+    ClearFilter {
+        addr: IpV4Addr,
+        direction: Direction,
+    },
+    Neighbors,
+    /// Rules of a neighbor's filters
+    Filters {
+        addr: IpV4Addr,
+    },
+    /// Best paths
+    Rib,
+    /// Routes received from a neighbor, before the import filter
+    RibIn {
+        addr: IpV4Addr,
+    },
+    /// Routes advertised to a neighbor
+    RibOut {
+        addr: IpV4Addr,
+    },
+    /// Runs the speaker in the background
+    Start,
+    /// Stops the speaker and removes the routes it learned
+    Stop {
+        pid: u64,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum NeighborCmd {
+    Add { addr: IpV4Addr, remote_as: u16 },
+    Del { addr: IpV4Addr },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum NetworkCmd {
+    Add { network: IpV4Addr, mask: u8 },
+    Del { network: IpV4Addr, mask: u8 },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Direction {
+    Import,
+    Export,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum FilterAction {
+    Permit,
+    Deny,
+}
+
+pub struct BgpCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRead<Bgp> for BgpCommand {
+    async fn run(
+        &mut self,
+        cmd: Bgp,
+        _: &CtrlC,
+        name: String,
+        ChassisData {
+            ip_v4_conf,
+            tcp_handles: (tcp_handle, _),
+            processes,
+            bgp_conf,
+            ..
+        }: &ChassisData,
+    ) -> bool {
+        match cmd {
+            Bgp::As { asn } => {
+                bgp_conf.write().await.asn = asn;
+            }
+            Bgp::RouterId { id } => {
+                bgp_conf.write().await.router_id = Some(id);
+            }
+            Bgp::Timers {
+                hold_secs,
+                connect_retry_secs,
+            } => {
+                if matches!(hold_secs, 1 | 2) {
+                    warn!("The hold time has to be 0 or at least 3 seconds");
+                    return false;
+                }
+                let mut config = bgp_conf.write().await;
+                config.hold_time = hold_secs;
+                config.connect_retry = connect_retry_secs;
+            }
+            Bgp::Neighbor(cmd) => match cmd {
+                NeighborCmd::Add { addr, remote_as } => {
+                    info!("Adding BGP neighbor {addr} of AS {remote_as} to chassis {name}");
+                    bgp_conf
+                        .write()
+                        .await
+                        .peers
+                        .insert(addr, BgpPeer::new(remote_as));
+                }
+                NeighborCmd::Del { addr } => {
+                    if bgp_conf.write().await.peers.remove(&addr).is_none() {
+                        warn!("{addr} isn't a BGP neighbor");
+                    }
+                }
+            },
+            Bgp::Network(cmd) => match cmd {
+                NetworkCmd::Add { network, mask } => {
+                    let mask = IpV4Mask::new(mask);
+                    bgp_conf
+                        .write()
+                        .await
+                        .networks
+                        .insert((mask & network, mask));
+                }
+                NetworkCmd::Del { network, mask } => {
+                    let mask = IpV4Mask::new(mask);
+                    bgp_conf
+                        .write()
+                        .await
+                        .networks
+                        .remove(&(mask & network, mask));
+                }
+            },
+            Bgp::Filter {
+                addr,
+                direction,
+                action,
+                network,
+                mask,
+                or_longer,
+                local_pref,
+                med,
+                prepend,
+            } => {
+                let mask = IpV4Mask::new(mask);
+                let rule = Rule {
+                    prefix: (mask & network, mask),
+                    or_longer,
+                    action: match action {
+                        FilterAction::Permit => Action::Permit(Set {
+                            local_pref,
+                            med,
+                            prepend,
+                        }),
+                        FilterAction::Deny => Action::Deny,
+                    },
+                };
+                let mut config = bgp_conf.write().await;
+                let Some(peer) = config.peers.get_mut(&addr) else {
+                    warn!("{addr} isn't a BGP neighbor");
+                    return false;
+                };
+                match direction {
+                    Direction::Import => peer.import.rules.push(rule),
+                    Direction::Export => peer.export.rules.push(rule),
+                }
+            }
+            Bgp::ClearFilter { addr, direction } => {
+                let mut config = bgp_conf.write().await;
+                let Some(peer) = config.peers.get_mut(&addr) else {
+                    warn!("{addr} isn't a BGP neighbor");
+                    return false;
+                };
+                match direction {
+                    Direction::Import => peer.import.rules.clear(),
+                    Direction::Export => peer.export.rules.clear(),
+                }
+            }
+            Bgp::Neighbors => {
+                info!(
+                    "Chassis {name} BGP neighbors:\n{}",
+                    bgp_conf.read().await.print_neighbors()
+                );
+            }
+            Bgp::Filters { addr } => match bgp_conf.read().await.print_filters(addr) {
+                Some(table) => info!("Chassis {name} BGP filters of {addr}:\n{table}"),
+                None => warn!("{addr} isn't a BGP neighbor"),
+            },
+            Bgp::Rib => {
+                info!(
+                    "Chassis {name} BGP best paths:\n{}",
+                    bgp_conf.read().await.print_rib()
+                );
+            }
+            Bgp::RibIn { addr } => match bgp_conf.read().await.print_adj_rib(addr, false) {
+                Some(table) => info!("Chassis {name} BGP routes from {addr}:\n{table}"),
+                None => warn!("{addr} isn't a BGP neighbor"),
+            },
+            Bgp::RibOut { addr } => match bgp_conf.read().await.print_adj_rib(addr, true) {
+                Some(table) => info!("Chassis {name} BGP routes to {addr}:\n{table}"),
+                None => warn!("{addr} isn't a BGP neighbor"),
+            },
+            Bgp::Start => {
+                match BgpRouter::new(bgp_conf.clone(), ip_v4_conf.clone(), tcp_handle).await {
+                    Ok(router) => {
+                        let pid = processes.add(|_| router.run()).await;
+                        info!("BGP speaker started (pid {pid})");
+                    }
+                    Err(()) => warn!("Unable to start the BGP speaker"),
+                }
+            }
+            Bgp::Stop { pid } => {
+                let _ = processes.stop_process(pid).await;
+                bgp_conf.write().await.flush(&mut *ip_v4_conf.write().await);
+                info!("Stopped process {pid}");
+            }
+        }
+        false
+    }
+}
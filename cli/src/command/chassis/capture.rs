@@ -0,0 +1,291 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use routing::{
+    chassis::{switch::Switch, LinkLayerId, NicHandle},
+    dissector::{filter::Filter, Dissection},
+    link::capture::{self, Capture, Ring},
+    mac,
+};
+use tokio::{select, sync::RwLock, task::JoinSet};
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, ctrlc::CtrlC};
+
+use super::ParsedChassisCommandRwLock;
+
+#[derive(Debug, clap::Parser)]
+pub enum CaptureCmd {
+    /// Captures the frames the Ethernet NICs send and receive to the file, a NIC leaves its
+    /// previous capture
+    Start {
+        path: PathBuf,
+        #[arg(required = true)]
+        ids: Vec<u16>,
+        /// The ids are ports of this switch
+        #[arg(long)]
+        switch: Option<u16>,
+        #[arg(long, value_enum, default_value_t = Format::Pcapng)]
+        format: Format,
+        /// Kilobytes of a file of the ring, the files are named `<stem>_<n>.<extension>`
+        #[arg(long, requires = "ring_files")]
+        ring_size: Option<u64>,
+        /// Files kept in the ring, the oldest ones are deleted
+        #[arg(long, requires = "ring_size")]
+        ring_files: Option<usize>,
+    },
+    Stop {
+        path: PathBuf,
+    },
+    List,
+    /// Prints the frames of the Ethernet NICs matching the filter, until Ctrl-C
+    Watch {
+        /// Ethernet NIC ids, comma separated
+        #[arg(short, long = "iface", value_delimiter = ',', required = true)]
+        ids: Vec<u16>,
+        /// The ids are ports of this switch
+        #[arg(long)]
+        switch: Option<u16>,
+        /// Like tcpdump's, e.g. `host 10.0.0.1 and (icmp or udp port 520)`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        filter: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    Pcapng,
+    Pcap,
+}
+
+pub struct CaptureCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRwLock<CaptureCmd> for CaptureCommand {
+    async fn run(
+        &mut self,
+        cmd: CaptureCmd,
+        ctrlc: &CtrlC,
+        name: String,
+        chassis: &RwLock<ChassisData>,
+    ) -> bool {
+        if let CaptureCmd::Watch {
+            ids,
+            switch,
+            filter,
+        } = cmd
+        {
+            watch(ids, switch, filter, ctrlc, name, chassis).await;
+            return false;
+        }
+        let mut guard = chassis.write().await;
+        let ChassisData {
+            nics,
+            switches,
+            captures,
+            ..
+        } = &mut *guard;
+        match cmd {
+            CaptureCmd::Start {
+                path,
+                ids,
+                switch,
+                format,
+                ring_size,
+                ring_files,
+            } => {
+                let Entry::Vacant(entry) = captures.entry(path.clone()) else {
+                    warn!("Capture {path:?} is already running");
+                    return false;
+                };
+                let ports = match switch {
+                    Some(switch) => {
+                        let Some(ports) = switch_ports(switches.get(&switch), &ids).await else {
+                            warn!("Chassis `{name}` doesn't have all the ports in switch{switch}");
+                            return false;
+                        };
+                        Some(ports)
+                    }
+                    None => {
+                        if let Some(id) = ids.iter().find(|id| {
+                            !nics.contains_key(&LinkLayerId::Ethernet(**id, mac::BROADCAST))
+                        }) {
+                            warn!("Chassis `{name}` doesn't have interface eth{id}");
+                            return false;
+                        }
+                        None
+                    }
+                };
+                let format = match format {
+                    Format::Pcapng => capture::Format::Pcapng,
+                    Format::Pcap => capture::Format::Pcap,
+                };
+                let ring = ring_size.zip(ring_files).map(|(size, files)| Ring {
+                    size: size * 1024,
+                    files,
+                });
+                let capture = match Capture::create(&path, format, ring) {
+                    Ok(capture) => capture,
+                    Err(e) => {
+                        warn!("Couldn't create capture {path:?}: {e}");
+                        return false;
+                    }
+                };
+                for (i, id) in ids.into_iter().enumerate() {
+                    let iface = match switch {
+                        Some(switch) => format!("{name} switch{switch} eth{id}"),
+                        None => format!("{name} eth{id}"),
+                    };
+                    let tap = match capture.add_interface(iface) {
+                        Ok(tap) => tap,
+                        Err(e) => {
+                            warn!("Couldn't capture eth{id}: {e}");
+                            continue;
+                        }
+                    };
+                    match &ports {
+                        Some(ports) => ports[i].read().await.start_capture(tap).await,
+                        None => {
+                            let handle = &nics[&LinkLayerId::Ethernet(id, mac::BROADCAST)];
+                            handle.start_capture(tap).await
+                        }
+                    }
+                }
+                info!("Capturing {:?} to {path:?}", capture.interfaces());
+                entry.insert(capture);
+            }
+            CaptureCmd::Stop { path } => match captures.remove(&path) {
+                Some(capture) => {
+                    stop(nics, switches, &capture).await;
+                    info!("Capture {path:?} stopped");
+                }
+                None => warn!("No capture {path:?}"),
+            },
+            CaptureCmd::List => {
+                info!("Chassis {name} captures:");
+                for (path, capture) in captures.iter() {
+                    let ring = capture
+                        .ring()
+                        .map_or_else(String::new, |Ring { size, files }| {
+                            format!(", ring of {files} files of {size} bytes")
+                        });
+                    info!(
+                        "{path:?} {}{ring}: {}",
+                        capture.format(),
+                        capture.interfaces().join(", ")
+                    );
+                }
+            }
+            CaptureCmd::Watch { .. } => unreachable!(),
+        }
+        false
+    }
+}
+
+async fn watch(
+    ids: Vec<u16>,
+    switch: Option<u16>,
+    filter: Vec<String>,
+    ctrlc: &CtrlC,
+    name: String,
+    chassis: &RwLock<ChassisData>,
+) {
+    let filter = match filter.join(" ").parse::<Filter>() {
+        Ok(filter) => filter,
+        Err(e) => {
+            warn!("{e}");
+            return;
+        }
+    };
+    let (tx, rx) = flume::unbounded();
+    let mut join_set = JoinSet::new();
+    {
+        let guard = chassis.read().await;
+        let ports = match switch {
+            Some(switch) => {
+                let Some(ports) = switch_ports(guard.switches.get(&switch), &ids).await else {
+                    warn!("Chassis `{name}` doesn't have all the ports in switch{switch}");
+                    return;
+                };
+                Some(ports)
+            }
+            None => None,
+        };
+        for (i, id) in ids.into_iter().enumerate() {
+            let frames = match &ports {
+                Some(ports) => ports[i].read().await.watch().await,
+                None => {
+                    let Some(handle) = guard.nics.get(&LinkLayerId::Ethernet(id, mac::BROADCAST))
+                    else {
+                        warn!("Chassis `{name}` doesn't have interface eth{id}");
+                        return;
+                    };
+                    handle.watch().await
+                }
+            };
+            let tx = tx.clone();
+            join_set.spawn(async move {
+                while let Ok((frame, outbound)) = frames.recv_async().await {
+                    if tx.send_async((id, frame, outbound)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+    let handler = ctrlc.add_handler().await;
+    loop {
+        select! {
+            frame = rx.recv_async() => {
+                let Ok((id, frame, outbound)) = frame else {
+                    break;
+                };
+                let dissection = Dissection::new(frame);
+                if filter.matches(&dissection) {
+                    info!("eth{id} {} {dissection}", if outbound { "out" } else { "in " });
+                }
+            }
+            _ = handler.next() => {
+                info!("Ctrl-C");
+                break;
+            }
+        }
+    }
+}
+
+/// Stops the taps of the capture on the NICs and the switch ports
+async fn stop(
+    nics: &HashMap<LinkLayerId, NicHandle>,
+    switches: &HashMap<u16, Switch>,
+    capture: &Arc<Capture>,
+) {
+    for handle in nics.values() {
+        stop_handle(handle, capture).await;
+    }
+    for switch in switches.values() {
+        for (_, _, handle) in switch.ports().await {
+            stop_handle(&*handle.read().await, capture).await;
+        }
+    }
+}
+
+async fn stop_handle(handle: &NicHandle, capture: &Arc<Capture>) {
+    if handle
+        .capture()
+        .await
+        .is_some_and(|tap| Arc::ptr_eq(tap.capture(), capture))
+    {
+        handle.stop_capture().await;
+    }
+}
+
+/// Handles of the switch's ports, `None` if one of them doesn't exist
+async fn switch_ports(switch: Option<&Switch>, ids: &[u16]) -> Option<Vec<Arc<RwLock<NicHandle>>>> {
+    let ports = switch?.ports().await;
+    ids.iter()
+        .map(|id| Some(ports.get(*id as usize)?.2.clone()))
+        .collect()
+}
@@ -0,0 +1,169 @@
+use routing::{
+    application::dhcp::{
+        client::DhcpClient,
+        server::{DhcpServer, Pool},
+    },
+    mac::Mac,
+    network::ipv4::addr::{IpV4Addr, IpV4Mask},
+};
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, ctrlc::CtrlC, IfaceId, LinkType};
+
+use super::ParsedChassisCommandRead;
+
+#[derive(Debug, clap::Parser)]
+pub enum Dhcp {
+    #[command(subcommand)]
+    Client(ClientCmd),
+    #[command(subcommand)]
+    Server(ServerCmd),
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ClientCmd {
+    /// Gets the chassis' address and default route from the interface
+    Start {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+    /// Releases the lease
+    Stop,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ServerCmd {
+    /// Serves the addresses from start to end on the interface
+    Pool {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        start: IpV4Addr,
+        end: IpV4Addr,
+        mask: u8,
+        /// Default gateway for the clients, the interface's address by default
+        #[arg(long, short)]
+        router: Option<IpV4Addr>,
+        #[arg(long, short, default_value_t = 3600)]
+        lease_secs: i64,
+    },
+    /// Always gives the address to the MAC
+    Reserve {
+        mac: Mac,
+        addr: IpV4Addr,
+    },
+    Leases,
+    /// Runs the server in the background
+    Start,
+    Stop {
+        pid: u64,
+    },
+}
+
+pub struct DhcpCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRead<Dhcp> for DhcpCommand {
+    async fn run(
+        &mut self,
+        cmd: Dhcp,
+        _: &CtrlC,
+        name: String,
+        ChassisData {
+            ip_v4_conf,
+            nics,
+            udp_handles: (udp, _),
+            udp_broadcast_handle,
+            processes,
+            dhcp_server_conf,
+            ..
+        }: &ChassisData,
+    ) -> bool {
+        match cmd {
+            Dhcp::Client(ClientCmd::Start {
+                iface_type,
+                iface_id,
+            }) => {
+                let iface = iface_type.iface(iface_id);
+                // The client needs the real MAC, which is kept in the key
+                let Some((&iface, _)) = nics.get_key_value(&iface) else {
+                    warn!("Interface {iface} not found");
+                    return false;
+                };
+                if std::mem::replace(&mut ip_v4_conf.write().await.dhcp_run, true) {
+                    warn!("Chassis {name} already has a DHCP client");
+                    return false;
+                }
+                match DhcpClient::new(iface, ip_v4_conf.clone(), udp, udp_broadcast_handle).await {
+                    Ok(client) => {
+                        processes.add(|_| client.run()).await;
+                    }
+                    Err(()) => {
+                        warn!("Unable to open the DHCP client sockets");
+                        ip_v4_conf.write().await.dhcp_run = false;
+                    }
+                }
+            }
+            Dhcp::Client(ClientCmd::Stop) => {
+                // The client notices it and releases the lease by itself
+                ip_v4_conf.write().await.dhcp_run = false;
+            }
+            Dhcp::Server(cmd) => match cmd {
+                ServerCmd::Pool {
+                    iface_type,
+                    iface_id,
+                    start,
+                    end,
+                    mask,
+                    router,
+                    lease_secs,
+                } => {
+                    let iface = iface_type.iface(iface_id);
+                    info!("Setting chassis' {name} DHCP pool on {iface} to {start} - {end}");
+                    dhcp_server_conf.write().await.pools.insert(
+                        iface,
+                        Pool {
+                            start,
+                            end,
+                            mask: IpV4Mask::new(mask),
+                            router,
+                            lease_time: chrono::Duration::seconds(lease_secs),
+                        },
+                    );
+                }
+                ServerCmd::Reserve { mac, addr } => {
+                    match dhcp_server_conf.write().await.reserve(mac, addr) {
+                        Ok(()) => info!("Reserved {addr} for {mac}"),
+                        Err(other) => warn!("{addr} is already leased or reserved to {other}"),
+                    }
+                }
+                ServerCmd::Leases => {
+                    info!(
+                        "Chassis {name} DHCP leases:\n{}",
+                        dhcp_server_conf.read().await.print_leases()
+                    );
+                }
+                ServerCmd::Start => {
+                    match DhcpServer::new(
+                        dhcp_server_conf.clone(),
+                        ip_v4_conf.clone(),
+                        udp,
+                        udp_broadcast_handle,
+                    )
+                    .await
+                    {
+                        Ok(server) => {
+                            let pid = processes.add(|_| server.run()).await;
+                            info!("DHCP server started (pid {pid})");
+                        }
+                        Err(()) => warn!("Unable to open the DHCP server sockets"),
+                    }
+                }
+                ServerCmd::Stop { pid } => {
+                    let _ = processes.stop_process(pid).await;
+                    info!("Stopped process {pid}");
+                }
+            },
+        }
+        false
+    }
+}
@@ -1,12 +1,19 @@
 use routing::{
     chassis::LinkLayerId,
-    mac,
-    network::ipv4::addr::{IpV4Addr, IpV4Mask},
+    network::ipv4::{
+        acl::{self, Ports, Prefix, Rule},
+        addr::{IpV4Addr, IpV4Mask},
+        config::{InterfaceAddr, IpV4ConfigInner, DEFAULT_MTU},
+        conntrack::{self, Timeouts},
+        nat::{self, Endpoint, Forward},
+        protocol::ProtocolType,
+        tunnel::{self, Tunnel},
+    },
     route::RoutingEntry,
 };
 use tracing::{info, warn};
 
-use crate::{chassis::ChassisData, ctrlc::CtrlC, LinkType};
+use crate::{chassis::ChassisData, ctrlc::CtrlC, IfaceId, LinkType};
 
 use super::ParsedChassisCommandRead;
 
@@ -14,10 +21,256 @@ use super::ParsedChassisCommandRead;
 pub enum IpV4 {
     #[command(subcommand)]
     Route(RouteCmd),
+    /// Sets the primary address of the interface
     Set {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        addr: IpV4Addr,
+        mask: u8,
+    },
+    /// Adds a secondary address to the interface
+    Add {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        addr: IpV4Addr,
+        mask: u8,
+    },
+    Del {
+        iface_type: LinkType,
+        iface_id: IfaceId,
         addr: IpV4Addr,
     },
     Get,
+    #[command(subcommand)]
+    Mtu(MtuCmd),
+    #[command(subcommand)]
+    Acl(AclCmd),
+    #[command(subcommand)]
+    Conntrack(ConntrackCmd),
+    #[command(subcommand)]
+    Nat(NatCmd),
+    #[command(subcommand)]
+    Tunnel(TunnelCmd),
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum MtuCmd {
+    Set {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        mtu: u16,
+    },
+    Get {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum AclCmd {
+    /// Access lists with their rules' hits and the interfaces they're bound to
+    List,
+    /// Appends a rule to the access list, creating it
+    Rule {
+        name: String,
+        action: Action,
+        /// `any`, an address or `<addr>/<len>`
+        #[arg(long, default_value = "any")]
+        source: Prefix,
+        #[arg(long, default_value = "any")]
+        destination: Prefix,
+        /// `icmp`, `tcp`, `udp` or the protocol number
+        #[arg(long, value_parser = protocol)]
+        protocol: Option<ProtocolType>,
+        /// Port or `<first>-<last>`, for TCP and UDP
+        #[arg(long)]
+        source_port: Option<Ports>,
+        #[arg(long)]
+        destination_port: Option<Ports>,
+        #[arg(long)]
+        icmp_type: Option<u8>,
+        /// Connection tracking states, comma separated
+        #[arg(long, value_delimiter = ',')]
+        state: Vec<State>,
+        /// Logs the packets matching the rule
+        #[arg(long)]
+        log: bool,
+    },
+    /// Removes the rule with the number, or the whole access list and its bindings
+    Del { name: String, rule: Option<usize> },
+    /// Answers the denied packets with ICMP administratively prohibited instead of dropping them
+    /// silently
+    Prohibited {
+        name: String,
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Filters the packets of the interface direction with the access list
+    Bind {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        direction: Direction,
+        name: String,
+    },
+    Unbind {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        direction: Direction,
+    },
+    /// Resets the hits of the access list
+    Clear { name: String },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ConntrackCmd {
+    /// Tracked connections
+    List,
+    /// Forgets the tracked connections
+    Flush,
+    Timeouts {
+        udp_secs: i64,
+        icmp_secs: i64,
+        tcp_established_secs: i64,
+        /// TCP connections opening or closing
+        tcp_transitory_secs: i64,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum NatCmd {
+    /// Roles of the interfaces, port forwards and translations
+    List,
+    /// Hides the hosts of the interface behind the outside interfaces
+    Inside {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+    /// Translates the packets routed from the inside interfaces out of the interface to its
+    /// primary address
+    Outside {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+    /// Stops translating the packets of the interface
+    Disable {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+    /// Sends the packets to the port of the outside interfaces to the inside host
+    Forward {
+        protocol: NatProtocol,
+        port: u16,
+        inside: IpV4Addr,
+        /// The same port by default
+        inside_port: Option<u16>,
+    },
+    Unforward {
+        protocol: NatProtocol,
+        port: u16,
+    },
+    /// Forgets the translations, the port forwards are kept
+    Flush,
+    Timeouts {
+        udp_secs: i64,
+        icmp_secs: i64,
+        tcp_secs: i64,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum TunnelCmd {
+    List,
+    /// Adds the `tun<id>` interface, whose packets are carried from the source address to the
+    /// destination inside outer packets
+    Add {
+        id: u16,
+        mode: TunnelMode,
+        source: IpV4Addr,
+        destination: IpV4Addr,
+    },
+    Del {
+        id: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum TunnelMode {
+    Gre,
+    Ipip,
+}
+
+impl From<TunnelMode> for tunnel::Mode {
+    fn from(mode: TunnelMode) -> Self {
+        match mode {
+            TunnelMode::Gre => Self::Gre,
+            TunnelMode::Ipip => Self::IpIp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum NatProtocol {
+    Udp,
+    Tcp,
+}
+
+impl From<NatProtocol> for ProtocolType {
+    fn from(protocol: NatProtocol) -> Self {
+        match protocol {
+            NatProtocol::Udp => Self::UDP,
+            NatProtocol::Tcp => Self::TCP,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum State {
+    New,
+    Established,
+    Related,
+}
+
+impl From<State> for conntrack::State {
+    fn from(state: State) -> Self {
+        match state {
+            State::New => Self::New,
+            State::Established => Self::Established,
+            State::Related => Self::Related,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Action {
+    Permit,
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl From<Direction> for acl::Direction {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::In => Self::In,
+            Direction::Out => Self::Out,
+        }
+    }
+}
+
+fn protocol(s: &str) -> Result<ProtocolType, String> {
+    match s {
+        "icmp" => Ok(ProtocolType::ICMP),
+        "tcp" => Ok(ProtocolType::TCP),
+        "udp" => Ok(ProtocolType::UDP),
+        _ => s
+            .parse()
+            .map(ProtocolType::new)
+            .map_err(|_| format!("Invalid protocol {s}")),
+    }
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -28,7 +281,10 @@ pub enum RouteCmd {
         mask: u8,
         next_hop: IpV4Addr,
         iface_type: LinkType,
-        iface_id: u16,
+        iface_id: IfaceId,
+        /// Routes with the same metric share the traffic
+        #[arg(long, short, default_value_t = 0)]
+        metric: u32,
     },
     Get {
         destination: IpV4Addr,
@@ -44,7 +300,11 @@ impl ParsedChassisCommandRead<IpV4> for IpV4Command {
         cmd: IpV4,
         _: &CtrlC,
         name: String,
-        ChassisData { ip_v4_conf, .. }: &ChassisData,
+        ChassisData {
+            ip_v4_conf,
+            ip_v4_arp_handle,
+            ..
+        }: &ChassisData,
     ) -> bool {
         match cmd {
             IpV4::Route(cmd) => match cmd {
@@ -60,19 +320,17 @@ impl ParsedChassisCommandRead<IpV4> for IpV4Command {
                     next_hop,
                     iface_type,
                     iface_id,
+                    metric,
                 } => {
-                    ip_v4_conf
-                        .write()
-                        .await
-                        .routing
-                        .add_route(RoutingEntry::new(
+                    ip_v4_conf.write().await.routing.add_route(
+                        RoutingEntry::new(
                             destination,
                             next_hop,
                             IpV4Mask::new(mask),
-                            match iface_type {
-                                LinkType::Eth => LinkLayerId::Ethernet(iface_id, mac::BROADCAST),
-                            },
-                        ));
+                            iface_type.iface(iface_id),
+                        )
+                        .with_metric(metric),
+                    );
                 }
                 RouteCmd::Get { destination } => ip_v4_conf
                     .read()
@@ -88,17 +346,354 @@ impl ParsedChassisCommandRead<IpV4> for IpV4Command {
                         },
                     ),
             },
-            IpV4::Set { addr } => {
-                info!("Setting chassis' {name} IPv4 addr to {addr}");
-                ip_v4_conf.write().await.addr = addr;
+            IpV4::Set {
+                iface_type,
+                iface_id,
+                addr,
+                mask,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                info!("Setting chassis' {name} {iface} IPv4 addr to {addr}/{mask}");
+                ip_v4_conf
+                    .write()
+                    .await
+                    .set_addr(iface, InterfaceAddr::new(addr, IpV4Mask::new(mask)));
+                ip_v4_arp_handle.announce(iface).await;
+            }
+            IpV4::Add {
+                iface_type,
+                iface_id,
+                addr,
+                mask,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                if ip_v4_conf
+                    .write()
+                    .await
+                    .add_addr(iface, InterfaceAddr::new(addr, IpV4Mask::new(mask)))
+                {
+                    info!("Added {addr}/{mask} to chassis' {name} {iface}");
+                    ip_v4_arp_handle.announce(iface).await;
+                } else {
+                    warn!("Chassis {name} {iface} already has {addr}");
+                }
+            }
+            IpV4::Del {
+                iface_type,
+                iface_id,
+                addr,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                if ip_v4_conf.write().await.remove_addr(iface, addr) {
+                    info!("Removed {addr} from chassis' {name} {iface}");
+                } else {
+                    warn!("Chassis {name} {iface} doesn't have {addr}");
+                }
             }
             IpV4::Get => {
                 info!(
-                    "Chassis {name} IPv4 addr is {}",
-                    ip_v4_conf.read().await.addr
+                    "Chassis {name} IPv4 addresses:\n{}",
+                    ip_v4_conf.read().await.print_addrs()
                 );
             }
+            IpV4::Mtu(cmd) => match cmd {
+                MtuCmd::Set {
+                    iface_type,
+                    iface_id,
+                    mtu,
+                } => {
+                    let iface = iface_type.iface(iface_id);
+                    // 68 is the minimum every IPv4 link must support
+                    if (68..=DEFAULT_MTU).contains(&mtu) {
+                        info!("Setting chassis' {name} {iface} MTU to {mtu}");
+                        ip_v4_conf.write().await.mtu.insert(iface, mtu);
+                    } else {
+                        warn!("MTU must be between 68 and {DEFAULT_MTU}");
+                    }
+                }
+                MtuCmd::Get {
+                    iface_type,
+                    iface_id,
+                } => {
+                    let iface = iface_type.iface(iface_id);
+                    info!(
+                        "Chassis {name} {iface} MTU is {}",
+                        ip_v4_conf.read().await.get_mtu(&iface)
+                    );
+                }
+            },
+            IpV4::Acl(cmd) => acl(cmd, &name, &mut *ip_v4_conf.write().await),
+            IpV4::Nat(cmd) => nat(cmd, &name, &mut *ip_v4_conf.write().await),
+            IpV4::Tunnel(cmd) => tunnel(cmd, &name, &mut *ip_v4_conf.write().await),
+            IpV4::Conntrack(cmd) => {
+                let mut config = ip_v4_conf.write().await;
+                match cmd {
+                    ConntrackCmd::List => info!(
+                        "Chassis {name} tracked connections:\n{}",
+                        config.conntrack.print()
+                    ),
+                    ConntrackCmd::Flush => config.conntrack.flush(),
+                    ConntrackCmd::Timeouts {
+                        udp_secs,
+                        icmp_secs,
+                        tcp_established_secs,
+                        tcp_transitory_secs,
+                    } => {
+                        config.conntrack.timeouts = Timeouts {
+                            udp: chrono::Duration::seconds(udp_secs),
+                            icmp: chrono::Duration::seconds(icmp_secs),
+                            tcp_established: chrono::Duration::seconds(tcp_established_secs),
+                            tcp_transitory: chrono::Duration::seconds(tcp_transitory_secs),
+                        }
+                    }
+                }
+            }
         }
         false
     }
 }
+
+fn acl(cmd: AclCmd, name: &str, config: &mut IpV4ConfigInner) {
+    match cmd {
+        AclCmd::List => {
+            let mut acls = config.acls.iter().collect::<Vec<_>>();
+            acls.sort_by_key(|(acl, _)| *acl);
+            info!("Chassis {name} IPv4 access lists:");
+            for (acl, list) in acls {
+                let mut bindings = config
+                    .acl_bindings
+                    .iter()
+                    .filter(|(_, bound)| *bound == acl)
+                    .map(|((iface, direction), _)| format!("{iface} {direction}"))
+                    .collect::<Vec<_>>();
+                bindings.sort();
+                info!(
+                    "{acl}{}, bound to [{}]:\n{}",
+                    if list.prohibited {
+                        ", denied packets prohibited"
+                    } else {
+                        ""
+                    },
+                    bindings.join(", "),
+                    list.print()
+                );
+            }
+        }
+        AclCmd::Rule {
+            name: acl,
+            action,
+            source,
+            destination,
+            protocol,
+            source_port,
+            destination_port,
+            icmp_type,
+            state,
+            log,
+        } => {
+            let protocol = protocol.or(icmp_type.map(|_| ProtocolType::ICMP));
+            let ports = source_port.is_some() || destination_port.is_some();
+            if ports && !matches!(protocol, Some(ProtocolType::TCP | ProtocolType::UDP)) {
+                warn!("Ports only apply to TCP and UDP");
+                return;
+            }
+            if icmp_type.is_some() && protocol != Some(ProtocolType::ICMP) {
+                warn!("ICMP types only apply to ICMP");
+                return;
+            }
+            let rule = Rule {
+                source,
+                destination,
+                protocol,
+                source_ports: source_port,
+                destination_ports: destination_port,
+                icmp_type,
+                states: state.into_iter().map(Into::into).collect(),
+                log,
+                ..Rule::new(match action {
+                    Action::Permit => acl::Action::Permit,
+                    Action::Deny => acl::Action::Deny,
+                })
+            };
+            info!("Chassis {name} access list {acl}: {rule}");
+            config.acls.entry(acl).or_default().rules.push(rule);
+        }
+        AclCmd::Del { name: acl, rule } => match (config.acls.get_mut(&acl), rule) {
+            (Some(list), Some(rule)) if rule < list.rules.len() => {
+                list.rules.remove(rule);
+            }
+            (Some(_), Some(rule)) => warn!("Access list {acl} doesn't have rule {rule}"),
+            (Some(_), None) => {
+                config.acls.remove(&acl);
+                config.acl_bindings.retain(|_, bound| *bound != acl);
+            }
+            (None, _) => warn!("Chassis {name} doesn't have access list {acl}"),
+        },
+        AclCmd::Prohibited { name: acl, enabled } => match config.acls.get_mut(&acl) {
+            Some(list) => list.prohibited = enabled,
+            None => warn!("Chassis {name} doesn't have access list {acl}"),
+        },
+        AclCmd::Bind {
+            iface_type,
+            iface_id,
+            direction,
+            name: acl,
+        } => {
+            let iface = iface_type.iface(iface_id);
+            if !config.acls.contains_key(&acl) {
+                warn!("Access list {acl} doesn't exist yet, it permits everything until then");
+            }
+            let direction = direction.into();
+            info!("Filtering chassis' {name} {iface} {direction} with access list {acl}");
+            config.acl_bindings.insert((iface, direction), acl);
+        }
+        AclCmd::Unbind {
+            iface_type,
+            iface_id,
+            direction,
+        } => {
+            let iface = iface_type.iface(iface_id);
+            if config
+                .acl_bindings
+                .remove(&(iface, direction.into()))
+                .is_none()
+            {
+                warn!("Chassis {name} {iface} has no access list bound");
+            }
+        }
+        AclCmd::Clear { name: acl } => match config.acls.get_mut(&acl) {
+            Some(list) => {
+                list.rules.iter_mut().for_each(|rule| rule.hits = 0);
+                list.implicit_hits = 0;
+            }
+            None => warn!("Chassis {name} doesn't have access list {acl}"),
+        },
+    }
+}
+
+fn nat(cmd: NatCmd, name: &str, config: &mut IpV4ConfigInner) {
+    let nat = &mut config.nat;
+    match cmd {
+        NatCmd::List => {
+            let mut roles = nat
+                .roles
+                .iter()
+                .map(|(iface, role)| format!("{iface} {role}"))
+                .collect::<Vec<_>>();
+            roles.sort();
+            info!("Chassis {name} NAT interfaces: [{}]", roles.join(", "));
+            for forward in nat.forwards.iter() {
+                info!("Forward {forward}");
+            }
+            info!("Translations:\n{}", nat.print());
+        }
+        NatCmd::Inside {
+            iface_type,
+            iface_id,
+        } => {
+            nat.roles
+                .insert(iface_type.iface(iface_id), nat::Role::Inside);
+        }
+        NatCmd::Outside {
+            iface_type,
+            iface_id,
+        } => {
+            let iface = iface_type.iface(iface_id);
+            if config.iface_addrs(&iface).is_empty() {
+                warn!(
+                    "Chassis {name} {iface} has no address yet, nothing is translated until then"
+                );
+            }
+            config.nat.roles.insert(iface, nat::Role::Outside);
+        }
+        NatCmd::Disable {
+            iface_type,
+            iface_id,
+        } => {
+            let iface = iface_type.iface(iface_id);
+            if nat.roles.remove(&iface).is_none() {
+                warn!("Chassis {name} {iface} doesn't translate addresses");
+            }
+        }
+        NatCmd::Forward {
+            protocol,
+            port,
+            inside,
+            inside_port,
+        } => {
+            let protocol = protocol.into();
+            nat.forwards
+                .retain(|forward| forward.protocol != protocol || forward.port != port);
+            let forward = Forward {
+                protocol,
+                port,
+                inside: Endpoint {
+                    addr: inside,
+                    port: inside_port.unwrap_or(port),
+                },
+            };
+            info!("Chassis {name} forwarding {forward}");
+            nat.forwards.push(forward);
+        }
+        NatCmd::Unforward { protocol, port } => {
+            let protocol = protocol.into();
+            let len = nat.forwards.len();
+            nat.forwards
+                .retain(|forward| forward.protocol != protocol || forward.port != port);
+            if nat.forwards.len() == len {
+                warn!("Chassis {name} doesn't forward port {port}");
+            }
+        }
+        NatCmd::Flush => nat.flush(),
+        NatCmd::Timeouts {
+            udp_secs,
+            icmp_secs,
+            tcp_secs,
+        } => {
+            nat.timeouts = nat::Timeouts {
+                udp: chrono::Duration::seconds(udp_secs),
+                icmp: chrono::Duration::seconds(icmp_secs),
+                tcp: chrono::Duration::seconds(tcp_secs),
+            }
+        }
+    }
+}
+
+fn tunnel(cmd: TunnelCmd, name: &str, config: &mut IpV4ConfigInner) {
+    match cmd {
+        TunnelCmd::List => info!("Chassis {name} IPv4 tunnels:\n{}", config.print_tunnels()),
+        TunnelCmd::Add {
+            id,
+            mode,
+            source,
+            destination,
+        } => {
+            let iface = LinkLayerId::Tunnel(id);
+            if config.tunnels.contains_key(&iface) {
+                warn!("Chassis {name} already has {iface}");
+                return;
+            }
+            if !config.is_local(source) {
+                warn!(
+                    "Chassis {name} doesn't have {source} yet, {iface} receives nothing until then"
+                );
+            }
+            let tunnel = Tunnel {
+                mode: mode.into(),
+                source,
+                destination,
+            };
+            info!(
+                "Adding chassis' {name} {} {iface} from {source} to {destination}",
+                tunnel.mode
+            );
+            config.tunnels.insert(iface, tunnel);
+        }
+        TunnelCmd::Del { id } => {
+            let iface = LinkLayerId::Tunnel(id);
+            if config.tunnels.remove(&iface).is_none() {
+                warn!("Chassis {name} doesn't have {iface}");
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use routing::{
+    network::ipv6::addr::{IpV6Addr, IpV6Mask, UNSPECIFIED},
+    route::RoutingEntry,
+};
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, ctrlc::CtrlC, IfaceId, LinkType};
+
+use super::ParsedChassisCommandRead;
+
+#[derive(Debug, clap::Parser)]
+pub enum IpV6 {
+    #[command(subcommand)]
+    Route(RouteCmd),
+    /// Sets the address of the chassis on the network of the interface
+    Set {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        addr: IpV6Addr,
+        prefix_len: u8,
+    },
+    Get,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum RouteCmd {
+    List,
+    Add {
+        destination: IpV6Addr,
+        prefix_len: u8,
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        /// The destination is on the link without it
+        next_hop: Option<IpV6Addr>,
+    },
+    Get {
+        destination: IpV6Addr,
+    },
+}
+
+pub struct IpV6Command;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRead<IpV6> for IpV6Command {
+    async fn run(
+        &mut self,
+        cmd: IpV6,
+        _: &CtrlC,
+        name: String,
+        ChassisData { ip_v6_conf, .. }: &ChassisData,
+    ) -> bool {
+        match cmd {
+            IpV6::Route(cmd) => match cmd {
+                RouteCmd::List => {
+                    info!(
+                        "Chassis {name} IPv6 routes:\n{}",
+                        ip_v6_conf.read().await.routing.print()
+                    )
+                }
+                RouteCmd::Add {
+                    destination,
+                    prefix_len,
+                    iface_type,
+                    iface_id,
+                    next_hop,
+                } => {
+                    ip_v6_conf
+                        .write()
+                        .await
+                        .routing
+                        .add_route(RoutingEntry::new(
+                            destination,
+                            next_hop.unwrap_or(UNSPECIFIED),
+                            IpV6Mask::new(prefix_len),
+                            iface_type.iface(iface_id),
+                        ));
+                }
+                RouteCmd::Get { destination } => ip_v6_conf
+                    .read()
+                    .await
+                    .routing
+                    .get_route(destination)
+                    .map_or_else(
+                        || {
+                            warn!("Route to {destination} not found");
+                        },
+                        |(route, iface)| {
+                            info!("Route to {destination} through {route} ({iface})");
+                        },
+                    ),
+            },
+            IpV6::Set {
+                iface_type,
+                iface_id,
+                addr,
+                prefix_len,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                info!("Setting chassis' {name} {iface} IPv6 addr to {addr}/{prefix_len}");
+                ip_v6_conf
+                    .write()
+                    .await
+                    .set_addr(addr, IpV6Mask::new(prefix_len), iface);
+            }
+            IpV6::Get => {
+                let config = ip_v6_conf.read().await;
+                match config.prefix {
+                    Some((mask, iface)) => {
+                        info!(
+                            "Chassis {name} IPv6 addr is {}{mask} on {iface}",
+                            config.addr
+                        )
+                    }
+                    None => info!("Chassis {name} IPv6 addr is {}", config.addr),
+                }
+            }
+        }
+        false
+    }
+}
@@ -1,8 +1,11 @@
-use std::sync::Arc;
+use std::{collections::hash_map::Entry, sync::Arc, time::Duration};
 
 use routing::{
     chassis::LinkLayerId,
-    link::ethernet::nic::Nic,
+    link::{
+        ethernet::nic::Nic,
+        impairment::{self, Burst, Impairments},
+    },
     mac::{self, Mac},
 };
 use tokio::sync::RwLock;
@@ -30,6 +33,104 @@ pub enum Link {
         other_chassis: String,
         other_id: u16,
     },
+    /// Adds the `<id>.<vlan>` sub-interface, its frames are tagged with the VLAN id
+    Vlan {
+        link_type: LinkType,
+        id: u16,
+        #[arg(value_parser = clap::value_parser!(u16).range(1..=4094))]
+        vlan: u16,
+    },
+    /// Adds the `bond<id>` aggregation of the Ethernet NICs, negotiated with LACP. It has the
+    /// address of its first NIC
+    Bond {
+        id: u16,
+        #[arg(required = true)]
+        members: Vec<u16>,
+    },
+    /// LACP state of a bond's members
+    Lacp {
+        id: u16,
+    },
+    Disconnect {
+        link_type: LinkType,
+        id: u16,
+    },
+    /// Sets the impairments of the frames the NIC transmits, the ones not given are removed
+    Impair {
+        link_type: LinkType,
+        id: u16,
+        #[command(flatten)]
+        impairments: ImpairArgs,
+    },
+}
+
+/// Impairments of the frames a NIC or a switch port transmits
+#[derive(Debug, clap::Args)]
+pub struct ImpairArgs {
+    /// Milliseconds
+    #[arg(long, default_value_t = 0)]
+    delay: u64,
+    /// Milliseconds, frames can be reordered when it's larger than their spacing
+    #[arg(long, default_value_t = 0)]
+    jitter: u64,
+    #[arg(long, value_enum, default_value_t = Distribution::Uniform)]
+    distribution: Distribution,
+    /// Percentage of frames lost
+    #[arg(long, value_parser = percent, default_value_t = 0.0)]
+    loss: f64,
+    /// Percentages of frames starting and ending a burst of losses
+    #[arg(long, value_parser = percent, num_args = 2, value_names = ["ENTER", "EXIT"])]
+    burst: Option<Vec<f64>>,
+    #[arg(long, value_parser = percent, default_value_t = 0.0)]
+    duplicate: f64,
+    /// Percentage of frames sent without the delay
+    #[arg(long, value_parser = percent, default_value_t = 0.0)]
+    reorder: f64,
+    /// Percentage of frames with a bit of their payload flipped
+    #[arg(long, value_parser = percent, default_value_t = 0.0)]
+    corrupt: f64,
+    /// Bits per second
+    #[arg(long)]
+    rate: Option<u64>,
+    /// Frames waiting for the rate limit before the next ones are dropped
+    #[arg(long, default_value_t = impairment::DEFAULT_QUEUE_LIMIT)]
+    queue: usize,
+}
+
+impl From<ImpairArgs> for Impairments {
+    fn from(args: ImpairArgs) -> Self {
+        Self {
+            delay: Duration::from_millis(args.delay),
+            jitter: Duration::from_millis(args.jitter),
+            distribution: match args.distribution {
+                Distribution::Uniform => impairment::Distribution::Uniform,
+                Distribution::Normal => impairment::Distribution::Normal,
+            },
+            loss: args.loss,
+            burst: args.burst.map(|burst| Burst {
+                enter: burst[0],
+                exit: burst[1],
+            }),
+            duplicate: args.duplicate,
+            reorder: args.reorder,
+            corrupt: args.corrupt,
+            rate: args.rate,
+            queue_limit: args.queue,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Distribution {
+    Uniform,
+    Normal,
+}
+
+fn percent(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p / 100.0),
+        _ => Err(format!("Invalid percentage {s}")),
+    }
 }
 
 pub struct LinkCommand;
@@ -45,12 +146,22 @@ impl ParsedChassisCommand<Link> for LinkCommand {
     ) -> bool {
         let chassis_guard = chassis.read().await;
         let mut guard = chassis_guard.get(&name).unwrap().write().await;
-        let ChassisData { c, nics, .. } = &mut *guard;
+        let ChassisData {
+            c,
+            nics,
+            bonds,
+            ip_v4_arp_handle,
+            ..
+        } = &mut *guard;
         match cmd {
             Link::List => {
                 info!("Chassis {name} interfaces:");
                 for (iface, handle) in nics.iter() {
-                    info!("{iface:<5} {}", handle);
+                    info!(
+                        "{iface:<5} {}, {}",
+                        handle,
+                        handle.impairments().read().await
+                    );
                 }
             }
             Link::Add {
@@ -64,6 +175,93 @@ impl ParsedChassisCommand<Link> for LinkCommand {
                 );
                 info!("NIC added");
             }
+            Link::Vlan {
+                link_type: LinkType::Eth,
+                id,
+                vlan,
+            } => match c.add_sub_interface(id, vlan) {
+                Some(iface) => info!("Sub-interface {iface} added"),
+                None => warn!("Chassis `{name}` doesn't have interface eth{id}"),
+            },
+            Link::Bond { id, members } => match bonds.entry(id) {
+                Entry::Occupied(_) => warn!("Chassis `{name}` already has interface bond{id}"),
+                Entry::Vacant(entry) => match c.add_bond(id, &members) {
+                    Some((iface, lacp)) => {
+                        entry.insert(lacp);
+                        info!("Bond {iface} added");
+                    }
+                    None => warn!("Chassis `{name}` doesn't have all the member interfaces"),
+                },
+            },
+            Link::Lacp { id } => match bonds.get(&id) {
+                Some(lacp) => info!("Chassis {name} bond{id}:\n{}", lacp.read().await.print()),
+                None => warn!("Chassis `{name}` doesn't have interface bond{id}"),
+            },
+            Link::Disconnect {
+                link_type: LinkType::Eth,
+                id,
+            } => match nics.get_mut(&LinkLayerId::Ethernet(id, mac::BROADCAST)) {
+                Some(handle) => {
+                    if handle.disconnect().await {
+                        info!("Disconnected");
+                    } else {
+                        warn!("Didn't disconnect")
+                    }
+                }
+                None => warn!("Chassis `{name}` doesn't have interface eth{id}"),
+            },
+            Link::Impair {
+                link_type: LinkType::Eth,
+                id,
+                impairments,
+            } => match nics.get(&LinkLayerId::Ethernet(id, mac::BROADCAST)) {
+                Some(handle) => {
+                    let impairments = Impairments::from(impairments);
+                    info!("eth{id}: {impairments}");
+                    *handle.impairments().write().await = impairments;
+                }
+                None => warn!("Chassis `{name}` doesn't have interface eth{id}"),
+            },
+            Link::Add {
+                link_type: LinkType::Bond,
+                ..
+            } => warn!("Bonds are added with `link bond`"),
+            Link::Vlan {
+                link_type: LinkType::Bond,
+                ..
+            } => warn!("Bonds have no sub-interfaces"),
+            Link::Connect {
+                link_type: LinkType::Bond,
+                ..
+            }
+            | Link::Disconnect {
+                link_type: LinkType::Bond,
+                ..
+            }
+            | Link::Impair {
+                link_type: LinkType::Bond,
+                ..
+            } => warn!("The members of a bond are connected, not the bond"),
+            Link::Add {
+                link_type: LinkType::Tun,
+                ..
+            }
+            | Link::Vlan {
+                link_type: LinkType::Tun,
+                ..
+            }
+            | Link::Connect {
+                link_type: LinkType::Tun,
+                ..
+            }
+            | Link::Disconnect {
+                link_type: LinkType::Tun,
+                ..
+            }
+            | Link::Impair {
+                link_type: LinkType::Tun,
+                ..
+            } => warn!("Tunnels are added with `ip-v4 tunnel`, they have no link"),
             Link::Connect {
                 link_type: LinkType::Eth,
                 id,
@@ -79,6 +277,9 @@ impl ParsedChassisCommand<Link> for LinkCommand {
                         if let Some(other_handle) = other_handles.get_mut(&other_id) {
                             if handle.connect_other(other_handle).await {
                                 info!("Connected");
+                                // Both NICs came up, their neighbors learn where their addresses are
+                                ip_v4_arp_handle.announce(self_id).await;
+                                guard.ip_v4_arp_handle.announce(other_id).await;
                             } else {
                                 warn!("Didn't connect")
                             }
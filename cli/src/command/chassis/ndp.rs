@@ -0,0 +1,80 @@
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, ctrlc::CtrlC, IfaceId, LinkType};
+
+use super::ParsedChassisCommandRead;
+
+#[derive(Debug, clap::Parser)]
+pub enum Ndp {
+    List,
+    RouterSolicit {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+    /// Router lifetime in seconds advertised to router solicitations, 0 disables them
+    RouterLifetime {
+        lifetime: u16,
+    },
+}
+
+pub struct NdpCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRead<Ndp> for NdpCommand {
+    async fn run(
+        &mut self,
+        cmd: Ndp,
+        _: &CtrlC,
+        name: String,
+        ChassisData {
+            ndp_handle,
+            ip_v6_conf,
+            ..
+        }: &ChassisData,
+    ) -> bool {
+        match cmd {
+            Ndp::List => {
+                if let Some(data) = ndp_handle.get_table().await {
+                    let mut table = prettytable::table!([
+                        "IPv6",
+                        "interface",
+                        "MAC",
+                        "state",
+                        "router",
+                        "updated"
+                    ]);
+                    if data.is_empty() {
+                        table.add_empty_row();
+                    }
+                    for ((ip, iface), entry) in data.into_iter() {
+                        table.add_row(prettytable::row![
+                            ip,
+                            iface,
+                            entry
+                                .mac
+                                .map_or_else(|| "-".to_string(), |mac| mac.to_string()),
+                            entry.state,
+                            entry.router,
+                            entry.updated.format("%d/%m/%Y %H:%M:%S%.f")
+                        ]);
+                    }
+                    info!("NDP neighbor list:\n{table}");
+                }
+            }
+            Ndp::RouterSolicit {
+                iface_type,
+                iface_id,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                if ndp_handle.router_solicit(iface).await.is_none() {
+                    warn!("Unable to send router solicitation through {iface}");
+                }
+            }
+            Ndp::RouterLifetime { lifetime } => {
+                info!("Setting chassis' {name} router lifetime to {lifetime}s");
+                ip_v6_conf.write().await.router_lifetime = lifetime;
+            }
+        }
+        false
+    }
+}
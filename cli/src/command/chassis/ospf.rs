@@ -0,0 +1,129 @@
+use routing::{
+    application::ospf::router::{OspfIface, OspfRouter},
+    network::ipv4::addr::IpV4Addr,
+};
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, ctrlc::CtrlC, IfaceId, LinkType};
+
+use super::ParsedChassisCommandRead;
+
+#[derive(Debug, clap::Parser)]
+pub enum Ospf {
+    /// Sends hellos and forms adjacencies on the interface
+    Enable {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        #[arg(long, short, default_value = "0.0.0.0")]
+        area: IpV4Addr,
+        #[arg(long, short, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..))]
+        cost: u16,
+    },
+    Disable {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+    /// Used by the routers started afterwards, the highest interface address otherwise
+    RouterId {
+        id: IpV4Addr,
+    },
+    /// Has to match the neighbors' for them to be heard
+    Timers {
+        hello_secs: u16,
+        dead_secs: u32,
+    },
+    Neighbors,
+    /// Link state database of every area
+    Database,
+    /// Runs the router in the background
+    Start,
+    /// Stops the router and removes the routes it learned
+    Stop {
+        pid: u64,
+    },
+}
+
+pub struct OspfCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRead<Ospf> for OspfCommand {
+    async fn run(
+        &mut self,
+        cmd: Ospf,
+        _: &CtrlC,
+        name: String,
+        ChassisData {
+            ip_v4_conf,
+            ospf_handle,
+            processes,
+            ospf_conf,
+            ..
+        }: &ChassisData,
+    ) -> bool {
+        match cmd {
+            Ospf::Enable {
+                iface_type,
+                iface_id,
+                area,
+                cost,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                info!("Enabling OSPF on chassis' {name} {iface} in area {area}");
+                ospf_conf
+                    .write()
+                    .await
+                    .ifaces
+                    .insert(iface, OspfIface { area, cost });
+            }
+            Ospf::Disable {
+                iface_type,
+                iface_id,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                info!("Disabling OSPF on chassis' {name} {iface}");
+                ospf_conf.write().await.ifaces.remove(&iface);
+            }
+            Ospf::RouterId { id } => {
+                ospf_conf.write().await.router_id = Some(id);
+            }
+            Ospf::Timers {
+                hello_secs,
+                dead_secs,
+            } => {
+                let mut config = ospf_conf.write().await;
+                config.hello_interval = hello_secs;
+                config.dead_interval = dead_secs;
+            }
+            Ospf::Neighbors => {
+                info!(
+                    "Chassis {name} OSPF neighbors:\n{}",
+                    ospf_conf.read().await.print_neighbors()
+                );
+            }
+            Ospf::Database => {
+                info!(
+                    "Chassis {name} OSPF database:\n{}",
+                    ospf_conf.read().await.print_database()
+                );
+            }
+            Ospf::Start => {
+                match OspfRouter::new(ospf_conf.clone(), ip_v4_conf.clone(), ospf_handle).await {
+                    Ok(router) => {
+                        let pid = processes.add(|_| router.run()).await;
+                        info!("OSPF router started (pid {pid})");
+                    }
+                    Err(()) => warn!("Unable to start the OSPF router"),
+                }
+            }
+            Ospf::Stop { pid } => {
+                let _ = processes.stop_process(pid).await;
+                ospf_conf
+                    .write()
+                    .await
+                    .flush(&mut *ip_v4_conf.write().await);
+                info!("Stopped process {pid}");
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,111 @@
+use routing::application::rip::router::RipRouter;
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, ctrlc::CtrlC, IfaceId, LinkType};
+
+use super::ParsedChassisCommandRead;
+
+#[derive(Debug, clap::Parser)]
+pub enum Rip {
+    /// Sends and receives updates on the interface
+    Enable {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+    Disable {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+    },
+    /// Advertises the routes back to the interface they were learned from as unreachable,
+    /// instead of leaving them out
+    PoisonedReverse {
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    Timers {
+        update_secs: i64,
+        timeout_secs: i64,
+        garbage_secs: i64,
+    },
+    Routes,
+    /// Runs the router in the background
+    Start,
+    /// Stops the router and removes the routes it learned
+    Stop {
+        pid: u64,
+    },
+}
+
+pub struct RipCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRead<Rip> for RipCommand {
+    async fn run(
+        &mut self,
+        cmd: Rip,
+        _: &CtrlC,
+        name: String,
+        ChassisData {
+            ip_v4_conf,
+            udp_broadcast_handle,
+            processes,
+            rip_conf,
+            ..
+        }: &ChassisData,
+    ) -> bool {
+        match cmd {
+            Rip::Enable {
+                iface_type,
+                iface_id,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                info!("Enabling RIP on chassis' {name} {iface}");
+                rip_conf.write().await.ifaces.insert(iface);
+            }
+            Rip::Disable {
+                iface_type,
+                iface_id,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                info!("Disabling RIP on chassis' {name} {iface}");
+                rip_conf.write().await.ifaces.remove(&iface);
+            }
+            Rip::PoisonedReverse { enabled } => {
+                rip_conf.write().await.poisoned_reverse = enabled;
+            }
+            Rip::Timers {
+                update_secs,
+                timeout_secs,
+                garbage_secs,
+            } => {
+                let mut config = rip_conf.write().await;
+                config.update_interval = chrono::Duration::seconds(update_secs);
+                config.timeout = chrono::Duration::seconds(timeout_secs);
+                config.garbage_collection = chrono::Duration::seconds(garbage_secs);
+            }
+            Rip::Routes => {
+                info!(
+                    "Chassis {name} RIP routes:\n{}",
+                    rip_conf.read().await.print_routes()
+                );
+            }
+            Rip::Start => {
+                match RipRouter::new(rip_conf.clone(), ip_v4_conf.clone(), udp_broadcast_handle)
+                    .await
+                {
+                    Ok(router) => {
+                        let pid = processes.add(|_| router.run()).await;
+                        info!("RIP router started (pid {pid})");
+                    }
+                    Err(()) => warn!("Unable to open the RIP socket"),
+                }
+            }
+            Rip::Stop { pid } => {
+                let _ = processes.stop_process(pid).await;
+                rip_conf.write().await.flush(&mut *ip_v4_conf.write().await);
+                info!("Stopped process {pid}");
+            }
+        }
+        false
+    }
+}
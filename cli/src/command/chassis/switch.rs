@@ -0,0 +1,316 @@
+use std::{collections::hash_map::Entry, sync::Arc, time::Duration};
+
+use routing::{
+    chassis::{
+        switch::{stp, PortType, Switch},
+        LinkLayerId, NicHandle,
+    },
+    link::{
+        ethernet::{dot1q::Tag, nic::Nic},
+        impairment::Impairments,
+    },
+    mac::{self, Mac},
+};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::{
+    chassis::{ChassisData, ChassisManager},
+    ctrlc::CtrlC,
+};
+
+use super::{link::ImpairArgs, ParsedChassisCommand};
+
+/// Time a learned address is kept in the MAC table
+const MAC_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, clap::Parser)]
+pub enum SwitchCmd {
+    List,
+    /// Adds the switch with untagged ports, they get consecutive addresses from the MAC. RSTP
+    /// runs on it
+    Add {
+        id: u16,
+        ports: usize,
+        mac: Mac,
+    },
+    /// Connects the switch port to an Ethernet NIC of the chassis, or to a port of one of its
+    /// switches
+    Connect {
+        id: u16,
+        port: usize,
+        other_chassis: String,
+        other_id: u16,
+        /// The other id is a port of this switch of the other chassis
+        #[arg(long)]
+        switch: Option<u16>,
+    },
+    Disconnect {
+        id: u16,
+        port: usize,
+    },
+    /// Sets how the port tags the frames
+    Port {
+        id: u16,
+        port: usize,
+        port_type: PortTypeArg,
+        /// VLAN of an access port, service VLAN of a tunnel port
+        #[arg(value_parser = clap::value_parser!(u16).range(1..=4094))]
+        vlan: Option<u16>,
+    },
+    /// Spanning tree state of the switch, or the version it runs from now on
+    Stp {
+        id: u16,
+        version: Option<StpVersion>,
+    },
+    /// Whether the port is an edge port, auto edge ports are the ones no BPDU is received on
+    Edge {
+        id: u16,
+        port: usize,
+        edge: Edge,
+    },
+    /// Sets the impairments of the frames the port transmits, the ones not given are removed
+    Impair {
+        id: u16,
+        port: usize,
+        #[command(flatten)]
+        impairments: ImpairArgs,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum StpVersion {
+    /// 802.1D
+    Stp,
+    Rstp,
+    /// Every port forwards
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Edge {
+    Auto,
+    Yes,
+    No,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum PortTypeArg {
+    /// Every frame is tagged
+    Trunk,
+    /// The frames are in a single VLAN, untagged on the port
+    Access,
+    /// Provider edge, the customer frames are carried in a service tag (QinQ)
+    Tunnel,
+    /// Untagged frames only
+    Untagged,
+    /// Becomes a trunk when a tagged frame is sent to it
+    Unknown,
+}
+
+/// Address of the nth port of a switch whose first port has the MAC
+fn port_mac(mac: Mac, n: usize) -> Mac {
+    let mut bytes = [0; 8];
+    bytes[2..].copy_from_slice(mac.as_slice());
+    let addr = u64::from_be_bytes(bytes)
+        .wrapping_add(n as u64)
+        .to_be_bytes();
+    Mac::new(addr[2..].try_into().unwrap())
+}
+
+pub struct SwitchCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommand<SwitchCmd> for SwitchCommand {
+    async fn run(
+        &mut self,
+        cmd: SwitchCmd,
+        chassis: Arc<RwLock<ChassisManager>>,
+        _: &CtrlC,
+        name: String,
+    ) -> bool {
+        let chassis_guard = chassis.read().await;
+        let mut guard = chassis_guard.get(&name).unwrap().write().await;
+        let ChassisData { switches, .. } = &mut *guard;
+        match cmd {
+            SwitchCmd::List => {
+                info!("Chassis {name} switches:");
+                for (id, switch) in switches.iter() {
+                    info!("switch{id}:");
+                    for (port, port_type, handle) in switch.ports().await {
+                        let handle = handle.read().await;
+                        info!(
+                            "    eth{port} {port_type}, {}, {}",
+                            handle,
+                            handle.impairments().read().await
+                        );
+                    }
+                }
+            }
+            SwitchCmd::Add { id, ports, mac } => match switches.entry(id) {
+                Entry::Occupied(_) => warn!("Chassis `{name}` already has switch{id}"),
+                Entry::Vacant(entry) => {
+                    let switch = Switch::new(MAC_TTL);
+                    for n in 0..ports {
+                        switch
+                            .add_nic(Nic::new_with_mac(port_mac(mac, n)), PortType::NoDot1q)
+                            .await;
+                    }
+                    entry.insert(switch);
+                    info!("Switch added");
+                }
+            },
+            SwitchCmd::Connect {
+                id,
+                port,
+                other_chassis,
+                other_id,
+                switch,
+            } => {
+                let Some(handle) = port_handle(switches.get(&id), port).await else {
+                    warn!("Chassis `{name}` doesn't have port {port} of switch{id}");
+                    return false;
+                };
+                // This chassis is locked already
+                let mut other_guard = if other_chassis == name {
+                    None
+                } else if let Some(other) = chassis_guard.get(&other_chassis) {
+                    Some(other.write().await)
+                } else {
+                    warn!("Chassis `{other_chassis}` doesn't exist");
+                    return false;
+                };
+                let other = other_guard.as_deref_mut().unwrap_or(&mut *guard);
+                let connected = match switch {
+                    Some(other_switch) => {
+                        let other_switches = other.switches.get(&other_switch);
+                        let Some(other_port) = port_handle(other_switches, other_id.into()).await
+                        else {
+                            warn!("Chassis `{other_chassis}` doesn't have port {other_id} of switch{other_switch}");
+                            return false;
+                        };
+                        if Arc::ptr_eq(&handle, &other_port) {
+                            warn!("A port can't be connected to itself");
+                            return false;
+                        }
+                        let mut other_port = other_port.write().await;
+                        handle.write().await.connect_other(&mut other_port).await
+                    }
+                    None => {
+                        let iface = LinkLayerId::Ethernet(other_id, mac::BROADCAST);
+                        let Some(nic) = other.nics.get_mut(&iface) else {
+                            warn!("Chassis `{other_chassis}` doesn't have interface {iface}");
+                            return false;
+                        };
+                        let connected = handle.write().await.connect_other(nic).await;
+                        if connected {
+                            // The NIC came up, its neighbors learn where its address is
+                            other.ip_v4_arp_handle.announce(iface).await;
+                        }
+                        connected
+                    }
+                };
+                if connected {
+                    info!("Connected");
+                } else {
+                    warn!("Didn't connect");
+                }
+            }
+            SwitchCmd::Disconnect { id, port } => {
+                match port_handle(switches.get(&id), port).await {
+                    Some(handle) => {
+                        if handle.write().await.disconnect().await {
+                            info!("Disconnected");
+                        } else {
+                            warn!("Didn't disconnect")
+                        }
+                    }
+                    None => warn!("Chassis `{name}` doesn't have port {port} of switch{id}"),
+                }
+            }
+            SwitchCmd::Port {
+                id,
+                port,
+                port_type,
+                vlan,
+            } => {
+                let Some(switch) = switches.get(&id) else {
+                    warn!("Chassis `{name}` doesn't have switch{id}");
+                    return false;
+                };
+                if port >= switch.ports_len().await {
+                    warn!("Chassis `{name}` doesn't have port {port} of switch{id}");
+                    return false;
+                }
+                let port_type = match (port_type, vlan) {
+                    (PortTypeArg::Trunk, None) => PortType::Trunk,
+                    (PortTypeArg::Untagged, None) => PortType::NoDot1q,
+                    (PortTypeArg::Unknown, None) => PortType::Unknown,
+                    (PortTypeArg::Access, Some(vlan)) => PortType::Vlan(Tag::new(0, false, vlan)),
+                    (PortTypeArg::Tunnel, Some(vlan)) => {
+                        PortType::Tunnel(Tag::new_service(0, false, vlan))
+                    }
+                    (PortTypeArg::Access | PortTypeArg::Tunnel, None) => {
+                        warn!("The port needs a VLAN");
+                        return false;
+                    }
+                    (_, Some(_)) => {
+                        warn!("The port has no VLAN");
+                        return false;
+                    }
+                };
+                switch.set_port_type(port, port_type).await;
+                info!("switch{id} eth{port} is now {port_type}");
+            }
+            SwitchCmd::Stp { id, version } => {
+                let Some(switch) = switches.get(&id) else {
+                    warn!("Chassis `{name}` doesn't have switch{id}");
+                    return false;
+                };
+                match version {
+                    None => info!(
+                        "Chassis {name} switch{id}:\n{}",
+                        switch.stp().read().await.print()
+                    ),
+                    Some(StpVersion::Stp) => switch.enable_stp(stp::Version::Stp).await,
+                    Some(StpVersion::Rstp) => switch.enable_stp(stp::Version::Rstp).await,
+                    Some(StpVersion::Off) => switch.disable_stp().await,
+                }
+            }
+            SwitchCmd::Edge { id, port, edge } => {
+                let Some(switch) = switches.get(&id) else {
+                    warn!("Chassis `{name}` doesn't have switch{id}");
+                    return false;
+                };
+                let edge = match edge {
+                    Edge::Auto => None,
+                    Edge::Yes => Some(true),
+                    Edge::No => Some(false),
+                };
+                let now = tokio::time::Instant::now();
+                if !switch.stp().write().await.set_edge(port, edge, now) {
+                    warn!("Chassis `{name}` doesn't have port {port} of switch{id}");
+                }
+            }
+            SwitchCmd::Impair {
+                id,
+                port,
+                impairments,
+            } => match port_handle(switches.get(&id), port).await {
+                Some(handle) => {
+                    let impairments = Impairments::from(impairments);
+                    info!("switch{id} eth{port}: {impairments}");
+                    *handle.read().await.impairments().write().await = impairments;
+                }
+                None => warn!("Chassis `{name}` doesn't have port {port} of switch{id}"),
+            },
+        }
+        false
+    }
+}
+
+/// Handle of the switch's port
+async fn port_handle(switch: Option<&Switch>, port: usize) -> Option<Arc<RwLock<NicHandle>>> {
+    let (_, _, handle) = switch?.ports().await.into_iter().nth(port)?;
+    Some(handle)
+}
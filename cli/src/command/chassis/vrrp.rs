@@ -0,0 +1,182 @@
+use routing::{
+    application::vrrp::{
+        router::{VirtualRouter, VrrpRouter},
+        MAX_INTERVAL,
+    },
+    network::ipv4::addr::IpV4Addr,
+};
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, ctrlc::CtrlC, IfaceId, LinkType};
+
+use super::ParsedChassisCommandRead;
+
+#[derive(Debug, clap::Parser)]
+pub enum Vrrp {
+    /// Adds the virtual router to the Ethernet interface, or replaces its addresses
+    Add {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        #[arg(value_parser = clap::value_parser!(u8).range(1..))]
+        vrid: u8,
+        #[arg(required = true)]
+        addrs: Vec<IpV4Addr>,
+    },
+    /// Removes the virtual router, a master hands over to the backups first
+    Remove {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        vrid: u8,
+    },
+    /// The highest one is master, 255 is for the router whose interface has the addresses
+    Priority {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        vrid: u8,
+        #[arg(value_parser = clap::value_parser!(u8).range(1..))]
+        priority: u8,
+    },
+    /// Takes over from a master of lower priority
+    Preempt {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        vrid: u8,
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Centiseconds between the advertisements as master
+    Interval {
+        iface_type: LinkType,
+        iface_id: IfaceId,
+        vrid: u8,
+        #[arg(value_parser = clap::value_parser!(u16).range(1..=MAX_INTERVAL as i64))]
+        centisecs: u16,
+    },
+    List,
+    /// Runs the router in the background
+    Start,
+    /// Stops the router and gives up the virtual addresses
+    Stop {
+        pid: u64,
+    },
+}
+
+pub struct VrrpCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRead<Vrrp> for VrrpCommand {
+    async fn run(
+        &mut self,
+        cmd: Vrrp,
+        _: &CtrlC,
+        name: String,
+        ChassisData {
+            c,
+            ip_v4_conf,
+            vrrp_handle,
+            processes,
+            vrrp_conf,
+            ..
+        }: &ChassisData,
+    ) -> bool {
+        match cmd {
+            Vrrp::Add {
+                iface_type,
+                iface_id,
+                vrid,
+                addrs,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                let mut config = vrrp_conf.write().await;
+                if let Some(router) = config.routers.get_mut(&(iface, vrid)) {
+                    router.addrs = addrs;
+                    return false;
+                }
+                let Some(link) = c.virtual_link(iface) else {
+                    warn!("Chassis `{name}` doesn't have the Ethernet interface {iface}");
+                    return false;
+                };
+                info!("Adding VRRP virtual router {vrid} on chassis' {name} {iface}");
+                config
+                    .routers
+                    .insert((iface, vrid), VirtualRouter::new(link, addrs));
+            }
+            Vrrp::Remove {
+                iface_type,
+                iface_id,
+                vrid,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                match vrrp_conf.write().await.routers.remove(&(iface, vrid)) {
+                    Some(mut router) => {
+                        router
+                            .shutdown(iface, vrid, &mut *ip_v4_conf.write().await)
+                            .await
+                    }
+                    None => warn!("No virtual router {vrid} on {iface}"),
+                }
+            }
+            Vrrp::Priority {
+                iface_type,
+                iface_id,
+                vrid,
+                priority,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                match vrrp_conf.write().await.routers.get_mut(&(iface, vrid)) {
+                    Some(router) => router.priority = priority,
+                    None => warn!("No virtual router {vrid} on {iface}"),
+                }
+            }
+            Vrrp::Preempt {
+                iface_type,
+                iface_id,
+                vrid,
+                enabled,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                match vrrp_conf.write().await.routers.get_mut(&(iface, vrid)) {
+                    Some(router) => router.preempt = enabled,
+                    None => warn!("No virtual router {vrid} on {iface}"),
+                }
+            }
+            Vrrp::Interval {
+                iface_type,
+                iface_id,
+                vrid,
+                centisecs,
+            } => {
+                let iface = iface_type.iface(iface_id);
+                match vrrp_conf.write().await.routers.get_mut(&(iface, vrid)) {
+                    Some(router) => router.interval = centisecs,
+                    None => warn!("No virtual router {vrid} on {iface}"),
+                }
+            }
+            Vrrp::List => {
+                info!(
+                    "Chassis {name} VRRP virtual routers:\n{}",
+                    vrrp_conf.read().await.print()
+                );
+            }
+            Vrrp::Start => {
+                match VrrpRouter::new(vrrp_conf.clone(), ip_v4_conf.clone(), vrrp_handle).await {
+                    Ok(router) => {
+                        let pid = processes.add(|_| router.run()).await;
+                        info!("VRRP router started (pid {pid})");
+                    }
+                    Err(()) => warn!("Unable to start the VRRP router"),
+                }
+            }
+            Vrrp::Stop { pid } => {
+                let _ = processes.stop_process(pid).await;
+                vrrp_conf
+                    .write()
+                    .await
+                    .shutdown(&mut *ip_v4_conf.write().await)
+                    .await;
+                info!("Stopped process {pid}");
+            }
+        }
+        false
+    }
+}
@@ -5,10 +5,15 @@ use routing::{
     network::{
         arp::ArpProcess,
         ipv4::{config::IpV4Config, IpV4Process},
+        ipv6::{config::IpV6Config, Ipv6Process},
+        ndp::NdpProcess,
     },
     transport::{
         icmp::IcmpProcess,
+        ospf::OspfProcess,
+        tcp::{TcpProcess, TcpProcessGeneric},
         udp::{UdpProcess, UdpProcessGeneric},
+        vrrp::VrrpProcess,
     },
 };
 use tokio::sync::RwLock;
@@ -83,23 +88,52 @@ impl ParsedCommand<New, (), Option<String>> for NewCommand {
             info!("Created new chassis with name: {name}");
             let current_chassis = Some(name.clone());
             let conf = IpV4Config::default();
+            let conf_v6 = IpV6Config::default();
             let mut c = Chassis::new();
-            let (arp, arphandle) = ArpProcess::new(Some(conf.clone()), None);
+            let (arp, arphandle) = ArpProcess::new(Some(conf.clone()));
             c.add_network_layer_process(NetworkLayerId::Arp, arp);
             let ip = IpV4Process::new(conf.clone(), arphandle.get_new_ipv4_handle().await.unwrap());
             c.add_network_layer_process(NetworkLayerId::Ipv4, ip);
+            let (ndp, ndp_handle) = NdpProcess::new(conf_v6.clone());
+            c.add_network_layer_process(NetworkLayerId::Ndp, ndp);
+            let ip_v6 =
+                Ipv6Process::new(conf_v6.clone(), ndp_handle.get_new_handle().await.unwrap());
+            c.add_network_layer_process(NetworkLayerId::Ipv6, ip_v6);
             let (icmp, icmp_api) = IcmpProcess::new();
             c.add_transport_layer_process(TransportLayerId::Icmp, icmp);
             let (udp_ip_v4, udp_ip_v4_handle) = UdpProcessGeneric::new();
-            c.add_transport_layer_process(TransportLayerId::Udp, UdpProcess::new(udp_ip_v4));
+            let (udp_ip_v6, udp_ip_v6_handle) = UdpProcessGeneric::new();
+            let (udp_broadcast, udp_broadcast_handle) = UdpProcessGeneric::new();
+            c.add_transport_layer_process(
+                TransportLayerId::Udp,
+                UdpProcess::new(udp_ip_v4, udp_ip_v6, udp_broadcast),
+            );
+            let (tcp_ip_v4, tcp_ip_v4_handle) = TcpProcessGeneric::new();
+            let (tcp_ip_v6, tcp_ip_v6_handle) = TcpProcessGeneric::new();
+            c.add_transport_layer_process(
+                TransportLayerId::Tcp,
+                TcpProcess::new(tcp_ip_v4, tcp_ip_v6),
+            );
+            let (ospf, ospf_handle) = OspfProcess::new();
+            c.add_transport_layer_process(TransportLayerId::Ospf, ospf);
+            let (vrrp, vrrp_handle) = VrrpProcess::new();
+            c.add_transport_layer_process(TransportLayerId::Vrrp, vrrp);
             chassis.write().await.insert(
                 name,
                 RwLock::new(ChassisData::new(
                     c,
                     conf,
+                    conf_v6,
                     arphandle,
+                    ndp_handle,
                     icmp_api,
                     udp_ip_v4_handle,
+                    udp_ip_v6_handle,
+                    udp_broadcast_handle,
+                    tcp_ip_v4_handle,
+                    tcp_ip_v6_handle,
+                    ospf_handle,
+                    vrrp_handle,
                 )),
             );
             current_chassis
@@ -1,9 +1,13 @@
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
 
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
-use routing::{mac::Mac, network::ipv4::addr::IpV4Addr};
+use routing::{
+    chassis::LinkLayerId,
+    mac::{self, Mac},
+    network::ipv4::addr::IpV4Addr,
+};
 
 use crate::{
     arguments::ArgumentsIter,
@@ -14,6 +18,7 @@ use crate::{
     },
     ctrlc::CtrlC,
     ping::PingCommand,
+    tcp::TcpCommand,
     traceroute::TracerouteCommand,
 };
 
@@ -23,6 +28,7 @@ mod cli;
 mod command;
 mod ctrlc;
 mod ping;
+mod tcp;
 mod traceroute;
 // #[derive(Debug, clap::Parser)]
 // #[command(name = ">")]
@@ -97,6 +103,48 @@ enum LinkCmd {
 #[derive(Debug, Clone, clap::ValueEnum)]
 pub enum LinkType {
     Eth,
+    Bond,
+    Tun,
+}
+
+impl LinkType {
+    pub fn iface(&self, IfaceId { id, vlan }: IfaceId) -> LinkLayerId {
+        match (self, vlan) {
+            (Self::Eth, None) => LinkLayerId::Ethernet(id, mac::BROADCAST),
+            (Self::Eth, Some(vlan)) => LinkLayerId::Dot1q(id, vlan, mac::BROADCAST),
+            // Bonds and tunnels have no sub-interfaces
+            (Self::Bond, _) => LinkLayerId::Bond(id, mac::BROADCAST),
+            (Self::Tun, _) => LinkLayerId::Tunnel(id),
+        }
+    }
+}
+
+/// Interface number, `<id>.<vlan>` for a VLAN sub-interface
+#[derive(Debug, Clone, Copy)]
+pub struct IfaceId {
+    pub id: u16,
+    pub vlan: Option<u16>,
+}
+
+impl FromStr for IfaceId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, vlan) = match s.split_once('.') {
+            Some((id, vlan)) => (id, Some(vlan)),
+            None => (s, None),
+        };
+        let id = id
+            .parse()
+            .map_err(|e| format!("Invalid interface id: {e}"))?;
+        let vlan = vlan
+            .map(|vlan| match vlan.parse() {
+                Ok(vlan @ 1..=4094) => Ok(vlan),
+                _ => Err(format!("Invalid VLAN id {vlan}")),
+            })
+            .transpose()?;
+        Ok(Self { id, vlan })
+    }
 }
 
 #[tokio::main]
@@ -135,12 +183,31 @@ async fn start() {
     chassis_command_manager.register::<PCmd<_, _, _, _>, _, _>("exit", command::chassis::Exit);
     chassis_command_manager.register::<PCmd<_, _, _, _>, _, _>("ping", PingCommand);
     chassis_command_manager.register::<PCmd<_, _, _, _>, _, _>("traceroute", TracerouteCommand);
+    chassis_command_manager.register::<PCmd<_, _, _, _>, _, _>("tcp", TcpCommand);
     chassis_command_manager
         .register::<PCmd<_, _, _, _>, _, _>("arp", command::chassis::arp::ArpCommand);
     chassis_command_manager
         .register::<PCmd<_, _, _, _>, _, _>("link", command::chassis::link::LinkCommand);
     chassis_command_manager
         .register::<PCmd<_, _, _, _>, _, _>("ip-v4", command::chassis::ip_v4::IpV4Command);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("ip-v6", command::chassis::ip_v6::IpV6Command);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("ndp", command::chassis::ndp::NdpCommand);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("dhcp", command::chassis::dhcp::DhcpCommand);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("rip", command::chassis::rip::RipCommand);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("ospf", command::chassis::ospf::OspfCommand);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("vrrp", command::chassis::vrrp::VrrpCommand);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("bgp", command::chassis::bgp::BgpCommand);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("capture", command::chassis::capture::CaptureCommand);
+    chassis_command_manager
+        .register::<PCmd<_, _, _, _>, _, _>("switch", command::chassis::switch::SwitchCommand);
     // register_commands(&mut chassis_command_manager);
 
     loop {
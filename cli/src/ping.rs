@@ -1,6 +1,9 @@
 use std::sync::Arc;
 
-use routing::{network::ipv4::addr::IpV4Addr, transport::icmp::IcmpApi};
+use routing::{
+    network::ip::IpAddr,
+    transport::icmp::{packet::DestinationUnreachable, IcmpApi},
+};
 use tokio::{select, sync::RwLock};
 use tracing::{info, warn};
 
@@ -8,26 +11,52 @@ use crate::{chassis::ChassisData, command::chassis::ParsedChassisCommandRead, ct
 
 #[derive(Debug, clap::Parser)]
 pub struct Ping {
-    ip: IpV4Addr,
+    ip: IpAddr,
     #[arg(long, short, default_value_t = 5.)]
     timeout_secs: f32,
     #[arg(short)]
     number: Option<usize>,
 }
 
+/// Description and traceroute style flag of the error
+pub fn unreachable_description(error: &DestinationUnreachable) -> (&'static str, &'static str) {
+    match error {
+        DestinationUnreachable::NetUnreachable { .. } => ("Destination Net Unreachable", "!N"),
+        DestinationUnreachable::HostUnreachable { .. } => ("Destination Host Unreachable", "!H"),
+        DestinationUnreachable::ProtocolUnreachable { .. } => {
+            ("Destination Protocol Unreachable", "!P")
+        }
+        DestinationUnreachable::PortUnreachable { .. } => ("Destination Port Unreachable", "!p"),
+        DestinationUnreachable::FragmentationNeeded { .. } => ("Fragmentation Needed", "!F"),
+        DestinationUnreachable::AdministrativelyProhibited { .. } => {
+            ("Communication Administratively Prohibited", "!X")
+        }
+    }
+}
+
+type EchoResult = Result<(u16, u16, IpAddr, u8), (IpAddr, DestinationUnreachable)>;
+
 async fn echo(
-    ip: IpV4Addr,
+    ip: IpAddr,
     timeout: f32,
     id: u16,
     seq: u16,
     icmp_api: &IcmpApi,
-) -> Option<((u16, u16, IpV4Addr, u8), std::time::Duration)> {
+) -> Option<(EchoResult, std::time::Duration)> {
     let start = std::time::Instant::now();
 
-    match tokio::time::timeout(
-        std::time::Duration::from_secs_f32(timeout),
-        icmp_api.echo_ip_v4(id, seq, ip),
-    )
+    match tokio::time::timeout(std::time::Duration::from_secs_f32(timeout), async {
+        match ip {
+            IpAddr::V4(ip) => icmp_api.echo_ip_v4(id, seq, ip).await.map(|res| {
+                res.map(|(id, seq, addr, ttl)| (id, seq, IpAddr::V4(addr), ttl))
+                    .map_err(|(addr, error)| (IpAddr::V4(addr), error))
+            }),
+            IpAddr::V6(ip) => icmp_api
+                .echo_ip_v6(id, seq, ip)
+                .await
+                .map(|(id, seq, addr, ttl)| Ok((id, seq, IpAddr::V6(addr), ttl))),
+        }
+    })
     .await
     {
         Ok(Some(data)) => Some((data, std::time::Instant::now() - start)),
@@ -66,10 +95,15 @@ pub async fn ping(
                 res.write().await.push(None);
                 join_set.write().await.spawn(async move {
                     let res = echo(ip, timeout_secs, id, s as u16, &icmp_api).await;
-                    if let Some(((id, seq, addr, ttl), time)) = res.as_ref() {
-                        info!(
+                    match res.as_ref() {
+                        Some((Ok((id, seq, addr, ttl)), time)) => info!(
                             "Received reply from {addr} icmp_seq={seq} icmp_id={id} ttl={ttl} time={time:?}"
-                        )
+                        ),
+                        Some((Err((addr, error)), _)) => {
+                            let (description, flag) = unreachable_description(error);
+                            info!("From {addr} icmp_seq={s} {description} ({flag})")
+                        }
+                        None => {}
                     }
                     res
                 });
@@ -93,7 +127,7 @@ pub async fn ping(
         f.abort();
     }
     while let Some(data) = join_set.write().await.join_next().await {
-        if let Ok(Some(((_, seq, _, _), d))) = data {
+        if let Ok(Some((Ok((_, seq, _, _)), d))) = data {
             res.write().await[seq as usize] = Some(d)
         }
     }
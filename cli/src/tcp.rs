@@ -0,0 +1,140 @@
+use std::fmt::Display;
+
+use routing::{
+    network::{ip::IpAddr, ipv4::addr::IpV4Addr, ipv6::addr::IpV6Addr},
+    transport::tcp::{Listener, Stream, TcpHandleGeneric},
+};
+use tokio::select;
+use tracing::{info, warn};
+
+use crate::{chassis::ChassisData, command::chassis::ParsedChassisCommandRead, ctrlc::CtrlC};
+
+#[derive(Debug, clap::Parser)]
+pub enum Tcp {
+    /// Echoes back what the connections to the port send, in the background
+    Listen { port: u16 },
+    /// Sends the message and prints what comes back until the peer closes
+    Connect {
+        ip: IpAddr,
+        port: u16,
+        message: String,
+        #[arg(long, short, default_value_t = 5.)]
+        timeout_secs: f32,
+    },
+    /// Stops a listener
+    Stop { pid: u64 },
+}
+
+async fn echo<Addr: Copy + Display + Send + Sync + 'static>(stream: Stream<Addr>) {
+    let (addr, port) = stream.peer();
+    info!("Accepted connection from {addr}:{port}");
+    while let Some(data) = stream.read().await {
+        info!(
+            "Received from {addr}:{port}: {}",
+            String::from_utf8_lossy(&data)
+        );
+        if stream.write(data).await.is_err() {
+            break;
+        }
+    }
+    info!("Connection from {addr}:{port} closed");
+}
+
+async fn accept<Addr: Copy + Display + Send + Sync + 'static>(listener: Listener<Addr>) {
+    while let Ok(stream) = listener.accept().await {
+        tokio::spawn(echo(stream));
+    }
+}
+
+async fn connect<Addr: Copy + Display + Send>(
+    handle: &TcpHandleGeneric<Addr>,
+    dest: (Addr, u16),
+    message: String,
+    timeout: f32,
+) {
+    let timeout = std::time::Duration::from_secs_f32(timeout);
+    let Ok(Ok(stream)) = tokio::time::timeout(timeout, handle.connect(dest)).await else {
+        warn!("Unable to connect to {}:{}", dest.0, dest.1);
+        return;
+    };
+    info!(
+        "Connected to {}:{} from port {}",
+        dest.0,
+        dest.1,
+        stream.local_port()
+    );
+    if stream.write(message.into_bytes()).await.is_err() {
+        warn!("Unable to send the message");
+        return;
+    }
+    stream.close().await;
+    loop {
+        match tokio::time::timeout(timeout, stream.read()).await {
+            Ok(Some(data)) => info!(
+                "Received from {}:{}: {}",
+                dest.0,
+                dest.1,
+                String::from_utf8_lossy(&data)
+            ),
+            Ok(None) => {
+                info!("Connection closed");
+                break;
+            }
+            Err(_) => {
+                warn!("Timeout");
+                break;
+            }
+        }
+    }
+}
+
+pub struct TcpCommand;
+
+#[async_trait::async_trait]
+impl ParsedChassisCommandRead<Tcp> for TcpCommand {
+    async fn run(
+        &mut self,
+        args: Tcp,
+        _: &CtrlC,
+        _: String,
+        ChassisData {
+            tcp_handles: (ip_v4, ip_v6),
+            processes,
+            ..
+        }: &ChassisData,
+    ) -> bool {
+        match args {
+            Tcp::Listen { port } => {
+                let (Ok(listener_v4), Ok(listener_v6)) =
+                    (ip_v4.listen(port).await, ip_v6.listen(port).await)
+                else {
+                    warn!("Unable to listen on port {port}");
+                    return false;
+                };
+                let pid = processes
+                    .add(|_| async move {
+                        select! {
+                            _ = accept::<IpV4Addr>(listener_v4) => {}
+                            _ = accept::<IpV6Addr>(listener_v6) => {}
+                        }
+                    })
+                    .await;
+                info!("Listening on port {port} (pid {pid})");
+            }
+            Tcp::Connect {
+                ip,
+                port,
+                message,
+                timeout_secs,
+            } => match ip {
+                IpAddr::V4(ip) => connect(ip_v4, (ip, port), message, timeout_secs).await,
+                IpAddr::V6(ip) => connect(ip_v6, (ip, port), message, timeout_secs).await,
+            },
+            Tcp::Stop { pid } => {
+                let _ = processes.stop_process(pid).await;
+                info!("Stopped process {pid}");
+            }
+        }
+        false
+    }
+}
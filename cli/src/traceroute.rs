@@ -2,12 +2,22 @@ use std::sync::Arc;
 
 use flume::RecvError;
 use routing::{
-    network::ipv4::addr::IpV4Addr,
-    transport::{icmp::IcmpApi, udp::UdpHandleGeneric},
+    network::{
+        ipv4::{addr::IpV4Addr, packet::Ipv4Packet},
+        ipv6::addr::IpV6Addr,
+    },
+    transport::{
+        icmp::{packet::DestinationUnreachable, IcmpApi},
+        udp::UdpHandleGeneric,
+    },
 };
+use tokio::select;
 use tracing::{info, trace, warn};
 
-use crate::{chassis::ChassisData, command::chassis::ParsedChassisCommandRead, ctrlc::CtrlC};
+use crate::{
+    chassis::ChassisData, command::chassis::ParsedChassisCommandRead, ctrlc::CtrlC,
+    ping::unreachable_description,
+};
 
 #[derive(Debug, clap::Parser)]
 pub struct Traceroute {
@@ -26,29 +36,51 @@ pub async fn traceroute(
     }: Traceroute,
     icmp_api: &IcmpApi,
     _ctrlc: &CtrlC,
-    (udp_handle, ..): &(UdpHandleGeneric<IpV4Addr>,),
+    (udp_handle, ..): &(UdpHandleGeneric<IpV4Addr>, UdpHandleGeneric<IpV6Addr>),
 ) {
     let socket = udp_handle.get_socket(50000).await.unwrap(); // TODO Get random port
     trace!("Aquired socket");
     let handler = Arc::new(icmp_api.get_ttl_handler().await.unwrap());
-    trace!("Aquired handler");
+    let unreachable_handler = icmp_api.get_unreachable_handler().await.unwrap();
+    trace!("Aquired handlers");
     let mut i = 0;
     while max_hops.map(|x| x > 0).unwrap_or(true) {
         max_hops = max_hops.map(|x| x - 1);
         socket.send_ttl((ip, 0), vec![0x69, 0x69], i).await;
-        match tokio::time::timeout(
-            std::time::Duration::from_secs_f32(timeout_secs),
-            handler.recv_async(),
-        )
-        .await
-        {
+        let res = tokio::time::timeout(std::time::Duration::from_secs_f32(timeout_secs), async {
+            loop {
+                select! {
+                    x = handler.recv_async() => break x.map(|(addr, _payload)| (addr, None)),
+                    x = unreachable_handler.recv_async() => match x {
+                        // Only errors about our probes
+                        Ok((addr, error)) => if Ipv4Packet::from_vec_truncated(error.data())
+                            .is_some_and(|quoted| quoted.header.destination == ip)
+                        {
+                            break Ok((addr, Some(error)));
+                        },
+                        Err(e) => break Err(e),
+                    },
+                }
+            }
+        })
+        .await;
+        match res {
             Ok(x) => match x {
-                Ok((addr, _payload)) => {
+                Ok((addr, None)) => {
                     info!("[HOP {i}] {addr}");
                     if addr == ip {
                         break;
                     }
                 }
+                // The destination was reached
+                Ok((addr, Some(DestinationUnreachable::PortUnreachable { .. }))) if addr == ip => {
+                    info!("[HOP {i}] {addr}");
+                    break;
+                }
+                Ok((addr, Some(error))) => {
+                    info!("[HOP {i}] {addr} {}", unreachable_description(&error).1);
+                    break;
+                }
                 Err(RecvError::Disconnected) => {
                     warn!("Handler disconnected");
                     break;
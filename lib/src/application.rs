@@ -0,0 +1,5 @@
+pub mod bgp;
+pub mod dhcp;
+pub mod ospf;
+pub mod rip;
+pub mod vrrp;
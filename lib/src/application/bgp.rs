@@ -0,0 +1,13 @@
+use crate::network::ipv4::addr::{IpV4Addr, IpV4Mask};
+
+pub mod packet;
+pub mod policy;
+pub mod router;
+
+pub const PORT: u16 = 179;
+pub const VERSION: u8 = 4;
+/// Local preference of the routes that don't have one
+pub const DEFAULT_LOCAL_PREF: u32 = 100;
+
+/// (network, mask)
+pub type Prefix = (IpV4Addr, IpV4Mask);
@@ -0,0 +1,494 @@
+use std::fmt::Display;
+
+use tracing::warn;
+
+use crate::{
+    network::ipv4::addr::{IpV4Addr, IpV4Mask},
+    route::AddrMask,
+};
+
+use super::{Prefix, VERSION};
+
+pub const HEADER_LEN: usize = 19;
+pub const MAX_LEN: usize = 4096;
+const MARKER: [u8; 16] = [0xff; 16];
+const OPEN_LEN: usize = 10;
+
+/// Error codes of the notifications, RFC 4271 4.5
+pub const HEADER_ERROR: u8 = 1;
+pub const OPEN_ERROR: u8 = 2;
+pub const UPDATE_ERROR: u8 = 3;
+pub const HOLD_TIMER_EXPIRED: u8 = 4;
+pub const FSM_ERROR: u8 = 5;
+pub const CEASE: u8 = 6;
+
+mod attr {
+    pub const ORIGIN: u8 = 1;
+    pub const AS_PATH: u8 = 2;
+    pub const NEXT_HOP: u8 = 3;
+    pub const MED: u8 = 4;
+    pub const LOCAL_PREF: u8 = 5;
+
+    pub const OPTIONAL: u8 = 0x80;
+    pub const TRANSITIVE: u8 = 0x40;
+    pub const EXTENDED_LENGTH: u8 = 0x10;
+
+    pub const AS_SET: u8 = 1;
+    pub const AS_SEQUENCE: u8 = 2;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Open {
+    pub version: u8,
+    pub asn: u16,
+    /// Seconds, 0 to not use keepalives
+    pub hold_time: u16,
+    pub id: IpV4Addr,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Origin {
+    Igp = 0,
+    Egp = 1,
+    Incomplete = 2,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Igp => write!(f, "i"),
+            Self::Egp => write!(f, "e"),
+            Self::Incomplete => write!(f, "?"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AsSegment {
+    /// Unordered, left by aggregation
+    Set(Vec<u16>),
+    /// Most recent AS first
+    Sequence(Vec<u16>),
+}
+
+impl AsSegment {
+    fn ases(&self) -> &[u16] {
+        match self {
+            Self::Set(ases) | Self::Sequence(ases) => ases,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PathAttributes {
+    pub origin: Origin,
+    pub as_path: Vec<AsSegment>,
+    pub next_hop: IpV4Addr,
+    pub med: Option<u32>,
+    /// Only exchanged between internal peers
+    pub local_pref: Option<u32>,
+}
+
+impl PathAttributes {
+    /// Attributes of a route originated by this AS, the next hop is set when it's advertised
+    pub const fn local(next_hop: IpV4Addr) -> Self {
+        Self {
+            origin: Origin::Igp,
+            as_path: Vec::new(),
+            next_hop,
+            med: None,
+            local_pref: None,
+        }
+    }
+
+    /// Length compared by the decision process, a set counts as one AS
+    pub fn as_path_len(&self) -> usize {
+        self.as_path
+            .iter()
+            .map(|segment| match segment {
+                AsSegment::Set(_) => 1,
+                AsSegment::Sequence(ases) => ases.len(),
+            })
+            .sum()
+    }
+
+    pub fn contains_as(&self, asn: u16) -> bool {
+        self.as_path
+            .iter()
+            .any(|segment| segment.ases().contains(&asn))
+    }
+
+    /// AS the route was learned from, none for the ones originated in this AS
+    pub fn neighbor_as(&self) -> Option<u16> {
+        match self.as_path.first()? {
+            AsSegment::Sequence(ases) => ases.first().copied(),
+            AsSegment::Set(_) => None,
+        }
+    }
+
+    pub fn prepend(&mut self, asn: u16, count: usize) {
+        if count == 0 {
+            return;
+        }
+        match self.as_path.first_mut() {
+            Some(AsSegment::Sequence(ases)) if ases.len() + count <= u8::MAX as usize => {
+                ases.splice(0..0, std::iter::repeat_n(asn, count));
+            }
+            _ => self
+                .as_path
+                .insert(0, AsSegment::Sequence(vec![asn; count])),
+        }
+    }
+
+    pub fn print_as_path(&self) -> String {
+        self.as_path
+            .iter()
+            .map(|segment| match segment {
+                AsSegment::Sequence(ases) => ases
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                AsSegment::Set(ases) => format!(
+                    "{{{}}}",
+                    ases.iter()
+                        .map(u16::to_string)
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn read(data: &[u8]) -> Result<Option<Self>, Notification> {
+        let malformed = Notification::new(UPDATE_ERROR, 1);
+        let (mut origin, mut as_path, mut next_hop, mut med, mut local_pref) =
+            (None, None, None, None, None);
+        let mut i = 0;
+        while i < data.len() {
+            let (Some(&flags), Some(&code)) = (data.get(i), data.get(i + 1)) else {
+                return Err(malformed);
+            };
+            let (len, start) = if flags & attr::EXTENDED_LENGTH != 0 {
+                let len = data.get(i + 2..i + 4).ok_or(malformed.clone())?;
+                (u16::from_be_bytes([len[0], len[1]]) as usize, i + 4)
+            } else {
+                (*data.get(i + 2).ok_or(malformed.clone())? as usize, i + 3)
+            };
+            let value = data.get(start..start + len).ok_or(malformed.clone())?;
+            let length_error = Notification::with_data(UPDATE_ERROR, 5, data[i..start].to_vec());
+            let u32_value = || -> Result<u32, Notification> {
+                Ok(u32::from_be_bytes(
+                    value.try_into().map_err(|_| length_error.clone())?,
+                ))
+            };
+            match code {
+                attr::ORIGIN => {
+                    origin = Some(match value {
+                        [0] => Origin::Igp,
+                        [1] => Origin::Egp,
+                        [2] => Origin::Incomplete,
+                        [_] => return Err(Notification::new(UPDATE_ERROR, 6)),
+                        _ => return Err(length_error),
+                    })
+                }
+                attr::AS_PATH => {
+                    let mut segments = Vec::new();
+                    let mut j = 0;
+                    while j < value.len() {
+                        let malformed_path = Notification::new(UPDATE_ERROR, 11);
+                        let (Some(&segment_type), Some(&count)) = (value.get(j), value.get(j + 1))
+                        else {
+                            return Err(malformed_path);
+                        };
+                        let ases = value
+                            .get(j + 2..j + 2 + count as usize * 2)
+                            .ok_or(malformed_path.clone())?
+                            .chunks(2)
+                            .map(|asn| u16::from_be_bytes([asn[0], asn[1]]))
+                            .collect();
+                        segments.push(match segment_type {
+                            attr::AS_SET => AsSegment::Set(ases),
+                            attr::AS_SEQUENCE => AsSegment::Sequence(ases),
+                            _ => return Err(malformed_path),
+                        });
+                        j += 2 + count as usize * 2;
+                    }
+                    as_path = Some(segments);
+                }
+                attr::NEXT_HOP => {
+                    next_hop = Some(IpV4Addr::new(
+                        value.try_into().map_err(|_| length_error.clone())?,
+                    ))
+                }
+                attr::MED => med = Some(u32_value()?),
+                attr::LOCAL_PREF => local_pref = Some(u32_value()?),
+                _ if flags & attr::OPTIONAL != 0 => {
+                    warn!("BGP: Ignoring optional attribute {code}");
+                }
+                _ => {
+                    return Err(Notification::with_data(
+                        UPDATE_ERROR,
+                        2,
+                        data[i..start + len].to_vec(),
+                    ))
+                }
+            }
+            i = start + len;
+        }
+        if origin.is_none() && as_path.is_none() && next_hop.is_none() {
+            return Ok(None);
+        }
+        let missing = |code: u8| Notification::with_data(UPDATE_ERROR, 3, vec![code]);
+        Ok(Some(Self {
+            origin: origin.ok_or(missing(attr::ORIGIN))?,
+            as_path: as_path.ok_or(missing(attr::AS_PATH))?,
+            next_hop: next_hop.ok_or(missing(attr::NEXT_HOP))?,
+            med,
+            local_pref,
+        }))
+    }
+
+    fn write(&self, res: &mut Vec<u8>) {
+        let mut put = |flags: u8, code: u8, value: &[u8]| {
+            if value.len() > u8::MAX as usize {
+                res.extend_from_slice(&[flags | attr::EXTENDED_LENGTH, code]);
+                res.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            } else {
+                res.extend_from_slice(&[flags, code, value.len() as u8]);
+            }
+            res.extend_from_slice(value);
+        };
+        put(attr::TRANSITIVE, attr::ORIGIN, &[self.origin as u8]);
+        let mut as_path = Vec::new();
+        for segment in &self.as_path {
+            let segment_type = match segment {
+                AsSegment::Set(_) => attr::AS_SET,
+                AsSegment::Sequence(_) => attr::AS_SEQUENCE,
+            };
+            as_path.extend_from_slice(&[segment_type, segment.ases().len() as u8]);
+            for asn in segment.ases() {
+                as_path.extend_from_slice(&asn.to_be_bytes());
+            }
+        }
+        put(attr::TRANSITIVE, attr::AS_PATH, &as_path);
+        put(attr::TRANSITIVE, attr::NEXT_HOP, self.next_hop.as_slice());
+        if let Some(med) = self.med {
+            put(attr::OPTIONAL, attr::MED, &med.to_be_bytes());
+        }
+        if let Some(local_pref) = self.local_pref {
+            put(
+                attr::TRANSITIVE,
+                attr::LOCAL_PREF,
+                &local_pref.to_be_bytes(),
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Update {
+    pub withdrawn: Vec<Prefix>,
+    /// Only present when there are reachable prefixes
+    pub attributes: Option<PathAttributes>,
+    pub nlri: Vec<Prefix>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub code: u8,
+    pub subcode: u8,
+    pub data: Vec<u8>,
+}
+
+impl Notification {
+    pub const fn new(code: u8, subcode: u8) -> Self {
+        Self::with_data(code, subcode, Vec::new())
+    }
+
+    pub const fn with_data(code: u8, subcode: u8, data: Vec<u8>) -> Self {
+        Self {
+            code,
+            subcode,
+            data,
+        }
+    }
+}
+
+impl Display for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = match self.code {
+            HEADER_ERROR => "message header error",
+            OPEN_ERROR => "OPEN message error",
+            UPDATE_ERROR => "UPDATE message error",
+            HOLD_TIMER_EXPIRED => "hold timer expired",
+            FSM_ERROR => "finite state machine error",
+            CEASE => "cease",
+            _ => "unknown error",
+        };
+        write!(f, "{code} ({}/{})", self.code, self.subcode)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Open(Open),
+    Update(Update),
+    Notification(Notification),
+    Keepalive,
+}
+
+impl Message {
+    /// Reads the first message of the received stream data, returns it with its length. `None`
+    /// until the whole message was received, the notification to send back if it's invalid
+    pub fn read(data: &[u8]) -> Result<Option<(Self, usize)>, Notification> {
+        if data.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        if data[..16] != MARKER {
+            return Err(Notification::new(HEADER_ERROR, 1));
+        }
+        let len = u16::from_be_bytes([data[16], data[17]]) as usize;
+        let bad_length = Notification::with_data(HEADER_ERROR, 2, data[16..18].to_vec());
+        if !(HEADER_LEN..=MAX_LEN).contains(&len) {
+            return Err(bad_length);
+        }
+        if data.len() < len {
+            return Ok(None);
+        }
+        let body = &data[HEADER_LEN..len];
+        let message = match data[18] {
+            1 => {
+                if body.len() < OPEN_LEN || body.len() != OPEN_LEN + body[9] as usize {
+                    return Err(bad_length);
+                }
+                if body[9] != 0 {
+                    // No optional parameter is supported
+                    return Err(Notification::new(OPEN_ERROR, 4));
+                }
+                Self::Open(Open {
+                    version: body[0],
+                    asn: u16::from_be_bytes([body[1], body[2]]),
+                    hold_time: u16::from_be_bytes([body[3], body[4]]),
+                    id: IpV4Addr::new([body[5], body[6], body[7], body[8]]),
+                })
+            }
+            2 => Self::Update(read_update(body)?),
+            3 => match body {
+                [code, subcode, data @ ..] => {
+                    Self::Notification(Notification::with_data(*code, *subcode, data.to_vec()))
+                }
+                _ => return Err(bad_length),
+            },
+            4 if body.is_empty() => Self::Keepalive,
+            4 => return Err(bad_length),
+            t => return Err(Notification::with_data(HEADER_ERROR, 3, vec![t])),
+        };
+        Ok(Some((message, len)))
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut res = MARKER.to_vec();
+        res.extend_from_slice(&[0, 0]);
+        match self {
+            Self::Open(open) => {
+                res.push(1);
+                res.push(open.version);
+                res.extend_from_slice(&open.asn.to_be_bytes());
+                res.extend_from_slice(&open.hold_time.to_be_bytes());
+                res.extend_from_slice(open.id.as_slice());
+                res.push(0);
+            }
+            Self::Update(update) => {
+                res.push(2);
+                let mut withdrawn = Vec::new();
+                write_prefixes(&update.withdrawn, &mut withdrawn);
+                res.extend_from_slice(&(withdrawn.len() as u16).to_be_bytes());
+                res.extend_from_slice(&withdrawn);
+                let mut attributes = Vec::new();
+                if let Some(attrs) = &update.attributes {
+                    attrs.write(&mut attributes);
+                }
+                res.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+                res.extend_from_slice(&attributes);
+                write_prefixes(&update.nlri, &mut res);
+            }
+            Self::Notification(notification) => {
+                res.extend_from_slice(&[3, notification.code, notification.subcode]);
+                res.extend_from_slice(&notification.data);
+            }
+            Self::Keepalive => res.push(4),
+        }
+        let len = res.len() as u16;
+        res[16..18].copy_from_slice(&len.to_be_bytes());
+        res
+    }
+}
+
+impl Open {
+    pub const fn new(asn: u16, hold_time: u16, id: IpV4Addr) -> Self {
+        Self {
+            version: VERSION,
+            asn,
+            hold_time,
+            id,
+        }
+    }
+}
+
+fn read_update(body: &[u8]) -> Result<Update, Notification> {
+    let malformed = Notification::new(UPDATE_ERROR, 1);
+    let withdrawn_len =
+        u16::from_be_bytes(body.get(0..2).ok_or(malformed.clone())?.try_into().unwrap()) as usize;
+    let withdrawn = body.get(2..2 + withdrawn_len).ok_or(malformed.clone())?;
+    let i = 2 + withdrawn_len;
+    let attributes_len = u16::from_be_bytes(
+        body.get(i..i + 2)
+            .ok_or(malformed.clone())?
+            .try_into()
+            .unwrap(),
+    ) as usize;
+    let attributes = body
+        .get(i + 2..i + 2 + attributes_len)
+        .ok_or(malformed.clone())?;
+    let nlri = &body[i + 2 + attributes_len..];
+    let invalid_network = Notification::new(UPDATE_ERROR, 10);
+    let update = Update {
+        withdrawn: read_prefixes(withdrawn).ok_or(malformed)?,
+        attributes: PathAttributes::read(attributes)?,
+        nlri: read_prefixes(nlri).ok_or(invalid_network)?,
+    };
+    if !update.nlri.is_empty() && update.attributes.is_none() {
+        return Err(Notification::with_data(UPDATE_ERROR, 3, vec![attr::ORIGIN]));
+    }
+    Ok(update)
+}
+
+/// Prefixes as their length followed by the bytes needed for it
+fn read_prefixes(data: &[u8]) -> Option<Vec<Prefix>> {
+    let mut prefixes = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let len = data[i];
+        if len > 32 {
+            return None;
+        }
+        let bytes = (len as usize).div_ceil(8);
+        let mut addr = [0; 4];
+        addr[..bytes].copy_from_slice(data.get(i + 1..i + 1 + bytes)?);
+        let mask = IpV4Mask::new(len);
+        prefixes.push((mask & IpV4Addr::new(addr), mask));
+        i += 1 + bytes;
+    }
+    Some(prefixes)
+}
+
+fn write_prefixes(prefixes: &[Prefix], res: &mut Vec<u8>) {
+    for (addr, mask) in prefixes {
+        let len = mask.prefix_len();
+        res.push(len);
+        res.extend_from_slice(&addr.as_slice()[..(len as usize).div_ceil(8)]);
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt::Display;
+
+use crate::route::AddrMask;
+
+use super::{packet::PathAttributes, Prefix};
+
+/// Changes made to the attributes of the permitted routes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Set {
+    pub local_pref: Option<u32>,
+    pub med: Option<u32>,
+    /// Times the first AS of the path is repeated, which is the local one on export
+    pub prepend: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Permit(Set),
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub prefix: Prefix,
+    /// Also matches the longer prefixes inside it
+    pub or_longer: bool,
+    pub action: Action,
+}
+
+impl Rule {
+    pub fn matches(&self, (addr, mask): Prefix) -> bool {
+        let (network, network_mask) = self.prefix;
+        if self.or_longer {
+            mask.prefix_len() >= network_mask.prefix_len() && network_mask & addr == network
+        } else {
+            (addr, mask) == self.prefix
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (network, mask) = self.prefix;
+        let action = match self.action {
+            Action::Permit(_) => "permit",
+            Action::Deny => "deny",
+        };
+        write!(f, "{action} {network}/{}", mask.prefix_len())?;
+        if self.or_longer {
+            write!(f, " or longer")?;
+        }
+        if let Action::Permit(set) = self.action {
+            if let Some(local_pref) = set.local_pref {
+                write!(f, ", local-pref {local_pref}")?;
+            }
+            if let Some(med) = set.med {
+                write!(f, ", med {med}")?;
+            }
+            if set.prepend > 0 {
+                write!(f, ", prepend {}", set.prepend)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Import or export filter of a peer, the first matching rule applies and the routes no rule
+/// matches are permitted unchanged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub rules: Vec<Rule>,
+}
+
+impl Filter {
+    /// Attributes of the route once filtered, `None` if it's denied
+    pub fn apply(&self, prefix: Prefix, attrs: &PathAttributes) -> Option<PathAttributes> {
+        let mut attrs = attrs.clone();
+        let Some(rule) = self.rules.iter().find(|rule| rule.matches(prefix)) else {
+            return Some(attrs);
+        };
+        let Action::Permit(set) = rule.action else {
+            return None;
+        };
+        if let Some(local_pref) = set.local_pref {
+            attrs.local_pref = Some(local_pref);
+        }
+        if let Some(med) = set.med {
+            attrs.med = Some(med);
+        }
+        if let Some(asn) = attrs.neighbor_as() {
+            attrs.prepend(asn, set.prepend as usize);
+        }
+        Some(attrs)
+    }
+}
@@ -0,0 +1,1032 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Display,
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use tokio::{select, sync::RwLock, time::Instant};
+use tracing::{info, trace, warn};
+
+use crate::{
+    chassis::LinkLayerId,
+    network::ipv4::{
+        addr::{IpV4Addr, IpV4Mask, DEFAULT},
+        config::{IpV4Config, IpV4ConfigInner},
+    },
+    route::{RouteSource, RoutingEntry},
+    transport::tcp::{Listener, Stream, TcpHandleGeneric},
+};
+
+use super::{
+    packet::{
+        Message, Notification, Open, PathAttributes, Update, CEASE, FSM_ERROR, HOLD_TIMER_EXPIRED,
+        OPEN_ERROR,
+    },
+    policy::Filter,
+    Prefix, DEFAULT_LOCAL_PREF, PORT, VERSION,
+};
+
+/// Interval at which the timers and the config are checked
+const TICK: Duration = Duration::from_secs(1);
+/// Hold time until the peer's OPEN is received, RFC 4271 8.2.2
+const LARGE_HOLD_TIME: Duration = Duration::from_secs(240);
+/// Prefixes per UPDATE, well under the maximum message length
+const MAX_PREFIXES: usize = 500;
+
+/// States of the finite state machine of RFC 4271 8.2.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BgpState {
+    Idle,
+    /// Waiting for the connection to the peer to be established
+    Connect,
+    /// Waiting for the peer to connect
+    Active,
+    OpenSent,
+    OpenConfirm,
+    Established,
+}
+
+impl Display for BgpState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Idle => write!(f, "Idle"),
+            Self::Connect => write!(f, "Connect"),
+            Self::Active => write!(f, "Active"),
+            Self::OpenSent => write!(f, "OpenSent"),
+            Self::OpenConfirm => write!(f, "OpenConfirm"),
+            Self::Established => write!(f, "Established"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BgpPeer {
+    pub remote_as: u16,
+    pub import: Filter,
+    pub export: Filter,
+    pub state: BgpState,
+    /// BGP identifier from the peer's OPEN
+    pub router_id: Option<IpV4Addr>,
+    pub established: Option<DateTime<Local>>,
+    /// Routes received from the peer, before the import filter
+    pub adj_rib_in: BTreeMap<Prefix, PathAttributes>,
+    /// Routes advertised to the peer, after the export filter
+    pub adj_rib_out: BTreeMap<Prefix, PathAttributes>,
+}
+
+impl BgpPeer {
+    pub fn new(remote_as: u16) -> Self {
+        Self {
+            remote_as,
+            import: Filter::default(),
+            export: Filter::default(),
+            state: BgpState::Idle,
+            router_id: None,
+            established: None,
+            adj_rib_in: BTreeMap::new(),
+            adj_rib_out: BTreeMap::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = BgpState::Idle;
+        self.router_id = None;
+        self.established = None;
+        self.adj_rib_in.clear();
+        self.adj_rib_out.clear();
+    }
+}
+
+/// Best path to a prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BgpRoute {
+    /// Peer it was learned from, `None` if it's originated here
+    pub peer: Option<IpV4Addr>,
+    /// BGP identifier of the peer, it breaks the last ties
+    pub router_id: IpV4Addr,
+    /// Learned from a peer of the same AS
+    pub internal: bool,
+    /// After the import filter
+    pub attrs: PathAttributes,
+    /// Gateway and interface the next hop resolves to, `None` if it's originated here
+    pub next_hop: Option<(IpV4Addr, LinkLayerId)>,
+}
+
+impl BgpRoute {
+    fn entry(
+        &self,
+        (network, mask): Prefix,
+    ) -> Option<RoutingEntry<IpV4Addr, IpV4Mask, LinkLayerId>> {
+        let (gateway, iface) = self.next_hop?;
+        let source = if self.internal {
+            RouteSource::Ibgp
+        } else {
+            RouteSource::Ebgp
+        };
+        Some(
+            RoutingEntry::new(network, gateway, mask, iface)
+                .with_source(source)
+                .with_metric(self.attrs.med.unwrap_or(0)),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BgpConfigInner {
+    /// Local AS, has to be set before starting
+    pub asn: u16,
+    pub router_id: Option<IpV4Addr>,
+    /// Offered to the peers, the smallest of both is used
+    pub hold_time: u16,
+    /// Seconds before connecting again to a peer
+    pub connect_retry: u16,
+    pub peers: HashMap<IpV4Addr, BgpPeer>,
+    /// Prefixes originated here, as long as the routing table has a route to them
+    pub networks: BTreeSet<Prefix>,
+    pub loc_rib: BTreeMap<Prefix, BgpRoute>,
+}
+
+impl BgpConfigInner {
+    pub fn print_neighbors(&self) -> prettytable::Table {
+        let mut table = prettytable::table!([
+            "neighbor",
+            "AS",
+            "state",
+            "router id",
+            "up since",
+            "received",
+            "advertised"
+        ]);
+        if self.peers.is_empty() {
+            table.add_empty_row();
+        }
+        let mut peers = self.peers.iter().collect::<Vec<_>>();
+        peers.sort_by_key(|(addr, _)| **addr);
+        for (addr, peer) in peers {
+            table.add_row(prettytable::row![
+                addr,
+                peer.remote_as,
+                peer.state,
+                peer.router_id.map_or("-".to_string(), |id| id.to_string()),
+                peer.established
+                    .map_or("-".to_string(), |since| since.format("%T").to_string()),
+                peer.adj_rib_in.len(),
+                peer.adj_rib_out.len()
+            ]);
+        }
+        table
+    }
+
+    /// Best paths, `*` marks the ones originated here
+    pub fn print_rib(&self) -> prettytable::Table {
+        let mut table = prettytable::table!([
+            "network",
+            "mask",
+            "next hop",
+            "peer",
+            "local pref",
+            "med",
+            "as path",
+            "origin"
+        ]);
+        if self.loc_rib.is_empty() {
+            table.add_empty_row();
+        }
+        for ((network, mask), route) in &self.loc_rib {
+            table.add_row(prettytable::row![
+                network,
+                mask,
+                route.attrs.next_hop,
+                route.peer.map_or("*".to_string(), |peer| peer.to_string()),
+                route.attrs.local_pref.unwrap_or(DEFAULT_LOCAL_PREF),
+                route.attrs.med.unwrap_or(0),
+                route.attrs.print_as_path(),
+                route.attrs.origin
+            ]);
+        }
+        table
+    }
+
+    /// Routes received from the peer, or advertised to it if `out`
+    pub fn print_adj_rib(&self, peer: IpV4Addr, out: bool) -> Option<prettytable::Table> {
+        let peer = self.peers.get(&peer)?;
+        let rib = if out {
+            &peer.adj_rib_out
+        } else {
+            &peer.adj_rib_in
+        };
+        let mut table = prettytable::table!([
+            "network",
+            "mask",
+            "next hop",
+            "local pref",
+            "med",
+            "as path",
+            "origin"
+        ]);
+        if rib.is_empty() {
+            table.add_empty_row();
+        }
+        for ((network, mask), attrs) in rib {
+            table.add_row(prettytable::row![
+                network,
+                mask,
+                attrs.next_hop,
+                attrs
+                    .local_pref
+                    .map_or("-".to_string(), |local_pref| local_pref.to_string()),
+                attrs.med.map_or("-".to_string(), |med| med.to_string()),
+                attrs.print_as_path(),
+                attrs.origin
+            ]);
+        }
+        Some(table)
+    }
+
+    pub fn print_filters(&self, peer: IpV4Addr) -> Option<prettytable::Table> {
+        let peer = self.peers.get(&peer)?;
+        let mut table = prettytable::table!(["filter", "rule"]);
+        if peer.import.rules.is_empty() && peer.export.rules.is_empty() {
+            table.add_empty_row();
+        }
+        for (name, filter) in [("import", &peer.import), ("export", &peer.export)] {
+            for rule in &filter.rules {
+                table.add_row(prettytable::row![name, rule]);
+            }
+        }
+        Some(table)
+    }
+
+    /// Forgets the sessions and the best paths, and removes them from the routing table
+    pub fn flush(&mut self, ip_config: &mut IpV4ConfigInner) {
+        for (prefix, route) in std::mem::take(&mut self.loc_rib) {
+            if let Some(entry) = route.entry(prefix) {
+                ip_config.routing.remove_route(&entry);
+            }
+        }
+        for peer in self.peers.values_mut() {
+            peer.reset();
+        }
+    }
+}
+
+impl Default for BgpConfigInner {
+    fn default() -> Self {
+        Self {
+            asn: 0,
+            router_id: None,
+            hold_time: 90,
+            connect_retry: 10,
+            peers: Default::default(),
+            networks: Default::default(),
+            loc_rib: Default::default(),
+        }
+    }
+}
+
+pub type BgpConfig = Arc<RwLock<BgpConfigInner>>;
+
+/// Connection to a peer
+struct Session {
+    stream: Option<Arc<Stream<IpV4Addr>>>,
+    /// Identifies the connection, what's read from the previous ones is dropped
+    conn: u64,
+    /// Received data that isn't a whole message yet
+    buffer: Vec<u8>,
+    /// Offered in the OPEN, then the negotiated one. 0 for no keepalives
+    hold_time: u16,
+    hold: Option<Instant>,
+    keepalive: Option<Instant>,
+    /// Next attempt to start the session
+    connect_retry: Option<Instant>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            stream: None,
+            conn: 0,
+            buffer: Vec::new(),
+            hold_time: 0,
+            hold: None,
+            keepalive: None,
+            connect_retry: Some(Instant::now()),
+        }
+    }
+}
+
+enum Event {
+    Accepted(Stream<IpV4Addr>),
+    Connected(IpV4Addr, Result<Stream<IpV4Addr>, ()>),
+    /// What was read from a connection, `None` once it's closed
+    Read(IpV4Addr, u64, Arc<Stream<IpV4Addr>>, Option<Vec<u8>>),
+    Tick,
+}
+
+fn read(peer: IpV4Addr, conn: u64, stream: Arc<Stream<IpV4Addr>>) -> BoxFuture<'static, Event> {
+    async move {
+        let data = stream.read().await;
+        Event::Read(peer, conn, stream, data)
+    }
+    .boxed()
+}
+
+/// BGP-4 speaker for the peers in the config.
+///
+/// Of the two ends of a session, the one with the lower address opens the connection and the
+/// other waits for it, so that they never collide. Routes learned from internal peers aren't
+/// advertised to the other internal ones, there's no route reflection.
+pub struct BgpRouter {
+    config: BgpConfig,
+    ip_config: IpV4Config,
+    tcp: TcpHandleGeneric<IpV4Addr>,
+    listener: Listener<IpV4Addr>,
+    asn: u16,
+    router_id: IpV4Addr,
+    sessions: HashMap<IpV4Addr, Session>,
+    next_conn: u64,
+    /// Connections being opened and reads
+    pending: FuturesUnordered<BoxFuture<'static, Event>>,
+}
+
+impl BgpRouter {
+    pub async fn new(
+        config: BgpConfig,
+        ip_config: IpV4Config,
+        tcp: &TcpHandleGeneric<IpV4Addr>,
+    ) -> Result<Self, ()> {
+        let (asn, router_id) = {
+            let config = config.read().await;
+            (config.asn, config.router_id)
+        };
+        if asn == 0 {
+            warn!("BGP: The AS number isn't set");
+            return Err(());
+        }
+        let router_id = match router_id {
+            Some(router_id) => router_id,
+            None => {
+                let ip_config = ip_config.read().await;
+                let Some(router_id) = ip_config
+                    .addrs
+                    .values()
+                    .flatten()
+                    .map(|iface_addr| iface_addr.addr)
+                    .max()
+                else {
+                    warn!("BGP: No router id nor address to use as one");
+                    return Err(());
+                };
+                router_id
+            }
+        };
+        let Ok(listener) = tcp.listen(PORT).await else {
+            warn!("BGP: Unable to listen on port {PORT}");
+            return Err(());
+        };
+        Ok(Self {
+            config,
+            ip_config,
+            tcp: tcp.clone(),
+            listener,
+            asn,
+            router_id,
+            sessions: HashMap::new(),
+            next_conn: 0,
+            pending: FuturesUnordered::new(),
+        })
+    }
+
+    pub async fn run(mut self) {
+        info!("BGP speaker {} of AS {} started", self.router_id, self.asn);
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            let event = select! {
+                r = self.listener.accept() => match r {
+                    Ok(stream) => Event::Accepted(stream),
+                    Err(_) => break,
+                },
+                Some(event) = self.pending.next(), if !self.pending.is_empty() => event,
+                _ = tick.tick() => Event::Tick,
+            };
+            match event {
+                Event::Accepted(stream) => self.on_accept(stream).await,
+                Event::Connected(peer, r) => self.on_connect(peer, r).await,
+                Event::Read(peer, conn, stream, data) => {
+                    self.on_read(peer, conn, stream, data).await
+                }
+                Event::Tick => self.on_tick().await,
+            }
+        }
+        warn!("BGP speaker stopped");
+    }
+
+    /// Follows the peers in the config and runs the timers
+    async fn on_tick(&mut self) {
+        let peers = self
+            .config
+            .read()
+            .await
+            .peers
+            .keys()
+            .copied()
+            .collect::<HashSet<_>>();
+        let removed = self
+            .sessions
+            .keys()
+            .filter(|peer| !peers.contains(peer))
+            .copied()
+            .collect::<Vec<_>>();
+        for peer in removed {
+            // Peer de-configured
+            self.send(peer, Message::Notification(Notification::new(CEASE, 3)))
+                .await;
+            if let Some(stream) = self
+                .sessions
+                .remove(&peer)
+                .and_then(|session| session.stream)
+            {
+                stream.close().await;
+            }
+            info!("BGP: Removed peer {peer}");
+        }
+        for &peer in &peers {
+            self.sessions.entry(peer).or_insert_with(Session::new);
+        }
+        let now = Instant::now();
+        for peer in peers {
+            let session = &self.sessions[&peer];
+            if session.connect_retry.is_some_and(|retry| retry <= now) {
+                self.start(peer).await;
+            } else if session.hold.is_some_and(|hold| hold <= now) {
+                warn!("BGP: Hold timer of {peer} expired");
+                self.close(peer, Some(Notification::new(HOLD_TIMER_EXPIRED, 0)))
+                    .await;
+            } else if session.keepalive.is_some_and(|keepalive| keepalive <= now) {
+                self.send(peer, Message::Keepalive).await;
+                if let Some(session) = self.sessions.get_mut(&peer) {
+                    session.keepalive =
+                        Some(now + Duration::from_secs(session.hold_time as u64 / 3));
+                }
+            }
+        }
+        self.update().await;
+    }
+
+    /// Opens the connection if this end is the one that does, waits for the peer otherwise
+    async fn start(&mut self, peer: IpV4Addr) {
+        let own = self.ip_config.read().await.source_addr_to(peer);
+        let retry = self.connect_retry().await;
+        let Some(session) = self.sessions.get_mut(&peer) else {
+            return;
+        };
+        if own == DEFAULT || own > peer {
+            // Checked again later in case the route to the peer changes
+            session.connect_retry = Some(Instant::now() + retry);
+            self.set_state(peer, BgpState::Active).await;
+            return;
+        }
+        session.connect_retry = None;
+        let tcp = self.tcp.clone();
+        self.pending.push(
+            async move {
+                let r = tokio::time::timeout(retry, tcp.connect((peer, PORT)))
+                    .await
+                    .unwrap_or(Err(()));
+                Event::Connected(peer, r)
+            }
+            .boxed(),
+        );
+        self.set_state(peer, BgpState::Connect).await;
+    }
+
+    async fn on_connect(&mut self, peer: IpV4Addr, r: Result<Stream<IpV4Addr>, ()>) {
+        let retry = self.connect_retry().await;
+        let Some(session) = self.sessions.get_mut(&peer) else {
+            return;
+        };
+        if session.stream.is_some() {
+            return;
+        }
+        match r {
+            Ok(stream) => self.open(peer, stream).await,
+            Err(()) => {
+                trace!("BGP: Unable to connect to {peer}");
+                session.connect_retry = Some(Instant::now() + retry);
+                self.set_state(peer, BgpState::Active).await;
+            }
+        }
+    }
+
+    async fn on_accept(&mut self, stream: Stream<IpV4Addr>) {
+        let (peer, _) = stream.peer();
+        let Some(state) = self
+            .config
+            .read()
+            .await
+            .peers
+            .get(&peer)
+            .map(|peer| peer.state)
+        else {
+            warn!("BGP: Refused connection from {peer}, it isn't a peer");
+            return;
+        };
+        // The peer may have been added since the last tick
+        let session = self.sessions.entry(peer).or_insert_with(Session::new);
+        if session.stream.is_some()
+            && matches!(state, BgpState::OpenConfirm | BgpState::Established)
+        {
+            warn!("BGP: Refused second connection from {peer}");
+            return;
+        }
+        self.open(peer, stream).await;
+    }
+
+    /// Uses the connection for the session and sends the OPEN, the previous connection is closed
+    async fn open(&mut self, peer: IpV4Addr, stream: Stream<IpV4Addr>) {
+        let hold_time = self.config.read().await.hold_time;
+        let Some(session) = self.sessions.get_mut(&peer) else {
+            return;
+        };
+        let conn = self.next_conn;
+        self.next_conn += 1;
+        let stream = Arc::new(stream);
+        if let Some(old) = session.stream.replace(stream.clone()) {
+            old.close().await;
+        }
+        session.conn = conn;
+        session.buffer.clear();
+        session.hold_time = hold_time;
+        session.hold = Some(Instant::now() + LARGE_HOLD_TIME);
+        session.keepalive = None;
+        session.connect_retry = None;
+        self.pending.push(read(peer, conn, stream));
+        self.send(
+            peer,
+            Message::Open(Open::new(self.asn, hold_time, self.router_id)),
+        )
+        .await;
+        self.set_state(peer, BgpState::OpenSent).await;
+    }
+
+    async fn on_read(
+        &mut self,
+        peer: IpV4Addr,
+        conn: u64,
+        stream: Arc<Stream<IpV4Addr>>,
+        data: Option<Vec<u8>>,
+    ) {
+        let Some(session) = self
+            .sessions
+            .get_mut(&peer)
+            .filter(|session| session.conn == conn && session.stream.is_some())
+        else {
+            return;
+        };
+        let Some(data) = data else {
+            warn!("BGP: Connection to {peer} closed");
+            self.close(peer, None).await;
+            return;
+        };
+        session.buffer.extend_from_slice(&data);
+        self.pending.push(read(peer, conn, stream));
+        // The session may be closed by any message
+        while let Some(session) = self
+            .sessions
+            .get_mut(&peer)
+            .filter(|session| session.conn == conn && session.stream.is_some())
+        {
+            match Message::read(&session.buffer) {
+                Ok(Some((message, len))) => {
+                    session.buffer.drain(..len);
+                    self.on_message(peer, message).await;
+                }
+                Ok(None) => break,
+                Err(notification) => {
+                    warn!("BGP: Invalid message from {peer}: {notification}");
+                    self.close(peer, Some(notification)).await;
+                }
+            }
+        }
+    }
+
+    async fn on_message(&mut self, peer: IpV4Addr, message: Message) {
+        trace!("BGP received {message:?} from {peer}");
+        let Some(state) = self
+            .config
+            .read()
+            .await
+            .peers
+            .get(&peer)
+            .map(|peer| peer.state)
+        else {
+            return;
+        };
+        match (state, message) {
+            (_, Message::Notification(notification)) => {
+                warn!("BGP: {peer} closed the session: {notification}");
+                self.close(peer, None).await;
+            }
+            (BgpState::OpenSent, Message::Open(open)) => self.on_open(peer, open).await,
+            (BgpState::OpenConfirm, Message::Keepalive) => {
+                self.restart_hold(peer);
+                if let Some(peer) = self.config.write().await.peers.get_mut(&peer) {
+                    peer.established = Some(Local::now());
+                }
+                self.set_state(peer, BgpState::Established).await;
+                // The whole Adj-RIB-Out is sent since it's empty
+                self.update().await;
+            }
+            (BgpState::Established, Message::Keepalive) => self.restart_hold(peer),
+            (BgpState::Established, Message::Update(update)) => {
+                self.restart_hold(peer);
+                self.on_update(peer, update).await;
+            }
+            (state, message) => {
+                warn!("BGP: Unexpected {message:?} from {peer} in state {state}");
+                self.close(peer, Some(Notification::new(FSM_ERROR, 0)))
+                    .await;
+            }
+        }
+    }
+
+    /// Checks the peer's OPEN as described in RFC 4271 6.2 and negotiates the hold time
+    async fn on_open(&mut self, peer: IpV4Addr, open: Open) {
+        let remote_as = {
+            let config = self.config.read().await;
+            config.peers.get(&peer).map(|peer| peer.remote_as)
+        };
+        let error = if open.version != VERSION {
+            Some(Notification::with_data(
+                OPEN_ERROR,
+                1,
+                (VERSION as u16).to_be_bytes().to_vec(),
+            ))
+        } else if Some(open.asn) != remote_as {
+            Some(Notification::new(OPEN_ERROR, 2))
+        } else if open.id == DEFAULT || open.id == self.router_id {
+            Some(Notification::new(OPEN_ERROR, 3))
+        } else if matches!(open.hold_time, 1 | 2) {
+            Some(Notification::new(OPEN_ERROR, 6))
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            warn!("BGP: Refused OPEN from {peer}: {error}");
+            self.close(peer, Some(error)).await;
+            return;
+        }
+        let Some(session) = self.sessions.get_mut(&peer) else {
+            return;
+        };
+        session.hold_time = session.hold_time.min(open.hold_time);
+        session.keepalive = (session.hold_time > 0)
+            .then(|| Instant::now() + Duration::from_secs(session.hold_time as u64 / 3));
+        self.restart_hold(peer);
+        if let Some(peer) = self.config.write().await.peers.get_mut(&peer) {
+            peer.router_id = Some(open.id);
+        }
+        self.send(peer, Message::Keepalive).await;
+        self.set_state(peer, BgpState::OpenConfirm).await;
+    }
+
+    /// Stores the peer's routes in its Adj-RIB-In, the ones whose path has a loop are dropped
+    async fn on_update(&mut self, peer: IpV4Addr, update: Update) {
+        {
+            let mut config = self.config.write().await;
+            let Some(bgp_peer) = config.peers.get_mut(&peer) else {
+                return;
+            };
+            let external = bgp_peer.remote_as != self.asn;
+            for prefix in &update.withdrawn {
+                bgp_peer.adj_rib_in.remove(prefix);
+            }
+            if let Some(mut attrs) = update.attributes {
+                if external {
+                    // Only meaningful inside an AS
+                    attrs.local_pref = None;
+                }
+                for prefix in update.nlri {
+                    if attrs.contains_as(self.asn) {
+                        trace!(
+                            "BGP: Dropped {} {} from {peer}, its path has a loop",
+                            prefix.0,
+                            prefix.1
+                        );
+                        bgp_peer.adj_rib_in.remove(&prefix);
+                    } else if external && attrs.neighbor_as() != Some(bgp_peer.remote_as) {
+                        warn!("BGP: Dropped {} {} from {peer}, its path doesn't start with the peer's AS", prefix.0, prefix.1);
+                        bgp_peer.adj_rib_in.remove(&prefix);
+                    } else {
+                        bgp_peer.adj_rib_in.insert(prefix, attrs.clone());
+                    }
+                }
+            }
+        }
+        self.update().await;
+    }
+
+    /// Sends the notification if any and closes the connection, the session starts again after
+    /// the connect retry time
+    async fn close(&mut self, peer: IpV4Addr, notification: Option<Notification>) {
+        if let Some(notification) = notification {
+            self.send(peer, Message::Notification(notification)).await;
+        }
+        let retry = self.connect_retry().await;
+        if let Some(session) = self.sessions.get_mut(&peer) {
+            if let Some(stream) = session.stream.take() {
+                stream.close().await;
+            }
+            session.buffer.clear();
+            session.hold = None;
+            session.keepalive = None;
+            session.connect_retry = Some(Instant::now() + retry);
+        }
+        if let Some(bgp_peer) = self.config.write().await.peers.get_mut(&peer) {
+            if bgp_peer.state == BgpState::Established {
+                warn!("BGP: Session with {peer} lost");
+            }
+            bgp_peer.reset();
+        }
+        self.update().await;
+    }
+
+    /// Runs the decision process, installs the best paths that changed and advertises the changes
+    /// to the established peers
+    async fn update(&mut self) {
+        let updates = {
+            let mut config = self.config.write().await;
+            let mut ip_config = self.ip_config.write().await;
+            let loc_rib = self.decide(&config, &ip_config);
+            for (prefix, route) in &config.loc_rib {
+                if loc_rib.get(prefix) == Some(route) {
+                    continue;
+                }
+                if let Some(entry) = route.entry(*prefix) {
+                    ip_config.routing.remove_route(&entry);
+                }
+                if !loc_rib.contains_key(prefix) {
+                    info!("BGP: No path to {} {} anymore", prefix.0, prefix.1);
+                }
+            }
+            for (prefix, route) in &loc_rib {
+                if config.loc_rib.get(prefix) == Some(route) {
+                    continue;
+                }
+                if let Some(entry) = route.entry(*prefix) {
+                    ip_config.routing.add_route(entry);
+                }
+                match route.peer {
+                    Some(peer) if route.attrs.as_path.is_empty() => {
+                        info!("BGP: Best path to {} {} through {peer}", prefix.0, prefix.1)
+                    }
+                    Some(peer) => info!(
+                        "BGP: Best path to {} {} through {peer} (AS path {})",
+                        prefix.0,
+                        prefix.1,
+                        route.attrs.print_as_path()
+                    ),
+                    None => {}
+                }
+            }
+            config.loc_rib = loc_rib;
+            let BgpConfigInner { peers, loc_rib, .. } = &mut *config;
+            let mut updates = Vec::new();
+            for (&addr, peer) in peers
+                .iter_mut()
+                .filter(|(_, peer)| peer.state == BgpState::Established)
+            {
+                let adj_rib_out = self.adj_rib_out(addr, peer, loc_rib, &ip_config);
+                for update in updates_between(&peer.adj_rib_out, &adj_rib_out) {
+                    updates.push((addr, update));
+                }
+                peer.adj_rib_out = adj_rib_out;
+            }
+            updates
+        };
+        for (peer, update) in updates {
+            self.send(peer, Message::Update(update)).await;
+        }
+    }
+
+    /// Best path to every prefix as described in RFC 4271 9.1.2, among the routes originated here
+    /// and the ones permitted by the import filters whose next hop is reachable
+    fn decide(
+        &self,
+        config: &BgpConfigInner,
+        ip_config: &IpV4ConfigInner,
+    ) -> BTreeMap<Prefix, BgpRoute> {
+        let mut candidates = BTreeMap::<Prefix, Vec<BgpRoute>>::new();
+        for &prefix in &config.networks {
+            let routed = ip_config.routing.iter().any(|route| {
+                (*route.mask() & *route.destination(), *route.mask()) == prefix
+                    && !matches!(route.source(), RouteSource::Ebgp | RouteSource::Ibgp)
+            });
+            if routed {
+                candidates.entry(prefix).or_default().push(BgpRoute {
+                    peer: None,
+                    router_id: self.router_id,
+                    internal: false,
+                    attrs: PathAttributes::local(DEFAULT),
+                    next_hop: None,
+                });
+            }
+        }
+        for (&addr, peer) in &config.peers {
+            if peer.state != BgpState::Established {
+                continue;
+            }
+            for (&prefix, attrs) in &peer.adj_rib_in {
+                let Some(attrs) = peer.import.apply(prefix, attrs) else {
+                    continue;
+                };
+                let Some(next_hop) = resolve(ip_config, attrs.next_hop) else {
+                    continue;
+                };
+                candidates.entry(prefix).or_default().push(BgpRoute {
+                    peer: Some(addr),
+                    router_id: peer.router_id.unwrap_or(addr),
+                    internal: peer.remote_as == self.asn,
+                    attrs,
+                    next_hop: Some(next_hop),
+                });
+            }
+        }
+        candidates
+            .into_iter()
+            .filter_map(|(prefix, routes)| Some((prefix, best(routes)?)))
+            .collect()
+    }
+
+    /// Routes to advertise to the peer, routes learned from internal peers aren't advertised to
+    /// the internal ones
+    fn adj_rib_out(
+        &self,
+        addr: IpV4Addr,
+        peer: &BgpPeer,
+        loc_rib: &BTreeMap<Prefix, BgpRoute>,
+        ip_config: &IpV4ConfigInner,
+    ) -> BTreeMap<Prefix, PathAttributes> {
+        let internal = peer.remote_as == self.asn;
+        let own = ip_config.source_addr_to(addr);
+        let mut adj_rib_out = BTreeMap::new();
+        for (&prefix, route) in loc_rib {
+            if route.peer == Some(addr) || (internal && route.internal) {
+                continue;
+            }
+            let mut attrs = route.attrs.clone();
+            if internal {
+                attrs.local_pref = Some(attrs.local_pref.unwrap_or(DEFAULT_LOCAL_PREF));
+                if route.peer.is_none() {
+                    attrs.next_hop = own;
+                }
+            } else {
+                attrs.prepend(self.asn, 1);
+                attrs.next_hop = own;
+                attrs.local_pref = None;
+                if route.peer.is_some() {
+                    // Not passed on to other ASes
+                    attrs.med = None;
+                }
+            }
+            if let Some(attrs) = peer.export.apply(prefix, &attrs) {
+                adj_rib_out.insert(prefix, attrs);
+            }
+        }
+        adj_rib_out
+    }
+
+    async fn send(&mut self, peer: IpV4Addr, message: Message) {
+        let Some(stream) = self
+            .sessions
+            .get(&peer)
+            .and_then(|session| session.stream.as_ref())
+        else {
+            return;
+        };
+        trace!("BGP sending {message:?} to {peer}");
+        if stream.write(message.to_vec()).await.is_err() {
+            warn!("BGP: Unable to send to {peer}");
+        }
+    }
+
+    fn restart_hold(&mut self, peer: IpV4Addr) {
+        if let Some(session) = self.sessions.get_mut(&peer) {
+            session.hold = (session.hold_time > 0)
+                .then(|| Instant::now() + Duration::from_secs(session.hold_time as u64));
+        }
+    }
+
+    async fn set_state(&mut self, peer: IpV4Addr, state: BgpState) {
+        let mut config = self.config.write().await;
+        let Some(bgp_peer) = config.peers.get_mut(&peer) else {
+            return;
+        };
+        if bgp_peer.state == state {
+            return;
+        }
+        trace!("BGP: {peer} {} -> {state}", bgp_peer.state);
+        if state == BgpState::Established {
+            info!(
+                "BGP: Session with {peer} (AS {}) established",
+                bgp_peer.remote_as
+            );
+        }
+        bgp_peer.state = state;
+    }
+
+    async fn connect_retry(&mut self) -> Duration {
+        Duration::from_secs(self.config.read().await.connect_retry as u64)
+    }
+}
+
+/// Gateway and interface of the next hop, through a route not learned by BGP
+fn resolve(ip_config: &IpV4ConfigInner, next_hop: IpV4Addr) -> Option<(IpV4Addr, LinkLayerId)> {
+    if ip_config.is_local(next_hop) {
+        return None;
+    }
+    let route = ip_config.routing.lookup(&next_hop).first()?;
+    if matches!(route.source(), RouteSource::Ebgp | RouteSource::Ibgp) {
+        return None;
+    }
+    match *route.gateway() {
+        DEFAULT => Some((next_hop, *route.iface())),
+        gateway => Some((gateway, *route.iface())),
+    }
+}
+
+/// Keeps the routes with the smallest key
+fn keep_best<K: Ord>(routes: &mut Vec<BgpRoute>, key: impl Fn(&BgpRoute) -> K) {
+    let Some(best) = routes.iter().map(&key).min() else {
+        return;
+    };
+    routes.retain(|route| key(route) == best);
+}
+
+/// Tie breaking of RFC 4271 9.1.2.2, the routes originated here win and the IGP cost to the next
+/// hop isn't compared
+fn best(mut routes: Vec<BgpRoute>) -> Option<BgpRoute> {
+    if let Some(i) = routes.iter().position(|route| route.peer.is_none()) {
+        return Some(routes.swap_remove(i));
+    }
+    keep_best(&mut routes, |route| {
+        Reverse(route.attrs.local_pref.unwrap_or(DEFAULT_LOCAL_PREF))
+    });
+    keep_best(&mut routes, |route| route.attrs.as_path_len());
+    keep_best(&mut routes, |route| route.attrs.origin);
+    // The MEDs are only compared between routes from the same AS, a missing one is the lowest
+    let others = routes.clone();
+    routes.retain(|route| {
+        !others.iter().any(|other| {
+            other.attrs.neighbor_as() == route.attrs.neighbor_as()
+                && other.attrs.med.unwrap_or(0) < route.attrs.med.unwrap_or(0)
+        })
+    });
+    keep_best(&mut routes, |route| route.internal);
+    keep_best(&mut routes, |route| (route.router_id, route.peer));
+    routes.into_iter().next()
+}
+
+/// UPDATEs turning the advertised routes into the new ones, prefixes with the same attributes
+/// share one
+fn updates_between(
+    old: &BTreeMap<Prefix, PathAttributes>,
+    new: &BTreeMap<Prefix, PathAttributes>,
+) -> Vec<Update> {
+    let withdrawn = old
+        .keys()
+        .filter(|prefix| !new.contains_key(prefix))
+        .copied()
+        .collect::<Vec<_>>();
+    let mut groups = Vec::<(&PathAttributes, Vec<Prefix>)>::new();
+    for (&prefix, attrs) in new {
+        if old.get(&prefix) == Some(attrs) {
+            continue;
+        }
+        match groups.iter_mut().find(|(other, _)| *other == attrs) {
+            Some((_, prefixes)) => prefixes.push(prefix),
+            None => groups.push((attrs, vec![prefix])),
+        }
+    }
+    let mut updates = withdrawn
+        .chunks(MAX_PREFIXES)
+        .map(|chunk| Update {
+            withdrawn: chunk.to_vec(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    for (attrs, prefixes) in groups {
+        for chunk in prefixes.chunks(MAX_PREFIXES) {
+            updates.push(Update {
+                withdrawn: Vec::new(),
+                attributes: Some(attrs.clone()),
+                nlri: chunk.to_vec(),
+            });
+        }
+    }
+    updates
+}
@@ -0,0 +1,6 @@
+pub mod client;
+pub mod packet;
+pub mod server;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
@@ -0,0 +1,343 @@
+use std::time::Duration;
+
+use tokio::{select, time::Instant};
+use tracing::{info, trace, warn};
+
+use crate::{
+    chassis::LinkLayerId,
+    mac::Mac,
+    network::ipv4::{
+        addr::{IpV4Addr, IpV4Mask, DEFAULT},
+        config::{InterfaceAddr, IpV4Config},
+    },
+    route::{RouteSource, RoutingEntry},
+    transport::udp::{Socket, UdpHandleGeneric},
+};
+
+use super::{
+    packet::{DhcpPacket, MessageType},
+    CLIENT_PORT, SERVER_PORT,
+};
+
+/// First retransmission timeout, doubled on every retry
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(4);
+const MAX_TIMEOUT: Duration = Duration::from_secs(64);
+/// Requests sent before going back to discovery
+const MAX_REQUESTS: u32 = 4;
+/// Interval at which `IpV4ConfigInner::dhcp_run` is checked
+const POLL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lease {
+    addr: IpV4Addr,
+    mask: Option<IpV4Mask>,
+    router: Option<IpV4Addr>,
+    server: IpV4Addr,
+    obtained: Instant,
+    duration: Duration,
+}
+
+impl Lease {
+    fn from_ack(ack: &DhcpPacket, server: IpV4Addr) -> Self {
+        Self {
+            addr: ack.yiaddr,
+            mask: ack.subnet_mask,
+            router: ack.router,
+            server: ack.server_id.unwrap_or(server),
+            obtained: Instant::now(),
+            // Leases without a time are considered infinite
+            duration: Duration::from_secs(ack.lease_time.unwrap_or(u32::MAX).into()),
+        }
+    }
+
+    /// Time to start renewing with the server that gave the lease (T1)
+    fn renew_at(&self) -> Instant {
+        self.obtained + self.duration / 2
+    }
+
+    /// Time to start asking any server (T2)
+    fn rebind_at(&self) -> Instant {
+        self.obtained + self.duration * 7 / 8
+    }
+
+    fn expires_at(&self) -> Instant {
+        self.obtained + self.duration
+    }
+
+    fn default_route(
+        &self,
+        iface: LinkLayerId,
+    ) -> Option<RoutingEntry<IpV4Addr, IpV4Mask, LinkLayerId>> {
+        self.router.map(|router| {
+            RoutingEntry::new(DEFAULT, router, IpV4Mask::new(0), iface)
+                .with_source(RouteSource::Dhcp)
+        })
+    }
+}
+
+enum Received {
+    Packet(DhcpPacket),
+    Timeout,
+    Stopped,
+}
+
+/// DHCP client of an interface, it manages the interface's leased address and the default route
+/// while `IpV4ConfigInner::dhcp_run` is set
+pub struct DhcpClient {
+    iface: LinkLayerId,
+    mac: Mac,
+    config: IpV4Config,
+    broadcast: Socket<LinkLayerId>,
+    unicast: Socket<IpV4Addr>,
+    xid: u32,
+    lease: Option<Lease>,
+}
+
+impl DhcpClient {
+    pub async fn new(
+        iface: LinkLayerId,
+        config: IpV4Config,
+        udp: &UdpHandleGeneric<IpV4Addr>,
+        udp_broadcast: &UdpHandleGeneric<LinkLayerId>,
+    ) -> Result<Self, ()> {
+        let mac = iface.mac();
+        // Transaction ids start from the end of the MAC so clients don't collide
+        let mut xid = [0; 4];
+        xid.copy_from_slice(&mac.as_slice()[2..]);
+        Ok(Self {
+            iface,
+            mac,
+            config,
+            broadcast: udp_broadcast.get_socket(CLIENT_PORT).await?,
+            unicast: udp.get_socket(CLIENT_PORT).await?,
+            xid: u32::from_be_bytes(xid),
+            lease: None,
+        })
+    }
+
+    /// Runs until `IpV4ConfigInner::dhcp_run` is cleared, then releases the lease
+    pub async fn run(mut self) {
+        info!("DHCP client started on {}", self.iface);
+        while let Some(offer) = self.discover().await {
+            let Some(ack) = self.request(&offer).await else {
+                continue;
+            };
+            let server = offer.server_id.unwrap_or(DEFAULT);
+            self.bind(Lease::from_ack(&ack, server)).await;
+            if !self.keep_lease().await {
+                break;
+            }
+        }
+        self.release().await;
+        info!("DHCP client stopped on {}", self.iface);
+    }
+
+    fn new_packet(&mut self, message_type: MessageType) -> DhcpPacket {
+        let mut packet = DhcpPacket::new(message_type, self.xid, self.mac);
+        packet.broadcast = true;
+        packet
+    }
+
+    async fn send_broadcast(&self, packet: &DhcpPacket) {
+        trace!("DHCP client on {} sending {packet:?}", self.iface);
+        self.broadcast
+            .send((self.iface, SERVER_PORT), packet.to_vec())
+            .await;
+    }
+
+    /// Waits for a reply to the current transaction
+    async fn recv(&self, until: Instant) -> Received {
+        loop {
+            if !self.config.read().await.dhcp_run {
+                return Received::Stopped;
+            }
+            let now = Instant::now();
+            if now >= until {
+                return Received::Timeout;
+            }
+            let timeout = tokio::time::sleep((until - now).min(POLL));
+            let data = select! {
+                r = self.broadcast.recv() => r.ok().filter(|(iface, ..)| iface == &self.iface).map(|(_, _, data, _)| data),
+                r = self.unicast.recv() => r.ok().map(|(_, _, data, _)| data),
+                () = timeout => None,
+            };
+            if let Some(packet) = data.and_then(|data| DhcpPacket::from_vec(&data)) {
+                if packet.xid == self.xid && packet.chaddr == self.mac {
+                    trace!("DHCP client on {} received {packet:?}", self.iface);
+                    return Received::Packet(packet);
+                }
+            }
+        }
+    }
+
+    /// Broadcasts discovers until an offer arrives, `None` if the client was stopped
+    async fn discover(&mut self) -> Option<DhcpPacket> {
+        let mut timeout = INITIAL_TIMEOUT;
+        loop {
+            self.xid = self.xid.wrapping_add(1);
+            let mut discover = self.new_packet(MessageType::Discover);
+            discover.requested_ip = self.lease.map(|lease| lease.addr);
+            self.send_broadcast(&discover).await;
+            let until = Instant::now() + timeout;
+            loop {
+                match self.recv(until).await {
+                    Received::Packet(offer) if offer.message_type == MessageType::Offer => {
+                        info!(
+                            "DHCP client on {} offered {} by {:?}",
+                            self.iface, offer.yiaddr, offer.server_id
+                        );
+                        return Some(offer);
+                    }
+                    Received::Packet(_) => {}
+                    Received::Timeout => break,
+                    Received::Stopped => return None,
+                }
+            }
+            timeout = (timeout * 2).min(MAX_TIMEOUT);
+        }
+    }
+
+    /// Requests the offered address, `None` if it was refused or nobody answered
+    async fn request(&mut self, offer: &DhcpPacket) -> Option<DhcpPacket> {
+        let mut request = self.new_packet(MessageType::Request);
+        request.server_id = offer.server_id;
+        request.requested_ip = Some(offer.yiaddr);
+        let mut timeout = INITIAL_TIMEOUT;
+        for _ in 0..MAX_REQUESTS {
+            self.send_broadcast(&request).await;
+            let until = Instant::now() + timeout;
+            loop {
+                match self.recv(until).await {
+                    Received::Packet(ack) if ack.message_type == MessageType::Ack => {
+                        return Some(ack)
+                    }
+                    Received::Packet(nak) if nak.message_type == MessageType::Nak => {
+                        warn!("DHCP client on {} refused {}", self.iface, offer.yiaddr);
+                        return None;
+                    }
+                    Received::Packet(_) => {}
+                    Received::Timeout => break,
+                    Received::Stopped => return None,
+                }
+            }
+            timeout = (timeout * 2).min(MAX_TIMEOUT);
+        }
+        warn!("DHCP client on {}: No answer to request", self.iface);
+        None
+    }
+
+    /// Renews the lease until it's lost, returns false if the client was stopped
+    async fn keep_lease(&mut self) -> bool {
+        while let Some(lease) = self.lease {
+            let now = Instant::now();
+            let (until, renewing) = if now < lease.renew_at() {
+                match self.recv(lease.renew_at()).await {
+                    Received::Stopped => return false,
+                    _ => continue,
+                }
+            } else if now < lease.rebind_at() {
+                (lease.rebind_at(), true)
+            } else if now < lease.expires_at() {
+                (lease.expires_at(), false)
+            } else {
+                warn!(
+                    "DHCP client on {}: Lease of {} expired",
+                    self.iface, lease.addr
+                );
+                self.unbind().await;
+                return true;
+            };
+            self.xid = self.xid.wrapping_add(1);
+            let mut request = self.new_packet(MessageType::Request);
+            request.ciaddr = lease.addr;
+            request.broadcast = false;
+            if renewing {
+                trace!(
+                    "DHCP client on {} renewing with {}",
+                    self.iface,
+                    lease.server
+                );
+                self.unicast
+                    .send((lease.server, SERVER_PORT), request.to_vec())
+                    .await;
+            } else {
+                trace!("DHCP client on {} rebinding", self.iface);
+                self.send_broadcast(&request).await;
+            }
+            // Retransmit halfway to the next deadline, as RFC 2131 suggests
+            let retransmit = now
+                + ((until - now) / 2)
+                    .max(Duration::from_secs(60))
+                    .min(until - now);
+            loop {
+                match self.recv(retransmit).await {
+                    Received::Packet(ack) if ack.message_type == MessageType::Ack => {
+                        self.bind(Lease::from_ack(&ack, lease.server)).await;
+                        break;
+                    }
+                    Received::Packet(nak) if nak.message_type == MessageType::Nak => {
+                        warn!(
+                            "DHCP client on {}: Lease of {} refused",
+                            self.iface, lease.addr
+                        );
+                        self.unbind().await;
+                        return true;
+                    }
+                    Received::Packet(_) => {}
+                    Received::Timeout => break,
+                    Received::Stopped => return false,
+                }
+            }
+        }
+        true
+    }
+
+    async fn bind(&mut self, lease: Lease) {
+        if self
+            .lease
+            .is_some_and(|old| old.addr == lease.addr && old.router == lease.router)
+        {
+            trace!("DHCP client on {} renewed {}", self.iface, lease.addr);
+            self.lease = Some(lease);
+            return;
+        }
+        self.unbind().await;
+        info!(
+            "DHCP client on {} bound to {} (mask {:?}, router {:?}) for {:?}",
+            self.iface, lease.addr, lease.mask, lease.router, lease.duration
+        );
+        let mut config = self.config.write().await;
+        // Without a mask the address is only known to be the client's
+        let mask = lease.mask.unwrap_or(IpV4Mask::new(32));
+        config.add_addr(self.iface, InterfaceAddr::new(lease.addr, mask));
+        if let Some(route) = lease.default_route(self.iface) {
+            config.routing.add_route(route);
+        }
+        self.lease = Some(lease);
+    }
+
+    async fn unbind(&mut self) {
+        let Some(lease) = self.lease.take() else {
+            return;
+        };
+        let mut config = self.config.write().await;
+        config.remove_addr(self.iface, lease.addr);
+        if let Some(route) = lease.default_route(self.iface) {
+            config.routing.remove_route(&route);
+        }
+    }
+
+    async fn release(&mut self) {
+        let Some(lease) = self.lease else {
+            return;
+        };
+        info!("DHCP client on {} releasing {}", self.iface, lease.addr);
+        let mut release = self.new_packet(MessageType::Release);
+        release.broadcast = false;
+        release.ciaddr = lease.addr;
+        release.server_id = Some(lease.server);
+        // The address and routes are gone before a unicast would be routed, so it's broadcast
+        self.send_broadcast(&release).await;
+        self.unbind().await;
+    }
+}
@@ -0,0 +1,204 @@
+use tracing::warn;
+
+use crate::{
+    mac::Mac,
+    network::ipv4::addr::{IpV4Addr, IpV4Mask, DEFAULT},
+};
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const BROADCAST_FLAG: u16 = 0x8000;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl MessageType {
+    const fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            1 => Some(Self::Discover),
+            2 => Some(Self::Offer),
+            3 => Some(Self::Request),
+            4 => Some(Self::Decline),
+            5 => Some(Self::Ack),
+            6 => Some(Self::Nak),
+            7 => Some(Self::Release),
+            8 => Some(Self::Inform),
+            _ => None,
+        }
+    }
+
+    /// BOOTREQUEST (1) for the client messages, BOOTREPLY (2) for the server ones
+    const fn op(self) -> u8 {
+        match self {
+            Self::Offer | Self::Ack | Self::Nak => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// DHCP message, only the options used by the client and server are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpPacket {
+    pub message_type: MessageType,
+    pub xid: u32,
+    pub secs: u16,
+    /// The client can't receive unicast until it's configured
+    pub broadcast: bool,
+    pub ciaddr: IpV4Addr,
+    pub yiaddr: IpV4Addr,
+    pub siaddr: IpV4Addr,
+    pub giaddr: IpV4Addr,
+    pub chaddr: Mac,
+    pub subnet_mask: Option<IpV4Mask>,
+    pub router: Option<IpV4Addr>,
+    /// Lease time in seconds
+    pub lease_time: Option<u32>,
+    pub server_id: Option<IpV4Addr>,
+    pub requested_ip: Option<IpV4Addr>,
+}
+
+fn read_addr(data: &[u8]) -> Option<IpV4Addr> {
+    Some(IpV4Addr::new(data.get(..4)?.try_into().ok()?))
+}
+
+impl DhcpPacket {
+    pub const fn new(message_type: MessageType, xid: u32, chaddr: Mac) -> Self {
+        Self {
+            message_type,
+            xid,
+            secs: 0,
+            broadcast: false,
+            ciaddr: DEFAULT,
+            yiaddr: DEFAULT,
+            siaddr: DEFAULT,
+            giaddr: DEFAULT,
+            chaddr,
+            subnet_mask: None,
+            router: None,
+            lease_time: None,
+            server_id: None,
+            requested_ip: None,
+        }
+    }
+
+    pub fn from_vec(data: &[u8]) -> Option<Self> {
+        if data.len() < 240 {
+            warn!("DHCP packet: Not enough data");
+            return None;
+        }
+        let (htype, hlen) = (data[1], data[2]);
+        if htype != 1 || hlen != 6 {
+            warn!("DHCP packet: Unknown hardware type {htype} with length {hlen}");
+            return None;
+        }
+        if data[236..240] != MAGIC_COOKIE {
+            warn!("DHCP packet: Invalid magic cookie");
+            return None;
+        }
+        let xid = u32::from_be_bytes(data[4..8].try_into().ok()?);
+        let secs = u16::from_be_bytes(data[8..10].try_into().ok()?);
+        let flags = u16::from_be_bytes(data[10..12].try_into().ok()?);
+        let mut res = Self::new(
+            MessageType::Discover,
+            xid,
+            Mac::new(data[28..34].try_into().ok()?),
+        );
+        res.secs = secs;
+        res.broadcast = flags & BROADCAST_FLAG != 0;
+        res.ciaddr = read_addr(&data[12..])?;
+        res.yiaddr = read_addr(&data[16..])?;
+        res.siaddr = read_addr(&data[20..])?;
+        res.giaddr = read_addr(&data[24..])?;
+
+        let mut message_type = None;
+        let mut options = &data[240..];
+        while let Some(&code) = options.first() {
+            match code {
+                // Pad
+                0 => options = &options[1..],
+                // End
+                255 => break,
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    let value = options.get(2..2 + len).or_else(|| {
+                        warn!("DHCP option {code}: Invalid length {len}");
+                        None
+                    })?;
+                    match code {
+                        1 => res.subnet_mask = read_addr(value).map(IpV4Mask::from_addr),
+                        3 => res.router = read_addr(value),
+                        50 => res.requested_ip = read_addr(value),
+                        51 => {
+                            res.lease_time = value.try_into().ok().map(u32::from_be_bytes);
+                        }
+                        53 => message_type = value.first().copied().and_then(MessageType::from_u8),
+                        54 => res.server_id = read_addr(value),
+                        _ => {}
+                    }
+                    options = &options[2 + len..];
+                }
+            }
+        }
+        let Some(message_type) = message_type else {
+            warn!("DHCP packet: Missing message type");
+            return None;
+        };
+        if data[0] != message_type.op() {
+            warn!("DHCP packet: Invalid op {} for {message_type:?}", data[0]);
+            return None;
+        }
+        res.message_type = message_type;
+        Some(res)
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(300);
+        res.push(self.message_type.op());
+        res.push(1); // Ethernet
+        res.push(6);
+        res.push(0); // Hops
+        res.extend_from_slice(&self.xid.to_be_bytes());
+        res.extend_from_slice(&self.secs.to_be_bytes());
+        let flags = if self.broadcast { BROADCAST_FLAG } else { 0 };
+        res.extend_from_slice(&flags.to_be_bytes());
+        res.extend_from_slice(self.ciaddr.as_slice());
+        res.extend_from_slice(self.yiaddr.as_slice());
+        res.extend_from_slice(self.siaddr.as_slice());
+        res.extend_from_slice(self.giaddr.as_slice());
+        res.extend_from_slice(self.chaddr.as_slice());
+        // Rest of chaddr, sname and file
+        res.resize(236, 0);
+        res.extend_from_slice(&MAGIC_COOKIE);
+
+        res.extend_from_slice(&[53, 1, self.message_type as u8]);
+        let mut add_addr = |code, addr: Option<IpV4Addr>| {
+            if let Some(addr) = addr {
+                res.extend_from_slice(&[code, 4]);
+                res.extend_from_slice(addr.as_slice());
+            }
+        };
+        add_addr(1, self.subnet_mask.map(IpV4Mask::to_addr));
+        add_addr(3, self.router);
+        add_addr(50, self.requested_ip);
+        add_addr(54, self.server_id);
+        if let Some(lease_time) = self.lease_time {
+            res.extend_from_slice(&[51, 4]);
+            res.extend_from_slice(&lease_time.to_be_bytes());
+        }
+        res.push(255);
+        // BOOTP relays expect at least 300 bytes
+        if res.len() < 300 {
+            res.resize(300, 0);
+        }
+        res
+    }
+}
@@ -0,0 +1,397 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Local};
+use tokio::{select, sync::RwLock};
+use tracing::{info, trace, warn};
+
+use crate::{
+    chassis::LinkLayerId,
+    mac::Mac,
+    network::ipv4::{
+        addr::{IpV4Addr, IpV4Mask, DEFAULT},
+        config::IpV4Config,
+    },
+    transport::udp::{Socket, UdpHandleGeneric},
+};
+
+use super::{
+    packet::{DhcpPacket, MessageType},
+    CLIENT_PORT, SERVER_PORT,
+};
+
+/// Interval at which expired leases are dropped
+const TICK: Duration = Duration::from_secs(1);
+
+/// Addresses handed out on an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pool {
+    pub start: IpV4Addr,
+    pub end: IpV4Addr,
+    pub mask: IpV4Mask,
+    /// Default gateway given to the clients, the interface's address if not set
+    pub router: Option<IpV4Addr>,
+    pub lease_time: chrono::Duration,
+}
+
+impl Pool {
+    fn contains(&self, addr: IpV4Addr) -> bool {
+        (u32::from(self.start)..=u32::from(self.end)).contains(&u32::from(addr))
+    }
+
+    fn in_subnet(&self, addr: IpV4Addr) -> bool {
+        self.mask & addr == self.mask & self.start
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseState {
+    /// Offered and waiting for the client's request
+    Offered,
+    Bound,
+    /// The client found the address in use, it isn't handed out until it expires
+    Declined,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub mac: Mac,
+    pub iface: LinkLayerId,
+    pub state: LeaseState,
+    pub expires: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpServerConfigInner {
+    pub pools: HashMap<LinkLayerId, Pool>,
+    /// Addresses always given to a client, they must be in the subnet of the interface's pool
+    pub reservations: HashMap<Mac, IpV4Addr>,
+    pub leases: HashMap<IpV4Addr, Lease>,
+    /// Time an offered address is kept for the client
+    pub offer_timeout: chrono::Duration,
+}
+
+impl DhcpServerConfigInner {
+    pub fn print_leases(&self) -> prettytable::Table {
+        let mut table = prettytable::table!(["address", "mac", "interface", "state", "expires"]);
+        if self.leases.is_empty() {
+            table.add_empty_row();
+        }
+        let mut leases = self.leases.iter().collect::<Vec<_>>();
+        leases.sort_by_key(|(addr, _)| **addr);
+        for (addr, lease) in leases {
+            table.add_row(prettytable::row![
+                addr,
+                lease.mac,
+                lease.iface,
+                format!("{:?}", lease.state),
+                lease.expires.format("%T")
+            ]);
+        }
+        table
+    }
+
+    /// Reserves the address for the client, `Err` with the other client's MAC if it's already
+    /// leased or reserved to it
+    pub fn reserve(&mut self, mac: Mac, addr: IpV4Addr) -> Result<(), Mac> {
+        if let Some(lease) = self.leases.get(&addr).filter(|lease| lease.mac != mac) {
+            return Err(lease.mac);
+        }
+        if let Some((&other, _)) = self
+            .reservations
+            .iter()
+            .find(|(&reserved_mac, &reserved)| reserved == addr && reserved_mac != mac)
+        {
+            return Err(other);
+        }
+        self.reservations.insert(mac, addr);
+        Ok(())
+    }
+
+    fn is_free(&self, addr: IpV4Addr, mac: Mac) -> bool {
+        !self.leases.contains_key(&addr)
+            && self
+                .reservations
+                .iter()
+                .all(|(reserved_mac, reserved)| reserved != &addr || reserved_mac == &mac)
+    }
+
+    /// Address for the client: its reservation, its current lease, the one it asked for or the
+    /// first free one of the pool
+    fn choose(
+        &self,
+        pool: &Pool,
+        mac: Mac,
+        iface: LinkLayerId,
+        requested: Option<IpV4Addr>,
+        own: IpV4Addr,
+    ) -> Option<IpV4Addr> {
+        if let Some(&reserved) = self.reservations.get(&mac) {
+            if pool.in_subnet(reserved) {
+                return Some(reserved);
+            }
+        }
+        let leased = self.leases.iter().find_map(|(&addr, lease)| {
+            (lease.mac == mac && lease.iface == iface && lease.state != LeaseState::Declined)
+                .then_some(addr)
+        });
+        leased
+            .or(requested
+                .filter(|&addr| pool.contains(addr) && addr != own && self.is_free(addr, mac)))
+            .or_else(|| {
+                (u32::from(pool.start)..=u32::from(pool.end))
+                    .map(IpV4Addr::from)
+                    .find(|&addr| addr != own && self.is_free(addr, mac))
+            })
+    }
+
+    /// Whether the client may get the address it requested
+    fn may_bind(&self, pool: &Pool, mac: Mac, addr: IpV4Addr, own: IpV4Addr) -> bool {
+        if self.reservations.get(&mac) == Some(&addr) {
+            return pool.in_subnet(addr);
+        }
+        match self.leases.get(&addr) {
+            Some(lease) => lease.mac == mac && lease.state != LeaseState::Declined,
+            None => pool.contains(addr) && addr != own && self.is_free(addr, mac),
+        }
+    }
+}
+
+impl Default for DhcpServerConfigInner {
+    fn default() -> Self {
+        Self {
+            pools: Default::default(),
+            reservations: Default::default(),
+            leases: Default::default(),
+            offer_timeout: chrono::Duration::seconds(10),
+        }
+    }
+}
+
+pub type DhcpServerConfig = Arc<RwLock<DhcpServerConfigInner>>;
+
+/// DHCP server for the interfaces with a pool in the config, the interfaces need an address in
+/// the pool's subnet so the clients are reachable through the connected route
+pub struct DhcpServer {
+    config: DhcpServerConfig,
+    ip_config: IpV4Config,
+    broadcast: Socket<LinkLayerId>,
+    unicast: Socket<IpV4Addr>,
+}
+
+impl DhcpServer {
+    pub async fn new(
+        config: DhcpServerConfig,
+        ip_config: IpV4Config,
+        udp: &UdpHandleGeneric<IpV4Addr>,
+        udp_broadcast: &UdpHandleGeneric<LinkLayerId>,
+    ) -> Result<Self, ()> {
+        Ok(Self {
+            config,
+            ip_config,
+            broadcast: udp_broadcast.get_socket(SERVER_PORT).await?,
+            unicast: udp.get_socket(SERVER_PORT).await?,
+        })
+    }
+
+    pub async fn run(self) {
+        info!("DHCP server started");
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            select! {
+                r = self.broadcast.recv() => match r {
+                    Ok((iface, _, data, _)) => {
+                        if let Some(packet) = DhcpPacket::from_vec(&data) {
+                            self.on_packet(Some(iface), packet).await;
+                        }
+                    }
+                    Err(_) => break,
+                },
+                r = self.unicast.recv() => match r {
+                    Ok((_, _, data, _)) => {
+                        if let Some(packet) = DhcpPacket::from_vec(&data) {
+                            self.on_packet(None, packet).await;
+                        }
+                    }
+                    Err(_) => break,
+                },
+                _ = tick.tick() => self.expire().await,
+            }
+        }
+        warn!("DHCP server stopped");
+    }
+
+    /// Drops the expired leases
+    async fn expire(&self) {
+        let now = Local::now();
+        let mut config = self.config.write().await;
+        let expired = config
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.expires <= now)
+            .map(|(&addr, _)| addr)
+            .collect::<Vec<_>>();
+        for addr in expired {
+            if let Some(lease) = config.leases.remove(&addr) {
+                trace!("DHCP server: Lease of {addr} to {} expired", lease.mac);
+            }
+        }
+    }
+
+    /// Handles a client message, `iface` is `None` for the ones received by unicast
+    async fn on_packet(&self, iface: Option<LinkLayerId>, packet: DhcpPacket) {
+        trace!("DHCP server received {packet:?} from {iface:?}");
+        let mut config = self.config.write().await;
+        // Unicast clients already have a lease, which tells their interface
+        let Some(iface) = iface.or_else(|| {
+            config
+                .leases
+                .values()
+                .find(|lease| lease.mac == packet.chaddr)
+                .map(|lease| lease.iface)
+        }) else {
+            return;
+        };
+        let Some(pool) = config.pools.get(&iface).cloned() else {
+            return;
+        };
+        let own = self.ip_config.read().await.source_addr(&iface, pool.start);
+        let mac = packet.chaddr;
+        let mut reply = DhcpPacket::new(MessageType::Ack, packet.xid, mac);
+        reply.broadcast = packet.broadcast;
+        reply.giaddr = packet.giaddr;
+        reply.server_id = Some(own);
+        match packet.message_type {
+            MessageType::Discover => {
+                let Some(addr) = config.choose(&pool, mac, iface, packet.requested_ip, own) else {
+                    warn!("DHCP server: No address left on {iface} for {mac}");
+                    return;
+                };
+                let expires = Local::now() + config.offer_timeout;
+                let lease = config.leases.entry(addr).or_insert(Lease {
+                    mac,
+                    iface,
+                    state: LeaseState::Offered,
+                    expires,
+                });
+                // A bound lease keeps its expiration until it's requested again
+                if lease.state == LeaseState::Offered {
+                    lease.expires = expires;
+                }
+                reply.message_type = MessageType::Offer;
+                reply.yiaddr = addr;
+            }
+            MessageType::Request => {
+                if packet.server_id.is_some_and(|server| server != own) {
+                    // The client chose another server
+                    config
+                        .leases
+                        .retain(|_, lease| lease.mac != mac || lease.state != LeaseState::Offered);
+                    return;
+                }
+                let Some(addr) = packet
+                    .requested_ip
+                    .or(Some(packet.ciaddr).filter(|&addr| addr != DEFAULT))
+                else {
+                    return;
+                };
+                if config.may_bind(&pool, mac, addr, own) {
+                    let bound = config
+                        .leases
+                        .get(&addr)
+                        .is_some_and(|lease| lease.state == LeaseState::Bound);
+                    config.leases.insert(
+                        addr,
+                        Lease {
+                            mac,
+                            iface,
+                            state: LeaseState::Bound,
+                            expires: Local::now() + pool.lease_time,
+                        },
+                    );
+                    if !bound {
+                        info!("DHCP server: Leased {addr} to {mac} on {iface}");
+                    }
+                    reply.yiaddr = addr;
+                } else {
+                    warn!("DHCP server: Refused {addr} to {mac}");
+                    reply.message_type = MessageType::Nak;
+                }
+            }
+            MessageType::Decline => {
+                if let Some(addr) = packet.requested_ip {
+                    warn!("DHCP server: {mac} declined {addr}");
+                    config.leases.insert(
+                        addr,
+                        Lease {
+                            mac,
+                            iface,
+                            state: LeaseState::Declined,
+                            expires: Local::now() + pool.lease_time,
+                        },
+                    );
+                }
+                return;
+            }
+            MessageType::Release => {
+                if config
+                    .leases
+                    .get(&packet.ciaddr)
+                    .is_some_and(|lease| lease.mac == mac)
+                {
+                    info!("DHCP server: {mac} released {}", packet.ciaddr);
+                    config.leases.remove(&packet.ciaddr);
+                }
+                return;
+            }
+            _ => return,
+        }
+        if reply.message_type != MessageType::Nak {
+            reply.subnet_mask = Some(pool.mask);
+            reply.router = Some(pool.router.unwrap_or(own));
+            reply.lease_time = Some(pool.lease_time.num_seconds().clamp(0, u32::MAX.into()) as u32);
+        }
+        drop(config);
+        trace!("DHCP server sending {reply:?}");
+        // Configured clients get unicast, the rest broadcast on their interface
+        if packet.ciaddr != DEFAULT && reply.message_type != MessageType::Nak {
+            self.unicast
+                .send((packet.ciaddr, CLIENT_PORT), reply.to_vec())
+                .await;
+        } else {
+            self.broadcast
+                .send((iface, CLIENT_PORT), reply.to_vec())
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use crate::{chassis::LinkLayerId, mac::Mac, network::ipv4::addr::IpV4Addr};
+
+    use super::{DhcpServerConfigInner, Lease, LeaseState};
+
+    #[test]
+    fn refuses_reservation_of_leased_address() {
+        let leased_mac = Mac::new([0, 1, 0, 0, 0, 1]);
+        let other_mac = Mac::new([0, 1, 0, 0, 0, 2]);
+        let addr = IpV4Addr::new([10, 0, 0, 10]);
+        let mut config = DhcpServerConfigInner::default();
+        config.leases.insert(
+            addr,
+            Lease {
+                mac: leased_mac,
+                iface: LinkLayerId::Ethernet(0, Mac::new([0, 1, 0, 0, 0, 0])),
+                state: LeaseState::Bound,
+                expires: Local::now() + chrono::Duration::seconds(3600),
+            },
+        );
+        assert_eq!(config.reserve(other_mac, addr), Err(leased_mac));
+        assert!(config.reservations.is_empty());
+        assert_eq!(config.reserve(leased_mac, addr), Ok(()));
+        assert_eq!(config.reserve(other_mac, addr), Err(leased_mac));
+        assert_eq!(config.reservations.get(&leased_mac), Some(&addr));
+    }
+}
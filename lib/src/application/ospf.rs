@@ -0,0 +1,26 @@
+use crate::network::ipv4::addr::IpV4Addr;
+
+pub mod lsa;
+pub mod lsdb;
+pub mod neighbor;
+pub mod packet;
+pub mod router;
+pub mod spf;
+
+/// Multicast group of every OSPF router, hellos and floods are sent to it
+pub const ALL_SPF_ROUTERS: IpV4Addr = IpV4Addr::new([224, 0, 0, 5]);
+/// Area every other area is attached to, inter-area routes go through it
+pub const BACKBONE: IpV4Addr = IpV4Addr::new([0, 0, 0, 0]);
+
+/// Age in seconds at which an LSA is flushed from the routing domain
+pub const MAX_AGE: u16 = 3600;
+/// Age at which the originator refreshes its LSAs
+pub const LS_REFRESH_TIME: u16 = 1800;
+/// Age difference under which two instances of an LSA are considered the same
+pub const MAX_AGE_DIFF: u16 = 900;
+/// Seconds added to the age of an LSA when it's sent
+pub const INF_TRANS_DELAY: u16 = 1;
+pub const INITIAL_SEQUENCE_NUMBER: i32 = i32::MIN + 1;
+pub const MAX_SEQUENCE_NUMBER: i32 = i32::MAX;
+/// Metric of unreachable destinations in summary LSAs
+pub const LS_INFINITY: u32 = 0xff_ffff;
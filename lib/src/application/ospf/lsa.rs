@@ -0,0 +1,324 @@
+use std::{cmp::Ordering, fmt::Display};
+
+use tracing::warn;
+
+use crate::network::ipv4::addr::{IpV4Addr, IpV4Mask};
+
+use super::{MAX_AGE, MAX_AGE_DIFF};
+
+pub const HEADER_LEN: usize = 20;
+const ROUTER_LINK_LEN: usize = 12;
+/// Offset of the checksum in the header
+const CHECKSUM_OFFSET: usize = 16;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LsType {
+    Router = 1,
+    Network = 2,
+    /// Network in another area, advertised by an area border router
+    Summary = 3,
+    /// AS boundary router in another area
+    AsbrSummary = 4,
+}
+
+impl LsType {
+    pub const fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            1 => Some(Self::Router),
+            2 => Some(Self::Network),
+            3 => Some(Self::Summary),
+            4 => Some(Self::AsbrSummary),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LsType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Router => write!(f, "router"),
+            Self::Network => write!(f, "network"),
+            Self::Summary => write!(f, "summary"),
+            Self::AsbrSummary => write!(f, "asbr-summary"),
+        }
+    }
+}
+
+/// Identifies an LSA in an area, its instances only differ by sequence number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LsaKey {
+    pub ls_type: LsType,
+    pub id: IpV4Addr,
+    pub adv_router: IpV4Addr,
+}
+
+impl LsaKey {
+    pub const fn new(ls_type: LsType, id: IpV4Addr, adv_router: IpV4Addr) -> Self {
+        Self {
+            ls_type,
+            id,
+            adv_router,
+        }
+    }
+
+    /// Reads the key as found in link state requests, the type takes 4 bytes there
+    pub fn read_request(data: &[u8]) -> Option<Self> {
+        let ls_type = u32::from_be_bytes(data.get(0..4)?.try_into().ok()?);
+        let Some(ls_type) = u8::try_from(ls_type).ok().and_then(LsType::from_u8) else {
+            warn!("OSPF LSA: Unknown type {ls_type}");
+            return None;
+        };
+        Some(Self::new(
+            ls_type,
+            IpV4Addr::new(data.get(4..8)?.try_into().ok()?),
+            IpV4Addr::new(data.get(8..12)?.try_into().ok()?),
+        ))
+    }
+
+    pub fn write_request(&self, res: &mut Vec<u8>) {
+        res.extend_from_slice(&(self.ls_type as u32).to_be_bytes());
+        res.extend_from_slice(self.id.as_slice());
+        res.extend_from_slice(self.adv_router.as_slice());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LsaHeader {
+    /// Seconds since the LSA was originated
+    pub age: u16,
+    pub options: u8,
+    pub key: LsaKey,
+    pub seq: i32,
+    pub checksum: u16,
+    /// Length of the whole LSA, header included
+    pub length: u16,
+}
+
+impl LsaHeader {
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let addr = |i: usize| Some(IpV4Addr::new(data.get(i..i + 4)?.try_into().ok()?));
+        let ls_type = *data.get(3)?;
+        let Some(ls_type) = LsType::from_u8(ls_type) else {
+            warn!("OSPF LSA: Unknown type {ls_type}");
+            return None;
+        };
+        Some(Self {
+            age: u16::from_be_bytes(data.get(0..2)?.try_into().ok()?).min(MAX_AGE),
+            options: data[2],
+            key: LsaKey::new(ls_type, addr(4)?, addr(8)?),
+            seq: i32::from_be_bytes(data.get(12..16)?.try_into().ok()?),
+            checksum: u16::from_be_bytes(data.get(16..18)?.try_into().ok()?),
+            length: u16::from_be_bytes(data.get(18..20)?.try_into().ok()?),
+        })
+    }
+
+    pub fn write(&self, res: &mut Vec<u8>) {
+        res.extend_from_slice(&self.age.to_be_bytes());
+        res.extend_from_slice(&[self.options, self.key.ls_type as u8]);
+        res.extend_from_slice(self.key.id.as_slice());
+        res.extend_from_slice(self.key.adv_router.as_slice());
+        res.extend_from_slice(&self.seq.to_be_bytes());
+        res.extend_from_slice(&self.checksum.to_be_bytes());
+        res.extend_from_slice(&self.length.to_be_bytes());
+    }
+
+    /// Which instance of the LSA is the most recent one as described in RFC 2328 13.1, `Greater`
+    /// if it's this one
+    pub fn compare(&self, other: &Self) -> Ordering {
+        self.seq
+            .cmp(&other.seq)
+            .then(self.checksum.cmp(&other.checksum))
+            .then_with(|| match (self.age == MAX_AGE, other.age == MAX_AGE) {
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                _ if self.age.abs_diff(other.age) > MAX_AGE_DIFF => other.age.cmp(&self.age),
+                _ => Ordering::Equal,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LinkType {
+    PointToPoint = 1,
+    Transit = 2,
+    Stub = 3,
+    Virtual = 4,
+}
+
+/// Link of a router LSA, what the id and data are depends on the type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouterLink {
+    /// Neighbor's router id for point-to-point links, network address for stubs
+    pub id: IpV4Addr,
+    /// Interface address for point-to-point links, network mask for stubs
+    pub data: IpV4Addr,
+    pub link_type: LinkType,
+    pub metric: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterLsa {
+    pub flags: u8,
+    pub links: Vec<RouterLink>,
+}
+
+impl RouterLsa {
+    /// The router is an area border router
+    pub const BORDER: u8 = 0x01;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LsaBody {
+    Router(RouterLsa),
+    Summary {
+        mask: IpV4Mask,
+        metric: u32,
+    },
+    /// Kept and flooded but not used in the routing table calculation
+    Other(Vec<u8>),
+}
+
+impl LsaBody {
+    fn read(ls_type: LsType, data: &[u8]) -> Option<Self> {
+        let addr = |i: usize| Some(IpV4Addr::new(data.get(i..i + 4)?.try_into().ok()?));
+        match ls_type {
+            LsType::Router => {
+                let count = u16::from_be_bytes(data.get(2..4)?.try_into().ok()?);
+                let mut links = Vec::with_capacity(count as usize);
+                let mut i = 4;
+                for _ in 0..count {
+                    let Some(link_type) = (match data.get(i + 8)? {
+                        1 => Some(LinkType::PointToPoint),
+                        2 => Some(LinkType::Transit),
+                        3 => Some(LinkType::Stub),
+                        4 => Some(LinkType::Virtual),
+                        _ => None,
+                    }) else {
+                        warn!("OSPF router LSA: Unknown link type {}", data[i + 8]);
+                        return None;
+                    };
+                    let tos_count = *data.get(i + 9)? as usize;
+                    links.push(RouterLink {
+                        id: addr(i)?,
+                        data: addr(i + 4)?,
+                        link_type,
+                        metric: u16::from_be_bytes(data.get(i + 10..i + 12)?.try_into().ok()?),
+                    });
+                    // The TOS metrics aren't supported, they're skipped
+                    i += ROUTER_LINK_LEN + tos_count * 4;
+                }
+                Some(Self::Router(RouterLsa {
+                    flags: data[0],
+                    links,
+                }))
+            }
+            LsType::Summary => Some(Self::Summary {
+                mask: IpV4Mask::from_addr(addr(0)?),
+                metric: u32::from_be_bytes(data.get(4..8)?.try_into().ok()?) & 0xff_ffff,
+            }),
+            LsType::Network | LsType::AsbrSummary => Some(Self::Other(data.to_vec())),
+        }
+    }
+
+    fn write(&self, res: &mut Vec<u8>) {
+        match self {
+            Self::Router(router) => {
+                res.extend_from_slice(&[router.flags, 0]);
+                res.extend_from_slice(&(router.links.len() as u16).to_be_bytes());
+                for link in &router.links {
+                    res.extend_from_slice(link.id.as_slice());
+                    res.extend_from_slice(link.data.as_slice());
+                    res.extend_from_slice(&[link.link_type as u8, 0]);
+                    res.extend_from_slice(&link.metric.to_be_bytes());
+                }
+            }
+            Self::Summary { mask, metric } => {
+                res.extend_from_slice(mask.to_addr().as_slice());
+                res.extend_from_slice(&(metric & 0xff_ffff).to_be_bytes());
+            }
+            Self::Other(data) => res.extend_from_slice(data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lsa {
+    pub header: LsaHeader,
+    pub body: LsaBody,
+}
+
+impl Lsa {
+    /// Builds the LSA with its length and checksum
+    pub fn new(key: LsaKey, options: u8, seq: i32, body: LsaBody) -> Self {
+        let mut lsa = Self {
+            header: LsaHeader {
+                age: 0,
+                options,
+                key,
+                seq,
+                checksum: 0,
+                length: 0,
+            },
+            body,
+        };
+        lsa.header.length = lsa.to_vec().len() as u16;
+        let data = lsa.to_vec();
+        lsa.header.checksum = fletcher_checksum(&data[2..], CHECKSUM_OFFSET - 2);
+        lsa
+    }
+
+    /// Reads an LSA at the beginning of the data, returns it with its length
+    pub fn read(data: &[u8]) -> Option<(Self, usize)> {
+        let header = LsaHeader::read(data)?;
+        let len = header.length as usize;
+        if len < HEADER_LEN || data.len() < len {
+            warn!("OSPF LSA: Invalid length {len}");
+            return None;
+        }
+        let (c0, c1) = fletcher_sums(&data[2..len]);
+        if c0 != 0 || c1 != 0 {
+            warn!("OSPF LSA: Checksum error for {:?}", header.key);
+            return None;
+        }
+        let body = LsaBody::read(header.key.ls_type, &data[HEADER_LEN..len])?;
+        Some((Self { header, body }, len))
+    }
+
+    pub fn write(&self, res: &mut Vec<u8>) {
+        self.header.write(res);
+        self.body.write(res);
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(self.header.length as usize);
+        self.write(&mut res);
+        res
+    }
+}
+
+fn fletcher_sums(data: &[u8]) -> (u32, u32) {
+    data.iter().fold((0, 0), |(c0, c1), &b| {
+        let c0 = (c0 + b as u32) % 255;
+        (c0, (c1 + c0) % 255)
+    })
+}
+
+/// Fletcher checksum of ISO 8473 for the data with a zeroed checksum at the offset, the age isn't
+/// part of the data so that it can change in transit
+fn fletcher_checksum(data: &[u8], offset: usize) -> u16 {
+    let (c0, c1) = fletcher_sums(data);
+    let (c0, c1) = (c0 as i64, c1 as i64);
+    let len = data.len() as i64;
+    let offset = offset as i64;
+    let mut x = ((len - offset - 1) * c0 - c1).rem_euclid(255);
+    let mut y = (c1 - (len - offset) * c0).rem_euclid(255);
+    if x == 0 {
+        x = 255;
+    }
+    if y == 0 {
+        y = 255;
+    }
+    ((x as u16) << 8) | y as u16
+}
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use tokio::time::Instant;
+
+use super::{
+    lsa::{Lsa, LsaKey},
+    INF_TRANS_DELAY, MAX_AGE,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsdbEntry {
+    /// The instance as received, its age is the one it had then
+    pub lsa: Lsa,
+    installed: Instant,
+    /// Reached the max age and was flooded, it's removed once acknowledged by the neighbors
+    pub flushed: bool,
+}
+
+impl LsdbEntry {
+    pub fn age(&self) -> u16 {
+        let elapsed = self.installed.elapsed().as_secs();
+        (self.lsa.header.age as u64 + elapsed).min(MAX_AGE as u64) as u16
+    }
+
+    /// When the instance was received or originated
+    pub const fn installed(&self) -> Instant {
+        self.installed
+    }
+
+    /// Copy of the LSA to send, aged by the elapsed time and the transmission delay
+    pub fn to_send(&self) -> Lsa {
+        let mut lsa = self.lsa.clone();
+        lsa.header.age = (self.age() + INF_TRANS_DELAY).min(MAX_AGE);
+        lsa
+    }
+
+    /// Copy of the LSA with its current age
+    pub fn current(&self) -> Lsa {
+        let mut lsa = self.lsa.clone();
+        lsa.header.age = self.age();
+        lsa
+    }
+}
+
+/// Link state database of an area, every router of the area has the same one once synchronized
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lsdb {
+    entries: BTreeMap<LsaKey, LsdbEntry>,
+}
+
+impl Lsdb {
+    pub fn get(&self, key: &LsaKey) -> Option<&LsdbEntry> {
+        self.entries.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &LsaKey) -> Option<&mut LsdbEntry> {
+        self.entries.get_mut(key)
+    }
+
+    /// Replaces the previous instance of the LSA, one at max age is being flushed
+    pub fn install(&mut self, lsa: Lsa) {
+        self.entries.insert(
+            lsa.header.key,
+            LsdbEntry {
+                flushed: lsa.header.age >= MAX_AGE,
+                lsa,
+                installed: Instant::now(),
+            },
+        );
+    }
+
+    pub fn remove(&mut self, key: &LsaKey) -> Option<LsdbEntry> {
+        self.entries.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&LsaKey, &LsdbEntry)> {
+        self.entries.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&LsaKey, &mut LsdbEntry)> {
+        self.entries.iter_mut()
+    }
+}
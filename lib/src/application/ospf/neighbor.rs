@@ -0,0 +1,112 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use tokio::time::Instant;
+
+use crate::{chassis::LinkLayerId, network::ipv4::addr::IpV4Addr};
+
+use super::{
+    lsa::{LsaHeader, LsaKey},
+    packet::DatabaseDescription,
+};
+
+/// States of the conversation with a neighbor, every neighbor becomes adjacent so the 2-Way state
+/// is only crossed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NeighborState {
+    Down,
+    /// Heard from the neighbor, it hasn't heard from us yet
+    Init,
+    TwoWay,
+    /// Deciding which one is the master of the database exchange
+    ExStart,
+    /// Describing the databases to each other
+    Exchange,
+    /// Requesting the LSAs the neighbor has a more recent instance of
+    Loading,
+    Full,
+}
+
+impl Display for NeighborState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Down => write!(f, "down"),
+            Self::Init => write!(f, "init"),
+            Self::TwoWay => write!(f, "2-way"),
+            Self::ExStart => write!(f, "exstart"),
+            Self::Exchange => write!(f, "exchange"),
+            Self::Loading => write!(f, "loading"),
+            Self::Full => write!(f, "full"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighbor {
+    pub router_id: IpV4Addr,
+    /// Source of the neighbor's packets on the interface
+    pub addr: IpV4Addr,
+    pub iface: LinkLayerId,
+    pub area: IpV4Addr,
+    pub state: NeighborState,
+    /// Declared down if no hello is received before
+    pub dead: Instant,
+    /// We're the master of the database exchange
+    pub master: bool,
+    pub dd_seq: u32,
+    /// Flags and sequence number of the last database description received, to spot duplicates
+    pub last_received: Option<(u8, u32)>,
+    /// Sent again when the neighbor doesn't answer or repeats itself
+    pub last_sent: Option<DatabaseDescription>,
+    /// Headers of the database left to describe to the neighbor
+    pub summary: Vec<LsaHeader>,
+    /// LSAs the neighbor has a more recent instance of
+    pub requests: BTreeMap<LsaKey, LsaHeader>,
+    /// LSAs flooded to the neighbor and not acknowledged yet
+    pub retransmissions: BTreeSet<LsaKey>,
+    /// Next time the unanswered packets are sent again
+    pub retransmit: Instant,
+}
+
+impl Neighbor {
+    pub fn new(
+        router_id: IpV4Addr,
+        addr: IpV4Addr,
+        iface: LinkLayerId,
+        area: IpV4Addr,
+        dead: Instant,
+    ) -> Self {
+        Self {
+            router_id,
+            addr,
+            iface,
+            area,
+            state: NeighborState::Down,
+            dead,
+            master: false,
+            dd_seq: 0,
+            last_received: None,
+            last_sent: None,
+            summary: Vec::new(),
+            requests: BTreeMap::new(),
+            retransmissions: BTreeSet::new(),
+            retransmit: Instant::now(),
+        }
+    }
+
+    /// Forgets the state of the database exchange
+    pub fn clear_lists(&mut self) {
+        self.last_received = None;
+        self.last_sent = None;
+        self.summary.clear();
+        self.requests.clear();
+        self.retransmissions.clear();
+    }
+
+    /// The neighbor takes part in the flooding
+    pub fn floods(&self) -> bool {
+        self.state >= NeighborState::Exchange
+    }
+}
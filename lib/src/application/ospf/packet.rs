@@ -0,0 +1,255 @@
+use tracing::warn;
+
+use crate::network::ipv4::addr::{IpV4Addr, IpV4Mask};
+
+use super::lsa::{self, Lsa, LsaHeader, LsaKey};
+
+const VERSION: u8 = 2;
+pub const HEADER_LEN: usize = 24;
+const HELLO_LEN: usize = 20;
+const DD_LEN: usize = 8;
+const REQUEST_LEN: usize = 12;
+
+/// Options sent in hellos, LSAs and database descriptions, only external routing (E) is set
+pub const OPTIONS: u8 = 0x02;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub mask: IpV4Mask,
+    pub hello_interval: u16,
+    pub options: u8,
+    pub priority: u8,
+    /// Seconds without hellos before the neighbor is declared down
+    pub dead_interval: u32,
+    pub designated: IpV4Addr,
+    pub backup: IpV4Addr,
+    /// Router ids of the neighbors heard on the interface
+    pub neighbors: Vec<IpV4Addr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseDescription {
+    pub mtu: u16,
+    pub options: u8,
+    pub flags: u8,
+    pub seq: u32,
+    pub headers: Vec<LsaHeader>,
+}
+
+impl DatabaseDescription {
+    /// First packet of the exchange
+    pub const INIT: u8 = 0x04;
+    /// More packets follow
+    pub const MORE: u8 = 0x02;
+    /// Sent by the master
+    pub const MASTER: u8 = 0x01;
+
+    pub const fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    Hello(Hello),
+    DatabaseDescription(DatabaseDescription),
+    LinkStateRequest(Vec<LsaKey>),
+    LinkStateUpdate(Vec<Lsa>),
+    LinkStateAck(Vec<LsaHeader>),
+}
+
+impl Body {
+    const fn packet_type(&self) -> u8 {
+        match self {
+            Self::Hello(_) => 1,
+            Self::DatabaseDescription(_) => 2,
+            Self::LinkStateRequest(_) => 3,
+            Self::LinkStateUpdate(_) => 4,
+            Self::LinkStateAck(_) => 5,
+        }
+    }
+
+    fn read(packet_type: u8, data: &[u8]) -> Option<Self> {
+        let addr = |i: usize| Some(IpV4Addr::new(data.get(i..i + 4)?.try_into().ok()?));
+        let headers = |data: &[u8]| {
+            data.chunks(lsa::HEADER_LEN)
+                .map(LsaHeader::read)
+                .collect::<Option<Vec<_>>>()
+        };
+        match packet_type {
+            1 => {
+                if data.len() < HELLO_LEN || !(data.len() - HELLO_LEN).is_multiple_of(4) {
+                    warn!("OSPF hello: Invalid length {}", data.len());
+                    return None;
+                }
+                Some(Self::Hello(Hello {
+                    mask: IpV4Mask::from_addr(addr(0)?),
+                    hello_interval: u16::from_be_bytes(data[4..6].try_into().ok()?),
+                    options: data[6],
+                    priority: data[7],
+                    dead_interval: u32::from_be_bytes(data[8..12].try_into().ok()?),
+                    designated: addr(12)?,
+                    backup: addr(16)?,
+                    neighbors: (HELLO_LEN..data.len())
+                        .step_by(4)
+                        .map(addr)
+                        .collect::<Option<_>>()?,
+                }))
+            }
+            2 => {
+                if data.len() < DD_LEN || !(data.len() - DD_LEN).is_multiple_of(lsa::HEADER_LEN) {
+                    warn!("OSPF database description: Invalid length {}", data.len());
+                    return None;
+                }
+                Some(Self::DatabaseDescription(DatabaseDescription {
+                    mtu: u16::from_be_bytes(data[0..2].try_into().ok()?),
+                    options: data[2],
+                    flags: data[3],
+                    seq: u32::from_be_bytes(data[4..8].try_into().ok()?),
+                    headers: headers(&data[DD_LEN..])?,
+                }))
+            }
+            3 => {
+                if !data.len().is_multiple_of(REQUEST_LEN) {
+                    warn!("OSPF link state request: Invalid length {}", data.len());
+                    return None;
+                }
+                Some(Self::LinkStateRequest(
+                    data.chunks(REQUEST_LEN)
+                        .map(LsaKey::read_request)
+                        .collect::<Option<_>>()?,
+                ))
+            }
+            4 => {
+                let count = u32::from_be_bytes(data.get(0..4)?.try_into().ok()?);
+                let mut lsas = Vec::new();
+                let mut i = 4;
+                for _ in 0..count {
+                    let (lsa, len) = Lsa::read(data.get(i..)?)?;
+                    lsas.push(lsa);
+                    i += len;
+                }
+                Some(Self::LinkStateUpdate(lsas))
+            }
+            5 => {
+                if !data.len().is_multiple_of(lsa::HEADER_LEN) {
+                    warn!("OSPF link state ack: Invalid length {}", data.len());
+                    return None;
+                }
+                Some(Self::LinkStateAck(headers(data)?))
+            }
+            _ => {
+                warn!("OSPF packet: Unknown type {packet_type}");
+                None
+            }
+        }
+    }
+
+    fn write(&self, res: &mut Vec<u8>) {
+        match self {
+            Self::Hello(hello) => {
+                res.extend_from_slice(hello.mask.to_addr().as_slice());
+                res.extend_from_slice(&hello.hello_interval.to_be_bytes());
+                res.extend_from_slice(&[hello.options, hello.priority]);
+                res.extend_from_slice(&hello.dead_interval.to_be_bytes());
+                res.extend_from_slice(hello.designated.as_slice());
+                res.extend_from_slice(hello.backup.as_slice());
+                for neighbor in &hello.neighbors {
+                    res.extend_from_slice(neighbor.as_slice());
+                }
+            }
+            Self::DatabaseDescription(dd) => {
+                res.extend_from_slice(&dd.mtu.to_be_bytes());
+                res.extend_from_slice(&[dd.options, dd.flags]);
+                res.extend_from_slice(&dd.seq.to_be_bytes());
+                for header in &dd.headers {
+                    header.write(res);
+                }
+            }
+            Self::LinkStateRequest(keys) => {
+                for key in keys {
+                    key.write_request(res);
+                }
+            }
+            Self::LinkStateUpdate(lsas) => {
+                res.extend_from_slice(&(lsas.len() as u32).to_be_bytes());
+                for lsa in lsas {
+                    lsa.write(res);
+                }
+            }
+            Self::LinkStateAck(headers) => {
+                for header in headers {
+                    header.write(res);
+                }
+            }
+        }
+    }
+}
+
+/// OSPFv2 packet, only the null authentication is supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OspfPacket {
+    pub router_id: IpV4Addr,
+    pub area_id: IpV4Addr,
+    pub body: Body,
+}
+
+impl OspfPacket {
+    pub fn from_vec(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_LEN {
+            warn!("OSPF packet: Not enough data");
+            return None;
+        }
+        if data[0] != VERSION {
+            warn!("OSPF packet: Unsupported version {}", data[0]);
+            return None;
+        }
+        let len = u16::from_be_bytes(data[2..4].try_into().ok()?) as usize;
+        if len < HEADER_LEN || data.len() < len {
+            warn!("OSPF packet: Invalid length {len}");
+            return None;
+        }
+        let data = &data[..len];
+        let autype = u16::from_be_bytes(data[14..16].try_into().ok()?);
+        if autype != 0 {
+            warn!("OSPF packet: Unsupported authentication type {autype}");
+            return None;
+        }
+        if checksum(data) != 0 {
+            warn!("OSPF packet: Checksum error");
+            return None;
+        }
+        Some(Self {
+            router_id: IpV4Addr::new(data[4..8].try_into().ok()?),
+            area_id: IpV4Addr::new(data[8..12].try_into().ok()?),
+            body: Body::read(data[1], &data[HEADER_LEN..])?,
+        })
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut res = vec![VERSION, self.body.packet_type(), 0, 0];
+        res.extend_from_slice(self.router_id.as_slice());
+        res.extend_from_slice(self.area_id.as_slice());
+        res.extend_from_slice(&[0; 12]);
+        self.body.write(&mut res);
+        let len = res.len() as u16;
+        res[2..4].copy_from_slice(&len.to_be_bytes());
+        let checksum = checksum(&res);
+        res[12..14].copy_from_slice(&checksum.to_be_bytes());
+        res
+    }
+}
+
+/// Internet checksum of the packet, the authentication field is left out
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .enumerate()
+        .filter(|(i, _)| !(8..12).contains(i))
+        .map(|(_, word)| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,1175 @@
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{select, sync::RwLock, time::Instant};
+use tracing::{info, trace, warn};
+
+use crate::{
+    chassis::LinkLayerId,
+    network::ipv4::{
+        addr::{IpV4Addr, IpV4Mask, DEFAULT},
+        config::{IpV4Config, IpV4ConfigInner},
+    },
+    route::{RouteSource, RoutingEntry},
+    transport::ospf::{OspfHandle, OspfSocket},
+};
+
+use super::{
+    lsa::{LinkType, LsType, Lsa, LsaBody, LsaHeader, LsaKey, RouterLink, RouterLsa},
+    lsdb::Lsdb,
+    neighbor::{Neighbor, NeighborState},
+    packet::{Body, DatabaseDescription, Hello, OspfPacket, OPTIONS},
+    spf::{self, Path},
+    ALL_SPF_ROUTERS, BACKBONE, INITIAL_SEQUENCE_NUMBER, LS_INFINITY, LS_REFRESH_TIME, MAX_AGE,
+    MAX_SEQUENCE_NUMBER,
+};
+
+/// Interval at which the timers are checked
+const TICK: Duration = Duration::from_secs(1);
+/// A new instance of an LSA received sooner than this after the previous one is ignored
+const MIN_LS_ARRIVAL: Duration = Duration::from_secs(1);
+/// Most LSA headers in a database description, so that it fits in an ethernet frame
+const MAX_DD_HEADERS: usize = 64;
+const MAX_REQUESTS: usize = 64;
+const MAX_UPDATE_LSAS: usize = 16;
+
+/// Neighbors are identified by their router id on each interface
+type NeighborKey = (LinkLayerId, IpV4Addr);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OspfIface {
+    pub area: IpV4Addr,
+    /// Metric of the links through the interface
+    pub cost: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OspfRoute {
+    /// Area the route was calculated in
+    pub area: IpV4Addr,
+    /// Learned from a summary of an area border router
+    pub inter_area: bool,
+    pub path: Path,
+}
+
+impl OspfRoute {
+    fn entries(
+        &self,
+        network: IpV4Addr,
+        mask: IpV4Mask,
+    ) -> impl Iterator<Item = RoutingEntry<IpV4Addr, IpV4Mask, LinkLayerId>> + '_ {
+        self.path.next_hops.iter().map(move |&(gateway, iface)| {
+            RoutingEntry::new(network, gateway, mask, iface)
+                .with_source(RouteSource::Ospf)
+                .with_metric(self.path.cost)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OspfConfigInner {
+    /// Highest interface address if unset
+    pub router_id: Option<IpV4Addr>,
+    /// Interfaces OSPF runs on, every one behaves as a point-to-multipoint network: an adjacency
+    /// is formed with each neighbor and there's no designated router
+    pub ifaces: HashMap<LinkLayerId, OspfIface>,
+    pub hello_interval: u16,
+    pub dead_interval: u32,
+    pub retransmit_interval: u16,
+    pub neighbors: HashMap<NeighborKey, Neighbor>,
+    pub areas: BTreeMap<IpV4Addr, Lsdb>,
+    /// Result of the last routing table calculation, including the chassis' own networks
+    pub routes: HashMap<(IpV4Addr, IpV4Mask), OspfRoute>,
+}
+
+impl OspfConfigInner {
+    pub fn print_neighbors(&self) -> prettytable::Table {
+        let mut table = prettytable::table!([
+            "router id",
+            "address",
+            "interface",
+            "area",
+            "state",
+            "dead in"
+        ]);
+        if self.neighbors.is_empty() {
+            table.add_empty_row();
+        }
+        let mut neighbors = self.neighbors.values().collect::<Vec<_>>();
+        neighbors.sort_by_key(|neighbor| (neighbor.router_id, neighbor.addr));
+        let now = Instant::now();
+        for neighbor in neighbors {
+            table.add_row(prettytable::row![
+                neighbor.router_id,
+                neighbor.addr,
+                neighbor.iface,
+                neighbor.area,
+                neighbor.state,
+                format!(
+                    "{}s",
+                    neighbor.dead.saturating_duration_since(now).as_secs()
+                )
+            ]);
+        }
+        table
+    }
+
+    pub fn print_database(&self) -> prettytable::Table {
+        let mut table = prettytable::table!([
+            "area",
+            "type",
+            "link state id",
+            "advertising router",
+            "age",
+            "sequence",
+            "checksum",
+            "contents"
+        ]);
+        if self.areas.values().all(|lsdb| lsdb.iter().next().is_none()) {
+            table.add_empty_row();
+        }
+        for (area, lsdb) in &self.areas {
+            for (key, entry) in lsdb.iter() {
+                let contents = match &entry.lsa.body {
+                    LsaBody::Router(router) => format!("{} links", router.links.len()),
+                    LsaBody::Summary { mask, metric } => format!("{mask} metric {metric}"),
+                    LsaBody::Other(data) => format!("{} bytes", data.len()),
+                };
+                table.add_row(prettytable::row![
+                    area,
+                    key.ls_type,
+                    key.id,
+                    key.adv_router,
+                    entry.age(),
+                    format!("{:#010x}", entry.lsa.header.seq),
+                    format!("{:#06x}", entry.lsa.header.checksum),
+                    contents
+                ]);
+            }
+        }
+        table
+    }
+
+    /// Forgets the neighbors and the databases, and removes the learned routes from the routing
+    /// table
+    pub fn flush(&mut self, ip_config: &mut IpV4ConfigInner) {
+        for ((network, mask), route) in self.routes.drain() {
+            for entry in route.entries(network, mask) {
+                ip_config.routing.remove_route(&entry);
+            }
+        }
+        self.neighbors.clear();
+        self.areas.clear();
+    }
+
+    /// Neighbors of the area that take part in the flooding
+    fn flooding_neighbors(&mut self, area: IpV4Addr) -> impl Iterator<Item = &mut Neighbor> {
+        self.neighbors
+            .values_mut()
+            .filter(move |neighbor| neighbor.area == area && neighbor.floods())
+    }
+}
+
+impl Default for OspfConfigInner {
+    fn default() -> Self {
+        Self {
+            router_id: None,
+            ifaces: Default::default(),
+            hello_interval: 10,
+            dead_interval: 40,
+            retransmit_interval: 5,
+            neighbors: Default::default(),
+            areas: Default::default(),
+            routes: Default::default(),
+        }
+    }
+}
+
+pub type OspfConfig = Arc<RwLock<OspfConfigInner>>;
+
+/// OSPFv2 router for the interfaces in the config, as described in RFC 2328.
+///
+/// The neighbors are discovered with hellos sent to AllSPFRouters, their databases are
+/// synchronized and the LSAs are flooded to every adjacent neighbor. The routes are calculated
+/// again whenever a database changes.
+pub struct OspfRouter {
+    config: OspfConfig,
+    ip_config: IpV4Config,
+    socket: OspfSocket,
+    router_id: IpV4Addr,
+}
+
+impl OspfRouter {
+    pub async fn new(
+        config: OspfConfig,
+        ip_config: IpV4Config,
+        handle: &OspfHandle,
+    ) -> Result<Self, ()> {
+        let router_id = match config.read().await.router_id {
+            Some(router_id) => router_id,
+            None => {
+                let ip_config = ip_config.read().await;
+                let Some(router_id) = ip_config
+                    .addrs
+                    .values()
+                    .flatten()
+                    .map(|iface_addr| iface_addr.addr)
+                    .max()
+                else {
+                    warn!("OSPF: No router id nor address to use as one");
+                    return Err(());
+                };
+                router_id
+            }
+        };
+        Ok(Self {
+            config,
+            ip_config,
+            socket: handle.get_socket().await?,
+            router_id,
+        })
+    }
+
+    pub async fn run(self) {
+        info!("OSPF router {} started", self.router_id);
+        let mut next_hello = Instant::now();
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            let packets = select! {
+                r = self.socket.recv() => match r {
+                    Ok((iface, source, data)) => match OspfPacket::from_vec(&data) {
+                        Some(packet) => {
+                            self.process(|process| process.on_packet(iface, source, packet))
+                                .await
+                        }
+                        None => Vec::new(),
+                    },
+                    Err(_) => break,
+                },
+                () = tokio::time::sleep_until(next_hello) => {
+                    let packets = self.process(|process| process.send_hellos()).await;
+                    let interval = self.config.read().await.hello_interval;
+                    next_hello = Instant::now() + Duration::from_secs(interval as u64);
+                    packets
+                }
+                _ = tick.tick() => self.process(|process| process.on_tick()).await,
+            };
+            for (iface, destination, packet) in packets {
+                trace!("OSPF sending {packet:?} to {destination} on {iface}");
+                self.socket.send(iface, destination, packet.to_vec()).await;
+            }
+        }
+        warn!("OSPF router stopped");
+    }
+
+    /// Runs the handler with the configs locked, then originates the LSAs and calculates the
+    /// routes again if needed. Returns the packets to send
+    async fn process(
+        &self,
+        handler: impl FnOnce(&mut Process<'_>),
+    ) -> Vec<(LinkLayerId, IpV4Addr, OspfPacket)> {
+        let mut config = self.config.write().await;
+        let mut ip_config = self.ip_config.write().await;
+        let mut process = Process {
+            router_id: self.router_id,
+            config: &mut config,
+            ip_config: &mut ip_config,
+            packets: Vec::new(),
+            changed: false,
+        };
+        handler(&mut process);
+        process.originate_router_lsas();
+        if process.changed {
+            process.update_routes();
+            process.originate_summaries();
+        }
+        process.packets
+    }
+}
+
+/// State of the router while handling an event
+struct Process<'a> {
+    router_id: IpV4Addr,
+    config: &'a mut OspfConfigInner,
+    ip_config: &'a mut IpV4ConfigInner,
+    /// (iface, destination, packet)
+    packets: Vec<(LinkLayerId, IpV4Addr, OspfPacket)>,
+    /// A database changed, the routes have to be calculated again
+    changed: bool,
+}
+
+impl Process<'_> {
+    fn send(&mut self, iface: LinkLayerId, destination: IpV4Addr, body: Body) {
+        if let Some(ospf_iface) = self.config.ifaces.get(&iface) {
+            self.packets.push((
+                iface,
+                destination,
+                OspfPacket {
+                    router_id: self.router_id,
+                    area_id: ospf_iface.area,
+                    body,
+                },
+            ));
+        }
+    }
+
+    fn send_updates(&mut self, iface: LinkLayerId, destination: IpV4Addr, lsas: Vec<Lsa>) {
+        for chunk in lsas.chunks(MAX_UPDATE_LSAS) {
+            self.send(iface, destination, Body::LinkStateUpdate(chunk.to_vec()));
+        }
+    }
+
+    fn retransmit_interval(&self) -> Duration {
+        Duration::from_secs(self.config.retransmit_interval as u64)
+    }
+
+    fn on_packet(&mut self, iface: LinkLayerId, source: IpV4Addr, packet: OspfPacket) {
+        trace!("OSPF received {packet:?} from {source} on {iface}");
+        let Some(ospf_iface) = self.config.ifaces.get(&iface).copied() else {
+            return;
+        };
+        if self.ip_config.iface_addrs(&iface).is_empty() || packet.router_id == self.router_id {
+            return;
+        }
+        if packet.area_id != ospf_iface.area {
+            warn!(
+                "OSPF: Packet from {source} on {iface} is for area {} instead of {}",
+                packet.area_id, ospf_iface.area
+            );
+            return;
+        }
+        let key = (iface, packet.router_id);
+        if let Body::Hello(hello) = packet.body {
+            self.on_hello(key, ospf_iface.area, source, hello);
+            return;
+        }
+        if !self.config.neighbors.contains_key(&key) {
+            trace!("OSPF: Packet from unknown neighbor {}", packet.router_id);
+            return;
+        }
+        match packet.body {
+            Body::Hello(_) => {}
+            Body::DatabaseDescription(dd) => self.on_database_description(key, dd),
+            Body::LinkStateRequest(keys) => self.on_request(key, keys),
+            Body::LinkStateUpdate(lsas) => self.on_update(key, lsas),
+            Body::LinkStateAck(headers) => self.on_ack(key, headers),
+        }
+    }
+
+    fn send_hellos(&mut self) {
+        let ifaces = self.config.ifaces.keys().copied().collect::<Vec<_>>();
+        for iface in ifaces {
+            self.send_hello(iface);
+        }
+    }
+
+    fn send_hello(&mut self, iface: LinkLayerId) {
+        let Some(iface_addr) = self.ip_config.iface_addrs(&iface).first().copied() else {
+            return;
+        };
+        let mut neighbors = self
+            .config
+            .neighbors
+            .values()
+            .filter(|neighbor| neighbor.iface == iface)
+            .map(|neighbor| neighbor.router_id)
+            .collect::<Vec<_>>();
+        neighbors.sort();
+        let hello = Hello {
+            mask: iface_addr.mask,
+            hello_interval: self.config.hello_interval,
+            options: OPTIONS,
+            // Never elected designated router
+            priority: 0,
+            dead_interval: self.config.dead_interval,
+            designated: DEFAULT,
+            backup: DEFAULT,
+            neighbors,
+        };
+        self.send(iface, ALL_SPF_ROUTERS, Body::Hello(hello));
+    }
+
+    /// Hello processing of RFC 2328 10.5
+    fn on_hello(&mut self, key: NeighborKey, area: IpV4Addr, source: IpV4Addr, hello: Hello) {
+        let (iface, router_id) = key;
+        if hello.hello_interval != self.config.hello_interval
+            || hello.dead_interval != self.config.dead_interval
+        {
+            warn!(
+                "OSPF: Timers of {router_id} on {iface} ({}s/{}s) don't match",
+                hello.hello_interval, hello.dead_interval
+            );
+            return;
+        }
+        let dead = Instant::now() + Duration::from_secs(self.config.dead_interval as u64);
+        let neighbor = self.config.neighbors.entry(key).or_insert_with(|| {
+            info!("OSPF: New neighbor {router_id} ({source}) on {iface}");
+            Neighbor::new(router_id, source, iface, area, dead)
+        });
+        neighbor.dead = dead;
+        neighbor.addr = source;
+        let new = neighbor.state == NeighborState::Down;
+        if new {
+            neighbor.state = NeighborState::Init;
+        }
+        if hello.neighbors.contains(&self.router_id) {
+            if neighbor.state == NeighborState::Init {
+                self.two_way(key);
+            }
+        } else if neighbor.state >= NeighborState::TwoWay {
+            info!("OSPF: Neighbor {router_id} on {iface} doesn't hear us anymore");
+            neighbor.state = NeighborState::Init;
+            neighbor.clear_lists();
+        }
+        // Answering right away saves a hello interval
+        if new {
+            self.send_hello(iface);
+        }
+    }
+
+    /// The communication is bidirectional, every neighbor becomes adjacent
+    fn two_way(&mut self, key: NeighborKey) {
+        info!("OSPF: Two-way communication with {} on {}", key.1, key.0);
+        self.exstart(key);
+    }
+
+    /// Starts negotiating the database exchange, claiming to be the master
+    fn exstart(&mut self, key: NeighborKey) {
+        let mtu = self.ip_config.get_mtu(&key.0);
+        let retransmit = Instant::now() + self.retransmit_interval();
+        let Some(neighbor) = self.config.neighbors.get_mut(&key) else {
+            return;
+        };
+        neighbor.clear_lists();
+        neighbor.state = NeighborState::ExStart;
+        neighbor.master = true;
+        neighbor.dd_seq = chrono::Local::now().timestamp() as u32;
+        let dd = DatabaseDescription {
+            mtu,
+            options: OPTIONS,
+            flags: DatabaseDescription::INIT
+                | DatabaseDescription::MORE
+                | DatabaseDescription::MASTER,
+            seq: neighbor.dd_seq,
+            headers: Vec::new(),
+        };
+        neighbor.last_sent = Some(dd.clone());
+        neighbor.retransmit = retransmit;
+        let addr = neighbor.addr;
+        self.send(key.0, addr, Body::DatabaseDescription(dd));
+    }
+
+    fn mismatch(&mut self, key: NeighborKey, reason: &str) {
+        warn!(
+            "OSPF: Restarting the database exchange with {} on {}: {reason}",
+            key.1, key.0
+        );
+        self.exstart(key);
+    }
+
+    /// Database description processing of RFC 2328 10.6
+    fn on_database_description(&mut self, key: NeighborKey, dd: DatabaseDescription) {
+        if dd.mtu > self.ip_config.get_mtu(&key.0) {
+            warn!(
+                "OSPF: MTU of {} on {} is too big ({})",
+                key.1, key.0, dd.mtu
+            );
+            return;
+        }
+        let Some(neighbor) = self.config.neighbors.get(&key) else {
+            return;
+        };
+        let duplicate = neighbor.last_received == Some((dd.flags, dd.seq));
+        match neighbor.state {
+            NeighborState::Down | NeighborState::TwoWay => {}
+            NeighborState::Init => {
+                self.two_way(key);
+                self.on_database_description(key, dd);
+            }
+            NeighborState::ExStart => {
+                let all_flags = DatabaseDescription::INIT
+                    | DatabaseDescription::MORE
+                    | DatabaseDescription::MASTER;
+                if dd.flags & all_flags == all_flags
+                    && dd.headers.is_empty()
+                    && key.1 > self.router_id
+                {
+                    let neighbor = self.config.neighbors.get_mut(&key).unwrap();
+                    neighbor.master = false;
+                    neighbor.dd_seq = dd.seq;
+                } else if !dd.has(DatabaseDescription::INIT)
+                    && !dd.has(DatabaseDescription::MASTER)
+                    && dd.seq == neighbor.dd_seq
+                    && key.1 < self.router_id
+                {
+                    self.config.neighbors.get_mut(&key).unwrap().master = true;
+                } else {
+                    return;
+                }
+                self.exchange(key);
+                self.accept_database_description(key, dd);
+            }
+            NeighborState::Exchange => {
+                if duplicate {
+                    if !neighbor.master {
+                        self.send_last_database_description(key);
+                    }
+                    return;
+                }
+                if dd.has(DatabaseDescription::MASTER) == neighbor.master {
+                    self.mismatch(key, "unexpected master bit");
+                    return;
+                }
+                if dd.has(DatabaseDescription::INIT) {
+                    self.mismatch(key, "unexpected init bit");
+                    return;
+                }
+                let expected = match neighbor.master {
+                    true => neighbor.dd_seq,
+                    false => neighbor.dd_seq.wrapping_add(1),
+                };
+                if dd.seq != expected {
+                    self.mismatch(key, "unexpected sequence number");
+                    return;
+                }
+                self.accept_database_description(key, dd);
+            }
+            NeighborState::Loading | NeighborState::Full => {
+                if !duplicate {
+                    self.mismatch(key, "database description after the exchange");
+                } else if !neighbor.master {
+                    self.send_last_database_description(key);
+                }
+            }
+        }
+    }
+
+    /// The negotiation is over, the database is described to the neighbor
+    fn exchange(&mut self, key: NeighborKey) {
+        let Some(neighbor) = self.config.neighbors.get_mut(&key) else {
+            return;
+        };
+        info!(
+            "OSPF: Exchanging databases with {} on {} as {}",
+            key.1,
+            key.0,
+            if neighbor.master { "master" } else { "slave" }
+        );
+        neighbor.state = NeighborState::Exchange;
+        neighbor.summary = self
+            .config
+            .areas
+            .get(&neighbor.area)
+            .into_iter()
+            .flat_map(Lsdb::iter)
+            .filter(|(_, entry)| entry.age() < MAX_AGE)
+            .map(|(_, entry)| entry.current().header)
+            .collect();
+    }
+
+    fn accept_database_description(&mut self, key: NeighborKey, dd: DatabaseDescription) {
+        let Some(neighbor) = self.config.neighbors.get_mut(&key) else {
+            return;
+        };
+        neighbor.last_received = Some((dd.flags, dd.seq));
+        let more = dd.has(DatabaseDescription::MORE);
+        let lsdb = self.config.areas.get(&neighbor.area);
+        for header in dd.headers {
+            let newer = lsdb
+                .and_then(|lsdb| lsdb.get(&header.key))
+                .is_none_or(|entry| header.compare(&entry.current().header) == Ordering::Greater);
+            if newer {
+                neighbor.requests.insert(header.key, header);
+            }
+        }
+        let described = neighbor
+            .last_sent
+            .as_ref()
+            .is_some_and(|sent| !sent.has(DatabaseDescription::MORE));
+        if neighbor.master {
+            neighbor.dd_seq = neighbor.dd_seq.wrapping_add(1);
+            if described && !more {
+                self.exchange_done(key);
+            } else {
+                self.send_next_database_description(key);
+            }
+        } else {
+            neighbor.dd_seq = dd.seq;
+            self.send_next_database_description(key);
+            let described = self.config.neighbors[&key]
+                .last_sent
+                .as_ref()
+                .is_some_and(|sent| !sent.has(DatabaseDescription::MORE));
+            if described && !more {
+                self.exchange_done(key);
+            }
+        }
+    }
+
+    fn send_next_database_description(&mut self, key: NeighborKey) {
+        let mtu = self.ip_config.get_mtu(&key.0);
+        let retransmit = Instant::now() + self.retransmit_interval();
+        let Some(neighbor) = self.config.neighbors.get_mut(&key) else {
+            return;
+        };
+        let headers = neighbor
+            .summary
+            .drain(..neighbor.summary.len().min(MAX_DD_HEADERS))
+            .collect();
+        let mut flags = 0;
+        if !neighbor.summary.is_empty() {
+            flags |= DatabaseDescription::MORE;
+        }
+        if neighbor.master {
+            flags |= DatabaseDescription::MASTER;
+        }
+        neighbor.last_sent = Some(DatabaseDescription {
+            mtu,
+            options: OPTIONS,
+            flags,
+            seq: neighbor.dd_seq,
+            headers,
+        });
+        neighbor.retransmit = retransmit;
+        self.send_last_database_description(key);
+    }
+
+    fn send_last_database_description(&mut self, key: NeighborKey) {
+        let Some(neighbor) = self.config.neighbors.get(&key) else {
+            return;
+        };
+        if let Some(dd) = neighbor.last_sent.clone() {
+            let addr = neighbor.addr;
+            self.send(key.0, addr, Body::DatabaseDescription(dd));
+        }
+    }
+
+    fn exchange_done(&mut self, key: NeighborKey) {
+        let Some(neighbor) = self.config.neighbors.get_mut(&key) else {
+            return;
+        };
+        if neighbor.requests.is_empty() {
+            self.full(key);
+        } else {
+            neighbor.state = NeighborState::Loading;
+            self.send_requests(key);
+        }
+    }
+
+    fn full(&mut self, key: NeighborKey) {
+        if let Some(neighbor) = self.config.neighbors.get_mut(&key) {
+            info!("OSPF: Adjacency with {} on {} is full", key.1, key.0);
+            neighbor.state = NeighborState::Full;
+        }
+    }
+
+    fn send_requests(&mut self, key: NeighborKey) {
+        let retransmit = Instant::now() + self.retransmit_interval();
+        let Some(neighbor) = self.config.neighbors.get_mut(&key) else {
+            return;
+        };
+        let keys = neighbor
+            .requests
+            .keys()
+            .take(MAX_REQUESTS)
+            .copied()
+            .collect();
+        neighbor.retransmit = retransmit;
+        let addr = neighbor.addr;
+        self.send(key.0, addr, Body::LinkStateRequest(keys));
+    }
+
+    fn on_request(&mut self, key: NeighborKey, keys: Vec<LsaKey>) {
+        let Some(neighbor) = self.config.neighbors.get(&key) else {
+            return;
+        };
+        if neighbor.state < NeighborState::Exchange {
+            return;
+        }
+        let addr = neighbor.addr;
+        let lsdb = self.config.areas.get(&neighbor.area);
+        let lsas = keys
+            .iter()
+            .map(|lsa_key| Some(lsdb?.get(lsa_key)?.to_send()))
+            .collect::<Option<Vec<_>>>();
+        match lsas {
+            Some(lsas) => self.send_updates(key.0, addr, lsas),
+            None => self.mismatch(key, "request for an unknown LSA"),
+        }
+    }
+
+    /// Link state update processing of RFC 2328 13
+    fn on_update(&mut self, key: NeighborKey, lsas: Vec<Lsa>) {
+        let Some(neighbor) = self.config.neighbors.get(&key) else {
+            return;
+        };
+        if neighbor.state < NeighborState::Exchange {
+            return;
+        }
+        let (area, addr) = (neighbor.area, neighbor.addr);
+        let mut acks = Vec::new();
+        for lsa in lsas {
+            let header = lsa.header;
+            let current = self
+                .config
+                .areas
+                .get(&area)
+                .and_then(|lsdb| lsdb.get(&header.key))
+                .map(|entry| (entry.current(), entry.installed()));
+            let exchanging = self.config.neighbors.values().any(|neighbor| {
+                matches!(
+                    neighbor.state,
+                    NeighborState::Exchange | NeighborState::Loading
+                )
+            });
+            if header.age == MAX_AGE && current.is_none() && !exchanging {
+                acks.push(header);
+                continue;
+            }
+            match current
+                .as_ref()
+                .map(|(current, _)| header.compare(&current.header))
+            {
+                None | Some(Ordering::Greater) => {
+                    if current.is_some_and(|(_, installed)| installed.elapsed() < MIN_LS_ARRIVAL) {
+                        continue;
+                    }
+                    self.flood(area, Some(key), lsa);
+                    acks.push(header);
+                }
+                Some(Ordering::Equal) => {
+                    let neighbor = self.config.neighbors.get_mut(&key).unwrap();
+                    // Implied acknowledgment
+                    if !neighbor.retransmissions.remove(&header.key) {
+                        acks.push(header);
+                    }
+                }
+                Some(Ordering::Less) => {
+                    let (current, _) = current.unwrap();
+                    if current.header.age == MAX_AGE && current.header.seq == MAX_SEQUENCE_NUMBER {
+                        continue;
+                    }
+                    let lsa = self.config.areas[&area].get(&header.key).unwrap().to_send();
+                    self.send_updates(key.0, addr, vec![lsa]);
+                }
+            }
+            let neighbor = self.config.neighbors.get_mut(&key).unwrap();
+            if let Some(requested) = neighbor.requests.get(&header.key) {
+                if header.compare(requested) != Ordering::Less {
+                    neighbor.requests.remove(&header.key);
+                }
+            }
+        }
+        if !acks.is_empty() {
+            self.send(key.0, addr, Body::LinkStateAck(acks));
+        }
+        let neighbor = &self.config.neighbors[&key];
+        if neighbor.state == NeighborState::Loading {
+            if neighbor.requests.is_empty() {
+                self.full(key);
+            } else {
+                self.send_requests(key);
+            }
+        }
+    }
+
+    fn on_ack(&mut self, key: NeighborKey, headers: Vec<LsaHeader>) {
+        let Some(neighbor) = self.config.neighbors.get_mut(&key) else {
+            return;
+        };
+        if neighbor.state < NeighborState::Exchange {
+            return;
+        }
+        let Some(lsdb) = self.config.areas.get(&neighbor.area) else {
+            return;
+        };
+        for header in headers {
+            let same = lsdb
+                .get(&header.key)
+                .is_some_and(|entry| header.compare(&entry.current().header) == Ordering::Equal);
+            if same {
+                neighbor.retransmissions.remove(&header.key);
+            }
+        }
+    }
+
+    /// Installs the LSA and sends it to every adjacent neighbor of the area but the one it came
+    /// from, until they acknowledge it
+    fn flood(&mut self, area: IpV4Addr, from: Option<NeighborKey>, lsa: Lsa) {
+        let Some(lsdb) = self.config.areas.get_mut(&area) else {
+            return;
+        };
+        let key = lsa.header.key;
+        lsdb.install(lsa);
+        let lsa = lsdb.get(&key).unwrap().to_send();
+        self.changed = true;
+        let mut ifaces = Vec::new();
+        for neighbor in self.config.flooding_neighbors(area) {
+            neighbor.retransmissions.remove(&key);
+            if from == Some((neighbor.iface, neighbor.router_id)) {
+                continue;
+            }
+            if let Some(requested) = neighbor.requests.get(&key) {
+                match lsa.header.compare(requested) {
+                    Ordering::Less => continue,
+                    Ordering::Equal => {
+                        neighbor.requests.remove(&key);
+                        continue;
+                    }
+                    Ordering::Greater => {
+                        neighbor.requests.remove(&key);
+                    }
+                }
+            }
+            neighbor.retransmissions.insert(key);
+            if !ifaces.contains(&neighbor.iface) {
+                ifaces.push(neighbor.iface);
+            }
+        }
+        for iface in ifaces {
+            self.send_updates(iface, ALL_SPF_ROUTERS, vec![lsa.clone()]);
+        }
+    }
+
+    /// Originates a new instance of the chassis' LSA if its contents changed or it needs to be
+    /// refreshed
+    fn originate(&mut self, area: IpV4Addr, key: LsaKey, body: LsaBody) {
+        let Some(lsdb) = self.config.areas.get(&area) else {
+            return;
+        };
+        let seq = match lsdb.get(&key) {
+            Some(entry) if entry.lsa.body == body && entry.age() < LS_REFRESH_TIME => return,
+            Some(entry) => entry
+                .lsa
+                .header
+                .seq
+                .checked_add(1)
+                .unwrap_or(INITIAL_SEQUENCE_NUMBER),
+            None => INITIAL_SEQUENCE_NUMBER,
+        };
+        trace!("OSPF: Originating {key:?} in area {area}");
+        self.flood(area, None, Lsa::new(key, OPTIONS, seq, body));
+    }
+
+    /// Removes the LSA from the routing domain by flooding it at max age
+    fn premature_age(&mut self, area: IpV4Addr, key: LsaKey) {
+        let Some(entry) = self.config.areas.get(&area).and_then(|lsdb| lsdb.get(&key)) else {
+            return;
+        };
+        let mut lsa = entry.lsa.clone();
+        lsa.header.age = MAX_AGE;
+        trace!("OSPF: Flushing {key:?} from area {area}");
+        self.flood(area, None, lsa);
+    }
+
+    fn is_border_router(&self) -> bool {
+        self.config.areas.len() > 1
+    }
+
+    fn originate_router_lsas(&mut self) {
+        let flags = match self.is_border_router() {
+            true => RouterLsa::BORDER,
+            false => 0,
+        };
+        let areas = self.config.areas.keys().copied().collect::<Vec<_>>();
+        for area in areas {
+            let mut links = Vec::new();
+            for (iface, ospf_iface) in &self.config.ifaces {
+                let addrs = self.ip_config.iface_addrs(iface);
+                let Some(primary) = addrs.first() else {
+                    continue;
+                };
+                if ospf_iface.area != area {
+                    continue;
+                }
+                for neighbor in self.config.neighbors.values() {
+                    if neighbor.iface == *iface && neighbor.state == NeighborState::Full {
+                        links.push(RouterLink {
+                            id: neighbor.router_id,
+                            data: primary.addr,
+                            link_type: LinkType::PointToPoint,
+                            metric: ospf_iface.cost,
+                        });
+                    }
+                }
+                for iface_addr in addrs {
+                    links.push(RouterLink {
+                        id: iface_addr.network(),
+                        data: iface_addr.mask.to_addr(),
+                        link_type: LinkType::Stub,
+                        metric: ospf_iface.cost,
+                    });
+                }
+            }
+            links.sort_by_key(|link| (link.link_type as u8, link.id, link.data));
+            links.dedup();
+            let key = LsaKey::new(LsType::Router, self.router_id, self.router_id);
+            self.originate(area, key, LsaBody::Router(RouterLsa { flags, links }));
+        }
+    }
+
+    /// Advertises the routes of every area into the others, withdraws the summaries of the routes
+    /// that are gone. Only the backbone's inter-area routes are advertised
+    fn originate_summaries(&mut self) {
+        let areas = self.config.areas.keys().copied().collect::<Vec<_>>();
+        for area in areas {
+            let mut summaries = HashMap::new();
+            if self.is_border_router() {
+                for (&(network, mask), route) in &self.config.routes {
+                    if route.area != area && route.path.cost < LS_INFINITY {
+                        summaries.entry(network).or_insert((mask, route.path.cost));
+                    }
+                }
+            }
+            let withdrawn = self.config.areas[&area]
+                .iter()
+                .filter(|(key, entry)| {
+                    key.ls_type == LsType::Summary
+                        && key.adv_router == self.router_id
+                        && entry.age() < MAX_AGE
+                        && !summaries.contains_key(&key.id)
+                })
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>();
+            for key in withdrawn {
+                self.premature_age(area, key);
+            }
+            for (network, (mask, metric)) in summaries {
+                let key = LsaKey::new(LsType::Summary, network, self.router_id);
+                self.originate(area, key, LsaBody::Summary { mask, metric });
+            }
+        }
+    }
+
+    /// Calculates the routes of every area and installs the changes in the routing table, as
+    /// described in RFC 2328 16.1 and 16.2
+    fn update_routes(&mut self) {
+        let mut routes = HashMap::<_, OspfRoute>::new();
+        let mut paths = HashMap::new();
+        for (&area, lsdb) in &self.config.areas {
+            let shortest = spf::shortest_paths(lsdb, self.router_id, |iface_addr, router_id| {
+                let iface = self.config.ifaces.iter().find_map(|(iface, ospf_iface)| {
+                    (ospf_iface.area == area && self.ip_config.has_addr(iface, iface_addr))
+                        .then_some(*iface)
+                })?;
+                let neighbor = self.config.neighbors.get(&(iface, router_id))?;
+                (neighbor.state == NeighborState::Full).then_some((neighbor.addr, iface))
+            });
+            for (&network, path) in &shortest.networks {
+                let route = OspfRoute {
+                    area,
+                    inter_area: false,
+                    path: path.clone(),
+                };
+                match routes.entry(network) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(route);
+                    }
+                    Entry::Occupied(mut entry) => entry.get_mut().path.merge(route.path),
+                }
+            }
+            paths.insert(area, shortest);
+        }
+        let border_router = self.is_border_router();
+        let mut inter_area = HashMap::<_, OspfRoute>::new();
+        for (&area, lsdb) in &self.config.areas {
+            // Border routers only use the summaries of the backbone
+            if border_router && area != BACKBONE {
+                continue;
+            }
+            for (key, entry) in lsdb.iter() {
+                let LsaBody::Summary { mask, metric } = entry.lsa.body else {
+                    continue;
+                };
+                if key.adv_router == self.router_id || entry.age() >= MAX_AGE {
+                    continue;
+                }
+                if metric >= LS_INFINITY {
+                    continue;
+                }
+                let network = (mask & key.id, mask);
+                if routes.contains_key(&network) {
+                    continue;
+                }
+                let Some(border) = paths[&area].routers.get(&key.adv_router) else {
+                    continue;
+                };
+                let route = OspfRoute {
+                    area,
+                    inter_area: true,
+                    path: Path::new(border.cost + metric, border.next_hops.clone()),
+                };
+                match inter_area.entry(network) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(route);
+                    }
+                    Entry::Occupied(mut entry) => entry.get_mut().path.merge(route.path),
+                }
+            }
+        }
+        routes.extend(inter_area);
+
+        let old = std::mem::take(&mut self.config.routes);
+        let old_entries = old
+            .iter()
+            .flat_map(|(&(network, mask), route)| route.entries(network, mask))
+            .collect::<Vec<_>>();
+        let new_entries = routes
+            .iter()
+            .flat_map(|(&(network, mask), route)| route.entries(network, mask))
+            .collect::<Vec<_>>();
+        for entry in old_entries.iter().filter(|e| !new_entries.contains(e)) {
+            trace!("OSPF: Removing route {entry:?}");
+            self.ip_config.routing.remove_route(entry);
+        }
+        for entry in new_entries.into_iter().filter(|e| !old_entries.contains(e)) {
+            trace!("OSPF: Adding route {entry:?}");
+            self.ip_config.routing.add_route(entry);
+        }
+        self.config.routes = routes;
+    }
+
+    fn on_tick(&mut self) {
+        let now = Instant::now();
+        self.sync_areas();
+
+        let dead = self
+            .config
+            .neighbors
+            .iter()
+            .filter(|(_, neighbor)| neighbor.dead <= now)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in dead {
+            warn!("OSPF: Neighbor {} on {} is dead", key.1, key.0);
+            self.config.neighbors.remove(&key);
+        }
+
+        let areas = self.config.areas.keys().copied().collect::<Vec<_>>();
+        for area in areas {
+            self.age_database(area);
+        }
+
+        let keys = self.config.neighbors.keys().copied().collect::<Vec<_>>();
+        for key in keys {
+            self.retransmit(key, now);
+        }
+    }
+
+    /// Keeps a database for every configured area and drops the neighbors of the interfaces that
+    /// aren't configured anymore
+    fn sync_areas(&mut self) {
+        let areas = self
+            .config
+            .ifaces
+            .values()
+            .map(|ospf_iface| ospf_iface.area)
+            .collect::<HashSet<_>>();
+        let before = self.config.areas.len();
+        self.config.areas.retain(|area, _| areas.contains(area));
+        for area in areas {
+            if let std::collections::btree_map::Entry::Vacant(entry) = self.config.areas.entry(area)
+            {
+                info!("OSPF: Joined area {area}");
+                entry.insert(Lsdb::default());
+                self.changed = true;
+            }
+        }
+        if self.config.areas.len() != before {
+            self.changed = true;
+        }
+        let ifaces = &self.config.ifaces;
+        self.config.neighbors.retain(|(iface, _), neighbor| {
+            ifaces
+                .get(iface)
+                .is_some_and(|ospf_iface| ospf_iface.area == neighbor.area)
+        });
+    }
+
+    /// Flushes the LSAs reaching the max age and removes the flushed ones once acknowledged,
+    /// then refreshes the chassis' summaries if needed
+    fn age_database(&mut self, area: IpV4Addr) {
+        let exchanging = self.config.neighbors.values().any(|neighbor| {
+            matches!(
+                neighbor.state,
+                NeighborState::Exchange | NeighborState::Loading
+            )
+        });
+        let lsdb = &self.config.areas[&area];
+        let mut expired = Vec::new();
+        let mut acknowledged = Vec::new();
+        let mut refresh = false;
+        for (key, entry) in lsdb.iter() {
+            if entry.age() >= MAX_AGE && !entry.flushed {
+                expired.push(*key);
+            } else if entry.flushed
+                && !exchanging
+                && !self
+                    .config
+                    .neighbors
+                    .values()
+                    .any(|neighbor| neighbor.retransmissions.contains(key))
+            {
+                acknowledged.push(*key);
+            }
+            if key.adv_router == self.router_id
+                && key.ls_type == LsType::Summary
+                && entry.age() >= LS_REFRESH_TIME
+                && !entry.flushed
+            {
+                refresh = true;
+            }
+        }
+        for key in expired {
+            trace!("OSPF: {key:?} reached max age");
+            self.premature_age(area, key);
+        }
+        if let Some(lsdb) = self.config.areas.get_mut(&area) {
+            for key in acknowledged {
+                lsdb.remove(&key);
+            }
+        }
+        self.changed |= refresh;
+    }
+
+    /// Sends the unanswered database description, the requests and the unacknowledged LSAs again
+    fn retransmit(&mut self, key: NeighborKey, now: Instant) {
+        let retransmit_interval = self.retransmit_interval();
+        let Some(neighbor) = self.config.neighbors.get_mut(&key) else {
+            return;
+        };
+        if neighbor.retransmit > now {
+            return;
+        }
+        neighbor.retransmit = now + retransmit_interval;
+        let (state, master, addr, area) = (
+            neighbor.state,
+            neighbor.master,
+            neighbor.addr,
+            neighbor.area,
+        );
+        match state {
+            NeighborState::ExStart => self.send_last_database_description(key),
+            NeighborState::Exchange if master => self.send_last_database_description(key),
+            NeighborState::Loading => self.send_requests(key),
+            _ => {}
+        }
+        let neighbor = &self.config.neighbors[&key];
+        if neighbor.retransmissions.is_empty() {
+            return;
+        }
+        let Some(lsdb) = self.config.areas.get(&area) else {
+            return;
+        };
+        let lsas = neighbor
+            .retransmissions
+            .iter()
+            .filter_map(|lsa_key| lsdb.get(lsa_key).map(|entry| entry.to_send()))
+            .collect();
+        self.send_updates(key.0, addr, lsas);
+    }
+}
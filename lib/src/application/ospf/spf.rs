@@ -0,0 +1,142 @@
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, BinaryHeap, HashMap, HashSet},
+};
+
+use crate::{
+    chassis::LinkLayerId,
+    network::ipv4::addr::{IpV4Addr, IpV4Mask},
+};
+
+use super::{
+    lsa::{LinkType, LsType, LsaBody, LsaKey, RouterLsa},
+    lsdb::Lsdb,
+    MAX_AGE,
+};
+
+/// (gateway, iface)
+pub type NextHop = (IpV4Addr, LinkLayerId);
+
+/// Shortest path to a destination, without next hops for the root's own networks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    pub cost: u32,
+    pub next_hops: Vec<NextHop>,
+}
+
+impl Path {
+    pub const fn new(cost: u32, next_hops: Vec<NextHop>) -> Self {
+        Self { cost, next_hops }
+    }
+
+    /// Keeps the cheapest path, or all the next hops if they cost the same
+    pub fn merge(&mut self, other: Self) {
+        if other.cost < self.cost {
+            *self = other;
+        } else if other.cost == self.cost {
+            for next_hop in other.next_hops {
+                if !self.next_hops.contains(&next_hop) {
+                    self.next_hops.push(next_hop);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShortestPaths {
+    pub routers: HashMap<IpV4Addr, Path>,
+    pub networks: HashMap<(IpV4Addr, IpV4Mask), Path>,
+}
+
+pub fn router_lsa(lsdb: &Lsdb, router_id: IpV4Addr) -> Option<&RouterLsa> {
+    let entry = lsdb.get(&LsaKey::new(LsType::Router, router_id, router_id))?;
+    match &entry.lsa.body {
+        LsaBody::Router(lsa) if entry.age() < MAX_AGE => Some(lsa),
+        _ => None,
+    }
+}
+
+/// Dijkstra's shortest path tree of the area rooted at the router as described in RFC 2328 16.1,
+/// only point-to-point and stub links are followed.
+///
+/// `next_hop` gives the neighbor's address and interface from one of the root's point-to-point
+/// links, as (interface address, neighbor router id).
+pub fn shortest_paths(
+    lsdb: &Lsdb,
+    root: IpV4Addr,
+    next_hop: impl Fn(IpV4Addr, IpV4Addr) -> Option<NextHop>,
+) -> ShortestPaths {
+    let mut routers = HashMap::from([(root, Path::new(0, Vec::new()))]);
+    let mut done = HashSet::new();
+    let mut candidates = BinaryHeap::from([Reverse((0, root))]);
+    while let Some(Reverse((cost, router_id))) = candidates.pop() {
+        if !done.insert(router_id) {
+            continue;
+        }
+        let Some(lsa) = router_lsa(lsdb, router_id) else {
+            continue;
+        };
+        let next_hops = routers[&router_id].next_hops.clone();
+        for link in &lsa.links {
+            if link.link_type != LinkType::PointToPoint || done.contains(&link.id) {
+                continue;
+            }
+            // The link is only used if the neighbor has one back
+            let back = router_lsa(lsdb, link.id).is_some_and(|neighbor| {
+                neighbor
+                    .links
+                    .iter()
+                    .any(|back| back.link_type == LinkType::PointToPoint && back.id == router_id)
+            });
+            if !back {
+                continue;
+            }
+            let next_hops = if router_id == root {
+                next_hop(link.data, link.id).into_iter().collect()
+            } else {
+                next_hops.clone()
+            };
+            if next_hops.is_empty() {
+                continue;
+            }
+            let path = Path::new(cost + link.metric as u32, next_hops);
+            match routers.entry(link.id) {
+                Entry::Vacant(entry) => {
+                    candidates.push(Reverse((path.cost, link.id)));
+                    entry.insert(path);
+                }
+                Entry::Occupied(mut entry) => {
+                    if path.cost < entry.get().cost {
+                        candidates.push(Reverse((path.cost, link.id)));
+                    }
+                    entry.get_mut().merge(path);
+                }
+            }
+        }
+    }
+    let mut networks = HashMap::<_, Path>::new();
+    for (&router_id, router_path) in &routers {
+        let Some(lsa) = router_lsa(lsdb, router_id) else {
+            continue;
+        };
+        for link in lsa
+            .links
+            .iter()
+            .filter(|link| link.link_type == LinkType::Stub)
+        {
+            let mask = IpV4Mask::from_addr(link.data);
+            let path = Path::new(
+                router_path.cost + link.metric as u32,
+                router_path.next_hops.clone(),
+            );
+            match networks.entry((mask & link.id, mask)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(path);
+                }
+                Entry::Occupied(mut entry) => entry.get_mut().merge(path),
+            }
+        }
+    }
+    ShortestPaths { routers, networks }
+}
@@ -0,0 +1,6 @@
+pub mod packet;
+pub mod router;
+
+pub const PORT: u16 = 520;
+/// Metric of unreachable routes
+pub const INFINITY: u32 = 16;
@@ -0,0 +1,170 @@
+use tracing::warn;
+
+use crate::network::ipv4::addr::{IpV4Addr, IpV4Mask, DEFAULT};
+
+use super::INFINITY;
+
+const VERSION: u8 = 2;
+/// Address family of IPv4 entries, whole table requests use 0
+const AFI_IP: u16 = 2;
+const ENTRY_LEN: usize = 20;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Request = 1,
+    Response = 2,
+}
+
+impl Command {
+    const fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            1 => Some(Self::Request),
+            2 => Some(Self::Response),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RipEntry {
+    pub afi: u16,
+    pub tag: u16,
+    pub addr: IpV4Addr,
+    pub mask: IpV4Mask,
+    /// Router to send the packets to, unspecified for the one that sent the entry
+    pub next_hop: IpV4Addr,
+    pub metric: u32,
+}
+
+impl RipEntry {
+    pub const fn new(addr: IpV4Addr, mask: IpV4Mask, next_hop: IpV4Addr, metric: u32) -> Self {
+        Self {
+            afi: AFI_IP,
+            tag: 0,
+            addr,
+            mask,
+            next_hop,
+            metric,
+        }
+    }
+
+    fn read(data: &[u8]) -> Option<Self> {
+        let addr = |i: usize| Some(IpV4Addr::new(data.get(i..i + 4)?.try_into().ok()?));
+        Some(Self {
+            afi: u16::from_be_bytes(data.get(0..2)?.try_into().ok()?),
+            tag: u16::from_be_bytes(data.get(2..4)?.try_into().ok()?),
+            addr: addr(4)?,
+            mask: IpV4Mask::from_addr(addr(8)?),
+            next_hop: addr(12)?,
+            metric: u32::from_be_bytes(data.get(16..20)?.try_into().ok()?),
+        })
+    }
+
+    fn write(&self, res: &mut Vec<u8>) {
+        res.extend_from_slice(&self.afi.to_be_bytes());
+        res.extend_from_slice(&self.tag.to_be_bytes());
+        res.extend_from_slice(self.addr.as_slice());
+        res.extend_from_slice(self.mask.to_addr().as_slice());
+        res.extend_from_slice(self.next_hop.as_slice());
+        res.extend_from_slice(&self.metric.to_be_bytes());
+    }
+}
+
+/// RIPv2 message, authentication entries aren't supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RipPacket {
+    pub command: Command,
+    pub entries: Vec<RipEntry>,
+}
+
+impl RipPacket {
+    /// Request for the neighbor's whole table
+    pub fn whole_table_request() -> Self {
+        Self {
+            command: Command::Request,
+            entries: vec![RipEntry {
+                afi: 0,
+                ..RipEntry::new(DEFAULT, IpV4Mask::new(0), DEFAULT, INFINITY)
+            }],
+        }
+    }
+
+    pub fn is_whole_table_request(&self) -> bool {
+        self.command == Command::Request
+            && matches!(self.entries[..], [entry] if entry.afi == 0 && entry.metric == INFINITY)
+    }
+
+    pub fn from_vec(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            warn!("RIP packet: Not enough data");
+            return None;
+        }
+        let Some(command) = Command::from_u8(data[0]) else {
+            warn!("RIP packet: Unknown command {}", data[0]);
+            return None;
+        };
+        if data[1] != VERSION {
+            warn!("RIP packet: Unsupported version {}", data[1]);
+            return None;
+        }
+        if !(data.len() - 4).is_multiple_of(ENTRY_LEN) {
+            warn!("RIP packet: Invalid length {}", data.len());
+            return None;
+        }
+        let entries = data[4..]
+            .chunks(ENTRY_LEN)
+            .map(RipEntry::read)
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { command, entries })
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(4 + self.entries.len() * ENTRY_LEN);
+        res.extend_from_slice(&[self.command as u8, VERSION, 0, 0]);
+        for entry in &self.entries {
+            entry.write(&mut res);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        corruption::corruptions,
+        network::ipv4::addr::{IpV4Addr, IpV4Mask, DEFAULT},
+    };
+
+    use super::{Command, RipEntry, RipPacket};
+
+    #[test]
+    fn decodes_whole_table_request() {
+        let data = RipPacket::whole_table_request().to_vec();
+        assert!(RipPacket::from_vec(&data).unwrap().is_whole_table_request());
+    }
+
+    #[test]
+    fn survives_corrupted_packets() {
+        let packet = RipPacket {
+            command: Command::Response,
+            entries: vec![
+                RipEntry::new(IpV4Addr::new([10, 0, 0, 0]), IpV4Mask::new(8), DEFAULT, 1),
+                RipEntry::new(
+                    IpV4Addr::new([192, 168, 1, 0]),
+                    IpV4Mask::new(24),
+                    IpV4Addr::new([10, 0, 0, 2]),
+                    15,
+                ),
+            ],
+        };
+        let data = packet.to_vec();
+        assert_eq!(RipPacket::from_vec(&data), Some(packet));
+        for data in corruptions(&data) {
+            if let Some(packet) = RipPacket::from_vec(&data) {
+                packet.is_whole_table_request();
+                packet.to_vec();
+            }
+        }
+    }
+}
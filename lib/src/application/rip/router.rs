@@ -0,0 +1,399 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use tokio::{select, sync::RwLock, time::Instant};
+use tracing::{info, trace, warn};
+
+use crate::{
+    chassis::LinkLayerId,
+    network::ipv4::{
+        addr::{IpV4Addr, IpV4Mask},
+        config::{IpV4Config, IpV4ConfigInner},
+    },
+    route::{RouteSource, RoutingEntry},
+    transport::udp::{Socket, UdpHandleGeneric},
+};
+
+use super::{
+    packet::{Command, RipEntry, RipPacket},
+    INFINITY, PORT,
+};
+
+/// Interval at which the route timers are checked
+const TICK: Duration = Duration::from_secs(1);
+/// Delay to gather changes in a single triggered update
+const TRIGGERED_DELAY: Duration = Duration::from_secs(1);
+const MAX_ENTRIES: usize = 25;
+
+/// Route learned from a neighbor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RipRoute {
+    pub gateway: IpV4Addr,
+    pub iface: LinkLayerId,
+    pub metric: u32,
+    /// Timeout of the route, or its deletion once it's unreachable
+    pub expires: DateTime<Local>,
+    /// Changed since the last update, it goes in the next triggered update
+    pub changed: bool,
+}
+
+impl RipRoute {
+    fn entry(
+        &self,
+        network: IpV4Addr,
+        mask: IpV4Mask,
+    ) -> RoutingEntry<IpV4Addr, IpV4Mask, LinkLayerId> {
+        RoutingEntry::new(network, self.gateway, mask, self.iface)
+            .with_source(RouteSource::Rip)
+            .with_metric(self.metric)
+    }
+
+    fn reachable(&self) -> bool {
+        self.metric < INFINITY
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RipConfigInner {
+    /// Interfaces the updates are sent and received on
+    pub ifaces: HashSet<LinkLayerId>,
+    /// Routes are advertised as unreachable back to the interface they were learned from,
+    /// instead of being left out
+    pub poisoned_reverse: bool,
+    pub update_interval: chrono::Duration,
+    /// Time a route is kept without hearing about it
+    pub timeout: chrono::Duration,
+    /// Time an unreachable route is advertised before it's deleted
+    pub garbage_collection: chrono::Duration,
+    pub routes: HashMap<(IpV4Addr, IpV4Mask), RipRoute>,
+}
+
+impl RipConfigInner {
+    pub fn print_routes(&self) -> prettytable::Table {
+        let mut table = prettytable::table!([
+            "network",
+            "mask",
+            "gateway",
+            "interface",
+            "metric",
+            "expires"
+        ]);
+        if self.routes.is_empty() {
+            table.add_empty_row();
+        }
+        let mut routes = self.routes.iter().collect::<Vec<_>>();
+        routes.sort_by_key(|((network, mask), _)| (*network, *mask));
+        for ((network, mask), route) in routes {
+            table.add_row(prettytable::row![
+                network,
+                mask,
+                route.gateway,
+                route.iface,
+                route.metric,
+                route.expires.format("%T")
+            ]);
+        }
+        table
+    }
+
+    /// Forgets the learned routes and removes them from the routing table
+    pub fn flush(&mut self, ip_config: &mut IpV4ConfigInner) {
+        for ((network, mask), route) in self.routes.drain() {
+            if route.reachable() {
+                ip_config.routing.remove_route(&route.entry(network, mask));
+            }
+        }
+    }
+}
+
+impl Default for RipConfigInner {
+    fn default() -> Self {
+        Self {
+            ifaces: Default::default(),
+            poisoned_reverse: true,
+            update_interval: chrono::Duration::seconds(30),
+            timeout: chrono::Duration::seconds(180),
+            garbage_collection: chrono::Duration::seconds(120),
+            routes: Default::default(),
+        }
+    }
+}
+
+pub type RipConfig = Arc<RwLock<RipConfigInner>>;
+
+/// RIPv2 router for the interfaces in the config.
+///
+/// Updates are sent as limited broadcasts, which don't tell the receiver the sender's address, so
+/// every entry carries the sending interface's address as next hop.
+pub struct RipRouter {
+    config: RipConfig,
+    ip_config: IpV4Config,
+    socket: Socket<LinkLayerId>,
+}
+
+impl RipRouter {
+    pub async fn new(
+        config: RipConfig,
+        ip_config: IpV4Config,
+        udp_broadcast: &UdpHandleGeneric<LinkLayerId>,
+    ) -> Result<Self, ()> {
+        Ok(Self {
+            config,
+            ip_config,
+            socket: udp_broadcast.get_socket(PORT).await?,
+        })
+    }
+
+    pub async fn run(self) {
+        info!("RIP router started");
+        let ifaces = self.config.read().await.ifaces.clone();
+        for iface in ifaces {
+            self.socket
+                .send((iface, PORT), RipPacket::whole_table_request().to_vec())
+                .await;
+        }
+        let mut next_update = Instant::now();
+        let mut triggered = None;
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            let changed = select! {
+                r = self.socket.recv() => match r {
+                    Ok((iface, _, data, _)) => match RipPacket::from_vec(&data) {
+                        Some(packet) => self.on_packet(iface, packet).await,
+                        None => false,
+                    },
+                    Err(_) => break,
+                },
+                () = tokio::time::sleep_until(next_update) => {
+                    self.send_updates(true).await;
+                    let interval = self.config.read().await.update_interval;
+                    next_update = Instant::now() + interval.to_std().unwrap_or_default();
+                    triggered = None;
+                    false
+                }
+                () = tokio::time::sleep_until(triggered.unwrap_or(next_update)), if triggered.is_some() => {
+                    self.send_updates(false).await;
+                    triggered = None;
+                    false
+                }
+                _ = tick.tick() => self.expire().await,
+            };
+            if changed && triggered.is_none() {
+                triggered = Some(Instant::now() + TRIGGERED_DELAY);
+            }
+        }
+        warn!("RIP router stopped");
+    }
+
+    /// Handles a neighbor's message, returns whether any route changed
+    async fn on_packet(&self, iface: LinkLayerId, packet: RipPacket) -> bool {
+        trace!("RIP received {packet:?} from {iface}");
+        if !self.config.read().await.ifaces.contains(&iface) {
+            return false;
+        }
+        match packet.command {
+            Command::Request if packet.is_whole_table_request() => {
+                self.send_update(iface, true).await;
+                false
+            }
+            Command::Request => {
+                trace!("RIP: Ignoring request for specific routes from {iface}");
+                false
+            }
+            Command::Response => {
+                let mut changed = false;
+                for entry in packet.entries {
+                    changed |= self.on_entry(iface, entry).await;
+                }
+                changed
+            }
+        }
+    }
+
+    /// Updates the route with the neighbor's entry as described in RFC 2453 3.9.2
+    async fn on_entry(&self, iface: LinkLayerId, entry: RipEntry) -> bool {
+        if entry.afi != 2 || !(1..=INFINITY).contains(&entry.metric) {
+            return false;
+        }
+        let network = entry.mask & entry.addr;
+        let mut config = self.config.write().await;
+        let mut ip_config = self.ip_config.write().await;
+        let on_link = ip_config
+            .iface_addrs(&iface)
+            .iter()
+            .any(|iface_addr| iface_addr.contains(entry.next_hop));
+        if !on_link || ip_config.is_local(entry.next_hop) {
+            trace!("RIP: Next hop {} not on {iface}", entry.next_hop);
+            return false;
+        }
+        let connected = ip_config
+            .addrs
+            .values()
+            .flatten()
+            .any(|iface_addr| iface_addr.network() == network && iface_addr.mask == entry.mask);
+        if connected {
+            return false;
+        }
+        let metric = (entry.metric + 1).min(INFINITY);
+        let expires = Local::now() + config.timeout;
+        let garbage_collection = config.garbage_collection;
+        let new = RipRoute {
+            gateway: entry.next_hop,
+            iface,
+            metric,
+            expires,
+            changed: true,
+        };
+        match config.routes.get_mut(&(network, entry.mask)) {
+            None if metric < INFINITY => {
+                info!(
+                    "RIP: Learned {network} {} through {} (metric {metric})",
+                    entry.mask, entry.next_hop
+                );
+                ip_config.routing.add_route(new.entry(network, entry.mask));
+                config.routes.insert((network, entry.mask), new);
+                true
+            }
+            None => false,
+            Some(route) if route.gateway == new.gateway && route.iface == iface => {
+                if route.metric == metric {
+                    if route.reachable() {
+                        route.expires = expires;
+                    }
+                    return false;
+                }
+                if route.reachable() {
+                    ip_config
+                        .routing
+                        .remove_route(&route.entry(network, entry.mask));
+                }
+                *route = new;
+                if route.reachable() {
+                    ip_config
+                        .routing
+                        .add_route(route.entry(network, entry.mask));
+                } else {
+                    info!("RIP: {network} {} is unreachable", entry.mask);
+                    route.expires = Local::now() + garbage_collection;
+                }
+                true
+            }
+            Some(route) if metric < route.metric => {
+                info!(
+                    "RIP: Better route to {network} {} through {} (metric {metric})",
+                    entry.mask, entry.next_hop
+                );
+                if route.reachable() {
+                    ip_config
+                        .routing
+                        .remove_route(&route.entry(network, entry.mask));
+                }
+                *route = new;
+                ip_config
+                    .routing
+                    .add_route(route.entry(network, entry.mask));
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    /// Marks the routes not heard about as unreachable and deletes the old unreachable ones,
+    /// returns whether any route became unreachable
+    async fn expire(&self) -> bool {
+        let now = Local::now();
+        let mut config = self.config.write().await;
+        let garbage_collection = config.garbage_collection;
+        let mut changed = false;
+        let mut ip_config = self.ip_config.write().await;
+        config.routes.retain(|&(network, mask), route| {
+            if route.expires > now {
+                return true;
+            }
+            if !route.reachable() {
+                trace!("RIP: Deleted {network} {mask}");
+                return false;
+            }
+            warn!(
+                "RIP: Route to {network} {mask} through {} timed out",
+                route.gateway
+            );
+            ip_config.routing.remove_route(&route.entry(network, mask));
+            route.metric = INFINITY;
+            route.expires = now + garbage_collection;
+            route.changed = true;
+            changed = true;
+            true
+        });
+        changed
+    }
+
+    /// Sends an update on every interface, only the changed routes unless it's `full`
+    async fn send_updates(&self, full: bool) {
+        let ifaces = self.config.read().await.ifaces.clone();
+        for iface in ifaces {
+            self.send_update(iface, full).await;
+        }
+        for route in self.config.write().await.routes.values_mut() {
+            route.changed = false;
+        }
+    }
+
+    async fn send_update(&self, iface: LinkLayerId, full: bool) {
+        let entries = {
+            let config = self.config.read().await;
+            let ip_config = self.ip_config.read().await;
+            let Some(own) = ip_config
+                .iface_addrs(&iface)
+                .first()
+                .map(|iface_addr| iface_addr.addr)
+            else {
+                return;
+            };
+            // The chassis' own routes are only sent in full updates since they rarely change
+            let mut entries = if full {
+                ip_config
+                    .routing
+                    .iter()
+                    .filter(|route| {
+                        matches!(route.source(), RouteSource::Connected | RouteSource::Static)
+                    })
+                    .map(|route| {
+                        RipEntry::new(*route.mask() & *route.destination(), *route.mask(), own, 1)
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                Vec::new()
+            };
+            let local = entries
+                .iter()
+                .map(|entry| (entry.addr, entry.mask))
+                .collect::<HashSet<_>>();
+            for (&(network, mask), route) in &config.routes {
+                if (!full && !route.changed) || local.contains(&(network, mask)) {
+                    continue;
+                }
+                // Split horizon
+                let metric = match (route.iface == iface, config.poisoned_reverse) {
+                    (true, true) => INFINITY,
+                    (true, false) => continue,
+                    (false, _) => route.metric,
+                };
+                entries.push(RipEntry::new(network, mask, own, metric));
+            }
+            entries
+        };
+        for chunk in entries.chunks(MAX_ENTRIES) {
+            let packet = RipPacket {
+                command: Command::Response,
+                entries: chunk.to_vec(),
+            };
+            trace!("RIP sending {packet:?} on {iface}");
+            self.socket.send((iface, PORT), packet.to_vec()).await;
+        }
+    }
+}
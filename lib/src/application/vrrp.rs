@@ -0,0 +1,18 @@
+use crate::{mac::Mac, network::ipv4::addr::IpV4Addr};
+
+pub mod packet;
+pub mod router;
+
+/// Multicast group the advertisements are sent to
+pub const ALL_VRRP_ROUTERS: IpV4Addr = IpV4Addr::new([224, 0, 0, 18]);
+/// Priority of the router whose interface has the virtual addresses
+pub const OWNER_PRIORITY: u8 = 255;
+/// Advertised by a master that stops, the backups take over without waiting
+pub const RESIGN_PRIORITY: u8 = 0;
+/// Maximum advertisement interval in centiseconds, it's a 12 bit field
+pub const MAX_INTERVAL: u16 = 0xfff;
+
+/// Virtual MAC of the IPv4 virtual router
+pub const fn virtual_mac(vrid: u8) -> Mac {
+    Mac::new([0x00, 0x00, 0x5e, 0x00, 0x01, vrid])
+}
@@ -0,0 +1,68 @@
+use tracing::warn;
+
+use crate::network::ipv4::addr::IpV4Addr;
+
+use super::MAX_INTERVAL;
+
+const VERSION: u8 = 3;
+const ADVERTISEMENT: u8 = 1;
+const HEADER_LEN: usize = 8;
+
+/// VRRPv3 advertisement of an IPv4 virtual router
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advertisement {
+    pub vrid: u8,
+    pub priority: u8,
+    /// Centiseconds between the advertisements
+    pub interval: u16,
+    pub addrs: Vec<IpV4Addr>,
+}
+
+impl Advertisement {
+    pub fn from_vec(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_LEN {
+            warn!("VRRP packet: Not enough data");
+            return None;
+        }
+        if data[0] != (VERSION << 4 | ADVERTISEMENT) {
+            warn!(
+                "VRRP packet: Unsupported version {} or type {}",
+                data[0] >> 4,
+                data[0] & 0xf
+            );
+            return None;
+        }
+        let count = data[3] as usize;
+        if data.len() != HEADER_LEN + count * 4 {
+            warn!("VRRP packet: Invalid length {}", data.len());
+            return None;
+        }
+        let addrs = data[HEADER_LEN..]
+            .chunks(4)
+            .map(|addr| Some(IpV4Addr::new(addr.try_into().ok()?)))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            vrid: data[1],
+            priority: data[2],
+            interval: u16::from_be_bytes([data[4], data[5]]) & MAX_INTERVAL,
+            addrs,
+        })
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(HEADER_LEN + self.addrs.len() * 4);
+        res.extend_from_slice(&[
+            VERSION << 4 | ADVERTISEMENT,
+            self.vrid,
+            self.priority,
+            self.addrs.len() as u8,
+        ]);
+        res.extend_from_slice(&(self.interval & MAX_INTERVAL).to_be_bytes());
+        // Checksum
+        res.extend_from_slice(&[0, 0]);
+        for addr in &self.addrs {
+            res.extend_from_slice(addr.as_slice());
+        }
+        res
+    }
+}
@@ -0,0 +1,416 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc, time::Duration};
+
+use tokio::{select, sync::RwLock, time::Instant};
+use tracing::{info, trace, warn};
+
+use crate::{
+    chassis::{LinkLayerId, VirtualLink},
+    link::ethernet::{ethertype::EtherType, packet::EthernetPacket},
+    mac,
+    network::{
+        arp::packet::ArpPacket,
+        ipv4::{
+            addr::IpV4Addr,
+            config::{IpV4Config, IpV4ConfigInner},
+            packet::{Ecn, Flags, IpV4Header, Ipv4Packet},
+            protocol::ProtocolType,
+        },
+    },
+    transport::vrrp::{VrrpHandle, VrrpSocket, TTL},
+};
+
+use super::{
+    packet::Advertisement, virtual_mac, ALL_VRRP_ROUTERS, OWNER_PRIORITY, RESIGN_PRIORITY,
+};
+
+/// Longest wait between the checks of the timers, so the configuration changes are picked up
+const TICK: Duration = Duration::from_secs(1);
+
+const fn centiseconds(n: u16) -> Duration {
+    Duration::from_millis(n as u64 * 10)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Not started
+    Init,
+    /// Waits for the master to go silent
+    Backup,
+    /// Owns the virtual addresses and MAC, and advertises them
+    Master,
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Init => write!(f, "init"),
+            Self::Backup => write!(f, "backup"),
+            Self::Master => write!(f, "master"),
+        }
+    }
+}
+
+/// Virtual router of an interface, as described in RFC 5798
+#[derive(Debug, Clone)]
+pub struct VirtualRouter {
+    link: VirtualLink,
+    pub addrs: Vec<IpV4Addr>,
+    pub priority: u8,
+    /// Takes over from a master of lower priority
+    pub preempt: bool,
+    /// Centiseconds between the advertisements as master
+    pub interval: u16,
+    pub state: State,
+    /// Address of the master, while backup
+    pub master: Option<IpV4Addr>,
+    /// Interval advertised by the master, the backups take over after 3 of them in silence
+    master_interval: u16,
+    /// Next advertisement as master, or takeover as backup
+    timer: Instant,
+}
+
+impl VirtualRouter {
+    pub fn new(link: VirtualLink, addrs: Vec<IpV4Addr>) -> Self {
+        Self {
+            link,
+            addrs,
+            priority: 100,
+            preempt: true,
+            interval: 100,
+            state: State::Init,
+            master: None,
+            master_interval: 100,
+            timer: Instant::now(),
+        }
+    }
+
+    /// Lets the higher priority backups take over first
+    fn skew(&self) -> Duration {
+        centiseconds(((256 - self.priority as u32) * self.master_interval as u32 / 256) as u16)
+    }
+
+    fn master_down(&self) -> Duration {
+        centiseconds(self.master_interval) * 3 + self.skew()
+    }
+
+    /// Sends an advertisement from the primary address of the interface
+    async fn advertise(
+        &self,
+        iface: LinkLayerId,
+        vrid: u8,
+        priority: u8,
+        ip_config: &IpV4ConfigInner,
+    ) {
+        let Some(source) = ip_config.iface_addrs(&iface).first() else {
+            warn!("VRRP: {iface} has no address to advertise virtual router {vrid} from");
+            return;
+        };
+        let payload = Advertisement {
+            vrid,
+            priority,
+            interval: self.interval,
+            addrs: self.addrs.clone(),
+        }
+        .to_vec();
+        let packet = Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                Ecn::NotECT,
+                payload.len() as u16,
+                0,
+                Flags::empty(),
+                0,
+                TTL,
+                ProtocolType::VRRP,
+                ALL_VRRP_ROUTERS,
+                source.addr,
+                vec![],
+            ),
+            payload,
+        );
+        if let Some(frame) = EthernetPacket::new_ip_v4(
+            ALL_VRRP_ROUTERS.multicast_mac(),
+            virtual_mac(vrid),
+            packet.to_vec(),
+        ) {
+            self.link.send(frame).await;
+        }
+    }
+
+    async fn become_master(
+        &mut self,
+        iface: LinkLayerId,
+        vrid: u8,
+        ip_config: &mut IpV4ConfigInner,
+    ) {
+        info!("VRRP: {iface} is master of virtual router {vrid}");
+        let mac = virtual_mac(vrid);
+        self.link.add_mac(mac).await;
+        let virtual_addrs = ip_config.virtual_addrs.entry(iface).or_default();
+        virtual_addrs.retain(|(_, virtual_mac)| *virtual_mac != mac);
+        virtual_addrs.extend(self.addrs.iter().map(|addr| (*addr, mac)));
+        self.advertise(iface, vrid, self.priority, ip_config).await;
+        // The switches learn the port the virtual MAC is on now
+        for addr in &self.addrs {
+            let arp = ArpPacket::new_request(
+                1,
+                EtherType::IP_V4,
+                mac.as_slice().to_vec(),
+                addr.as_slice().to_vec(),
+                addr.as_slice().to_vec(),
+            );
+            if let Some(frame) = EthernetPacket::new_arp(mac::BROADCAST, mac, arp.to_vec()) {
+                self.link.send(frame).await;
+            }
+        }
+        self.state = State::Master;
+        self.master = None;
+        self.timer = Instant::now() + centiseconds(self.interval);
+    }
+
+    async fn become_backup(
+        &mut self,
+        iface: LinkLayerId,
+        vrid: u8,
+        master: Option<IpV4Addr>,
+        ip_config: &mut IpV4ConfigInner,
+    ) {
+        info!("VRRP: {iface} is backup of virtual router {vrid}");
+        self.release(iface, vrid, ip_config).await;
+        self.state = State::Backup;
+        self.master = master;
+        self.timer = Instant::now() + self.master_down();
+    }
+
+    /// Gives up the virtual addresses and MAC
+    async fn release(&self, iface: LinkLayerId, vrid: u8, ip_config: &mut IpV4ConfigInner) {
+        let mac = virtual_mac(vrid);
+        self.link.remove_mac(mac).await;
+        if let Some(virtual_addrs) = ip_config.virtual_addrs.get_mut(&iface) {
+            virtual_addrs.retain(|(_, virtual_mac)| *virtual_mac != mac);
+            if virtual_addrs.is_empty() {
+                ip_config.virtual_addrs.remove(&iface);
+            }
+        }
+    }
+
+    /// Stops the virtual router, a master tells the backups to take over right away
+    pub async fn shutdown(
+        &mut self,
+        iface: LinkLayerId,
+        vrid: u8,
+        ip_config: &mut IpV4ConfigInner,
+    ) {
+        if self.state == State::Master {
+            self.advertise(iface, vrid, RESIGN_PRIORITY, ip_config)
+                .await;
+            self.release(iface, vrid, ip_config).await;
+        }
+        self.state = State::Init;
+        self.master = None;
+        self.master_interval = self.interval;
+        self.timer = Instant::now();
+    }
+
+    async fn on_timer(&mut self, iface: LinkLayerId, vrid: u8, ip_config: &mut IpV4ConfigInner) {
+        match self.state {
+            State::Init if self.priority == OWNER_PRIORITY => {
+                self.become_master(iface, vrid, ip_config).await
+            }
+            State::Init => {
+                self.master_interval = self.interval;
+                self.become_backup(iface, vrid, None, ip_config).await
+            }
+            State::Backup => self.become_master(iface, vrid, ip_config).await,
+            State::Master => {
+                self.advertise(iface, vrid, self.priority, ip_config).await;
+                self.timer = Instant::now() + centiseconds(self.interval);
+            }
+        }
+    }
+
+    async fn on_advertisement(
+        &mut self,
+        iface: LinkLayerId,
+        source: IpV4Addr,
+        advertisement: Advertisement,
+        ip_config: &mut IpV4ConfigInner,
+    ) {
+        let vrid = advertisement.vrid;
+        if advertisement.addrs != self.addrs {
+            trace!("VRRP: Virtual router {vrid} of {source} on {iface} has other addresses");
+        }
+        match self.state {
+            State::Init => {}
+            State::Backup if advertisement.priority == RESIGN_PRIORITY => {
+                self.timer = Instant::now() + self.skew();
+            }
+            State::Backup => {
+                if !self.preempt || advertisement.priority >= self.priority {
+                    self.master = Some(source);
+                    self.master_interval = advertisement.interval;
+                    self.timer = Instant::now() + self.master_down();
+                }
+            }
+            State::Master if advertisement.priority == RESIGN_PRIORITY => {
+                self.advertise(iface, vrid, self.priority, ip_config).await;
+                self.timer = Instant::now() + centiseconds(self.interval);
+            }
+            State::Master => {
+                let primary = ip_config.iface_addrs(&iface).first().map(|addr| addr.addr);
+                if advertisement.priority > self.priority
+                    || (advertisement.priority == self.priority
+                        && primary.is_some_and(|primary| source > primary))
+                {
+                    self.master_interval = advertisement.interval;
+                    self.become_backup(iface, vrid, Some(source), ip_config)
+                        .await;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VrrpConfigInner {
+    /// Virtual routers by interface and id
+    pub routers: HashMap<(LinkLayerId, u8), VirtualRouter>,
+}
+
+impl VrrpConfigInner {
+    pub fn print(&self) -> prettytable::Table {
+        let mut table = prettytable::table!([
+            "interface",
+            "vrid",
+            "addresses",
+            "priority",
+            "preempt",
+            "interval",
+            "state",
+            "master"
+        ]);
+        if self.routers.is_empty() {
+            table.add_empty_row();
+        }
+        let mut routers = self.routers.iter().collect::<Vec<_>>();
+        routers.sort_by_key(|((iface, vrid), _)| (iface.to_string(), *vrid));
+        for ((iface, vrid), router) in routers {
+            let addrs = router
+                .addrs
+                .iter()
+                .map(IpV4Addr::to_string)
+                .collect::<Vec<_>>();
+            let master = match (router.state, router.master) {
+                (State::Master, _) => "local".to_string(),
+                (_, Some(master)) => master.to_string(),
+                (_, None) => "-".to_string(),
+            };
+            table.add_row(prettytable::row![
+                iface,
+                vrid,
+                addrs.join(", "),
+                router.priority,
+                router.preempt,
+                format!("{}cs", router.interval),
+                router.state,
+                master
+            ]);
+        }
+        table
+    }
+
+    /// Stops every virtual router, they start over once a router runs again
+    pub async fn shutdown(&mut self, ip_config: &mut IpV4ConfigInner) {
+        for ((iface, vrid), router) in &mut self.routers {
+            router.shutdown(*iface, *vrid, ip_config).await;
+        }
+    }
+}
+
+pub type VrrpConfig = Arc<RwLock<VrrpConfigInner>>;
+
+/// VRRPv3 router running the virtual routers of the config.
+///
+/// The masters answer ARP for the virtual addresses and forward the packets sent to the virtual
+/// MAC, but only the owner of the addresses accepts the packets to them.
+pub struct VrrpRouter {
+    config: VrrpConfig,
+    ip_config: IpV4Config,
+    socket: VrrpSocket,
+}
+
+impl VrrpRouter {
+    pub async fn new(
+        config: VrrpConfig,
+        ip_config: IpV4Config,
+        handle: &VrrpHandle,
+    ) -> Result<Self, ()> {
+        Ok(Self {
+            config,
+            ip_config,
+            socket: handle.get_socket().await?,
+        })
+    }
+
+    pub async fn run(self) {
+        info!("VRRP router started");
+        loop {
+            let next = self
+                .config
+                .read()
+                .await
+                .routers
+                .values()
+                .map(|router| router.timer)
+                .min()
+                .map_or(Instant::now() + TICK, |timer| {
+                    timer.min(Instant::now() + TICK)
+                });
+            select! {
+                r = self.socket.recv() => match r {
+                    Ok((iface, source, data)) => {
+                        if let Some(advertisement) = Advertisement::from_vec(&data) {
+                            self.on_advertisement(iface, source, advertisement).await;
+                        }
+                    }
+                    Err(_) => break,
+                },
+                () = tokio::time::sleep_until(next) => self.on_timers().await,
+            }
+        }
+        warn!("VRRP router stopped");
+    }
+
+    async fn on_advertisement(
+        &self,
+        iface: LinkLayerId,
+        source: IpV4Addr,
+        advertisement: Advertisement,
+    ) {
+        trace!("VRRP received {advertisement:?} from {source} on {iface}");
+        let mut config = self.config.write().await;
+        let mut ip_config = self.ip_config.write().await;
+        // Ours, back from the shared link
+        if ip_config.has_addr(&iface, source) {
+            return;
+        }
+        let Some(router) = config.routers.get_mut(&(iface, advertisement.vrid)) else {
+            trace!("VRRP: No virtual router {} on {iface}", advertisement.vrid);
+            return;
+        };
+        router
+            .on_advertisement(iface, source, advertisement, &mut ip_config)
+            .await;
+    }
+
+    async fn on_timers(&self) {
+        let mut config = self.config.write().await;
+        let mut ip_config = self.ip_config.write().await;
+        let now = Instant::now();
+        for ((iface, vrid), router) in &mut config.routers {
+            if router.timer <= now {
+                router.on_timer(*iface, *vrid, &mut ip_config).await;
+            }
+        }
+    }
+}
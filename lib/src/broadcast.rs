@@ -0,0 +1,132 @@
+//! Broadcast channel carrying the frames of a link, every receiver gets all the messages sent
+//! after it was created, none are lost
+//!
+//! Each receiver has its own queue, so a message only wakes the receivers it is queued for.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+/// All the senders have been dropped and the queue is empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+
+/// There are no receivers, the message is given back
+#[derive(Debug)]
+pub struct SendError<T>(pub T);
+
+struct Shared<T> {
+    queues: Mutex<Vec<flume::Sender<T>>>,
+    senders: AtomicUsize,
+}
+
+pub struct Sender<T>(Arc<Shared<T>>);
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::Relaxed);
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Disconnects the receivers once they have emptied their queue
+            self.0.queues.lock().unwrap().clear();
+        }
+    }
+}
+
+impl<T: Clone> Sender<T> {
+    pub async fn send_async(&self, item: T) -> Result<(), SendError<T>> {
+        let mut queues = self.0.queues.lock().unwrap();
+        queues.retain(|queue| !queue.is_disconnected());
+        if queues.is_empty() {
+            return Err(SendError(item));
+        }
+        for queue in queues.iter() {
+            // Unbounded and checked just above, it can't fail
+            let _ = queue.send(item.clone());
+        }
+        Ok(())
+    }
+}
+
+pub struct Receiver<T> {
+    queue: flume::Receiver<T>,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.shared.clone())
+    }
+}
+
+impl<T> Receiver<T> {
+    fn new(shared: Arc<Shared<T>>) -> Self {
+        let (tx, queue) = flume::unbounded();
+        let mut queues = shared.queues.lock().unwrap();
+        if shared.senders.load(Ordering::Acquire) > 0 {
+            queues.push(tx);
+        }
+        drop(queues);
+        Self { queue, shared }
+    }
+
+    pub async fn recv_async(&self) -> Result<T, Disconnected> {
+        self.queue.recv_async().await.map_err(|_| Disconnected)
+    }
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queues: Mutex::new(Vec::new()),
+        senders: AtomicUsize::new(1),
+    });
+    (Sender(shared.clone()), Receiver::new(shared))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, Disconnected};
+
+    #[tokio::test]
+    async fn receivers_get_every_message_sent_after_them() {
+        let (tx, rx) = channel();
+        tx.send_async(0).await.unwrap();
+        let late = rx.clone();
+        for i in 1..10_000 {
+            tx.send_async(i).await.unwrap();
+        }
+        for i in 0..10_000 {
+            assert_eq!(rx.recv_async().await, Ok(i));
+        }
+        for i in 1..10_000 {
+            assert_eq!(late.recv_async().await, Ok(i));
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnects_when_the_senders_are_dropped() {
+        let (tx, rx) = channel();
+        let other = tx.clone();
+        tx.send_async(1).await.unwrap();
+        drop(tx);
+        other.send_async(2).await.unwrap();
+        drop(other);
+        assert_eq!(rx.recv_async().await, Ok(1));
+        assert_eq!(rx.recv_async().await, Ok(2));
+        assert_eq!(rx.recv_async().await, Err(Disconnected));
+        assert_eq!(rx.clone().recv_async().await, Err(Disconnected));
+    }
+
+    #[tokio::test]
+    async fn gives_back_messages_without_receivers() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(tx.send_async(1).await.map_err(|e| e.0), Err(1));
+    }
+}
@@ -1,19 +1,40 @@
-use std::{collections::HashMap, fmt::Display, hash::Hash, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    hash::Hash,
+    sync::Arc,
+    time::Instant,
+};
 
 use async_trait::async_trait;
 use derivative::Derivative;
 use either::Either;
 use flume::{Receiver, RecvError, Sender};
-use tokio::task::{JoinHandle, JoinSet};
+use tokio::{
+    select,
+    sync::RwLock,
+    task::{JoinHandle, JoinSet},
+};
 use tracing::{trace, warn};
 
 pub mod switch;
 
 use crate::{
+    broadcast,
     either::ThreeWayEither,
-    link::ethernet::{ethertype::EtherType, nic::Nic, packet::EthernetPacket},
+    link::{
+        capture::{Tap, Taps},
+        ethernet::{
+            dot1q::Tag,
+            ethertype::EtherType,
+            lacp::{Lacp, Lacpdu, PERIODIC_TIME, SLOW_PROTOCOLS_ADDR},
+            nic::Nic,
+            packet::EthernetPacket,
+        },
+        impairment::{Impairments, LinkSender},
+    },
     mac::Mac,
-    network::ipv4::addr::IpV4Addr,
+    network::{ipv4::addr::IpV4Addr, ipv6::addr::IpV6Addr, ndp::packet::is_ndp_packet},
 };
 
 #[derive(Debug, Clone, Copy, Eq, Derivative)]
@@ -25,12 +46,42 @@ pub enum LinkLayerId {
         #[derivative(Hash = "ignore")]
         Mac,
     ),
+    /// 802.1Q sub-interface of an Ethernet NIC, (NIC id, VLAN id, NIC address)
+    Dot1q(
+        u16,
+        u16,
+        #[derivative(PartialEq = "ignore")]
+        #[derivative(Hash = "ignore")]
+        Mac,
+    ),
+    /// Link aggregation of Ethernet NICs, (bond id, bond address)
+    Bond(
+        u16,
+        #[derivative(PartialEq = "ignore")]
+        #[derivative(Hash = "ignore")]
+        Mac,
+    ),
+    /// GRE or IP in IP tunnel, the IPv4 process encapsulates its packets
+    Tunnel(u16),
+}
+
+impl LinkLayerId {
+    /// Tunnels have no link layer address, they use the unspecified one
+    pub const fn mac(&self) -> Mac {
+        match self {
+            Self::Ethernet(_, mac) | Self::Dot1q(_, _, mac) | Self::Bond(_, mac) => *mac,
+            Self::Tunnel(_) => Mac::new([0; 6]),
+        }
+    }
 }
 
 impl Display for LinkLayerId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Ethernet(id, _) => write!(f, "eth{id}"),
+            Self::Dot1q(id, vlan, _) => write!(f, "eth{id}.{vlan}"),
+            Self::Bond(id, _) => write!(f, "bond{id}"),
+            Self::Tunnel(id) => write!(f, "tun{id}"),
         }
     }
 }
@@ -40,6 +91,7 @@ pub enum NetworkLayerId {
     Ipv4,
     Ipv6,
     Arp,
+    Ndp,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -47,6 +99,8 @@ pub enum TransportLayerId {
     Tcp,
     Udp,
     Icmp,
+    Ospf,
+    Vrrp,
 }
 
 pub enum ProcessMessage<SenderId, ReceiverId, Payload> {
@@ -64,7 +118,19 @@ pub type NetworkTransportPayload = NetworkTransportMessage;
 #[non_exhaustive]
 pub enum NetworkTransportMessage {
     IPv4(IpV4Addr, Option<u8>, Vec<u8>),
-    // IPv6(IpV6Addr, Vec<u8>),
+    IPv6(IpV6Addr, Option<u8>, Vec<u8>),
+    /// Received UDP packet, (source, ttl, payload, quote) where the quote is the header and the
+    /// first 8 bytes of the payload, which ICMP errors about it carry
+    IPv4Quoted(IpV4Addr, Option<u8>, Vec<u8>, Vec<u8>),
+    /// Sent down with the quote of the received packet when no socket is bound to its
+    /// destination port, (source, quote)
+    IPv4PortUnreachable(IpV4Addr, Vec<u8>),
+    /// Limited broadcast (255.255.255.255) received from or sent to an interface
+    IPv4Broadcast(LinkLayerId, Option<u8>, Vec<u8>),
+    /// Exchanged with a neighbor on the interface without routing, (iface, addr, ttl, payload).
+    /// The address is the source of a received packet or the destination of a sent one, which
+    /// can be the limited broadcast or a link-local multicast group
+    IPv4Link(LinkLayerId, IpV4Addr, Option<u8>, Vec<u8>),
 }
 
 type LinkLayerProcessHandle = (
@@ -83,20 +149,71 @@ type MidLayerProcessHandle<DownId, Id, UpId, DownPayload, UpPayload> = (
     Sender<ProcessMessage<UpId, Id, UpPayload>>,
 );
 
+/// Sent by the sub-interfaces to the NIC they're on
+#[derive(Debug)]
+enum SubInterfaceMessage {
+    /// The frames tagged with the VLAN are given to the sender from now on
+    Add(u16, Sender<EthernetPacket>),
+    Send(EthernetPacket),
+    /// Every frame received is given to the bond from now on, with the NIC id
+    Enslave(u16, Sender<(u16, MemberEvent)>),
+}
+
+/// Sent by the NICs to the bond they're members of
+#[derive(Debug)]
+enum MemberEvent {
+    /// The NIC was connected or disconnected
+    Link(bool),
+    Frame(EthernetPacket),
+}
+
+/// Addresses a NIC receives frames for besides its own
+type VirtualMacs = Arc<RwLock<HashSet<Mac>>>;
+
+/// Frames from and to other addresses than the interface's, like the virtual MAC of VRRP
+#[derive(Debug, Clone)]
+pub struct VirtualLink {
+    nic: Sender<SubInterfaceMessage>,
+    /// Tag of the sub-interface
+    tag: Option<Tag>,
+    macs: VirtualMacs,
+}
+
+impl VirtualLink {
+    /// The NIC receives the frames to the address from now on
+    pub async fn add_mac(&self, mac: Mac) {
+        self.macs.write().await.insert(mac);
+    }
+
+    pub async fn remove_mac(&self, mac: Mac) {
+        self.macs.write().await.remove(&mac);
+    }
+
+    /// Sends the frame as it is, with the sub-interface's tag
+    pub async fn send(&self, mut frame: EthernetPacket) {
+        if let Some(tag) = self.tag {
+            frame.push_tag(tag);
+        }
+        let _ = self.nic.send_async(SubInterfaceMessage::Send(frame)).await;
+    }
+}
+
 pub struct NicHandle {
     connected: bool,
+    impairments: Arc<RwLock<Impairments>>,
+    taps: Arc<RwLock<Taps>>,
     disconnect: (Sender<()>, Receiver<()>),
     connect: (
         Sender<()>,
         Receiver<(
-            barrage::Sender<EthernetPacket>,
-            barrage::Receiver<EthernetPacket>,
+            broadcast::Sender<EthernetPacket>,
+            broadcast::Receiver<EthernetPacket>,
         )>,
     ),
     connect_to_net: (
         Sender<(
-            barrage::Sender<EthernetPacket>,
-            barrage::Receiver<EthernetPacket>,
+            broadcast::Sender<EthernetPacket>,
+            broadcast::Receiver<EthernetPacket>,
         )>,
         Receiver<()>,
     ),
@@ -125,8 +242,8 @@ impl NicHandle {
     async fn get_connection_self(
         &self,
     ) -> Option<(
-        barrage::Sender<EthernetPacket>,
-        barrage::Receiver<EthernetPacket>,
+        broadcast::Sender<EthernetPacket>,
+        broadcast::Receiver<EthernetPacket>,
     )> {
         if self.connected {
             self.connect.0.send_async(()).await.ok()?;
@@ -139,8 +256,8 @@ impl NicHandle {
     async fn set_connection_self(
         &mut self,
         conn: (
-            barrage::Sender<EthernetPacket>,
-            barrage::Receiver<EthernetPacket>,
+            broadcast::Sender<EthernetPacket>,
+            broadcast::Receiver<EthernetPacket>,
         ),
     ) -> Option<()> {
         if !self.connected {
@@ -170,7 +287,7 @@ impl NicHandle {
                 }
             }
             (false, false) => {
-                let conn = barrage::unbounded();
+                let conn = broadcast::channel();
                 self.set_connection_self(conn.clone()).await.is_some()
                     && other.set_connection_self(conn).await.is_some()
             }
@@ -194,6 +311,29 @@ impl NicHandle {
     pub fn connected(&self) -> bool {
         self.connected
     }
+
+    /// Impairments of the frames the NIC transmits, they apply to the next frames
+    pub fn impairments(&self) -> &Arc<RwLock<Impairments>> {
+        &self.impairments
+    }
+
+    /// Capture of the frames the NIC sends and receives, replaces the previous one
+    pub async fn start_capture(&self, tap: Tap) {
+        *self.taps.write().await.file() = Some(tap);
+    }
+
+    pub async fn stop_capture(&self) -> Option<Tap> {
+        self.taps.write().await.file().take()
+    }
+
+    pub async fn capture(&self) -> Option<Tap> {
+        self.taps.write().await.file().clone()
+    }
+
+    /// Frames the NIC sends and receives from now on, and whether they're outbound
+    pub async fn watch(&self) -> Receiver<(EthernetPacket, bool)> {
+        self.taps.write().await.watch()
+    }
 }
 
 impl Display for NicHandle {
@@ -216,6 +356,8 @@ pub struct Chassis {
         >,
     >,
     transport_layer_processes: HashMap<TransportLayerId, TransportLayerProcessHandle>,
+    /// NIC address, channel for the sub-interfaces and the bonds, and virtual addresses, by NIC id
+    sub_interfaces: HashMap<u16, (Mac, Sender<SubInterfaceMessage>, VirtualMacs)>,
 }
 
 impl Chassis {
@@ -250,6 +392,10 @@ impl Chassis {
     }
 
     pub fn add_nic_with_id(&mut self, id: u16, nic: Nic) -> NicHandle {
+        let (sub_tx, sub_rx) = flume::unbounded();
+        let virtual_macs = VirtualMacs::default();
+        self.sub_interfaces
+            .insert(id, (nic.mac(), sub_tx, virtual_macs.clone()));
         let id = LinkLayerId::Ethernet(id, nic.mac());
         let (conn_tx, conn_rx) = flume::unbounded();
         let (dconn_tx, dconn_rx) = flume::unbounded();
@@ -257,8 +403,12 @@ impl Chassis {
         let (conn_reply_tx, conn_reply_rx) = flume::unbounded();
         let (dconn_reply_tx, dconn_reply_rx) = flume::unbounded();
         let (conn_net_reply_tx, conn_net_reply_rx) = flume::unbounded();
+        let impairments = Arc::new(RwLock::new(Impairments::default()));
+        let taps = Arc::new(RwLock::new(Taps::default()));
         let res = NicHandle {
             connected: nic.is_up(),
+            impairments: impairments.clone(),
+            taps: taps.clone(),
             disconnect: (dconn_tx, dconn_reply_rx),
             connect: (conn_tx, conn_reply_rx),
             connect_to_net: (conn_net_tx, conn_net_reply_rx),
@@ -268,16 +418,29 @@ impl Chassis {
             let dconn_rx = Arc::new(dconn_rx);
             let uplink_rx = Arc::new(up_link.rx);
             let conn_rx = Arc::new(conn_rx);
+            let sub_rx = Arc::new(sub_rx);
+            // Sub-interfaces by VLAN id
+            let mut vlans: HashMap<u16, Sender<EthernetPacket>> = HashMap::new();
+            // Bond the NIC is a member of
+            let mut bond: Option<(u16, Sender<(u16, MemberEvent)>)> = None;
             'state_change: loop {
                 if let Some((tx, rx)) = conn.take() {
                     let rx = Arc::new(rx);
+                    let link = LinkSender::new(tx.clone(), impairments.clone(), None);
+                    if let Some((nic_id, bond)) = &bond {
+                        let _ = bond.send_async((*nic_id, MemberEvent::Link(true))).await;
+                    }
                     let mut join_set = JoinSet::new();
                     // let nic_ref = &nic;
                     let rx_clone = rx.clone();
                     join_set.spawn(async move { ThreeWayEither::A(rx_clone.recv_async().await) });
                     let uplink_rx_clone = uplink_rx.clone();
                     join_set.spawn(async move {
-                        ThreeWayEither::B(uplink_rx_clone.recv_async().await)
+                        ThreeWayEither::B(Either::Left(uplink_rx_clone.recv_async().await))
+                    });
+                    let sub_rx_clone = sub_rx.clone();
+                    join_set.spawn(async move {
+                        ThreeWayEither::B(Either::Right(sub_rx_clone.recv_async().await))
                     });
                     let dconn_rx_clone = dconn_rx.clone();
                     join_set.spawn(async move {
@@ -294,28 +457,31 @@ impl Chassis {
                                     Err(_) => warn!(NIC = ?addr, "Error recieving eth packet: Disconnected"),
                                     Ok(eth_packet) => {
                                         let dest = eth_packet.get_dest();
-                                        if dest == addr || dest.is_multicast() {
+                                        // The link is shared, our own multicast frames come back to us
+                                        let own = eth_packet.get_source() == addr;
+                                        let ours = dest == addr
+                                            || dest.is_multicast()
+                                            || virtual_macs.read().await.contains(&dest);
+                                        if own || ours {
+                                            taps.read().await.write(&eth_packet, own);
+                                        }
+                                        if let Some((nic_id, bond)) = bond.as_ref().filter(|_| !own) {
+                                            // The bond filters on its own address
+                                            let _ = bond.send_async((*nic_id, MemberEvent::Frame(eth_packet))).await;
+                                        } else if ours && !own && bond.is_none() {
                                             trace!(
                                                 NIC = ?addr,
                                                 packet = ?eth_packet,
                                                 "Recieved packet"
                                             );
-                                            match eth_packet.get_ether_type() {
-                                                EtherType::IP_V4 => {
-                                                    if let Some(sender) = up_link.tx.get(&NetworkLayerId::Ipv4) {
-                                                        let _ = sender.send_async(ProcessMessage::Message(id, (eth_packet.get_source(), eth_packet.payload))).await.map_err(|e| warn!("Cant send ipv4 packet up: {e:?}"));
-                                                    }else{
-                                                        warn!(NIC = ?addr,"No IPv4 process to send packet")
-                                                    }
-                                                }
-                                                EtherType::ARP => {
-                                                    if let Some(sender) = up_link.tx.get(&NetworkLayerId::Arp) {
-                                                        let _ = sender.send_async(ProcessMessage::Message(id, (eth_packet.get_source(), eth_packet.payload))).await.map_err(|e| warn!("Cant send arp packet up: {e:?}"));
-                                                    }else{
-                                                        warn!(NIC = ?addr,"No IPv4 process to send packet")
+                                            match eth_packet.get_dot1q() {
+                                                Some(tag) => match vlans.get(&tag.vlan_id()).filter(|_| !tag.is_service()) {
+                                                    Some(sender) => {
+                                                        let _ = sender.send_async(eth_packet).await;
                                                     }
-                                                }
-                                                x => warn!(NIC = ?addr, "Unknown ether_type {:x}", x.to_u16())
+                                                    None => trace!(NIC = ?addr, "No sub-interface for VLAN {}", tag.vlan_id()),
+                                                },
+                                                None => deliver_frame(id, eth_packet, &up_link.tx).await,
                                             }
                                         }
                                     }
@@ -323,55 +489,50 @@ impl Chassis {
                                 let rx_clone = rx.clone();
                                 join_set.spawn(async move { ThreeWayEither::A(rx_clone.recv_async().await) });
                             }
-                            Some(Ok(ThreeWayEither::B(up_link_msg))) => {
+                            Some(Ok(ThreeWayEither::B(Either::Left(up_link_msg)))) => {
                                 match up_link_msg {
                                     Ok(up_link_msg) => match up_link_msg {
                                         ProcessMessage::NewConn(upper_id, sender) => {
                                             up_link.tx.insert(upper_id, sender);
                                         }
-                                        ProcessMessage::Message(id, (dest, payload)) => match id {
-                                            NetworkLayerId::Ipv4 => {
-                                                trace!(NIC = ?addr, "Transmitting ipv4 packet");
-                                                match EthernetPacket::new_ip_v4(dest, addr, payload) {
-                                                    Some(packet) => {
-                                                        let _ = tx.send_async(packet).await;
-                                                    }
-                                                    None => {
-                                                        warn!(NIC = ?addr, "Error building ethernet ipv4 packet")
-                                                    }
-                                                }
-                                            }
-                                            NetworkLayerId::Ipv6 => {
-                                                match EthernetPacket::new_ip_v6(dest, addr, payload) {
-                                                    Some(packet) => {
-                                                        let _ = tx.send_async(packet).await;
-                                                    }
-                                                    None => {
-                                                        warn!(NIC = ?addr, "Error building ethernet ipv6 packet")
-                                                    }
-                                                }
+                                        ProcessMessage::Message(id, (dest, payload)) => {
+                                            if let Some(packet) = build_frame(id, dest, addr, payload) {
+                                                let _ = link.send_async(packet).await;
                                             }
-                                            NetworkLayerId::Arp => {
-                                                trace!(NIC = ?addr, "Transmitting ARP packet");
-                                                match EthernetPacket::new_arp(dest, addr, payload) {
-                                                    Some(packet) => {
-                                                        let _ = tx.send_async(packet).await;
-                                                    }
-                                                    None => warn!(NIC = ?addr, "Error building ethernet ARP packet"),
-                                                }
-                                            }
-                                        },
+                                        }
                                     },
                                     Err(e) => warn!(NIC = ?addr, "Down link packet error: {e:?}"),
                                 }
                                 let uplink_rx_clone = uplink_rx.clone();
                                 join_set.spawn(async move {
-                                    ThreeWayEither::B(uplink_rx_clone.recv_async().await)
+                                    ThreeWayEither::B(Either::Left(uplink_rx_clone.recv_async().await))
+                                });
+                            }
+                            Some(Ok(ThreeWayEither::B(Either::Right(sub_msg)))) => {
+                                match sub_msg {
+                                    Ok(SubInterfaceMessage::Add(vlan, sender)) => {
+                                        vlans.insert(vlan, sender);
+                                    }
+                                    Ok(SubInterfaceMessage::Send(packet)) => {
+                                        let _ = link.send_async(packet).await;
+                                    }
+                                    Ok(SubInterfaceMessage::Enslave(nic_id, sender)) => {
+                                        let _ = sender.send_async((nic_id, MemberEvent::Link(true))).await;
+                                        bond = Some((nic_id, sender));
+                                    }
+                                    Err(e) => warn!(NIC = ?addr, "Sub-interface packet error: {e:?}"),
+                                }
+                                let sub_rx_clone = sub_rx.clone();
+                                join_set.spawn(async move {
+                                    ThreeWayEither::B(Either::Right(sub_rx_clone.recv_async().await))
                                 });
                             }
                             Some(Ok(ThreeWayEither::C(Either::Right(msg)))) => {
                                 match msg {
                                     Ok(()) => {
+                                        if let Some((nic_id, bond)) = &bond {
+                                            let _ = bond.send_async((*nic_id, MemberEvent::Link(false))).await;
+                                        }
                                         dconn_reply_tx.send_async(()).await.unwrap();
                                         continue 'state_change
                                     },
@@ -381,7 +542,9 @@ impl Chassis {
                             Some(Ok(ThreeWayEither::C(Either::Left(msg)))) => {
                                 match msg {
                                     Ok(()) => {
-                                        conn_reply_tx.send_async((tx, rx.as_ref().clone())).await.unwrap();
+                                        // The NIC stays on the link it shares
+                                        conn_reply_tx.send_async((tx.clone(), rx.as_ref().clone())).await.unwrap();
+                                        conn = Some((tx, rx.as_ref().clone()));
                                         continue 'state_change
                                     },
                                     Err(e) => warn!(NIC = ?addr, "Connect packet error: {e:?}"),
@@ -403,6 +566,143 @@ impl Chassis {
         res
     }
 
+    /// Adds the `eth<id>.<vlan>` sub-interface, whose frames are tagged with the VLAN id on the
+    /// NIC's link. `None` if there's no such NIC
+    pub fn add_sub_interface(&mut self, id: u16, vlan: u16) -> Option<LinkLayerId> {
+        let (mac, nic, _) = self.sub_interfaces.get(&id)?.clone();
+        let sub_id = LinkLayerId::Dot1q(id, vlan, mac);
+        self.add_link_layer_process(sub_id, move |mut up_link| async move {
+            let (frame_tx, frame_rx) = flume::unbounded();
+            if nic
+                .send_async(SubInterfaceMessage::Add(vlan, frame_tx))
+                .await
+                .is_err()
+            {
+                warn!("{sub_id}: NIC removed");
+                return;
+            }
+            let tag = Tag::new(0, false, vlan);
+            loop {
+                select! {
+                    frame = frame_rx.recv_async() => match frame {
+                        Ok(frame) => deliver_frame(sub_id, frame, &up_link.tx).await,
+                        Err(_) => break,
+                    },
+                    msg = up_link.rx.recv_async() => match msg {
+                        Ok(ProcessMessage::NewConn(upper_id, sender)) => {
+                            up_link.tx.insert(upper_id, sender);
+                        }
+                        Ok(ProcessMessage::Message(id, (dest, payload))) => {
+                            if let Some(mut packet) = build_frame(id, dest, mac, payload) {
+                                packet.push_tag(tag);
+                                let _ = nic.send_async(SubInterfaceMessage::Send(packet)).await;
+                            }
+                        }
+                        Err(_) => break,
+                    },
+                }
+            }
+        });
+        Some(sub_id)
+    }
+
+    /// Link to send and receive frames with other addresses on the NIC or sub-interface. `None` for
+    /// the bonds, the tunnels and if there's no such NIC
+    pub fn virtual_link(&self, iface: LinkLayerId) -> Option<VirtualLink> {
+        let (id, tag) = match iface {
+            LinkLayerId::Ethernet(id, _) => (id, None),
+            LinkLayerId::Dot1q(id, vlan, _) => (id, Some(Tag::new(0, false, vlan))),
+            LinkLayerId::Bond(..) | LinkLayerId::Tunnel(_) => return None,
+        };
+        let (_, nic, macs) = self.sub_interfaces.get(&id)?.clone();
+        Some(VirtualLink { nic, tag, macs })
+    }
+
+    /// Adds the `bond<id>` aggregation of the NICs, which negotiate their membership with LACP.
+    /// The bond has the address of its first NIC. `None` if there's no such NIC
+    pub fn add_bond(
+        &mut self,
+        id: u16,
+        members: &[u16],
+    ) -> Option<(LinkLayerId, Arc<RwLock<Lacp>>)> {
+        let members = members
+            .iter()
+            .map(|nic| Some((*nic, self.sub_interfaces.get(nic)?.clone())))
+            .collect::<Option<Vec<_>>>()?;
+        let mac = members.first()?.1 .0;
+        let bond_id = LinkLayerId::Bond(id, mac);
+        let mut lacp = Lacp::new(mac, id);
+        for (nic, (nic_mac, ..)) in &members {
+            lacp.add_member(*nic, *nic_mac);
+        }
+        let lacp = Arc::new(RwLock::new(lacp));
+        let lacp_inner = lacp.clone();
+        self.add_link_layer_process(bond_id, move |mut up_link| async move {
+            let lacp = lacp_inner;
+            let members = members
+                .into_iter()
+                .map(|(nic, (_, sender, _))| (nic, sender))
+                .collect::<HashMap<_, _>>();
+            let (event_tx, event_rx) = flume::unbounded();
+            for (nic, sender) in &members {
+                let msg = SubInterfaceMessage::Enslave(*nic, event_tx.clone());
+                if sender.send_async(msg).await.is_err() {
+                    warn!("{bond_id}: NIC eth{nic} removed");
+                    return;
+                }
+            }
+            let mut interval = tokio::time::interval(PERIODIC_TIME);
+            loop {
+                select! {
+                    event = event_rx.recv_async() => match event {
+                        Ok((nic, MemberEvent::Link(up))) => lacp.write().await.set_up(nic, up),
+                        Ok((nic, MemberEvent::Frame(frame))) => {
+                            let dest = frame.get_dest();
+                            if dest == SLOW_PROTOCOLS_ADDR {
+                                match Lacpdu::read(&frame.payload) {
+                                    Some(pdu) => lacp.write().await.on_lacpdu(nic, pdu, Instant::now()),
+                                    None => warn!("{bond_id}: Invalid LACPDU received on eth{nic}"),
+                                }
+                            } else if (dest == mac || dest.is_multicast())
+                                && frame.get_source() != mac
+                                && frame.get_dot1q().is_none()
+                                && lacp.read().await.collecting(nic)
+                            {
+                                deliver_frame(bond_id, frame, &up_link.tx).await;
+                            }
+                        }
+                        Err(_) => break,
+                    },
+                    _ = interval.tick() => lacp.write().await.on_tick(Instant::now()),
+                    msg = up_link.rx.recv_async() => match msg {
+                        Ok(ProcessMessage::NewConn(upper_id, sender)) => {
+                            up_link.tx.insert(upper_id, sender);
+                        }
+                        Ok(ProcessMessage::Message(id, (dest, payload))) => {
+                            if let Some(packet) = build_frame(id, dest, mac, payload) {
+                                let member = lacp.read().await.member_for(&packet);
+                                match member.and_then(|nic| members.get(&nic)) {
+                                    Some(sender) => {
+                                        let _ = sender.send_async(SubInterfaceMessage::Send(packet)).await;
+                                    }
+                                    None => trace!("{bond_id}: No member distributing"),
+                                }
+                            }
+                        }
+                        Err(_) => break,
+                    },
+                }
+                let output = lacp.write().await.take_output();
+                for (nic, frame) in output {
+                    if let Some(sender) = members.get(&nic) {
+                        let _ = sender.send_async(SubInterfaceMessage::Send(frame)).await;
+                    }
+                }
+            }
+        });
+        Some((bond_id, lacp))
+    }
+
     pub fn add_network_layer_process<
         P: MidLevelProcess<
                 NetworkLayerId,
@@ -499,6 +799,66 @@ impl Chassis {
     }
 }
 
+/// Gives a received frame to the network layer process of its ether type
+async fn deliver_frame(
+    id: LinkLayerId,
+    eth_packet: EthernetPacket,
+    up_link_tx: &HashMap<
+        NetworkLayerId,
+        Sender<ProcessMessage<LinkLayerId, NetworkLayerId, LinkNetworkPayload>>,
+    >,
+) {
+    let addr = id.mac();
+    let (up_id, name) = match eth_packet.get_ether_type() {
+        EtherType::IP_V4 => (NetworkLayerId::Ipv4, "IPv4"),
+        EtherType::IP_V6 if is_ndp_packet(&eth_packet.payload) => (NetworkLayerId::Ndp, "IPv6"),
+        EtherType::IP_V6 => (NetworkLayerId::Ipv6, "IPv6"),
+        EtherType::ARP => (NetworkLayerId::Arp, "ARP"),
+        x => {
+            warn!(NIC = ?addr, "Unknown ether_type {:x}", x.to_u16());
+            return;
+        }
+    };
+    if let Some(sender) = up_link_tx.get(&up_id) {
+        let _ = sender
+            .send_async(ProcessMessage::Message(
+                id,
+                (eth_packet.get_source(), eth_packet.payload),
+            ))
+            .await
+            .map_err(|e| warn!("Cant send {name} packet up: {e:?}"));
+    } else {
+        warn!(NIC = ?addr, "No {name} process to send packet")
+    }
+}
+
+/// Builds the frame carrying a packet of a network layer process
+fn build_frame(
+    id: NetworkLayerId,
+    dest: Mac,
+    addr: Mac,
+    payload: Vec<u8>,
+) -> Option<EthernetPacket> {
+    let packet = match id {
+        NetworkLayerId::Ipv4 => {
+            trace!(NIC = ?addr, "Transmitting ipv4 packet");
+            EthernetPacket::new_ip_v4(dest, addr, payload)
+        }
+        NetworkLayerId::Ipv6 | NetworkLayerId::Ndp => {
+            trace!(NIC = ?addr, "Transmitting ipv6 packet");
+            EthernetPacket::new_ip_v6(dest, addr, payload)
+        }
+        NetworkLayerId::Arp => {
+            trace!(NIC = ?addr, "Transmitting ARP packet");
+            EthernetPacket::new_arp(dest, addr, payload)
+        }
+    };
+    if packet.is_none() {
+        warn!(NIC = ?addr, "Error building ethernet {id:?} packet");
+    }
+    packet
+}
+
 fn add_mid_level_process<Id, UpId, DownId, DownPayload, UpPayload, F, Fut>(
     id: Id,
     curr_level: &mut HashMap<Id, MidLayerProcessHandle<DownId, Id, UpId, DownPayload, UpPayload>>,
@@ -1,10 +1,11 @@
 use std::{
-    collections::HashMap,
-    sync::Arc,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
-use barrage::Receiver;
+use crate::broadcast::{self, Receiver};
 use flume::RecvError;
 use tokio::{
     sync::RwLock,
@@ -13,25 +14,41 @@ use tokio::{
 use tracing::{info, warn};
 
 use crate::{
-    link::ethernet::{dot1q::Tag, nic::Nic, packet::EthernetPacket},
+    link::{
+        ethernet::{
+            dot1q::Tag,
+            lacp::{self, Lacp, Lacpdu},
+            nic::Nic,
+            packet::EthernetPacket,
+        },
+        capture::Taps,
+        impairment::{Impairments, LinkSender},
+    },
     mac::{authority::MacAdminAuthority, Mac},
 };
 
 use super::NicHandle;
 
+pub mod stp;
+
+use stp::{Bpdu, PortState, Stp};
+
+/// Period of the spanning tree timers
+const STP_TICK: Duration = Duration::from_secs(1);
+
 enum SwitchMessage {
     Connect,
     Disconnect,
     ConnectNetwork(
         (
-            barrage::Sender<EthernetPacket>,
-            barrage::Receiver<EthernetPacket>,
+            broadcast::Sender<EthernetPacket>,
+            broadcast::Receiver<EthernetPacket>,
         ),
     ),
     NicHandleError(flume::RecvError),
     EthernetFrame(EthernetPacket),
     SendFrame(EthernetPacket),
-    NetError(barrage::Disconnected),
+    NetError(broadcast::Disconnected),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -40,11 +57,26 @@ pub enum PortType {
     Unknown,
     NoDot1q,
     Vlan(Tag),
+    /// Customer facing provider edge port, every frame is carried in the service tag
+    Tunnel(Tag),
+}
+
+impl Display for PortType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Trunk => write!(f, "trunk"),
+            Self::Unknown => write!(f, "unknown"),
+            Self::NoDot1q => write!(f, "untagged"),
+            Self::Vlan(tag) => write!(f, "access VLAN {}", tag.vlan_id()),
+            Self::Tunnel(tag) => write!(f, "tunnel in service VLAN {}", tag.vlan_id()),
+        }
+    }
 }
 
 struct Port {
     _handle: JoinHandle<()>,
     nic_handle: Arc<RwLock<NicHandle>>,
+    mac: Mac,
     port_type: PortType,
     sender: flume::Sender<EthernetPacket>,
 }
@@ -54,12 +86,21 @@ pub struct Switch {
     link_layer_processes: Arc<RwLock<Vec<Port>>>,
     destination_if_table: Arc<RwLock<HashMap<Mac, (usize, Instant)>>>,
     destination_ttl: Arc<Duration>,
+    stp: Arc<RwLock<Stp>>,
+    stp_timer: Arc<RwLock<Option<JoinHandle<()>>>>,
+    /// Link aggregations of ports, the MAC table refers to them by their first member
+    bundles: Arc<RwLock<Vec<Lacp>>>,
+    lacp_timer: Arc<RwLock<Option<JoinHandle<()>>>>,
 }
 
 impl Switch {
+    /// RSTP runs from the start, so loops between the ports are blocked
     pub fn new(ttl: Duration) -> Self {
+        let mut spanning_tree = Stp::default();
+        spanning_tree.enable(stp::Version::Rstp, tokio::time::Instant::now());
         Self {
             destination_ttl: Arc::new(ttl),
+            stp: Arc::new(RwLock::new(spanning_tree)),
             ..Default::default()
         }
     }
@@ -69,10 +110,32 @@ impl Switch {
             link_layer_processes: self.link_layer_processes.clone(),
             destination_if_table: self.destination_if_table.clone(),
             destination_ttl: self.destination_ttl.clone(),
+            stp: self.stp.clone(),
+            stp_timer: self.stp_timer.clone(),
+            bundles: self.bundles.clone(),
+            lacp_timer: self.lacp_timer.clone(),
         }
     }
 
-    async fn send_frame(&self, frame: EthernetPacket) {
+    /// Sends the frame out of the port its destination was learned on, or floods it out of the
+    /// other forwarding ports
+    async fn send_frame(&self, frame: EthernetPacket, ingress: usize) {
+        let stp_forwarding = self.stp.read().await.forwarding_ports();
+        let bundles = self.bundles.read().await;
+        let bundle = |id: usize| bundles.iter().position(|lacp| lacp.has_member(id as u16));
+        // Bundles are a single port, the frames leave them through one member
+        let chosen = bundles
+            .iter()
+            .map(|lacp| lacp.member_for(&frame).map(usize::from))
+            .collect::<Vec<_>>();
+        let member = |id: usize| bundle(id).map_or(Some(id), |b| chosen[b]);
+        let same_segment =
+            |id: usize| id == ingress || bundle(id).is_some_and(|b| bundle(ingress) == Some(b));
+        let forwarding = |id: usize| {
+            !same_segment(id)
+                && stp_forwarding.get(id).copied().unwrap_or(false)
+                && bundle(id).is_none_or(|b| chosen[b] == Some(id))
+        };
         let dest = frame.get_dest();
         if let Some(tag) = frame.get_dot1q() {
             match match self.destination_if_table.read().await.get(&dest).copied() {
@@ -81,31 +144,44 @@ impl Switch {
                     None
                 }
                 Some((id, cr)) => match self.link_layer_processes.read().await[id].port_type {
-                    PortType::Vlan(tag2) if tag.vlan_id() != tag2.vlan_id() => {
+                    PortType::Vlan(tag2) | PortType::Tunnel(tag2)
+                        if (tag.tpid(), tag.vlan_id()) != (tag2.tpid(), tag2.vlan_id()) =>
+                    {
                         self.destination_if_table.write().await.remove(&dest);
                         None
                     } // Saved if doesnt match vlan
                     _ => Some((id, cr)),
                 },
                 x => x,
-            } {
-                Some((id, _)) => {
+            }
+            .and_then(|(id, cr)| Some((member(id)?, cr)))
+            {
+                // The destination is on the segment the frame came from
+                Some((id, _)) if same_segment(id) => {}
+                Some((id, _)) if forwarding(id) => {
                     let _ = self.link_layer_processes.read().await[id]
                         .sender
                         .send_async(frame)
                         .await;
                 }
-                None => {
+                _ => {
                     let llp = self.link_layer_processes.read().await;
-                    for Port {
-                        port_type, sender, ..
-                    } in llp.iter()
+                    for (
+                        id,
+                        Port {
+                            port_type, sender, ..
+                        },
+                    ) in llp.iter().enumerate()
                     {
-                        if match port_type {
-                            PortType::Trunk => true,
-                            PortType::Vlan(vlan) if vlan.vlan_id() == tag.vlan_id() => true,
-                            _ => false,
-                        } {
+                        if forwarding(id)
+                            && match port_type {
+                                PortType::Trunk => true,
+                                PortType::Vlan(vlan) | PortType::Tunnel(vlan) => {
+                                    (vlan.tpid(), vlan.vlan_id()) == (tag.tpid(), tag.vlan_id())
+                                }
+                                _ => false,
+                            }
+                        {
                             let _ = sender.send_async(frame.clone()).await;
                         }
                     }
@@ -119,20 +195,28 @@ impl Switch {
                     None
                 }
                 x => x,
-            } {
-                Some((id, _)) => {
+            }
+            .and_then(|(id, cr)| Some((member(id)?, cr)))
+            {
+                Some((id, _)) if same_segment(id) => {}
+                Some((id, _)) if forwarding(id) => {
                     let _ = self.link_layer_processes.read().await[id]
                         .sender
                         .send_async(frame)
                         .await;
                 }
-                None => {
+                _ => {
                     let llp = self.link_layer_processes.read().await;
-                    for Port {
-                        port_type, sender, ..
-                    } in llp.iter()
+                    for (
+                        id,
+                        Port {
+                            port_type, sender, ..
+                        },
+                    ) in llp.iter().enumerate()
                     {
-                        if matches!(port_type, PortType::NoDot1q | PortType::Unknown) {
+                        if forwarding(id)
+                            && matches!(port_type, PortType::NoDot1q | PortType::Unknown)
+                        {
                             let _ = sender.send_async(frame.clone()).await;
                         }
                     }
@@ -149,16 +233,25 @@ impl Switch {
         let (dconn_reply_tx, dconn_reply_rx) = flume::unbounded();
         let (conn_net_reply_tx, conn_net_reply_rx) = flume::unbounded();
         let (frame_tx, frame_rx) = flume::unbounded();
+        let impairments = Arc::new(RwLock::new(Impairments::default()));
+        let taps = Arc::new(RwLock::new(Taps::default()));
         let res = (
             self.link_layer_processes.read().await.len(),
             Arc::new(RwLock::new(NicHandle {
                 connected: nic.is_up(),
+                impairments: impairments.clone(),
+                taps: taps.clone(),
                 disconnect: (dconn_tx, dconn_reply_rx),
                 connect: (conn_tx, conn_reply_rx),
                 connect_to_net: (conn_net_tx, conn_net_reply_rx),
             })),
         );
         let id = res.0;
+        let mac = nic.mac();
+        self.stp.write().await.add_port(mac);
+        if self.stp.read().await.enabled {
+            self.start_stp_timer().await;
+        }
         let self_inner = self.internal_clone();
         self.link_layer_processes.write().await.push(Port {
             _handle: tokio::spawn(async move {
@@ -208,16 +301,25 @@ impl Switch {
                 let mut join_set = JoinSet::new();
                 join_set.spawn(dconn_task());
                 join_set.spawn(conn_net_task());
-                let mut conn = conn.map(|(tx, rx)| (tx, Arc::new(rx)));
-                if let Some((_, rx)) = conn.as_ref() {
+                join_set.spawn(frame_task());
+                // The link echoes the frames sent on it, they're recognized among the received
+                // ones as the oldest not seen back yet
+                let sent = Arc::new(Mutex::new(VecDeque::new()));
+                let link_sender = |tx: &broadcast::Sender<EthernetPacket>| {
+                    LinkSender::new(tx.clone(), impairments.clone(), Some(sent.clone()))
+                };
+                let mut conn = conn.map(|(tx, rx)| {
+                    let link = link_sender(&tx);
+                    (tx, Arc::new(rx), link)
+                });
+                if let Some((_, rx, _)) = conn.as_ref() {
                     join_set.spawn(conn_task());
                     join_set.spawn(ethernet_task(rx.clone()));
-                    join_set.spawn(frame_task());
                 }
                 loop {
                     match join_set.join_next().await {
                         Some(Ok(x)) => match x {
-                            SwitchMessage::Connect => if let Some((tx, rx)) = conn.as_ref() {
+                            SwitchMessage::Connect => if let Some((tx, rx, _)) = conn.as_ref() {
                                 let _ = conn_reply_tx
                                     .send_async((tx.clone(), rx.as_ref().clone()))
                                     .await;
@@ -225,81 +327,138 @@ impl Switch {
                             },
                             SwitchMessage::Disconnect => {
                                 conn = None;
+                                sent.lock().unwrap().clear();
                                 let _ = dconn_reply_tx.send_async(()).await;
                                 join_set.spawn(dconn_task());
                             }
                             SwitchMessage::ConnectNetwork((tx, rx)) => {
                                 let rx = Arc::new(rx);
-                                conn = Some((tx, rx.clone()));
+                                conn = Some((tx.clone(), rx.clone(), link_sender(&tx)));
                                 let _ = conn_net_reply_tx.send_async(()).await;
                                 join_set.spawn(conn_net_task());
                                 join_set.spawn(ethernet_task(rx));
-                                join_set.spawn(frame_task());
                             }
                             SwitchMessage::NicHandleError(RecvError::Disconnected) => {
                                 warn!("NIC handle disconnected")
                             }
                             SwitchMessage::EthernetFrame(mut frame) => {
-                                self_inner.destination_if_table.write().await.insert(frame.get_source(), (id, Instant::now()));
+                                // Left from a link the port was disconnected from otherwise
+                                let Some((_, rx, _)) = conn.as_ref() else {
+                                    continue;
+                                };
+                                join_set.spawn(ethernet_task(rx.clone()));
+                                let echo = {
+                                    let mut sent = sent.lock().unwrap();
+                                    let i = sent.iter().position(|sent| sent == &frame);
+                                    if let Some(i) = i {
+                                        sent.drain(..=i);
+                                    }
+                                    i.is_some()
+                                };
+                                taps.read().await.write(&frame, echo);
+                                if echo {
+                                    continue;
+                                }
+                                if frame.get_dest() == stp::GROUP_ADDR {
+                                    self_inner.receive_bpdu(id, &frame).await;
+                                    continue;
+                                }
+                                if frame.get_dest() == lacp::SLOW_PROTOCOLS_ADDR {
+                                    self_inner.receive_lacpdu(id, &frame).await;
+                                    continue;
+                                }
+                                let Some(anchor) = self_inner.collecting(id).await else {
+                                    continue;
+                                };
+                                let stp_state = self_inner.stp.read().await.port_state(id);
+                                if stp_state.learning() {
+                                    self_inner.destination_if_table.write().await.insert(frame.get_source(), (anchor, Instant::now()));
+                                }
+                                if stp_state != PortState::Forwarding {
+                                    continue;
+                                }
 								let port_state = self_inner.link_layer_processes.read().await[id].port_type;
 								match (port_state, frame.get_dot1q()) {
 									(PortType::Trunk, None) => warn!("Recieved non baby jumbo frame from trunk configured port eth{id}"),
 									(PortType::Trunk, Some(_)) => {
-										self_inner.send_frame(frame).await;
+										self_inner.send_frame(frame, id).await;
 									},
 									(PortType::Unknown, None) => {
 										info!("[eth{id}] Received normal frame from unknown state port, treating as no dot1q, no info on port");
-										self_inner.send_frame(frame).await;
+										self_inner.send_frame(frame, id).await;
 									},
 									(PortType::Unknown, Some(_)) => {
 										info!("[eth{id}] Received baby jumbo from unknown state port, configuring as trunk");
 										self_inner.link_layer_processes.write().await[id].port_type = PortType::Trunk;
-										self_inner.send_frame(frame).await;
+										self_inner.send_frame(frame, id).await;
 									},
 									(PortType::NoDot1q, None) => {
-										self_inner.send_frame(frame).await;
+										self_inner.send_frame(frame, id).await;
 									},
 									(PortType::NoDot1q, Some(tag)) => warn!(?tag, "Recieved baby jumbo frame from no dot1q configured port eth{id}"),
 									(PortType::Vlan(vlan_id), None) => {
-										frame.set_dot1q(vlan_id);
-										self_inner.send_frame(frame).await;
+										frame.push_tag(vlan_id);
+										self_inner.send_frame(frame, id).await;
 									},
 									(PortType::Vlan(vlan_id), Some(tag)) => warn!(?tag, ?vlan_id, "Recieved baby jumbo frame from no vlan endpoint configured port eth{id}"),
+									(PortType::Tunnel(service), _) => {
+										frame.push_tag(service);
+										self_inner.send_frame(frame, id).await;
+									},
 								}
 							}
-                            SwitchMessage::NetError(_) => todo!(),
-                            SwitchMessage::SendFrame(mut frame) => if let Some((tx, _)) = conn.as_ref() {
+                            SwitchMessage::NetError(_) => warn!("[eth{id}] Link closed"),
+                            SwitchMessage::SendFrame(mut frame) => {
+                                join_set.spawn(frame_task());
+                                let Some((_, _, link)) = conn.as_ref() else {
+                                    continue;
+                                };
+                                // BPDUs and LACPDUs are never tagged
+                                if matches!(frame.get_dest(), stp::GROUP_ADDR | lacp::SLOW_PROTOCOLS_ADDR) {
+                                    let _ = link.send_async(frame).await;
+                                    continue;
+                                }
 								let port_state = self_inner.link_layer_processes.read().await[id].port_type;
                                 match (port_state, frame.get_dot1q()) {
-									(PortType::Trunk, None) => warn!("Not sent non baby jumbo frame to trunk configured port eth{id}"),
-									(PortType::Trunk, Some(_)) => {
-										let _ = tx.send_async(frame).await;
-									},
+									(PortType::Trunk, None) => {
+                                        warn!("Not sent non baby jumbo frame to trunk configured port eth{id}");
+                                        continue;
+                                    },
+									(PortType::Trunk, Some(_)) => {},
 									(PortType::Unknown, None) => {
 										info!("[eth{id}] Sent normal frame from unknown state port, treating as no dot1q, no info on port");
-										let _ = tx.send_async(frame).await;
 									},
 									(PortType::Unknown, Some(_)) => {
 										info!("[eth{id}] Sent baby jumbo from unknown state port, configuring as trunk");
 										self_inner.link_layer_processes.write().await[id].port_type = PortType::Trunk;
-										let _ = tx.send_async(frame).await;
-									},
-									(PortType::NoDot1q, None) => {
-										let _ = tx.send_async(frame).await;
 									},
-									(PortType::NoDot1q, Some(tag)) => warn!(?tag, "Sent baby jumbo frame to no dot1q configured port eth{id}"),
+									(PortType::NoDot1q, None) => {},
+									(PortType::NoDot1q, Some(tag)) => {
+                                        warn!(?tag, "Sent baby jumbo frame to no dot1q configured port eth{id}");
+                                        continue;
+                                    },
 									(PortType::Vlan(vlan_id), None) => {
 										warn!(vlan_id = vlan_id.vlan_id(), "[eth{id}] Dropping packet attempted to send through vlan port without tag");
+                                        continue;
 									},
 									(PortType::Vlan(vlan_id), Some(tag)) => {
-                                        if vlan_id.vlan_id() == tag.vlan_id() {
-                                            frame.remove_dot1q();
-                                            let _ = tx.send_async(frame).await;
+                                        if (vlan_id.tpid(), vlan_id.vlan_id()) == (tag.tpid(), tag.vlan_id()) {
+                                            frame.pop_tag();
                                         }else{
 										    warn!(vlan_id = vlan_id.vlan_id(), tag = tag.vlan_id(), "[eth{id}] Dropping packet attempted to send through vlan port with wrong tag");
+                                            continue;
+                                        }
+                                    },
+									(PortType::Tunnel(service), tag) => {
+                                        if tag.is_some_and(|tag| (tag.tpid(), tag.vlan_id()) == (service.tpid(), service.vlan_id())) {
+                                            frame.pop_tag();
+                                        } else {
+										    warn!(service = service.vlan_id(), ?tag, "[eth{id}] Dropping packet attempted to send through tunnel port without its service tag");
+                                            continue;
                                         }
                                     },
 								}
+                                let _ = link.send_async(frame).await;
                             }
                         },
                         Some(Err(_)) => break,
@@ -308,6 +467,7 @@ impl Switch {
                 }
             }),
             nic_handle: res.1.clone(),
+            mac,
             port_type: t,
             sender: frame_tx,
         });
@@ -340,6 +500,191 @@ impl Switch {
             x.port_type = port_type
         }
     }
+
+    /// Bridge and port settings of the spanning tree, changes apply to the next BPDUs
+    pub fn stp(&self) -> &Arc<RwLock<Stp>> {
+        &self.stp
+    }
+
+    /// Starts the spanning tree again if it's running, every port blocks until it's given a role
+    pub async fn enable_stp(&self, version: stp::Version) {
+        self.stp.write().await.enable(version, tokio::time::Instant::now());
+        self.start_stp_timer().await;
+    }
+
+    /// Runs the spanning tree timers, unless they're already running
+    async fn start_stp_timer(&self) {
+        let mut timer = self.stp_timer.write().await;
+        if timer.is_none() {
+            let switch = self.internal_clone();
+            *timer = Some(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(STP_TICK);
+                loop {
+                    interval.tick().await;
+                    switch.stp_tick().await;
+                }
+            }));
+        }
+    }
+
+    /// Every port forwards again
+    pub async fn disable_stp(&self) {
+        if let Some(timer) = self.stp_timer.write().await.take() {
+            timer.abort();
+        }
+        self.stp.write().await.disable();
+        self.stp_output().await;
+    }
+
+    async fn stp_tick(&self) {
+        let nic_handles = self
+            .link_layer_processes
+            .read()
+            .await
+            .iter()
+            .map(|port| port.nic_handle.clone())
+            .collect::<Vec<_>>();
+        let mut up = Vec::with_capacity(nic_handles.len());
+        for nic_handle in nic_handles {
+            up.push(nic_handle.read().await.connected());
+        }
+        self.stp.write().await.on_tick(tokio::time::Instant::now(), &up);
+        self.stp_output().await;
+    }
+
+    async fn receive_bpdu(&self, id: usize, frame: &EthernetPacket) {
+        let Some(bpdu) = Bpdu::read(&frame.payload) else {
+            warn!("[eth{id}] Invalid BPDU received");
+            return;
+        };
+        self.stp.write().await.on_bpdu(id, bpdu, tokio::time::Instant::now());
+        self.stp_output().await;
+    }
+
+    /// Aggregates the ports with LACP, they have to be bundled with the same ports of a single
+    /// partner. `None` if a port doesn't exist or is already bundled
+    pub async fn add_bundle(&self, ports: &[usize]) -> Option<usize> {
+        let macs = {
+            let llp = self.link_layer_processes.read().await;
+            ports
+                .iter()
+                .map(|id| Some(llp.get(*id)?.mac))
+                .collect::<Option<Vec<_>>>()?
+        };
+        let mut bundles = self.bundles.write().await;
+        if ports
+            .iter()
+            .any(|id| bundles.iter().any(|lacp| lacp.has_member(*id as u16)))
+        {
+            return None;
+        }
+        let mut lacp = Lacp::new(*macs.first()?, bundles.len() as u16);
+        for (id, mac) in ports.iter().zip(macs) {
+            lacp.add_member(*id as u16, mac);
+        }
+        bundles.push(lacp);
+        let mut timer = self.lacp_timer.write().await;
+        if timer.is_none() {
+            let switch = self.internal_clone();
+            *timer = Some(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(lacp::PERIODIC_TIME);
+                loop {
+                    interval.tick().await;
+                    switch.lacp_tick().await;
+                }
+            }));
+        }
+        Some(bundles.len() - 1)
+    }
+
+    /// LACP of the bundles, by bundle id
+    pub fn bundles(&self) -> &Arc<RwLock<Vec<Lacp>>> {
+        &self.bundles
+    }
+
+    /// Port the MAC table learns the frames received on the port against, `None` if the port is
+    /// a member of a bundle that doesn't collect on it
+    async fn collecting(&self, id: usize) -> Option<usize> {
+        let bundles = self.bundles.read().await;
+        match bundles.iter().find(|lacp| lacp.has_member(id as u16)) {
+            None => Some(id),
+            Some(lacp) if lacp.collecting(id as u16) => lacp.members().next().map(usize::from),
+            Some(_) => None,
+        }
+    }
+
+    async fn lacp_tick(&self) {
+        let nic_handles = self
+            .link_layer_processes
+            .read()
+            .await
+            .iter()
+            .map(|port| port.nic_handle.clone())
+            .collect::<Vec<_>>();
+        let mut up = Vec::with_capacity(nic_handles.len());
+        for nic_handle in nic_handles {
+            up.push(nic_handle.read().await.connected());
+        }
+        let now = Instant::now();
+        for lacp in self.bundles.write().await.iter_mut() {
+            let members = lacp.members().collect::<Vec<_>>();
+            for id in members {
+                lacp.set_up(id, up.get(id as usize).copied().unwrap_or(false));
+            }
+            lacp.on_tick(now);
+        }
+        self.lacp_output().await;
+    }
+
+    async fn receive_lacpdu(&self, id: usize, frame: &EthernetPacket) {
+        let Some(pdu) = Lacpdu::read(&frame.payload) else {
+            warn!("[eth{id}] Invalid LACPDU received");
+            return;
+        };
+        if let Some(lacp) = self
+            .bundles
+            .write()
+            .await
+            .iter_mut()
+            .find(|lacp| lacp.has_member(id as u16))
+        {
+            lacp.on_lacpdu(id as u16, pdu, Instant::now());
+        }
+        self.lacp_output().await;
+    }
+
+    async fn lacp_output(&self) {
+        let outgoing = self
+            .bundles
+            .write()
+            .await
+            .iter_mut()
+            .flat_map(|lacp| lacp.take_output())
+            .collect::<Vec<_>>();
+        let llp = self.link_layer_processes.read().await;
+        for (id, frame) in outgoing {
+            if let Some(port) = llp.get(id as usize) {
+                let _ = port.sender.send_async(frame).await;
+            }
+        }
+    }
+
+    /// Sends the BPDUs and flushes the MAC table as the spanning tree asked
+    async fn stp_output(&self) {
+        let (outgoing, flush) = self.stp.write().await.take_output();
+        if let Some(keep) = flush {
+            self.destination_if_table
+                .write()
+                .await
+                .retain(|_, (id, _)| Some(*id) == keep);
+        }
+        let llp = self.link_layer_processes.read().await;
+        for (id, frame) in outgoing {
+            if let Some(port) = llp.get(id) {
+                let _ = port.sender.send_async(frame).await;
+            }
+        }
+    }
 }
 
 // #[async_trait::async_trait]
@@ -375,3 +720,69 @@ pub async fn standard_switch<A: MacAdminAuthority + Send>(
 ) -> Switch {
     switch(ports, mac_authority, PortType::Unknown).await
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::mac::authority::SequentialAuthority;
+
+    use super::{simple_switch, stp::PortState};
+
+    #[tokio::test(start_paused = true)]
+    async fn blocks_one_port_of_a_loop() {
+        let mut authority = SequentialAuthority::new([0, 1, 0]);
+        let mut switches = Vec::new();
+        for _ in 0..3 {
+            let switch = simple_switch(2, &mut authority).await;
+            switch.stp().write().await.forward_delay = 1;
+            switches.push(switch);
+        }
+        // Every switch is connected to the next one
+        for (i, switch) in switches.iter().enumerate() {
+            let (_, _, port) = switch.ports().await.remove(1);
+            let (_, _, next) = switches[(i + 1) % 3].ports().await.remove(0);
+            assert!(
+                port.write()
+                    .await
+                    .connect_other(&mut *next.write().await)
+                    .await
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        let mut states = Vec::new();
+        for switch in switches.iter() {
+            let stp = switch.stp().read().await;
+            assert!(stp.enabled);
+            assert!(stp.ports.iter().all(|port| !port.edge));
+            states.extend((0..2).map(|id| stp.port_state(id)));
+        }
+        let count = |state| states.iter().filter(|&&other| other == state).count();
+        assert_eq!(count(PortState::Blocking), 1);
+        assert_eq!(count(PortState::Forwarding), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forwards_to_hosts_without_bpdus() {
+        let mut authority = SequentialAuthority::new([0, 1, 0]);
+        let switch = simple_switch(1, &mut authority).await;
+        // Doesn't send BPDUs, like a host
+        let host = simple_switch(1, &mut authority).await;
+        host.disable_stp().await;
+        let (_, _, port) = switch.ports().await.remove(0);
+        let (_, _, other) = host.ports().await.remove(0);
+        assert!(
+            port.write()
+                .await
+                .connect_other(&mut *other.write().await)
+                .await
+        );
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(switch.stp().read().await.port_state(0), PortState::Blocking);
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let stp = switch.stp().read().await;
+        assert!(stp.ports[0].edge);
+        assert_eq!(stp.port_state(0), PortState::Forwarding);
+    }
+}
@@ -0,0 +1,950 @@
+use std::{fmt::Display, time::Duration};
+
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::{link::ethernet::packet::EthernetPacket, mac::Mac};
+
+/// Bridge group address the BPDUs are sent to, bridges never forward the frames sent to it
+pub const GROUP_ADDR: Mac = Mac::new([0x01, 0x80, 0xC2, 0x00, 0x00, 0x00]);
+/// LLC header of the BPDUs, the spanning tree SAP with unnumbered information
+const LLC: [u8; 3] = [0x42, 0x42, 0x03];
+
+pub const DEFAULT_BRIDGE_PRIORITY: u16 = 32768;
+pub const DEFAULT_PORT_PRIORITY: u8 = 128;
+/// Cost of a 100 Mb/s link
+pub const DEFAULT_PATH_COST: u32 = 19;
+pub const HELLO_TIME: u16 = 2;
+pub const MAX_AGE: u16 = 20;
+pub const FORWARD_DELAY: u16 = 15;
+/// Time without BPDUs after which an auto edge port becomes an edge port, 802.1D's migrate time
+const EDGE_DELAY: Duration = Duration::from_secs(3);
+
+const CONFIG: u8 = 0x00;
+const TCN: u8 = 0x80;
+const RST: u8 = 0x02;
+
+const TC: u8 = 0x01;
+const PROPOSAL: u8 = 0x02;
+const ROLE_SHIFT: u8 = 2;
+const LEARNING: u8 = 0x10;
+const FORWARDING: u8 = 0x20;
+const AGREEMENT: u8 = 0x40;
+const TC_ACK: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// 802.1D, ports go through listening and learning before forwarding
+    Stp,
+    /// 802.1w, designated ports forward as soon as the bridge downstream agrees
+    Rstp,
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stp => write!(f, "STP"),
+            Self::Rstp => write!(f, "RSTP"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BridgeId {
+    pub priority: u16,
+    pub mac: Mac,
+}
+
+impl BridgeId {
+    fn read(data: &[u8]) -> Self {
+        Self {
+            priority: u16::from_be_bytes([data[0], data[1]]),
+            mac: Mac::new([data[2], data[3], data[4], data[5], data[6], data[7]]),
+        }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.priority.to_be_bytes());
+        data.extend_from_slice(self.mac.as_slice());
+    }
+}
+
+impl Display for BridgeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.priority, self.mac)
+    }
+}
+
+/// Spanning tree priority vector, the lower one is the better
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Vector {
+    pub root: BridgeId,
+    pub cost: u32,
+    /// Designated bridge
+    pub bridge: BridgeId,
+    /// Designated port
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BpduKind {
+    Config,
+    /// Topology change notification, sent towards the root by 802.1D bridges
+    Tcn,
+    Rst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bpdu {
+    pub kind: BpduKind,
+    pub flags: u8,
+    pub vector: Vector,
+    /// Seconds, the frames count in 1/256th of second
+    pub message_age: u16,
+    pub max_age: u16,
+    pub hello_time: u16,
+    pub forward_delay: u16,
+}
+
+impl Bpdu {
+    fn tcn() -> Self {
+        Self {
+            kind: BpduKind::Tcn,
+            flags: 0,
+            vector: Vector {
+                root: BridgeId {
+                    priority: 0,
+                    mac: Mac::new([0; 6]),
+                },
+                cost: 0,
+                bridge: BridgeId {
+                    priority: 0,
+                    mac: Mac::new([0; 6]),
+                },
+                port: 0,
+            },
+            message_age: 0,
+            max_age: 0,
+            hello_time: 0,
+            forward_delay: 0,
+        }
+    }
+
+    /// Reads the LLC payload of a frame sent to the group address
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let data = data.strip_prefix(&LLC)?;
+        if data.len() < 4 || data[..2] != [0, 0] {
+            return None;
+        }
+        let kind = match data[3] {
+            TCN => return Some(Self::tcn()),
+            CONFIG if data.len() >= 35 => BpduKind::Config,
+            RST if data.len() >= 36 => BpduKind::Rst,
+            _ => return None,
+        };
+        let time = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]) / 256;
+        Some(Self {
+            kind,
+            flags: data[4],
+            vector: Vector {
+                root: BridgeId::read(&data[5..13]),
+                cost: u32::from_be_bytes([data[13], data[14], data[15], data[16]]),
+                bridge: BridgeId::read(&data[17..25]),
+                port: u16::from_be_bytes([data[25], data[26]]),
+            },
+            message_age: time(27),
+            max_age: time(29),
+            hello_time: time(31),
+            forward_delay: time(33),
+        })
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut data = LLC.to_vec();
+        data.extend_from_slice(&[0, 0]);
+        let (version, kind) = match self.kind {
+            BpduKind::Config => (0, CONFIG),
+            BpduKind::Tcn => (0, TCN),
+            BpduKind::Rst => (2, RST),
+        };
+        data.extend_from_slice(&[version, kind]);
+        if self.kind == BpduKind::Tcn {
+            return data;
+        }
+        data.push(self.flags);
+        self.vector.root.write(&mut data);
+        data.extend_from_slice(&self.vector.cost.to_be_bytes());
+        self.vector.bridge.write(&mut data);
+        data.extend_from_slice(&self.vector.port.to_be_bytes());
+        for time in [
+            self.message_age,
+            self.max_age,
+            self.hello_time,
+            self.forward_delay,
+        ] {
+            data.extend_from_slice(&time.saturating_mul(256).to_be_bytes());
+        }
+        if self.kind == BpduKind::Rst {
+            // Version 1 length
+            data.push(0);
+        }
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortRole {
+    Disabled,
+    Root,
+    Designated,
+    /// Other path to the root, blocked
+    Alternate,
+    /// Other port of this bridge on the segment of a designated one, blocked
+    Backup,
+}
+
+impl PortRole {
+    fn flags(self) -> u8 {
+        match self {
+            Self::Disabled => 0,
+            Self::Alternate | Self::Backup => 1 << ROLE_SHIFT,
+            Self::Root => 2 << ROLE_SHIFT,
+            Self::Designated => 3 << ROLE_SHIFT,
+        }
+    }
+}
+
+impl Display for PortRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "disabled"),
+            Self::Root => write!(f, "root"),
+            Self::Designated => write!(f, "designated"),
+            Self::Alternate => write!(f, "alternate"),
+            Self::Backup => write!(f, "backup"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PortState {
+    /// Discarding with RSTP
+    Blocking,
+    /// Only used by 802.1D
+    Listening,
+    Learning,
+    Forwarding,
+}
+
+impl PortState {
+    /// The source addresses of the frames received on the port are learned
+    pub fn learning(self) -> bool {
+        self >= Self::Learning
+    }
+}
+
+impl Display for PortState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Blocking => write!(f, "blocking"),
+            Self::Listening => write!(f, "listening"),
+            Self::Learning => write!(f, "learning"),
+            Self::Forwarding => write!(f, "forwarding"),
+        }
+    }
+}
+
+pub struct StpPort {
+    pub mac: Mac,
+    /// Multiple of 16
+    pub priority: u8,
+    pub cost: u32,
+    /// Connected to end hosts, forwards as soon as it's up with RSTP and doesn't cause topology
+    /// changes. Cleared when a BPDU is received on the port
+    pub edge: bool,
+    /// The port becomes an edge port when no BPDU is received within `EDGE_DELAY` of it coming
+    /// up, it isn't one until then
+    pub auto_edge: bool,
+    pub role: PortRole,
+    pub state: PortState,
+    up: bool,
+    /// Best information received on the port, and when it ages out
+    info: Option<(Vector, Instant)>,
+    /// When the port moves to the next state towards forwarding
+    next_state: Option<Instant>,
+    /// Auto edge, when the port becomes an edge port unless a BPDU is received before
+    edge_delay: Option<Instant>,
+    /// RSTP, the bridge downstream agreed to the port forwarding
+    agreed: bool,
+    /// RSTP, topology change advertised on the port until then
+    tc_while: Option<Instant>,
+    /// 802.1D, topology change notification to acknowledge
+    tc_ack: bool,
+}
+
+impl StpPort {
+    fn new(mac: Mac) -> Self {
+        Self {
+            mac,
+            priority: DEFAULT_PORT_PRIORITY,
+            cost: DEFAULT_PATH_COST,
+            edge: false,
+            auto_edge: true,
+            role: PortRole::Disabled,
+            state: PortState::Blocking,
+            up: false,
+            info: None,
+            next_state: None,
+            edge_delay: None,
+            agreed: false,
+            tc_while: None,
+            tc_ack: false,
+        }
+    }
+
+    fn tc_while(&self, now: Instant) -> bool {
+        self.tc_while.is_some_and(|until| now < until)
+    }
+}
+
+/// Spanning tree of a switch, it's disabled until started and all the ports forward meanwhile
+pub struct Stp {
+    pub enabled: bool,
+    pub version: Version,
+    pub bridge: BridgeId,
+    /// Seconds, used when this bridge is the root
+    pub hello_time: u16,
+    pub max_age: u16,
+    pub forward_delay: u16,
+    pub ports: Vec<StpPort>,
+    /// Best vector to the root, the root port's one with its cost added
+    root: Vector,
+    root_port: Option<usize>,
+    /// (message age, max age, hello time, forward delay) received from the root
+    root_times: (u16, u16, u16, u16),
+    /// 802.1D, topology change notifications are sent to the root until acknowledged
+    tcn_pending: bool,
+    /// 802.1D, topology change flag advertised by the root
+    topology_change: bool,
+    /// 802.1D, topology change advertised until then by the root
+    tc_until: Option<Instant>,
+    next_hello: Instant,
+    /// BPDUs to send, with the port
+    outgoing: Vec<(usize, EthernetPacket)>,
+    /// The MAC table has to be flushed, keeping the entries of the port if any
+    flush: Option<Option<usize>>,
+}
+
+impl Default for Stp {
+    fn default() -> Self {
+        let bridge = BridgeId {
+            priority: DEFAULT_BRIDGE_PRIORITY,
+            mac: Mac::new([0; 6]),
+        };
+        Self {
+            enabled: false,
+            version: Version::Rstp,
+            bridge,
+            hello_time: HELLO_TIME,
+            max_age: MAX_AGE,
+            forward_delay: FORWARD_DELAY,
+            ports: Vec::new(),
+            root: Vector {
+                root: bridge,
+                cost: 0,
+                bridge,
+                port: 0,
+            },
+            root_port: None,
+            root_times: (0, MAX_AGE, HELLO_TIME, FORWARD_DELAY),
+            tcn_pending: false,
+            topology_change: false,
+            tc_until: None,
+            next_hello: Instant::now(),
+            outgoing: Vec::new(),
+            flush: None,
+        }
+    }
+}
+
+impl Stp {
+    /// The first port gives its address to the bridge
+    pub(super) fn add_port(&mut self, mac: Mac) {
+        if self.ports.is_empty() {
+            self.bridge.mac = mac;
+        }
+        self.ports.push(StpPort::new(mac));
+    }
+
+    /// Makes the port an edge port or not, `None` detects it again from the BPDUs received.
+    /// `false` if there's no such port
+    pub fn set_edge(&mut self, id: usize, edge: Option<bool>, now: Instant) -> bool {
+        let Some(port) = self.ports.get_mut(id) else {
+            return false;
+        };
+        port.auto_edge = edge.is_none();
+        port.edge = edge.unwrap_or(false);
+        port.edge_delay = (port.auto_edge && port.up).then_some(now + EDGE_DELAY);
+        if port.edge && port.role == PortRole::Designated && self.version == Version::Rstp {
+            self.set_state(id, PortState::Forwarding, now);
+        }
+        true
+    }
+
+    pub fn port_state(&self, id: usize) -> PortState {
+        match self.ports.get(id) {
+            _ if !self.enabled => PortState::Forwarding,
+            Some(port) => port.state,
+            None => PortState::Blocking,
+        }
+    }
+
+    /// Whether each port forwards
+    pub fn forwarding_ports(&self) -> Vec<bool> {
+        (0..self.ports.len())
+            .map(|id| self.port_state(id) == PortState::Forwarding)
+            .collect()
+    }
+
+    pub fn root(&self) -> BridgeId {
+        self.root.root
+    }
+
+    pub fn root_port(&self) -> Option<usize> {
+        self.root_port
+    }
+
+    fn port_id(&self, id: usize) -> u16 {
+        ((self.ports[id].priority as u16) << 8) | ((id as u16 + 1) & 0x0fff)
+    }
+
+    /// Vector advertised by the port if it's designated
+    fn designated(&self, id: usize) -> Vector {
+        Vector {
+            root: self.root.root,
+            cost: self.root.cost,
+            bridge: self.bridge,
+            port: self.port_id(id),
+        }
+    }
+
+    /// BPDUs to send and MAC table flush asked since the last call
+    pub(super) fn take_output(&mut self) -> (Vec<(usize, EthernetPacket)>, Option<Option<usize>>) {
+        (std::mem::take(&mut self.outgoing), self.flush.take())
+    }
+
+    /// Every port starts blocking and this bridge as the root
+    pub(super) fn enable(&mut self, version: Version, now: Instant) {
+        self.enabled = true;
+        self.version = version;
+        self.root = Vector {
+            root: self.bridge,
+            cost: 0,
+            bridge: self.bridge,
+            port: 0,
+        };
+        self.root_port = None;
+        self.tcn_pending = false;
+        self.topology_change = false;
+        self.tc_until = None;
+        self.next_hello = now;
+        for port in self.ports.iter_mut() {
+            *port = StpPort {
+                priority: port.priority,
+                cost: port.cost,
+                edge: port.edge,
+                auto_edge: port.auto_edge,
+                ..StpPort::new(port.mac)
+            };
+        }
+        self.flush = Some(None);
+    }
+
+    pub(super) fn disable(&mut self) {
+        self.enabled = false;
+        self.outgoing.clear();
+        self.flush = Some(None);
+    }
+
+    fn flush(&mut self, keep: Option<usize>) {
+        self.flush = match self.flush {
+            None => Some(keep),
+            Some(kept) if kept == keep => Some(keep),
+            Some(_) => Some(None),
+        };
+    }
+
+    fn set_state(&mut self, id: usize, state: PortState, now: Instant) {
+        let port = &mut self.ports[id];
+        let old = port.state;
+        if old == state {
+            return;
+        }
+        port.state = state;
+        port.next_state = match state {
+            PortState::Listening | PortState::Learning => {
+                Some(now + Duration::from_secs(self.root_times.3 as u64))
+            }
+            PortState::Blocking | PortState::Forwarding => None,
+        };
+        info!("[eth{id}] Spanning tree port state {old} -> {state}");
+        let edge = port.edge;
+        match self.version {
+            _ if edge => {}
+            Version::Stp if state == PortState::Forwarding || old == PortState::Forwarding => {
+                self.topology_changed(id, now)
+            }
+            Version::Rstp if state == PortState::Forwarding => self.topology_changed(id, now),
+            _ => {}
+        }
+    }
+
+    fn set_role(&mut self, id: usize, role: PortRole, now: Instant) {
+        let port = &mut self.ports[id];
+        if port.role == role {
+            return;
+        }
+        info!("[eth{id}] Spanning tree port role {} -> {role}", port.role);
+        port.role = role;
+        port.agreed = false;
+        let state = port.state;
+        let edge = port.edge;
+        match (role, self.version) {
+            (PortRole::Disabled | PortRole::Alternate | PortRole::Backup, _) => {
+                self.ports[id].next_state = None;
+                self.set_state(id, PortState::Blocking, now)
+            }
+            (PortRole::Root | PortRole::Designated, Version::Stp) => {
+                if state == PortState::Blocking {
+                    self.set_state(id, PortState::Listening, now)
+                }
+            }
+            (PortRole::Root, Version::Rstp) => self.set_state(id, PortState::Forwarding, now),
+            (PortRole::Designated, Version::Rstp) if edge => {
+                self.set_state(id, PortState::Forwarding, now)
+            }
+            (PortRole::Designated, Version::Rstp) => {
+                if state == PortState::Blocking {
+                    self.start_proposing(id, now)
+                }
+            }
+        }
+    }
+
+    /// RSTP designated port waiting for an agreement, it forwards after two forward delays
+    /// otherwise
+    fn start_proposing(&mut self, id: usize, now: Instant) {
+        let port = &mut self.ports[id];
+        port.agreed = false;
+        port.next_state = Some(now + Duration::from_secs(self.root_times.3 as u64));
+    }
+
+    /// Elects the root and gives their role to the ports
+    fn update_roles(&mut self, now: Instant) {
+        let mut best: Option<(Vector, u16, usize)> = None;
+        for (id, port) in self.ports.iter().enumerate() {
+            let Some((info, _)) = port.info.filter(|_| port.up) else {
+                continue;
+            };
+            // Our own BPDUs received on another port
+            if info.bridge == self.bridge {
+                continue;
+            }
+            let vector = Vector {
+                cost: info.cost.saturating_add(port.cost),
+                ..info
+            };
+            let candidate = (vector, self.port_id(id), id);
+            if best.is_none_or(|best| (candidate.0, candidate.1) < (best.0, best.1)) {
+                best = Some(candidate);
+            }
+        }
+        let old_root = self.root.root;
+        let old_root_port = self.root_port;
+        (self.root, self.root_port) = match best {
+            Some((vector, _, id)) if vector.root < self.bridge => (vector, Some(id)),
+            _ => {
+                self.root_times = (0, self.max_age, self.hello_time, self.forward_delay);
+                (
+                    Vector {
+                        root: self.bridge,
+                        cost: 0,
+                        bridge: self.bridge,
+                        port: 0,
+                    },
+                    None,
+                )
+            }
+        };
+        if self.root.root != old_root {
+            info!("Spanning tree root is now {}", self.root.root);
+        }
+        let roles = (0..self.ports.len())
+            .map(|id| {
+                let port = &self.ports[id];
+                if !port.up {
+                    PortRole::Disabled
+                } else if Some(id) == self.root_port {
+                    PortRole::Root
+                } else {
+                    match port.info {
+                        Some((info, _)) if info < self.designated(id) => {
+                            if info.bridge == self.bridge {
+                                PortRole::Backup
+                            } else {
+                                PortRole::Alternate
+                            }
+                        }
+                        _ => PortRole::Designated,
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        // The root port comes last so that the ports it could loop through are blocked already
+        for (id, role) in roles.iter().enumerate() {
+            if *role != PortRole::Root {
+                self.set_role(id, *role, now);
+            }
+        }
+        if let Some(root_port) = self.root_port {
+            if self.version == Version::Rstp && old_root_port != self.root_port {
+                self.sync(now);
+            }
+            self.set_role(root_port, PortRole::Root, now);
+        }
+    }
+
+    /// RSTP, blocks the designated ports the bridges downstream didn't agree to, before the root
+    /// port forwards
+    fn sync(&mut self, now: Instant) {
+        for id in 0..self.ports.len() {
+            let port = &self.ports[id];
+            if port.role == PortRole::Designated && !port.edge && !port.agreed {
+                self.set_state(id, PortState::Blocking, now);
+                self.start_proposing(id, now);
+            }
+        }
+    }
+
+    fn topology_changed(&mut self, id: usize, now: Instant) {
+        match self.version {
+            Version::Stp if self.root_port.is_none() => {
+                self.tc_until =
+                    Some(now + Duration::from_secs((self.max_age + self.forward_delay) as u64));
+                self.flush(None);
+            }
+            Version::Stp => {
+                self.tcn_pending = true;
+                if let Some(root_port) = self.root_port {
+                    self.send(root_port, Bpdu::tcn());
+                }
+            }
+            Version::Rstp => {
+                self.propagate_tc(None, now);
+                self.flush(Some(id));
+            }
+        }
+    }
+
+    /// RSTP, advertises a topology change on the ports but the one it was received on. The ports
+    /// already advertising one don't send it again, so it doesn't go round a loop
+    fn propagate_tc(&mut self, from: Option<usize>, now: Instant) {
+        let until = now + Duration::from_secs(2 * self.hello_time as u64);
+        for id in 0..self.ports.len() {
+            let port = &mut self.ports[id];
+            if Some(id) != from
+                && !port.edge
+                && matches!(port.role, PortRole::Root | PortRole::Designated)
+                && !port.tc_while(now)
+            {
+                port.tc_while = Some(until);
+                self.transmit(id, now);
+            }
+        }
+    }
+
+    fn send(&mut self, id: usize, bpdu: Bpdu) {
+        let mac = self.ports[id].mac;
+        if let Some(frame) = EthernetPacket::new_generic(GROUP_ADDR, mac, bpdu.to_vec()) {
+            self.outgoing.push((id, frame));
+        }
+    }
+
+    /// Sends the BPDU of the port, with its role
+    fn transmit(&mut self, id: usize, now: Instant) {
+        let port = &self.ports[id];
+        let (message_age, max_age, hello_time, forward_delay) = self.root_times;
+        let mut flags = 0;
+        let kind = match self.version {
+            Version::Stp => {
+                if self.topology_change || self.tc_until.is_some_and(|until| now < until) {
+                    flags |= TC;
+                }
+                if port.tc_ack {
+                    flags |= TC_ACK;
+                }
+                BpduKind::Config
+            }
+            Version::Rstp => {
+                flags |= port.role.flags();
+                if port.tc_while(now) {
+                    flags |= TC;
+                }
+                if port.state.learning() {
+                    flags |= LEARNING;
+                }
+                if port.state == PortState::Forwarding {
+                    flags |= FORWARDING;
+                }
+                if port.role == PortRole::Designated && port.state != PortState::Forwarding {
+                    flags |= PROPOSAL;
+                }
+                BpduKind::Rst
+            }
+        };
+        let bpdu = Bpdu {
+            kind,
+            flags,
+            vector: self.designated(id),
+            message_age,
+            max_age,
+            hello_time,
+            forward_delay,
+        };
+        self.ports[id].tc_ack = false;
+        self.send(id, bpdu);
+    }
+
+    /// Answers a proposal, the port is blocked or forwards towards the root
+    fn agree(&mut self, id: usize) {
+        let bpdu = Bpdu {
+            kind: BpduKind::Rst,
+            flags: self.ports[id].role.flags() | AGREEMENT,
+            vector: self.designated(id),
+            message_age: self.root_times.0,
+            max_age: self.root_times.1,
+            hello_time: self.root_times.2,
+            forward_delay: self.root_times.3,
+        };
+        self.send(id, bpdu);
+    }
+
+    fn transmit_designated(&mut self, now: Instant) {
+        for id in 0..self.ports.len() {
+            if self.ports[id].role == PortRole::Designated {
+                self.transmit(id, now);
+            }
+        }
+    }
+
+    pub(super) fn on_bpdu(&mut self, id: usize, bpdu: Bpdu, now: Instant) {
+        if !self.enabled || id >= self.ports.len() || !self.ports[id].up {
+            return;
+        }
+        if self.ports[id].edge {
+            info!("[eth{id}] BPDU received on an edge port, it's not one anymore");
+            self.ports[id].edge = false;
+        }
+        self.ports[id].edge_delay = None;
+        if bpdu.kind == BpduKind::Tcn {
+            if self.ports[id].role == PortRole::Designated {
+                self.ports[id].tc_ack = true;
+                self.transmit(id, now);
+                self.topology_changed(id, now);
+            }
+            return;
+        }
+        // Proposals and agreements are only understood between RSTP bridges, the others use the
+        // timers
+        let rapid = self.version == Version::Rstp && bpdu.kind == BpduKind::Rst;
+        let vector = bpdu.vector;
+        if rapid
+            && bpdu.flags & AGREEMENT != 0
+            && self.ports[id].role == PortRole::Designated
+            && vector.root == self.root.root
+        {
+            self.ports[id].agreed = true;
+            self.set_state(id, PortState::Forwarding, now);
+        }
+        if self.version == Version::Rstp && bpdu.flags & TC != 0 {
+            self.flush(Some(id));
+            self.propagate_tc(Some(id), now);
+        }
+        let port = &self.ports[id];
+        let same_sender = port
+            .info
+            .is_some_and(|(info, _)| (info.bridge, info.port) == (vector.bridge, vector.port));
+        if vector >= self.designated(id) && !same_sender {
+            // Inferior information from another designated port, ours answers with its own
+            let designated = bpdu.kind == BpduKind::Config
+                || bpdu.flags & PortRole::Designated.flags() == PortRole::Designated.flags();
+            if port.role == PortRole::Designated && designated && bpdu.flags & AGREEMENT == 0 {
+                self.transmit(id, now);
+            }
+            return;
+        }
+        if bpdu.message_age >= bpdu.max_age {
+            return;
+        }
+        let expires = match self.version {
+            Version::Stp => now + Duration::from_secs((bpdu.max_age - bpdu.message_age) as u64),
+            Version::Rstp => now + Duration::from_secs(3 * bpdu.hello_time.max(1) as u64),
+        };
+        self.ports[id].info = Some((vector, expires));
+        self.update_roles(now);
+        if self.root_port != Some(id) {
+            match self.ports[id].role {
+                // The sender's information got worse than ours
+                PortRole::Designated => self.transmit(id, now),
+                _ if rapid && bpdu.flags & PROPOSAL != 0 => self.agree(id),
+                _ => {}
+            }
+            return;
+        }
+        self.root_times = (
+            bpdu.message_age + 1,
+            bpdu.max_age,
+            bpdu.hello_time,
+            bpdu.forward_delay,
+        );
+        if self.version == Version::Stp {
+            if bpdu.flags & TC_ACK != 0 {
+                self.tcn_pending = false;
+            }
+            let topology_change = bpdu.flags & TC != 0;
+            if topology_change && !self.topology_change {
+                self.flush(None);
+            }
+            self.topology_change = topology_change;
+        }
+        if rapid && bpdu.flags & PROPOSAL != 0 {
+            self.sync(now);
+            self.agree(id);
+        }
+        // The BPDUs of the root are relayed down the tree
+        self.transmit_designated(now);
+    }
+
+    /// Ages the information and the timers out, `up` tells whether each port is connected
+    pub(super) fn on_tick(&mut self, now: Instant, up: &[bool]) {
+        if !self.enabled {
+            return;
+        }
+        let mut changed = false;
+        for (id, port) in self.ports.iter_mut().enumerate() {
+            let up = up.get(id).copied().unwrap_or(false);
+            if port.up != up {
+                port.up = up;
+                port.info = None;
+                if port.auto_edge {
+                    port.edge = false;
+                    port.edge_delay = up.then_some(now + EDGE_DELAY);
+                }
+                changed = true;
+            }
+            if port.info.is_some_and(|(_, expires)| expires <= now) {
+                info!("[eth{id}] Spanning tree information aged out");
+                port.info = None;
+                changed = true;
+            }
+        }
+        if changed {
+            self.update_roles(now);
+        }
+        for id in 0..self.ports.len() {
+            let port = &mut self.ports[id];
+            if port.edge_delay.is_none_or(|until| now < until) {
+                continue;
+            }
+            info!("[eth{id}] No BPDU received, it's an edge port");
+            port.edge_delay = None;
+            port.edge = true;
+            if port.role == PortRole::Designated && self.version == Version::Rstp {
+                self.set_state(id, PortState::Forwarding, now);
+            }
+        }
+        for id in 0..self.ports.len() {
+            let port = &self.ports[id];
+            if port.next_state.is_none_or(|next| now < next) {
+                continue;
+            }
+            let next = match port.state {
+                PortState::Blocking | PortState::Listening => PortState::Learning,
+                PortState::Learning | PortState::Forwarding => PortState::Forwarding,
+            };
+            self.set_state(id, next, now);
+        }
+        if self.tc_until.is_some_and(|until| until <= now) {
+            self.tc_until = None;
+        }
+        if now < self.next_hello {
+            return;
+        }
+        let hello_time = match self.root_port {
+            None => self.hello_time,
+            Some(_) => self.root_times.2,
+        };
+        self.next_hello = now + Duration::from_secs(hello_time.max(1) as u64);
+        match (self.version, self.root_port) {
+            (Version::Stp, None) => self.transmit_designated(now),
+            (Version::Stp, Some(root_port)) => {
+                if self.tcn_pending {
+                    self.send(root_port, Bpdu::tcn());
+                }
+            }
+            (Version::Rstp, root_port) => {
+                self.transmit_designated(now);
+                if let Some(root_port) = root_port.filter(|id| self.ports[*id].tc_while(now)) {
+                    self.transmit(root_port, now);
+                }
+            }
+        }
+    }
+
+    pub fn print(&self) -> String {
+        let mut table = prettytable::table!([
+            "port",
+            "role",
+            "state",
+            "cost",
+            "designated bridge",
+            "designated port",
+            "edge"
+        ]);
+        if self.ports.is_empty() {
+            table.add_empty_row();
+        }
+        for (id, port) in self.ports.iter().enumerate() {
+            let designated = match port.role {
+                PortRole::Designated => Some(self.designated(id)),
+                _ => port.info.map(|(info, _)| info),
+            };
+            table.add_row(prettytable::row![
+                format!("eth{id}"),
+                port.role,
+                port.state,
+                port.cost,
+                designated.map_or("-".to_string(), |vector| vector.bridge.to_string()),
+                designated.map_or("-".to_string(), |vector| format!("{:#06x}", vector.port)),
+                match (port.edge, port.auto_edge) {
+                    (true, _) => "yes",
+                    (false, true) => "auto",
+                    (false, false) => "no",
+                }
+            ]);
+        }
+        let root = match self.root_port {
+            None => "this bridge is the root".to_string(),
+            Some(id) => format!(
+                "root {}, cost {} through eth{id}",
+                self.root.root, self.root.cost
+            ),
+        };
+        let status = if self.enabled {
+            self.version.to_string()
+        } else {
+            "disabled".to_string()
+        };
+        format!("{status}, bridge {}, {root}\n{table}", self.bridge)
+    }
+}
@@ -0,0 +1,12 @@
+//! Corrupted copies of valid messages, the parsers must reject or decode them without panicking
+
+/// Every copy of the data with a single bit flipped, then every truncation of it
+pub fn corruptions(data: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    let flips = (0..data.len() * 8).map(|bit| {
+        let mut data = data.to_vec();
+        data[bit / 8] ^= 0x80 >> (bit % 8);
+        data
+    });
+    let truncations = (0..data.len()).map(|len| data[..len].to_vec());
+    flips.chain(truncations)
+}
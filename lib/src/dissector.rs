@@ -0,0 +1,342 @@
+//! Decoding of the captured frames into one-line summaries, like tcpdump
+
+use std::fmt::Display;
+
+use crate::{
+    link::ethernet::{ethertype::EtherType, packet::EthernetPacket},
+    network::{
+        arp::packet::{ArpPacket, Operation},
+        ipv4::{addr::IpV4Addr, packet::Ipv4Packet, protocol::ProtocolType},
+        ipv6::packet::IpV6Header,
+    },
+    transport::{
+        icmp::packet::{DestinationUnreachable, IcmpPacket, TimeExceeded},
+        tcp::packet::{Flags, TcpPacket},
+        udp::packet::UdpPacket,
+    },
+};
+
+pub mod filter;
+
+pub enum Network {
+    Arp(ArpPacket),
+    IpV4(Ipv4Packet),
+    IpV6(IpV6Header),
+    /// Unknown ether type or not decodable
+    Other,
+}
+
+pub enum Transport {
+    Icmp(IcmpPacket),
+    Udp(UdpPacket),
+    Tcp(TcpPacket),
+    /// Unknown protocol, not decodable or a fragment after the first one
+    Other,
+    /// Not an IP packet
+    None,
+}
+
+/// Frame decoded as deep as the known protocols go
+pub struct Dissection {
+    pub frame: EthernetPacket,
+    pub network: Network,
+    pub transport: Transport,
+}
+
+impl Dissection {
+    pub fn new(frame: EthernetPacket) -> Self {
+        let network = match frame.get_ether_type() {
+            EtherType::ARP => ArpPacket::from_vec(&frame.payload).map(Network::Arp),
+            EtherType::IP_V4 => Ipv4Packet::from_vec(&frame.payload).map(Network::IpV4),
+            EtherType::IP_V6 => {
+                IpV6Header::from_vec(&frame.payload).map(|(header, _)| Network::IpV6(header))
+            }
+            _ => None,
+        }
+        .unwrap_or(Network::Other);
+        let transport = match &network {
+            Network::IpV4(packet) if packet.header.fragment_offset() != 0 => Transport::Other,
+            Network::IpV4(Ipv4Packet { header, payload }) => match header.protocol {
+                ProtocolType::ICMP => IcmpPacket::from_vec(payload).map(Transport::Icmp),
+                ProtocolType::UDP => UdpPacket::from_vec(payload).map(Transport::Udp),
+                ProtocolType::TCP => TcpPacket::from_vec(payload).map(Transport::Tcp),
+                _ => None,
+            }
+            .unwrap_or(Transport::Other),
+            _ => Transport::None,
+        };
+        Self {
+            frame,
+            network,
+            transport,
+        }
+    }
+
+    /// IPv4 source and destination, the sender and target of ARP
+    pub fn ip_v4_addrs(&self) -> Option<(IpV4Addr, IpV4Addr)> {
+        match &self.network {
+            Network::IpV4(packet) => Some((packet.header.source, packet.header.destination)),
+            Network::Arp(packet) => Some((
+                ip_v4(packet.sender_protocol_address())?,
+                ip_v4(packet.target_protocol_address())?,
+            )),
+            _ => None,
+        }
+    }
+
+    /// UDP or TCP source and destination ports
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        match &self.transport {
+            Transport::Udp(packet) => Some((packet.source_port, packet.destination_port)),
+            Transport::Tcp(packet) => Some((packet.source_port, packet.destination_port)),
+            _ => None,
+        }
+    }
+}
+
+fn ip_v4(addr: &[u8]) -> Option<IpV4Addr> {
+    addr.try_into().ok().map(IpV4Addr::new)
+}
+
+impl Display for Dissection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} > {}", self.frame.get_source(), self.frame.get_dest())?;
+        for tag in self.frame.tags() {
+            let kind = if tag.is_service() { "svlan" } else { "vlan" };
+            write!(f, ", {kind} {}", tag.vlan_id())?;
+        }
+        write!(f, ", ")?;
+        match &self.network {
+            Network::Arp(packet) => {
+                let sender = ip_v4(packet.sender_protocol_address());
+                let target = ip_v4(packet.target_protocol_address());
+                match (packet.operation(), sender, target) {
+                    (Operation::Request, Some(sender), Some(target)) => {
+                        write!(f, "ARP request who-has {target} tell {sender}")
+                    }
+                    (Operation::Reply, Some(sender), _) => write!(
+                        f,
+                        "ARP reply {sender} is-at {}",
+                        hex(packet.sender_hardware_address())
+                    ),
+                    (operation, ..) => write!(
+                        f,
+                        "ARP {operation:?} for protocol {:#06x}",
+                        packet.protocol_type().to_u16()
+                    ),
+                }
+            }
+            Network::IpV4(Ipv4Packet { header, payload }) => {
+                let (source, destination) = (header.source, header.destination);
+                match self.ports() {
+                    Some((source_port, destination_port)) => write!(
+                        f,
+                        "IPv4 {source}.{source_port} > {destination}.{destination_port}: "
+                    )?,
+                    _ => write!(f, "IPv4 {source} > {destination}: ")?,
+                }
+                match &self.transport {
+                    Transport::Icmp(packet) => write!(f, "ICMP {}", icmp(packet))?,
+                    Transport::Udp(packet) => write!(f, "UDP length {}", packet.payload.len())?,
+                    Transport::Tcp(packet) => write!(
+                        f,
+                        "TCP [{}] seq {} ack {} win {} length {}",
+                        tcp_flags(packet.flags),
+                        packet.seq,
+                        packet.ack,
+                        packet.window,
+                        packet.payload.len()
+                    )?,
+                    Transport::Other | Transport::None => write!(
+                        f,
+                        "protocol {} length {}",
+                        header.protocol.inner(),
+                        payload.len()
+                    )?,
+                }
+                if header.is_fragment() {
+                    write!(
+                        f,
+                        ", fragment id {} offset {}",
+                        header.identification(),
+                        header.fragment_offset()
+                    )?;
+                }
+                write!(f, ", ttl {}", header.time_to_live)
+            }
+            Network::IpV6(header) => write!(
+                f,
+                "IPv6 {} > {}: next header {}, hop limit {}",
+                header.source,
+                header.destination,
+                header.next_header.inner(),
+                header.hop_limit
+            ),
+            Network::Other => write!(
+                f,
+                "ether type {:#06x} length {}",
+                self.frame.get_ether_type().to_u16(),
+                self.frame.payload.len()
+            ),
+        }
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn icmp(packet: &IcmpPacket) -> String {
+    match packet {
+        IcmpPacket::EchoRequest { id, seq } => format!("echo request id {id} seq {seq}"),
+        IcmpPacket::EchoReply { id, seq } => format!("echo reply id {id} seq {seq}"),
+        IcmpPacket::DestinationUnreachable(unreachable) => match unreachable {
+            DestinationUnreachable::NetUnreachable { .. } => "net unreachable".into(),
+            DestinationUnreachable::HostUnreachable { .. } => "host unreachable".into(),
+            DestinationUnreachable::ProtocolUnreachable { .. } => "protocol unreachable".into(),
+            DestinationUnreachable::PortUnreachable { .. } => "port unreachable".into(),
+            DestinationUnreachable::FragmentationNeeded { next_hop_mtu, .. } => {
+                format!("fragmentation needed, mtu {next_hop_mtu}")
+            }
+            DestinationUnreachable::AdministrativelyProhibited { .. } => {
+                "administratively prohibited".into()
+            }
+        },
+        IcmpPacket::TimeExceeded(TimeExceeded::TtlTransit { .. }) => {
+            "time exceeded in transit".into()
+        }
+        IcmpPacket::TimeExceeded(TimeExceeded::FragmentReassembly { .. }) => {
+            "time exceeded in reassembly".into()
+        }
+    }
+}
+
+/// Flags in tcpdump's notation
+fn tcp_flags(flags: Flags) -> String {
+    let res = [
+        (Flags::SYN, 'S'),
+        (Flags::FIN, 'F'),
+        (Flags::RST, 'R'),
+        (Flags::PSH, 'P'),
+        (Flags::URG, 'U'),
+        (Flags::ACK, '.'),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .map(|(_, c)| c)
+    .collect::<String>();
+    if res.is_empty() {
+        "none".into()
+    } else {
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        application::rip::packet::RipPacket,
+        corruption::corruptions,
+        link::ethernet::{dot1q::Tag, ethertype::EtherType, packet::EthernetPacket},
+        mac::{self, Mac},
+        network::{
+            arp::packet::ArpPacket,
+            ipv4::{
+                addr::IpV4Addr,
+                packet::{Ecn, Flags, IpV4Header, Ipv4Packet},
+                protocol::ProtocolType,
+            },
+        },
+        transport::{
+            icmp::packet::{DestinationUnreachable, IcmpPacket},
+            tcp::packet::{self, TcpPacket},
+            udp::packet::UdpPacket,
+        },
+    };
+
+    use super::{Dissection, Network};
+
+    const SOURCE: Mac = Mac::new([2, 0, 0, 0, 0, 1]);
+
+    fn ip_v4(protocol: ProtocolType, payload: Vec<u8>) -> Vec<u8> {
+        Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                Ecn::NotECT,
+                payload.len() as u16,
+                1,
+                Flags::empty(),
+                0,
+                64,
+                protocol,
+                IpV4Addr::new([10, 0, 0, 2]),
+                IpV4Addr::new([10, 0, 0, 1]),
+                vec![],
+            ),
+            payload,
+        )
+        .to_vec()
+    }
+
+    #[test]
+    fn survives_corrupted_frames() {
+        let arp = ArpPacket::new_request(
+            1,
+            EtherType::IP_V4,
+            SOURCE.as_slice().to_vec(),
+            vec![10, 0, 0, 1],
+            vec![10, 0, 0, 2],
+        );
+        let mut tagged = EthernetPacket::new_arp(mac::BROADCAST, SOURCE, arp.to_vec()).unwrap();
+        tagged.push_tag(Tag::new(0, false, 10));
+        let rip = UdpPacket {
+            source_port: 520,
+            destination_port: 520,
+            payload: RipPacket::whole_table_request().to_vec(),
+        };
+        let syn = TcpPacket {
+            mss: Some(1460),
+            ..TcpPacket::new(1024, 80, 1, 0, packet::Flags::SYN, 65535, vec![])
+        };
+        let unreachable =
+            IcmpPacket::DestinationUnreachable(DestinationUnreachable::PortUnreachable {
+                data: ip_v4(ProtocolType::UDP, rip.to_vec()),
+            });
+        let frames = [
+            tagged,
+            EthernetPacket::new_ip_v4(
+                mac::BROADCAST,
+                SOURCE,
+                ip_v4(ProtocolType::UDP, rip.to_vec()),
+            )
+            .unwrap(),
+            EthernetPacket::new_ip_v4(
+                mac::BROADCAST,
+                SOURCE,
+                ip_v4(ProtocolType::TCP, syn.to_vec()),
+            )
+            .unwrap(),
+            EthernetPacket::new_ip_v4(
+                mac::BROADCAST,
+                SOURCE,
+                ip_v4(ProtocolType::ICMP, unreachable.to_vec()),
+            )
+            .unwrap(),
+        ];
+        for frame in frames {
+            let data = frame.to_vec();
+            assert!(!matches!(Dissection::new(frame).network, Network::Other));
+            for data in corruptions(&data) {
+                if let Some(frame) = EthernetPacket::from_vec(&data) {
+                    let dissection = Dissection::new(frame);
+                    dissection.to_string();
+                    dissection.ip_v4_addrs();
+                    dissection.ports();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Filter expressions over the dissected frames, a subset of tcpdump's:
+//!
+//! - `[src|dst] host <ip>`, `[src|dst] net <ip>/<len>`, `[src|dst] port <port>`
+//! - `arp`, `ip`, `ip6`, `icmp`, `udp`, `tcp` and `proto <name|number>` for the IP protocols
+//! - `vlan [id]`, `ether proto <name|number>` and `ether [src|dst] host <mac>`
+//! - combined with `and`/`&&`, `or`/`||`, `not`/`!` and parentheses, side by side primitives are
+//!   and-ed like in `udp port 520`
+
+use std::{fmt::Display, iter::Peekable, str::FromStr, vec::IntoIter};
+
+use crate::{
+    link::ethernet::ethertype::EtherType,
+    mac::Mac,
+    network::ipv4::{
+        addr::{IpV4Addr, IpV4Mask},
+        protocol::ProtocolType,
+    },
+};
+
+use super::{Dissection, Network};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Source,
+    Destination,
+    Either,
+}
+
+impl Direction {
+    fn matches<T: Copy>(self, (source, destination): (T, T), f: impl Fn(T) -> bool) -> bool {
+        match self {
+            Self::Source => f(source),
+            Self::Destination => f(destination),
+            Self::Either => f(source) || f(destination),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// Every frame, the empty expression
+    Any,
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    /// IPv4 address, or ARP sender and target
+    Host(Direction, IpV4Addr),
+    Net(Direction, IpV4Addr, IpV4Mask),
+    /// UDP or TCP port
+    Port(Direction, u16),
+    /// Any tag, or one with the VLAN id
+    Vlan(Option<u16>),
+    EtherType(EtherType),
+    EtherHost(Direction, Mac),
+    /// IPv4 protocol
+    Protocol(ProtocolType),
+}
+
+impl Filter {
+    pub fn matches(&self, dissection: &Dissection) -> bool {
+        match self {
+            Self::Any => true,
+            Self::And(a, b) => a.matches(dissection) && b.matches(dissection),
+            Self::Or(a, b) => a.matches(dissection) || b.matches(dissection),
+            Self::Not(a) => !a.matches(dissection),
+            Self::Host(direction, host) => dissection
+                .ip_v4_addrs()
+                .is_some_and(|addrs| direction.matches(addrs, |addr| addr == *host)),
+            Self::Net(direction, net, mask) => dissection
+                .ip_v4_addrs()
+                .is_some_and(|addrs| direction.matches(addrs, |addr| *mask & addr == *mask & *net)),
+            Self::Port(direction, port) => dissection
+                .ports()
+                .is_some_and(|ports| direction.matches(ports, |p| p == *port)),
+            Self::Vlan(id) => dissection
+                .frame
+                .tags()
+                .iter()
+                .any(|tag| id.is_none_or(|id| tag.vlan_id() == id)),
+            Self::EtherType(ether_type) => dissection.frame.get_ether_type() == *ether_type,
+            Self::EtherHost(direction, host) => direction.matches(
+                (dissection.frame.get_source(), dissection.frame.get_dest()),
+                |mac| mac == *host,
+            ),
+            Self::Protocol(protocol) => matches!(
+                &dissection.network,
+                Network::IpV4(packet) if packet.header.protocol == *protocol
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterParseError(String);
+
+impl Display for FilterParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid filter: {}", self.0)
+    }
+}
+
+impl std::error::Error for FilterParseError {}
+
+impl FromStr for Filter {
+    type Err = FilterParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = tokenize(s).into_iter().peekable();
+        if tokens.peek().is_none() {
+            return Ok(Self::Any);
+        }
+        let filter = parse_or(&mut tokens)?;
+        match tokens.next() {
+            Some(token) => Err(FilterParseError(format!("unexpected `{token}`"))),
+            None => Ok(filter),
+        }
+    }
+}
+
+type Tokens = Peekable<IntoIter<String>>;
+
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let operator = match c {
+            '(' | ')' => Some(c.to_string()),
+            '!' if chars.peek() != Some(&'=') => Some(c.to_string()),
+            '&' | '|' if chars.peek() == Some(&c) => {
+                chars.next();
+                Some(format!("{c}{c}"))
+            }
+            _ => None,
+        };
+        if operator.is_some() || c.is_whitespace() {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            tokens.extend(operator);
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn next(tokens: &mut Tokens, expected: &str) -> Result<String, FilterParseError> {
+    tokens
+        .next()
+        .ok_or_else(|| FilterParseError(format!("expected {expected}")))
+}
+
+fn value<T: FromStr>(tokens: &mut Tokens, expected: &str) -> Result<T, FilterParseError> {
+    let token = next(tokens, expected)?;
+    token
+        .parse()
+        .map_err(|_| FilterParseError(format!("`{token}` isn't {expected}")))
+}
+
+/// Decimal or `0x` hexadecimal number
+fn number<T: TryFrom<u32>>(token: &str) -> Option<T> {
+    match token.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => token.parse().ok(),
+    }
+    .and_then(|n| T::try_from(n).ok())
+}
+
+fn parse_or(tokens: &mut Tokens) -> Result<Filter, FilterParseError> {
+    let mut filter = parse_and(tokens)?;
+    while tokens.next_if(|t| t == "or" || t == "||").is_some() {
+        filter = Filter::Or(Box::new(filter), Box::new(parse_and(tokens)?));
+    }
+    Ok(filter)
+}
+
+fn parse_and(tokens: &mut Tokens) -> Result<Filter, FilterParseError> {
+    let mut filter = parse_not(tokens)?;
+    loop {
+        match tokens.peek().map(String::as_str) {
+            None | Some("or" | "||" | ")") => return Ok(filter),
+            Some("and" | "&&") => {
+                tokens.next();
+            }
+            // Implicit `and`
+            Some(_) => {}
+        }
+        filter = Filter::And(Box::new(filter), Box::new(parse_not(tokens)?));
+    }
+}
+
+fn parse_not(tokens: &mut Tokens) -> Result<Filter, FilterParseError> {
+    if tokens.next_if(|t| t == "not" || t == "!").is_some() {
+        return Ok(Filter::Not(Box::new(parse_not(tokens)?)));
+    }
+    if tokens.next_if(|t| t == "(").is_some() {
+        let filter = parse_or(tokens)?;
+        return match tokens.next().as_deref() {
+            Some(")") => Ok(filter),
+            _ => Err(FilterParseError("expected `)`".into())),
+        };
+    }
+    parse_primitive(tokens)
+}
+
+fn parse_primitive(tokens: &mut Tokens) -> Result<Filter, FilterParseError> {
+    let token = next(tokens, "a primitive")?;
+    let (direction, token) = match token.as_str() {
+        "src" => (Direction::Source, next(tokens, "a primitive after `src`")?),
+        "dst" => (
+            Direction::Destination,
+            next(tokens, "a primitive after `dst`")?,
+        ),
+        _ => (Direction::Either, token),
+    };
+    let directed = |filter| {
+        if direction == Direction::Either {
+            Ok(filter)
+        } else {
+            Err(FilterParseError(
+                "`src` and `dst` only apply to host, net and port".into(),
+            ))
+        }
+    };
+    match token.as_str() {
+        "host" => Ok(Filter::Host(direction, value(tokens, "an IPv4 address")?)),
+        "net" => {
+            let net = next(tokens, "a network")?;
+            let (addr, len) = net.split_once('/').unwrap_or((&net, "32"));
+            match (addr.parse(), len.parse::<u8>()) {
+                (Ok(addr), Ok(len)) if len <= 32 => {
+                    Ok(Filter::Net(direction, addr, IpV4Mask::new(len)))
+                }
+                _ => Err(FilterParseError(format!("`{net}` isn't a network"))),
+            }
+        }
+        "port" => Ok(Filter::Port(direction, value(tokens, "a port")?)),
+        "ether" => match next(tokens, "`proto`, `host`, `src` or `dst` after `ether`")?.as_str() {
+            sub @ ("src" | "dst") if direction == Direction::Either => {
+                tokens.next_if(|t| t == "host");
+                let direction = if sub == "src" {
+                    Direction::Source
+                } else {
+                    Direction::Destination
+                };
+                Ok(Filter::EtherHost(
+                    direction,
+                    value(tokens, "a MAC address")?,
+                ))
+            }
+            "proto" => {
+                let token = next(tokens, "an ether type")?;
+                let ether_type = match token.as_str() {
+                    "ip" => EtherType::IP_V4,
+                    "ip6" => EtherType::IP_V6,
+                    "arp" => EtherType::ARP,
+                    _ => EtherType::from_u16(number(&token).ok_or_else(|| {
+                        FilterParseError(format!("`{token}` isn't an ether type"))
+                    })?),
+                };
+                directed(Filter::EtherType(ether_type))
+            }
+            "host" => Ok(Filter::EtherHost(
+                direction,
+                value(tokens, "a MAC address")?,
+            )),
+            other => Err(FilterParseError(format!("unknown `ether {other}`"))),
+        },
+        "vlan" => {
+            let id = tokens.next_if(|t| number::<u16>(t).is_some());
+            directed(Filter::Vlan(id.and_then(|id| number(&id))))
+        }
+        "proto" => {
+            let token = next(tokens, "a protocol")?;
+            let protocol = protocol(&token)
+                .or_else(|| number(&token).map(ProtocolType::new))
+                .ok_or_else(|| FilterParseError(format!("`{token}` isn't a protocol")))?;
+            directed(Filter::Protocol(protocol))
+        }
+        "arp" => directed(Filter::EtherType(EtherType::ARP)),
+        "ip" => directed(Filter::EtherType(EtherType::IP_V4)),
+        "ip6" => directed(Filter::EtherType(EtherType::IP_V6)),
+        other => match protocol(other) {
+            Some(protocol) => directed(Filter::Protocol(protocol)),
+            None => Err(FilterParseError(format!("unknown primitive `{other}`"))),
+        },
+    }
+}
+
+fn protocol(name: &str) -> Option<ProtocolType> {
+    match name {
+        "icmp" => Some(ProtocolType::ICMP),
+        "udp" => Some(ProtocolType::UDP),
+        "tcp" => Some(ProtocolType::TCP),
+        "ospf" => Some(ProtocolType::OSPF),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::ipv4::{addr::IpV4Addr, protocol::ProtocolType};
+
+    use super::{Direction, Filter};
+
+    #[test]
+    fn parses_side_by_side_primitives() {
+        let filter = "host 10.0.0.1 and (icmp or udp port 520)".parse::<Filter>();
+        let udp_port = Filter::And(
+            Box::new(Filter::Protocol(ProtocolType::UDP)),
+            Box::new(Filter::Port(Direction::Either, 520)),
+        );
+        assert_eq!(
+            filter,
+            Ok(Filter::And(
+                Box::new(Filter::Host(
+                    Direction::Either,
+                    IpV4Addr::new([10, 0, 0, 1])
+                )),
+                Box::new(Filter::Or(
+                    Box::new(Filter::Protocol(ProtocolType::ICMP)),
+                    Box::new(udp_port)
+                )),
+            ))
+        );
+    }
+}
@@ -1,17 +1,14 @@
+use crate::broadcast;
+
 #[derive(Clone)]
-pub struct DuplexBarrage<P: Clone + Unpin> {
-    pub tx: barrage::Sender<P>,
-    pub rx: barrage::Receiver<P>,
+pub struct DuplexBroadcast<P: Clone> {
+    pub tx: broadcast::Sender<P>,
+    pub rx: broadcast::Receiver<P>,
 }
 
-impl<P: Clone + Unpin> DuplexBarrage<P> {
-    // pub fn bounded(bound: usize) -> Self {
-    //     let (tx, rx) = barrage::bounded(bound);
-    //     Self { tx, rx }
-    // }
-
-    pub fn unbounded() -> Self {
-        let (tx, rx) = barrage::unbounded();
+impl<P: Clone> DuplexBroadcast<P> {
+    pub fn channel() -> Self {
+        let (tx, rx) = broadcast::channel();
         Self { tx, rx }
     }
 }
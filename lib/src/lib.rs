@@ -1,4 +1,9 @@
+pub mod application;
+pub mod broadcast;
 pub mod chassis;
+#[cfg(test)]
+mod corruption;
+pub mod dissector;
 pub mod duplex_conn;
 pub mod either;
 pub mod link;
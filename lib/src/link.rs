@@ -1 +1,3 @@
+pub mod capture;
 pub mod ethernet;
+pub mod impairment;
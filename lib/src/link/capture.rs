@@ -0,0 +1,413 @@
+//! Packet capture of the frames seen by NICs, written to pcapng or classic pcap files
+
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::warn;
+
+use crate::link::ethernet::packet::EthernetPacket;
+
+/// LINKTYPE_ETHERNET
+const LINKTYPE_ETHERNET: u16 = 1;
+const SNAPLEN: u32 = 65535;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const EPB_FLAGS: u16 = 2;
+
+/// Classic pcap magic, microsecond timestamps
+const PCAP_MAGIC: u32 = 0xA1B2_C3D4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Interface description per captured NIC, frames flagged inbound or outbound
+    #[default]
+    Pcapng,
+    /// The frames of all the NICs are mixed, without their interface
+    Pcap,
+}
+
+impl Format {
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            Self::Pcapng => "pcapng",
+            Self::Pcap => "pcap",
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Ring buffer of capture files, the oldest ones are deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ring {
+    /// Bytes of a file before switching to the next one
+    pub size: u64,
+    /// Files kept
+    pub files: usize,
+}
+
+/// Messages to the writer thread, in the order the blocks go in the file
+enum Message {
+    /// Name of the next interface
+    Interface(String),
+    /// Block or record of a frame
+    Frame(Vec<u8>),
+}
+
+/// Owns the files, so the NICs don't wait for them
+struct Writer {
+    path: PathBuf,
+    format: Format,
+    ring: Option<Ring>,
+    file: BufWriter<File>,
+    written: u64,
+    /// Index of the current file of the ring
+    index: usize,
+    /// Files of the ring, oldest first
+    files: VecDeque<PathBuf>,
+    /// Names of the interfaces, by interface id
+    interfaces: Vec<String>,
+}
+
+/// Capture file shared by the NICs it taps
+pub struct Capture {
+    path: PathBuf,
+    format: Format,
+    ring: Option<Ring>,
+    /// Names of the interfaces, by interface id
+    interfaces: Mutex<Vec<String>>,
+    writer: flume::Sender<Message>,
+}
+
+impl Capture {
+    /// Files of a ring are named `<stem>_<n>.<extension>` after the path. They're written by
+    /// their own thread, which flushes them whenever it catches up with the frames
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: Format,
+        ring: Option<Ring>,
+    ) -> io::Result<Arc<Self>> {
+        let path = path.as_ref().to_path_buf();
+        let ring = ring.filter(|ring| ring.size > 0 && ring.files > 0);
+        let first = ring_path(&path, format, ring, 0);
+        let mut writer = Writer {
+            path: path.clone(),
+            format,
+            ring,
+            file: BufWriter::new(File::create(&first)?),
+            written: 0,
+            index: 0,
+            files: VecDeque::from([first]),
+            interfaces: Vec::new(),
+        };
+        writer.write_header()?;
+        let (tx, rx) = flume::unbounded();
+        thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || writer.run(rx))?;
+        Ok(Arc::new(Self {
+            path,
+            format,
+            ring,
+            interfaces: Mutex::new(Vec::new()),
+            writer: tx,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub const fn format(&self) -> Format {
+        self.format
+    }
+
+    pub const fn ring(&self) -> Option<Ring> {
+        self.ring
+    }
+
+    /// Describes the interface in the capture, its frames are written through the tap
+    pub fn add_interface(self: &Arc<Self>, name: impl Into<String>) -> io::Result<Tap> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let name = name.into();
+        self.send(Message::Interface(name.clone()))?;
+        interfaces.push(name);
+        Ok(Tap {
+            capture: self.clone(),
+            interface: interfaces.len() as u32 - 1,
+        })
+    }
+
+    /// Names of the captured interfaces
+    pub fn interfaces(&self) -> Vec<String> {
+        self.interfaces.lock().unwrap().clone()
+    }
+
+    fn send(&self, message: Message) -> io::Result<()> {
+        self.writer
+            .send(message)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "capture writer stopped"))
+    }
+
+    fn write(&self, interface: u32, frame: &EthernetPacket, outbound: bool) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let data = frame.to_vec();
+        let block = match self.format {
+            Format::Pcapng => {
+                enhanced_packet_block(interface, timestamp.as_micros() as u64, &data, outbound)
+            }
+            Format::Pcap => {
+                let mut record = Vec::with_capacity(16 + data.len());
+                record.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+                record.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+                record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                record.extend_from_slice(&data);
+                record
+            }
+        };
+        self.send(Message::Frame(block))
+    }
+}
+
+impl Writer {
+    /// Writes the messages until the capture is dropped
+    fn run(mut self, rx: flume::Receiver<Message>) {
+        while let Ok(message) = rx.recv() {
+            let res = match message {
+                Message::Interface(name) => self.add_interface(name),
+                Message::Frame(block) => self.write_frame(&block),
+            };
+            // Flushed whenever it catches up, so the file can be followed while capturing
+            let res = res.and_then(|()| {
+                if rx.is_empty() {
+                    self.file.flush()
+                } else {
+                    Ok(())
+                }
+            });
+            if let Err(e) = res {
+                warn!(path = ?self.path, "Capture write error: {e}");
+            }
+        }
+        if let Err(e) = self.file.flush() {
+            warn!(path = ?self.path, "Capture write error: {e}");
+        }
+    }
+
+    fn add_interface(&mut self, name: String) -> io::Result<()> {
+        if self.format == Format::Pcapng {
+            self.write(&interface_description_block(&name))?;
+        }
+        self.interfaces.push(name);
+        Ok(())
+    }
+
+    fn write_frame(&mut self, block: &[u8]) -> io::Result<()> {
+        if let Some(ring) = self.ring {
+            if self.written > 0 && self.written + block.len() as u64 > ring.size {
+                self.rotate(ring)?;
+            }
+        }
+        self.write(block)
+    }
+
+    /// Switches to the next file of the ring, with the header and the interfaces again
+    fn rotate(&mut self, ring: Ring) -> io::Result<()> {
+        self.file.flush()?;
+        self.index += 1;
+        let path = ring_path(&self.path, self.format, Some(ring), self.index);
+        self.file = BufWriter::new(File::create(&path)?);
+        self.written = 0;
+        self.files.push_back(path);
+        while self.files.len() > ring.files {
+            if let Some(old) = self.files.pop_front() {
+                if let Err(e) = fs::remove_file(&old) {
+                    warn!(?old, "Couldn't remove capture file: {e}");
+                }
+            }
+        }
+        self.write_header()?;
+        if self.format == Format::Pcapng {
+            for name in self.interfaces.clone() {
+                self.write(&interface_description_block(&name))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        match self.format {
+            Format::Pcapng => self.write(&section_header_block()),
+            Format::Pcap => {
+                let mut header = Vec::with_capacity(24);
+                header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+                header.extend_from_slice(&2u16.to_le_bytes());
+                header.extend_from_slice(&4u16.to_le_bytes());
+                // Timezone and timestamp accuracy
+                header.extend_from_slice(&[0; 8]);
+                header.extend_from_slice(&SNAPLEN.to_le_bytes());
+                header.extend_from_slice(&(LINKTYPE_ETHERNET as u32).to_le_bytes());
+                self.write(&header)
+            }
+        }
+    }
+}
+
+/// Interface of a capture, the NIC writes the frames it sees through it
+#[derive(Clone)]
+pub struct Tap {
+    capture: Arc<Capture>,
+    interface: u32,
+}
+
+impl Tap {
+    pub fn capture(&self) -> &Arc<Capture> {
+        &self.capture
+    }
+
+    pub fn write(&self, frame: &EthernetPacket, outbound: bool) {
+        if let Err(e) = self.capture.write(self.interface, frame, outbound) {
+            warn!(path = ?self.capture.path, "Capture write error: {e}");
+        }
+    }
+}
+
+/// Frames of a NIC go to its capture file and to the watchers of its traffic
+#[derive(Default)]
+pub struct Taps {
+    file: Option<Tap>,
+    /// Frames and whether they're outbound, the ones whose receiver is dropped are removed by the
+    /// next watch
+    watchers: Vec<flume::Sender<(EthernetPacket, bool)>>,
+}
+
+impl Taps {
+    pub fn is_empty(&self) -> bool {
+        self.file.is_none() && self.watchers.is_empty()
+    }
+
+    /// Capture file of the NIC
+    pub fn file(&mut self) -> &mut Option<Tap> {
+        &mut self.file
+    }
+
+    /// Frames the NIC sends and receives from now on, and whether they're outbound
+    pub fn watch(&mut self) -> flume::Receiver<(EthernetPacket, bool)> {
+        self.watchers.retain(|watcher| !watcher.is_disconnected());
+        let (tx, rx) = flume::unbounded();
+        self.watchers.push(tx);
+        rx
+    }
+
+    /// Only queues the frame, the capture file is written by its own thread
+    pub fn write(&self, frame: &EthernetPacket, outbound: bool) {
+        if let Some(tap) = &self.file {
+            tap.write(frame, outbound);
+        }
+        for watcher in self.watchers.iter() {
+            let _ = watcher.send((frame.clone(), outbound));
+        }
+    }
+}
+
+fn ring_path(path: &Path, format: Format, ring: Option<Ring>, index: usize) -> PathBuf {
+    match ring {
+        Some(_) => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let extension = path
+                .extension()
+                .map_or_else(|| format.extension().into(), |ext| ext.to_string_lossy());
+            path.with_file_name(format!("{stem}_{index:05}.{extension}"))
+        }
+        None => path.to_path_buf(),
+    }
+}
+
+/// Option padded to 32 bits
+fn option(block: &mut Vec<u8>, code: u16, value: &[u8]) {
+    block.extend_from_slice(&code.to_le_bytes());
+    block.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    block.extend_from_slice(value);
+    block.resize(block.len().next_multiple_of(4), 0);
+}
+
+/// Sets the lengths of the block, its body and options are already written after the type
+fn finish_block(mut block: Vec<u8>) -> Vec<u8> {
+    option(&mut block, OPT_END, &[]);
+    let len = block.len() as u32 + 4;
+    block[4..8].copy_from_slice(&len.to_le_bytes());
+    block.extend_from_slice(&len.to_le_bytes());
+    block
+}
+
+fn new_block(block_type: u32) -> Vec<u8> {
+    let mut block = block_type.to_le_bytes().to_vec();
+    // Total length, set by `finish_block`
+    block.extend_from_slice(&[0; 4]);
+    block
+}
+
+fn section_header_block() -> Vec<u8> {
+    let mut block = new_block(SECTION_HEADER_BLOCK);
+    block.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    block.extend_from_slice(&1u16.to_le_bytes());
+    block.extend_from_slice(&0u16.to_le_bytes());
+    // Section length unknown
+    block.extend_from_slice(&(-1i64).to_le_bytes());
+    option(&mut block, SHB_USERAPPL, b"routing");
+    finish_block(block)
+}
+
+fn interface_description_block(name: &str) -> Vec<u8> {
+    let mut block = new_block(INTERFACE_DESCRIPTION_BLOCK);
+    block.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    // Reserved
+    block.extend_from_slice(&[0; 2]);
+    block.extend_from_slice(&SNAPLEN.to_le_bytes());
+    option(&mut block, IF_NAME, name.as_bytes());
+    finish_block(block)
+}
+
+/// The timestamp is in microseconds, the default resolution of the interfaces
+fn enhanced_packet_block(interface: u32, timestamp: u64, data: &[u8], outbound: bool) -> Vec<u8> {
+    let mut block = new_block(ENHANCED_PACKET_BLOCK);
+    block.extend_from_slice(&interface.to_le_bytes());
+    block.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+    block.extend_from_slice(&(timestamp as u32).to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    block.extend_from_slice(data);
+    block.resize(block.len().next_multiple_of(4), 0);
+    // Direction bits, 1 inbound and 2 outbound
+    let flags: u32 = if outbound { 2 } else { 1 };
+    option(&mut block, EPB_FLAGS, &flags.to_le_bytes());
+    finish_block(block)
+}
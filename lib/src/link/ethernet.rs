@@ -1,4 +1,5 @@
 pub mod dot1q;
 pub mod ethertype;
+pub mod lacp;
 pub mod nic;
 pub mod packet;
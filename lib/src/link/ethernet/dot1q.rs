@@ -1,5 +1,11 @@
+/// TPID of the 802.1Q customer tags
+pub const CUSTOMER_TPID: u16 = 0x8100;
+/// TPID of the 802.1ad service tags, the outer ones of double tagged frames
+pub const SERVICE_TPID: u16 = 0x88A8;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Tag {
+    tpid: u16,
     // 3 bits
     priority_code_point: u8,
     drop_elegible: bool,
@@ -8,33 +14,48 @@ pub struct Tag {
 }
 
 impl Tag {
+    /// Customer tag
     pub const fn new(priority_code_point: u8, drop_elegible: bool, vlan_id: u16) -> Self {
         Self {
+            tpid: CUSTOMER_TPID,
             priority_code_point,
             drop_elegible,
             vlan_id,
         }
     }
 
+    /// Service tag, pushed by the provider bridges
+    pub const fn new_service(priority_code_point: u8, drop_elegible: bool, vlan_id: u16) -> Self {
+        Self {
+            tpid: SERVICE_TPID,
+            ..Self::new(priority_code_point, drop_elegible, vlan_id)
+        }
+    }
+
+    /// TPID then TCI
     pub fn to_vec(&self) -> Vec<u8> {
-        let res = ((self.priority_code_point as u16) << 13)
+        let tci = ((self.priority_code_point as u16) << 13)
             | (self.drop_elegible as u16) << 12
             | self.vlan_id;
-        res.to_be_bytes().to_vec()
+        let mut vec = self.tpid.to_be_bytes().to_vec();
+        vec.extend_from_slice(&tci.to_be_bytes());
+        vec
     }
 
+    /// Reads the tag at the start of the data, `Some(None)` if the data starts with an ether type
     pub fn from_vec(data: &[u8]) -> Option<Option<Self>> {
         if data.len() < 2 {
             return None;
         }
         let tpid = u16::from_be_bytes(data[0..2].try_into().ok()?);
 
-        Some(if tpid == 0x8100 {
+        Some(if matches!(tpid, CUSTOMER_TPID | SERVICE_TPID) {
             if data.len() < 4 {
                 return None;
             }
-            let tci = u16::from_be_bytes(data[0..2].try_into().ok()?);
+            let tci = u16::from_be_bytes(data[2..4].try_into().ok()?);
             Some(Self {
+                tpid,
                 priority_code_point: (tci >> 13) as u8,
                 drop_elegible: (tci & (1 << 12)) != 0,
                 vlan_id: tci & 0x0fff,
@@ -44,6 +65,14 @@ impl Tag {
         })
     }
 
+    pub const fn tpid(&self) -> u16 {
+        self.tpid
+    }
+
+    pub const fn is_service(&self) -> bool {
+        self.tpid == SERVICE_TPID
+    }
+
     pub const fn vlan_id(&self) -> u16 {
         self.vlan_id
     }
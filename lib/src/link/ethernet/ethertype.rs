@@ -14,4 +14,6 @@ impl EtherType {
     pub const IP_V4: Self = Self(0x0800);
     pub const IP_V6: Self = Self(0x86DD);
     pub const ARP: Self = Self(0x0806);
+    /// LACP and the other 802.3 slow protocols
+    pub const SLOW_PROTOCOLS: Self = Self(0x8809);
 }
@@ -0,0 +1,388 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+use crate::mac::Mac;
+
+use super::{ethertype::EtherType, packet::EthernetPacket};
+
+/// Slow protocols address the LACPDUs are sent to, bridges never forward the frames sent to it
+pub const SLOW_PROTOCOLS_ADDR: Mac = Mac::new([0x01, 0x80, 0xC2, 0x00, 0x00, 0x02]);
+/// Slow protocols subtype of LACP
+const SUBTYPE: u8 = 0x01;
+const VERSION: u8 = 0x01;
+
+const ACTOR_TLV: u8 = 0x01;
+const PARTNER_TLV: u8 = 0x02;
+const COLLECTOR_TLV: u8 = 0x03;
+const INFO_LEN: u8 = 20;
+const COLLECTOR_LEN: u8 = 16;
+/// The LACPDUs are padded to this length with the terminator
+const LACPDU_LEN: usize = 110;
+
+pub const DEFAULT_SYSTEM_PRIORITY: u16 = 32768;
+pub const DEFAULT_PORT_PRIORITY: u16 = 32768;
+/// Period of the LACPDUs, the fast rate every member asks for
+pub const PERIODIC_TIME: Duration = Duration::from_secs(1);
+/// Lifetime of the partner information, three fast periodic times
+const SHORT_TIMEOUT: Duration = Duration::from_secs(3);
+
+const ACTIVITY: u8 = 0x01;
+const TIMEOUT: u8 = 0x02;
+const AGGREGATION: u8 = 0x04;
+const SYNCHRONIZATION: u8 = 0x08;
+const COLLECTING: u8 = 0x10;
+const DISTRIBUTING: u8 = 0x20;
+const DEFAULTED: u8 = 0x40;
+
+/// Actor or partner information of a LACPDU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+    pub system_priority: u16,
+    pub system: Mac,
+    pub key: u16,
+    pub port_priority: u16,
+    pub port: u16,
+    pub state: u8,
+}
+
+impl Default for Info {
+    /// Partner information of a member that has none
+    fn default() -> Self {
+        Self {
+            system_priority: 0,
+            system: Mac::new([0; 6]),
+            key: 0,
+            port_priority: 0,
+            port: 0,
+            state: 0,
+        }
+    }
+}
+
+impl Info {
+    fn read(data: &[u8]) -> Self {
+        Self {
+            system_priority: u16::from_be_bytes([data[0], data[1]]),
+            system: Mac::new([data[2], data[3], data[4], data[5], data[6], data[7]]),
+            key: u16::from_be_bytes([data[8], data[9]]),
+            port_priority: u16::from_be_bytes([data[10], data[11]]),
+            port: u16::from_be_bytes([data[12], data[13]]),
+            state: data[14],
+        }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.system_priority.to_be_bytes());
+        data.extend_from_slice(self.system.as_slice());
+        data.extend_from_slice(&self.key.to_be_bytes());
+        data.extend_from_slice(&self.port_priority.to_be_bytes());
+        data.extend_from_slice(&self.port.to_be_bytes());
+        data.push(self.state);
+        data.extend_from_slice(&[0; 3]);
+    }
+
+    /// Same port of the same aggregation, whatever its state
+    fn same_port(&self, other: &Self) -> bool {
+        Self { state: 0, ..*self } == Self { state: 0, ..*other }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lacpdu {
+    pub actor: Info,
+    pub partner: Info,
+}
+
+impl Lacpdu {
+    /// Reads the payload of a slow protocols frame
+    pub fn read(data: &[u8]) -> Option<Self> {
+        if data.len() < 58
+            || data[..2] != [SUBTYPE, VERSION]
+            || data[2..4] != [ACTOR_TLV, INFO_LEN]
+            || data[22..24] != [PARTNER_TLV, INFO_LEN]
+        {
+            return None;
+        }
+        Some(Self {
+            actor: Info::read(&data[4..22]),
+            partner: Info::read(&data[24..42]),
+        })
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut data = vec![SUBTYPE, VERSION, ACTOR_TLV, INFO_LEN];
+        self.actor.write(&mut data);
+        data.extend_from_slice(&[PARTNER_TLV, INFO_LEN]);
+        self.partner.write(&mut data);
+        data.extend_from_slice(&[COLLECTOR_TLV, COLLECTOR_LEN]);
+        // Collector max delay and reserved
+        data.extend_from_slice(&[0; 14]);
+        // Terminator
+        data.resize(LACPDU_LEN, 0);
+        data
+    }
+}
+
+/// Hash of the addresses and ports of the frame, the frames of a flow always leave through the
+/// same member
+pub fn flow_hash(frame: &EthernetPacket) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    frame.get_source().hash(&mut hasher);
+    frame.get_dest().hash(&mut hasher);
+    let payload = &frame.payload;
+    if frame.get_ether_type() == EtherType::IP_V4 && payload.len() >= 20 {
+        payload[12..20].hash(&mut hasher);
+        let ihl = (payload[0] & 0x0f) as usize * 4;
+        // Only the first fragment has the ports
+        let fragment = payload[6] & 0x3f != 0 || payload[7] != 0;
+        if !fragment && matches!(payload[9], 6 | 17) && payload.len() >= ihl + 4 {
+            payload[ihl..ihl + 4].hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+#[derive(Debug)]
+struct Member {
+    /// NIC or switch port
+    nic: u16,
+    mac: Mac,
+    up: bool,
+    state: u8,
+    /// Partner information, what it knows of this port and when it expires
+    partner: Option<(Info, Info, Instant)>,
+    /// Need to transmit
+    ntt: bool,
+}
+
+/// LACP of a bundle of NICs, in active mode with the short timeout. The members the bundle
+/// aggregates with the partner of its first member collect and distribute the frames, the others
+/// are suspended
+#[derive(Debug)]
+pub struct Lacp {
+    pub system_priority: u16,
+    pub system: Mac,
+    pub key: u16,
+    members: Vec<Member>,
+    outgoing: Vec<(u16, EthernetPacket)>,
+}
+
+impl Lacp {
+    pub fn new(system: Mac, key: u16) -> Self {
+        Self {
+            system_priority: DEFAULT_SYSTEM_PRIORITY,
+            system,
+            key,
+            members: Vec::new(),
+            outgoing: Vec::new(),
+        }
+    }
+
+    /// Adds a member, down until it's told otherwise
+    pub fn add_member(&mut self, nic: u16, mac: Mac) {
+        self.members.push(Member {
+            nic,
+            mac,
+            up: false,
+            state: ACTIVITY | TIMEOUT | AGGREGATION | DEFAULTED,
+            partner: None,
+            ntt: false,
+        });
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = u16> + '_ {
+        self.members.iter().map(|member| member.nic)
+    }
+
+    pub fn has_member(&self, nic: u16) -> bool {
+        self.members().any(|x| x == nic)
+    }
+
+    fn position(&self, nic: u16) -> Option<usize> {
+        self.members.iter().position(|member| member.nic == nic)
+    }
+
+    fn actor(&self, i: usize) -> Info {
+        let member = &self.members[i];
+        Info {
+            system_priority: self.system_priority,
+            system: self.system,
+            key: self.key,
+            port_priority: DEFAULT_PORT_PRIORITY,
+            // Port 0 is reserved
+            port: member.nic + 1,
+            state: member.state,
+        }
+    }
+
+    /// The frames received by the member are given to the upper layers
+    pub fn collecting(&self, nic: u16) -> bool {
+        self.position(nic)
+            .is_some_and(|i| self.members[i].state & COLLECTING != 0)
+    }
+
+    /// Member the frame is sent through, `None` if no member distributes
+    pub fn member_for(&self, frame: &EthernetPacket) -> Option<u16> {
+        let distributing = self
+            .members
+            .iter()
+            .filter(|member| member.state & DISTRIBUTING != 0)
+            .map(|member| member.nic)
+            .collect::<Vec<_>>();
+        if distributing.is_empty() {
+            return None;
+        }
+        Some(distributing[(flow_hash(frame) % distributing.len() as u64) as usize])
+    }
+
+    pub fn take_output(&mut self) -> Vec<(u16, EthernetPacket)> {
+        for i in 0..self.members.len() {
+            if !std::mem::take(&mut self.members[i].ntt) || !self.members[i].up {
+                continue;
+            }
+            let member = &self.members[i];
+            let pdu = Lacpdu {
+                actor: self.actor(i),
+                partner: member.partner.map(|(info, _, _)| info).unwrap_or_default(),
+            };
+            if let Some(frame) =
+                EthernetPacket::new_slow_protocols(SLOW_PROTOCOLS_ADDR, member.mac, pdu.to_vec())
+            {
+                self.outgoing.push((member.nic, frame));
+            }
+        }
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// The partner information is lost when the member goes down
+    pub fn set_up(&mut self, nic: u16, up: bool) {
+        let Some(i) = self.position(nic) else {
+            return;
+        };
+        let member = &mut self.members[i];
+        if member.up == up {
+            return;
+        }
+        member.up = up;
+        member.ntt = up;
+        if !up {
+            member.partner = None;
+        }
+        self.update();
+    }
+
+    pub fn on_lacpdu(&mut self, nic: u16, pdu: Lacpdu, now: Instant) {
+        let Some(i) = self.position(nic) else {
+            return;
+        };
+        if !self.members[i].up {
+            return;
+        }
+        let actor = self.actor(i);
+        let member = &mut self.members[i];
+        member.partner = Some((pdu.actor, pdu.partner, now + SHORT_TIMEOUT));
+        // The partner has to learn the current state of this port
+        if pdu.partner != actor {
+            member.ntt = true;
+        }
+        self.update();
+    }
+
+    /// Ages the partner information out and sends the periodic LACPDUs
+    pub fn on_tick(&mut self, now: Instant) {
+        for member in self.members.iter_mut() {
+            if member.partner.is_some_and(|(_, _, expires)| expires <= now) {
+                info!("[eth{}] LACP partner timed out", member.nic);
+                member.partner = None;
+            }
+            member.ntt |= member.up;
+        }
+        self.update();
+    }
+
+    /// Selects the members and updates what they collect and distribute
+    fn update(&mut self) {
+        let aggregation = self.members.iter().find_map(|member| {
+            member
+                .partner
+                .filter(|(partner, _, _)| member.up && partner.state & AGGREGATION != 0)
+                .map(|(partner, _, _)| (partner.system_priority, partner.system, partner.key))
+        });
+        for i in 0..self.members.len() {
+            let actor = self.actor(i);
+            let member = &self.members[i];
+            let mut state = ACTIVITY | TIMEOUT | AGGREGATION;
+            match member.partner {
+                Some((partner, view, _))
+                    if member.up
+                        && Some((partner.system_priority, partner.system, partner.key))
+                            == aggregation =>
+                {
+                    state |= SYNCHRONIZATION;
+                    if view.same_port(&actor) && partner.state & SYNCHRONIZATION != 0 {
+                        state |= COLLECTING;
+                        if partner.state & COLLECTING != 0 {
+                            state |= DISTRIBUTING;
+                        }
+                    }
+                }
+                Some(_) => {}
+                None => state |= DEFAULTED,
+            }
+            let member = &mut self.members[i];
+            if (member.state ^ state) & DISTRIBUTING != 0 {
+                if state & DISTRIBUTING != 0 {
+                    info!("[eth{}] LACP member distributing", member.nic);
+                } else {
+                    info!("[eth{}] LACP member stopped distributing", member.nic);
+                }
+            }
+            if member.state != state {
+                member.state = state;
+                member.ntt |= member.up;
+            }
+        }
+    }
+
+    pub fn print(&self) -> String {
+        let mut table = prettytable::table!([
+            "member",
+            "link",
+            "selected",
+            "collecting",
+            "distributing",
+            "partner system",
+            "partner key",
+            "partner port"
+        ]);
+        if self.members.is_empty() {
+            table.add_empty_row();
+        }
+        let yes_no = |x: bool| if x { "yes" } else { "no" };
+        for member in &self.members {
+            let partner = member.partner.map(|(partner, _, _)| partner);
+            table.add_row(prettytable::row![
+                format!("eth{}", member.nic),
+                if member.up { "up" } else { "down" },
+                yes_no(member.state & SYNCHRONIZATION != 0),
+                yes_no(member.state & COLLECTING != 0),
+                yes_no(member.state & DISTRIBUTING != 0),
+                partner.map_or("-".to_string(), |partner| format!(
+                    "{}.{}",
+                    partner.system_priority, partner.system
+                )),
+                partner.map_or("-".to_string(), |partner| partner.key.to_string()),
+                partner.map_or("-".to_string(), |partner| partner.port.to_string())
+            ]);
+        }
+        format!(
+            "system {}.{}, key {}\n{table}",
+            self.system_priority, self.system, self.key
+        )
+    }
+}
@@ -1,14 +1,13 @@
-use barrage::{Receiver, Sender};
-
 use crate::{
-    duplex_conn::DuplexBarrage,
+    broadcast::{self, Receiver, Sender},
+    duplex_conn::DuplexBroadcast,
     mac::{authority::MacAdminAuthority, Mac},
 };
 
 use super::packet::EthernetPacket;
 
 pub struct Nic {
-    conn: Option<DuplexBarrage<EthernetPacket>>,
+    conn: Option<DuplexBroadcast<EthernetPacket>>,
     addr: Mac,
 }
 
@@ -33,7 +32,7 @@ impl Nic {
         if let Some(conn) = other.conn.as_ref() {
             self.conn = Some(conn.clone())
         } else {
-            let duplex = DuplexBarrage::unbounded();
+            let duplex = DuplexBroadcast::channel();
             self.conn = Some(duplex.clone());
             other.conn = Some(duplex);
         }
@@ -43,7 +42,10 @@ impl Nic {
         self.conn = None
     }
 
-    pub async fn send(&self, p: EthernetPacket) -> Result<(), barrage::SendError<EthernetPacket>> {
+    pub async fn send(
+        &self,
+        p: EthernetPacket,
+    ) -> Result<(), broadcast::SendError<EthernetPacket>> {
         if let Some(duplex) = &self.conn {
             duplex.tx.send_async(p).await
         } else {
@@ -51,11 +53,11 @@ impl Nic {
         }
     }
 
-    pub async fn recv(&self) -> Result<EthernetPacket, barrage::Disconnected> {
+    pub async fn recv(&self) -> Result<EthernetPacket, broadcast::Disconnected> {
         if let Some(duplex) = &self.conn {
             duplex.rx.recv_async().await
         } else {
-            Err(barrage::Disconnected)
+            Err(broadcast::Disconnected)
         }
     }
 
@@ -66,7 +68,7 @@ impl Nic {
         Mac,
     ) {
         (
-            self.conn.map(|DuplexBarrage { rx, tx }| (tx, rx)),
+            self.conn.map(|DuplexBroadcast { rx, tx }| (tx, rx)),
             self.addr,
         )
     }
@@ -76,7 +78,7 @@ impl Nic {
         mac: Mac,
     ) -> Self {
         Self {
-            conn: conn.map(|(tx, rx)| DuplexBarrage { tx, rx }),
+            conn: conn.map(|(tx, rx)| DuplexBroadcast { tx, rx }),
             addr: mac,
         }
     }
@@ -6,8 +6,8 @@ use super::{dot1q, ethertype::EtherType};
 pub struct EthernetPacket {
     destination: Mac,
     source: Mac,
-    // 16bit
-    dot1q_tag: Option<dot1q::Tag>,
+    // 32bit each, outermost first
+    tags: Vec<dot1q::Tag>,
     ether_type: EtherType,
     pub payload: Vec<u8>,
 }
@@ -18,7 +18,7 @@ impl EthernetPacket {
             Some(Self {
                 destination,
                 source,
-                dot1q_tag: None,
+                tags: Vec::new(),
                 ether_type: EtherType::from_u16(payload.len() as u16),
                 payload,
             })
@@ -32,7 +32,7 @@ impl EthernetPacket {
             Some(Self {
                 destination,
                 source,
-                dot1q_tag: None,
+                tags: Vec::new(),
                 ether_type: EtherType::IP_V4,
                 payload,
             })
@@ -46,7 +46,7 @@ impl EthernetPacket {
             Some(Self {
                 destination,
                 source,
-                dot1q_tag: None,
+                tags: Vec::new(),
                 ether_type: EtherType::IP_V6,
                 payload,
             })
@@ -60,7 +60,7 @@ impl EthernetPacket {
             Some(Self {
                 destination,
                 source,
-                dot1q_tag: None,
+                tags: Vec::new(),
                 ether_type: EtherType::ARP,
                 payload,
             })
@@ -69,16 +69,38 @@ impl EthernetPacket {
         }
     }
 
-    pub fn set_dot1q(&mut self, dot1q_tag: dot1q::Tag) {
-        self.dot1q_tag = Some(dot1q_tag)
+    pub fn new_slow_protocols(destination: Mac, source: Mac, payload: Vec<u8>) -> Option<Self> {
+        if payload.len() <= 1500 {
+            Some(Self {
+                destination,
+                source,
+                tags: Vec::new(),
+                ether_type: EtherType::SLOW_PROTOCOLS,
+                payload,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Adds an outermost tag
+    pub fn push_tag(&mut self, tag: dot1q::Tag) {
+        self.tags.insert(0, tag)
+    }
+
+    /// Removes the outermost tag
+    pub fn pop_tag(&mut self) -> Option<dot1q::Tag> {
+        (!self.tags.is_empty()).then(|| self.tags.remove(0))
     }
 
-    pub fn remove_dot1q(&mut self) {
-        self.dot1q_tag = None
+    /// Outermost tag
+    pub fn get_dot1q(&self) -> Option<dot1q::Tag> {
+        self.tags.first().copied()
     }
 
-    pub const fn get_dot1q(&self) -> Option<dot1q::Tag> {
-        self.dot1q_tag
+    /// Tag stack, outermost first
+    pub fn tags(&self) -> &[dot1q::Tag] {
+        &self.tags
     }
 
     pub const fn get_dest(&self) -> Mac {
@@ -98,11 +120,11 @@ impl EthernetPacket {
     }
 
     pub fn to_vec(&self) -> Vec<u8> {
-        let mut vec = Vec::with_capacity(14 + self.payload.len());
+        let mut vec = Vec::with_capacity(14 + 4 * self.tags.len() + self.payload.len());
         vec.extend_from_slice(self.destination.as_slice());
         vec.extend_from_slice(self.source.as_slice());
-        if let Some(dot1q_tag) = &self.dot1q_tag {
-            vec.extend_from_slice(dot1q_tag.to_vec().as_slice());
+        for tag in &self.tags {
+            vec.extend_from_slice(tag.to_vec().as_slice());
         }
         vec.extend_from_slice(&self.ether_type.to_u16().to_be_bytes());
         vec.extend_from_slice(&self.payload);
@@ -116,10 +138,11 @@ impl EthernetPacket {
         let destination = Mac::new(data[0..6].try_into().ok()?);
         let source = Mac::new(data[6..12].try_into().ok()?);
         let mut offset = 12;
-        let dot1q_tag = dot1q::Tag::from_vec(&data[offset..])?;
-        if dot1q_tag.is_some() {
+        let mut tags = Vec::new();
+        while let Some(tag) = dot1q::Tag::from_vec(&data[offset..])? {
+            tags.push(tag);
             offset += 4;
-            if data.len() < 18 {
+            if data.len() < offset + 2 {
                 // Baby jumbo
                 return None;
             }
@@ -130,9 +153,9 @@ impl EthernetPacket {
         Some(Self {
             destination,
             source,
-            dot1q_tag,
+            tags,
             ether_type,
-            payload: data[14..].to_vec(),
+            payload: data[(offset + 2)..].to_vec(),
         })
     }
 }
@@ -0,0 +1,491 @@
+//! Impairments of the frames a NIC transmits on its link, the delay, losses and rate of a real
+//! medium
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, VecDeque},
+    fmt::Display,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{select, sync::RwLock};
+use tracing::trace;
+
+use crate::{broadcast, link::ethernet::packet::EthernetPacket};
+
+/// Frames waiting for the rate limit before the next ones are dropped
+pub const DEFAULT_QUEUE_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Distribution {
+    /// Anywhere within the jitter of the delay
+    #[default]
+    Uniform,
+    /// The jitter is the standard deviation
+    Normal,
+}
+
+impl Display for Distribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::Normal => write!(f, "normal"),
+        }
+    }
+}
+
+/// Gilbert model of the burst losses, every frame is lost in the bad state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Burst {
+    /// Probability of going from the good state to the bad one at each frame
+    pub enter: f64,
+    /// Probability of going back to the good state at each frame
+    pub exit: f64,
+}
+
+/// Impairment profile, the probabilities are between 0 and 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Impairments {
+    pub delay: Duration,
+    /// The frames can overtake each other when it's larger than their spacing
+    pub jitter: Duration,
+    pub distribution: Distribution,
+    pub loss: f64,
+    pub burst: Option<Burst>,
+    pub duplicate: f64,
+    /// Frames sent right away instead of being delayed, they overtake the delayed ones
+    pub reorder: f64,
+    /// Frames with a bit of their payload flipped, their header is left intact
+    pub corrupt: f64,
+    /// Bits per second
+    pub rate: Option<u64>,
+    /// Frames waiting for the rate limit
+    pub queue_limit: usize,
+}
+
+impl Default for Impairments {
+    fn default() -> Self {
+        Self {
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            distribution: Distribution::Uniform,
+            loss: 0.0,
+            burst: None,
+            duplicate: 0.0,
+            reorder: 0.0,
+            corrupt: 0.0,
+            rate: None,
+            queue_limit: DEFAULT_QUEUE_LIMIT,
+        }
+    }
+}
+
+impl Impairments {
+    pub fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Display for Impairments {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_none() {
+            return write!(f, "no impairments");
+        }
+        let mut parts = Vec::new();
+        if !self.delay.is_zero() || !self.jitter.is_zero() {
+            parts.push(format!("delay {:?}", self.delay));
+        }
+        if !self.jitter.is_zero() {
+            parts.push(format!("jitter {:?} {}", self.jitter, self.distribution));
+        }
+        for (name, p) in [
+            ("loss", self.loss),
+            ("duplicate", self.duplicate),
+            ("reorder", self.reorder),
+            ("corrupt", self.corrupt),
+        ] {
+            if p > 0.0 {
+                parts.push(format!("{name} {}%", p * 100.0));
+            }
+        }
+        if let Some(Burst { enter, exit }) = self.burst {
+            parts.push(format!(
+                "burst loss {}% enter {}% exit",
+                enter * 100.0,
+                exit * 100.0
+            ));
+        }
+        if let Some(rate) = self.rate {
+            parts.push(format!("rate {rate} b/s queue {}", self.queue_limit));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// xorshift64*, good enough to draw the impairments
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        Self(RandomState::new().build_hasher().finish() | 1)
+    }
+
+    /// Between 0 and 1
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.next_f64() < p
+    }
+
+    /// Standard normal, Box-Muller
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+}
+
+/// State of the impairments of a link direction
+struct Shaper {
+    rng: Rng,
+    /// Gilbert model in the bad state
+    bad: bool,
+    /// The frames queued for the rate limit are all transmitted then
+    busy_until: Instant,
+    /// When each queued frame is done transmitting
+    queue: VecDeque<Instant>,
+}
+
+impl Shaper {
+    fn new() -> Self {
+        Self {
+            rng: Rng::new(),
+            bad: false,
+            busy_until: Instant::now(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn lost(&mut self, impairments: &Impairments) -> bool {
+        if let Some(Burst { enter, exit }) = impairments.burst {
+            self.bad = if self.bad {
+                !self.rng.chance(exit)
+            } else {
+                self.rng.chance(enter)
+            };
+        } else {
+            self.bad = false;
+        }
+        self.bad || self.rng.chance(impairments.loss)
+    }
+
+    /// When the frame and its copies get on the link
+    fn schedule(
+        &mut self,
+        impairments: &Impairments,
+        mut frame: EthernetPacket,
+        now: Instant,
+    ) -> Vec<(Instant, EthernetPacket)> {
+        if self.lost(impairments) {
+            trace!("Impairments: Frame lost");
+            return Vec::new();
+        }
+        let mut at = now;
+        if let Some(rate) = impairments.rate.filter(|rate| *rate > 0) {
+            while self.queue.front().is_some_and(|end| *end <= now) {
+                self.queue.pop_front();
+            }
+            if self.queue.len() >= impairments.queue_limit {
+                trace!("Impairments: Queue full, frame dropped");
+                return Vec::new();
+            }
+            let bits = frame.to_vec().len() as f64 * 8.0;
+            self.busy_until =
+                self.busy_until.max(now) + Duration::from_secs_f64(bits / rate as f64);
+            self.queue.push_back(self.busy_until);
+            at = self.busy_until;
+        }
+        if !self.rng.chance(impairments.reorder) {
+            let jitter = impairments.jitter.as_secs_f64();
+            let jitter = match impairments.distribution {
+                Distribution::Uniform => (self.rng.next_f64() * 2.0 - 1.0) * jitter,
+                Distribution::Normal => self.rng.normal() * jitter,
+            };
+            at += Duration::from_secs_f64((impairments.delay.as_secs_f64() + jitter).max(0.0));
+        }
+        if self.rng.chance(impairments.corrupt) && !frame.payload.is_empty() {
+            let bit = (self.rng.next_f64() * (frame.payload.len() * 8) as f64) as usize;
+            frame.payload[bit / 8] ^= 1 << (bit % 8);
+        }
+        if self.rng.chance(impairments.duplicate) {
+            vec![(at, frame.clone()), (at, frame)]
+        } else {
+            vec![(at, frame)]
+        }
+    }
+}
+
+/// Sender of the frames of a NIC on its link, they go through the impairments of the NIC
+pub struct LinkSender {
+    tx: flume::Sender<EthernetPacket>,
+}
+
+impl LinkSender {
+    /// The frames are pushed to `transmitted` as they get on the link, so that their echo can be
+    /// recognized
+    pub fn new(
+        link: broadcast::Sender<EthernetPacket>,
+        impairments: Arc<RwLock<Impairments>>,
+        transmitted: Option<Arc<Mutex<VecDeque<EthernetPacket>>>>,
+    ) -> Self {
+        let (tx, rx) = flume::unbounded();
+        tokio::spawn(async move {
+            let mut shaper = Shaper::new();
+            // Frames by when they get on the link, in the order they were sent
+            let mut pending = BTreeMap::new();
+            let mut seq = 0u64;
+            loop {
+                let next = pending.keys().next().map(|(at, _): &(Instant, u64)| *at);
+                select! {
+                    frame = rx.recv_async() => {
+                        let Ok(frame) = frame else {
+                            break;
+                        };
+                        let impairments = *impairments.read().await;
+                        if impairments.is_none() && pending.is_empty() {
+                            transmit(&link, &transmitted, frame).await;
+                            continue;
+                        }
+                        for (at, frame) in shaper.schedule(&impairments, frame, Instant::now()) {
+                            pending.insert((at, seq), frame);
+                            seq += 1;
+                        }
+                    }
+                    _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now).into()), if next.is_some() => {
+                        let now = Instant::now();
+                        while let Some(entry) = pending.first_entry() {
+                            if entry.key().0 > now {
+                                break;
+                            }
+                            transmit(&link, &transmitted, entry.remove()).await;
+                        }
+                    }
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    pub async fn send_async(
+        &self,
+        frame: EthernetPacket,
+    ) -> Result<(), flume::SendError<EthernetPacket>> {
+        self.tx.send_async(frame).await
+    }
+}
+
+async fn transmit(
+    link: &broadcast::Sender<EthernetPacket>,
+    transmitted: &Option<Arc<Mutex<VecDeque<EthernetPacket>>>>,
+    frame: EthernetPacket,
+) {
+    if let Some(transmitted) = transmitted {
+        transmitted.lock().unwrap().push_back(frame.clone());
+    }
+    let _ = link.send_async(frame).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use tokio::sync::RwLock;
+
+    use crate::{
+        broadcast,
+        link::ethernet::packet::EthernetPacket,
+        mac::{self, Mac},
+    };
+
+    use super::{Burst, Distribution, Impairments, LinkSender, Rng, Shaper};
+
+    fn frame() -> EthernetPacket {
+        let source = Mac::new([0, 1, 0, 0, 0, 1]);
+        EthernetPacket::new_generic(mac::BROADCAST, source, (0..100).collect()).unwrap()
+    }
+
+    /// Draws the same numbers on every run
+    fn shaper() -> Shaper {
+        Shaper {
+            rng: Rng(0x2545_F491_4F6C_DD1D),
+            ..Shaper::new()
+        }
+    }
+
+    /// Frames lost out of the ones sent
+    fn lost(impairments: &Impairments, sent: usize) -> usize {
+        let mut shaper = shaper();
+        let now = Instant::now();
+        (0..sent)
+            .filter(|_| shaper.schedule(impairments, frame(), now).is_empty())
+            .count()
+    }
+
+    #[test]
+    fn passes_frames_unimpaired() {
+        let now = Instant::now();
+        let frames = shaper().schedule(&Impairments::default(), frame(), now);
+        assert_eq!(frames, vec![(now, frame())]);
+    }
+
+    #[test]
+    fn delays_within_jitter() {
+        let mut shaper = shaper();
+        let now = Instant::now();
+        let delay = Duration::from_millis(50);
+        let jitter = Duration::from_millis(10);
+        let uniform = Impairments {
+            delay,
+            jitter,
+            ..Default::default()
+        };
+        let delays = (0..1000)
+            .map(|_| shaper.schedule(&uniform, frame(), now)[0].0 - now)
+            .collect::<Vec<_>>();
+        assert!(delays
+            .iter()
+            .all(|delay| (40..=60).contains(&delay.as_millis())));
+        assert!(delays.iter().any(|delay| delay.as_millis() < 45));
+        assert!(delays.iter().any(|delay| delay.as_millis() > 55));
+
+        let normal = Impairments {
+            distribution: Distribution::Normal,
+            ..uniform
+        };
+        let mean = (0..1000)
+            .map(|_| (shaper.schedule(&normal, frame(), now)[0].0 - now).as_secs_f64())
+            .sum::<f64>()
+            / 1000.0;
+        assert!((mean - 0.05).abs() < 0.002);
+    }
+
+    #[test]
+    fn loses_frames() {
+        let random = Impairments {
+            loss: 0.25,
+            ..Default::default()
+        };
+        assert!((2300..2700).contains(&lost(&random, 10000)));
+
+        let always = Impairments {
+            burst: Some(Burst {
+                enter: 1.0,
+                exit: 0.0,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(lost(&always, 100), 100);
+
+        // One frame in six is lost on average, in bursts of two
+        let bursts = Impairments {
+            burst: Some(Burst {
+                enter: 0.1,
+                exit: 0.5,
+            }),
+            ..Default::default()
+        };
+        assert!((1400..1900).contains(&lost(&bursts, 10000)));
+    }
+
+    #[test]
+    fn limits_rate() {
+        let mut shaper = shaper();
+        let now = Instant::now();
+        // A frame takes 100 ms to transmit
+        let rate = frame().to_vec().len() as u64 * 8 * 10;
+        let impairments = Impairments {
+            rate: Some(rate),
+            queue_limit: 2,
+            ..Default::default()
+        };
+        let ms = |ms| now + Duration::from_millis(ms);
+        let mut send = |at| {
+            let frames = shaper.schedule(&impairments, frame(), at);
+            frames.first().map(|(at, _)| *at)
+        };
+        assert_eq!(send(now), Some(ms(100)));
+        assert_eq!(send(now), Some(ms(200)));
+        assert_eq!(send(now), None);
+        // The first frame is out of the queue
+        assert_eq!(send(ms(150)), Some(ms(300)));
+        assert_eq!(send(ms(400)), Some(ms(500)));
+    }
+
+    #[test]
+    fn duplicates_reorders_and_corrupts() {
+        let now = Instant::now();
+        let duplicate = Impairments {
+            duplicate: 1.0,
+            ..Default::default()
+        };
+        let frames = shaper().schedule(&duplicate, frame(), now);
+        assert_eq!(frames, vec![(now, frame()), (now, frame())]);
+
+        let reorder = Impairments {
+            delay: Duration::from_secs(1),
+            reorder: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(shaper().schedule(&reorder, frame(), now)[0].0, now);
+
+        let corrupt = Impairments {
+            corrupt: 1.0,
+            ..Default::default()
+        };
+        let (_, corrupted) = shaper().schedule(&corrupt, frame(), now).remove(0);
+        let original = frame();
+        assert_eq!(corrupted.get_dest(), original.get_dest());
+        assert_eq!(corrupted.get_source(), original.get_source());
+        let flipped = corrupted
+            .payload
+            .iter()
+            .zip(original.payload.iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum::<u32>();
+        assert_eq!(flipped, 1);
+    }
+
+    #[tokio::test]
+    async fn sends_on_the_link_after_the_delay() {
+        let (tx, rx) = broadcast::channel();
+        let impairments = Arc::new(RwLock::new(Impairments {
+            delay: Duration::from_millis(50),
+            ..Default::default()
+        }));
+        let transmitted = Arc::new(Mutex::new(VecDeque::new()));
+        let sender = LinkSender::new(tx, impairments.clone(), Some(transmitted.clone()));
+
+        sender.send_async(frame()).await.unwrap();
+        let early = tokio::time::timeout(Duration::from_millis(20), rx.recv_async()).await;
+        assert!(early.is_err());
+        assert!(transmitted.lock().unwrap().is_empty());
+        assert_eq!(rx.recv_async().await, Ok(frame()));
+        assert_eq!(transmitted.lock().unwrap().pop_front(), Some(frame()));
+
+        // Changes apply to the next frames
+        impairments.write().await.loss = 1.0;
+        sender.send_async(frame()).await.unwrap();
+        let lost = tokio::time::timeout(Duration::from_millis(100), rx.recv_async()).await;
+        assert!(lost.is_err());
+    }
+}
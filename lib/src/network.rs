@@ -1,3 +1,5 @@
 pub mod arp;
 pub mod ip;
 pub mod ipv4;
+pub mod ipv6;
+pub mod ndp;
@@ -20,8 +20,6 @@ use packet::{ArpPacket, Operation};
 
 pub mod packet;
 
-type IpV6Addr = IpV4Addr;
-
 #[derive(Debug)]
 pub struct ArpProcess {
     ipv4: Option<(
@@ -29,10 +27,6 @@ pub struct ArpProcess {
         HashMap<(IpV4Addr, LinkLayerId), (Mac, DateTime<Local>)>,
     )>,
     ipv4_handle: Option<(Arc<Receiver<(IpV4Addr, LinkLayerId)>>, Sender<Mac>)>,
-    ipv6: Option<(
-        IpV6Addr,
-        HashMap<(IpV6Addr, LinkLayerId), (Mac, DateTime<Local>)>,
-    )>,
     get_new_ipv4_handle: (
         Arc<Receiver<()>>,
         Sender<ArpHandle<(IpV4Addr, LinkLayerId), Mac>>,
@@ -41,19 +35,20 @@ pub struct ArpProcess {
         Arc<Receiver<()>>,
         Sender<HashMap<(IpV4Addr, LinkLayerId), (Mac, DateTime<Local>)>>,
     ),
+    announce: Arc<Receiver<LinkLayerId>>,
 }
 
 impl ArpProcess {
-    pub fn new(ipv4: Option<IpV4Config>, ipv6: Option<IpV4Addr>) -> (Self, GenericArpHandle) {
+    pub fn new(ipv4: Option<IpV4Config>) -> (Self, GenericArpHandle) {
         let (new_ipv4_handle_external_tx, new_ipv4_handle_internal_rx) = flume::unbounded();
         let (new_ipv4_handle_internal_tx, new_ipv4_handle_external_rx) = flume::unbounded();
         let (get_ipv4_table_external_tx, get_ipv4_table_internal_rx) = flume::unbounded();
         let (get_ipv4_table_internal_tx, get_ipv4_table_external_rx) = flume::unbounded();
+        let (announce_tx, announce_rx) = flume::unbounded();
         (
             Self {
                 ipv4: ipv4.map(|ip| (ip, HashMap::new())),
                 ipv4_handle: None,
-                ipv6: ipv6.map(|ip| (ip, HashMap::new())),
                 get_new_ipv4_handle: (
                     Arc::new(new_ipv4_handle_internal_rx),
                     new_ipv4_handle_internal_tx,
@@ -62,10 +57,12 @@ impl ArpProcess {
                     Arc::new(get_ipv4_table_internal_rx),
                     get_ipv4_table_internal_tx,
                 ),
+                announce: Arc::new(announce_rx),
             },
             GenericArpHandle {
                 get_new_ipv4_handle: (new_ipv4_handle_external_tx, new_ipv4_handle_external_rx),
                 get_ipv4_table: (get_ipv4_table_external_tx, get_ipv4_table_external_rx),
+                announce: announce_tx,
             },
         )
     }
@@ -75,12 +72,52 @@ impl ArpProcess {
         self.ipv4_handle = Some((Arc::new(inner.rx), inner.tx));
         ext
     }
+
+    /// Sends gratuitous requests for the addresses of the interface, and of its sub-interfaces if
+    /// it's a NIC, so the neighbors update their caches
+    async fn announce(
+        &self,
+        iface: LinkLayerId,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) {
+        let Some((config, _)) = &self.ipv4 else {
+            return;
+        };
+        let ifaces = down_sender.iter().filter(|(id, _)| match (iface, id) {
+            (LinkLayerId::Ethernet(nic, _), LinkLayerId::Dot1q(parent, _, _)) => nic == *parent,
+            _ => iface == **id,
+        });
+        for (id, sender) in ifaces {
+            let addrs = config.read().await.iface_addrs(id).to_vec();
+            for iface_addr in addrs {
+                trace!("ARP: Announcing {} on {id}", iface_addr.addr);
+                let addr = iface_addr.addr.as_slice().to_vec();
+                let packet = ArpPacket::new_request(
+                    1,
+                    EtherType::IP_V4,
+                    id.mac().as_slice().to_vec(),
+                    addr.clone(),
+                    addr,
+                );
+                let _ = sender
+                    .send_async(ProcessMessage::Message(
+                        NetworkLayerId::Arp,
+                        (mac::BROADCAST, packet.to_vec()),
+                    ))
+                    .await;
+            }
+        }
+    }
 }
 
 pub enum ExtraMessage {
     GetIpV4(Result<(IpV4Addr, LinkLayerId), RecvError>),
     NewIpV4(Result<(), RecvError>),
     GetCurrentIPv4Table(Result<(), RecvError>),
+    Announce(Result<LinkLayerId, RecvError>),
 }
 
 #[async_trait::async_trait]
@@ -109,7 +146,13 @@ impl
         trace!(ARP = ?self, "Recieved from {down_id} {source_mac}: {msg:?}");
         if let Some(arp_packet) = ArpPacket::from_vec(&msg) {
             match (arp_packet.htype, arp_packet.ptype, down_id) {
-                (1, EtherType::IP_V4, LinkLayerId::Ethernet(_, mac)) => {
+                (
+                    1,
+                    EtherType::IP_V4,
+                    LinkLayerId::Ethernet(_, mac)
+                    | LinkLayerId::Dot1q(_, _, mac)
+                    | LinkLayerId::Bond(_, mac),
+                ) => {
                     if let Some((ip, table)) = &mut self.ipv4 {
                         if let (Ok(sha), Ok(spa)) = (
                             arp_packet.sender_harware_address.as_slice().try_into(),
@@ -117,21 +160,51 @@ impl
                         ) {
                             let ip = IpV4Addr::new(spa);
                             let mac = Mac::new(sha);
-                            table.entry((ip, down_id)).or_insert((mac, Local::now()));
-                            trace!("ARP: Tried to add pair {ip} -> {mac} to the table");
+                            // Gratuitous packets update the existing entries too
+                            table.insert((ip, down_id), (mac, Local::now()));
+                            trace!("ARP: Added pair {ip} -> {mac} to the table");
                         }
 
-                        if ip.read().await.addr.as_slice()
-                            == arp_packet.target_protocol_address.as_slice()
-                        {
+                        let target = arp_packet
+                            .target_protocol_address
+                            .as_slice()
+                            .try_into()
+                            .map(IpV4Addr::new);
+                        let Ok(target) = target else {
+                            return;
+                        };
+                        let gratuitous = arp_packet.sender_protocol_address
+                            == arp_packet.target_protocol_address;
+                        // Only the addresses of the interface the request came from are answered,
+                        // and with proxy ARP the ones routed through other interfaces
+                        let (owned, virtual_mac, proxied) = {
+                            let config = ip.read().await;
+                            let proxied = config.proxy_arp.contains(&down_id)
+                                && arp_packet.operation == Operation::Request
+                                && !gratuitous
+                                && config
+                                    .routing
+                                    .get_route(target)
+                                    .is_some_and(|(_, iface)| iface != down_id);
+                            (
+                                config.has_addr(&down_id, target),
+                                config.virtual_mac(&down_id, target),
+                                proxied,
+                            )
+                        };
+                        if owned || virtual_mac.is_some() || proxied {
                             match arp_packet.operation {
+                                // Gratuitous, it announces the sender's address
+                                Operation::Request if gratuitous => {}
                                 Operation::Request => {
                                     // trace!(ARP = ?self, "Received ARP IPv4 Request packet: {arp_packet:?}");
+                                    // Virtual routers' addresses are at their virtual MAC, the
+                                    // proxied ones at the interface's
                                     let reply = ArpPacket::new_reply(
                                         arp_packet.htype,
                                         arp_packet.ptype,
-                                        mac.as_slice().to_vec(),
-                                        ip.read().await.addr.as_slice().to_vec(),
+                                        virtual_mac.unwrap_or(mac).as_slice().to_vec(),
+                                        target.as_slice().to_vec(),
                                         arp_packet.sender_harware_address,
                                         arp_packet.sender_protocol_address,
                                     );
@@ -160,13 +233,6 @@ impl
                         }
                     }
                 }
-                (1, EtherType::IP_V6, LinkLayerId::Ethernet(_, _)) => {
-                    if let Some((ip, _)) = self.ipv6 {
-                        if ip.as_slice() == arp_packet.target_protocol_address.as_slice() {
-                            trace!(ARP = ?self, "Received ARP IPv6 packet: {arp_packet:?}")
-                        }
-                    }
-                }
                 (1, x, _) => warn!(ARP = ?self, "Unknown ptype: {x:?}"),
                 (x, _, _) => warn!(ARP = ?self, "Unknown htype: {x}"),
             }
@@ -224,6 +290,9 @@ impl
         join_set.spawn(async move {
             ThreeWayEither::C(ExtraMessage::GetCurrentIPv4Table(rx.recv_async().await))
         });
+        let rx = self.announce.clone();
+        join_set
+            .spawn(async move { ThreeWayEither::C(ExtraMessage::Announce(rx.recv_async().await)) });
         if let Some((rx, _)) = self.ipv4_handle.as_ref() {
             let rx = rx.clone();
             join_set.spawn(async move {
@@ -265,6 +334,16 @@ impl
                 }
                 Err(RecvError::Disconnected) => warn!("ARP: Disconnected get ipv4 arp table"),
             },
+            ExtraMessage::Announce(r) => match r {
+                Ok(iface) => {
+                    self.announce(iface, down_sender).await;
+                    let rx = self.announce.clone();
+                    join_set.spawn(async move {
+                        ThreeWayEither::C(ExtraMessage::Announce(rx.recv_async().await))
+                    });
+                }
+                Err(RecvError::Disconnected) => warn!("ARP: Disconnected announce"),
+            },
             ExtraMessage::NewIpV4(r) => {
                 match r {
                     Ok(()) => {
@@ -305,7 +384,9 @@ impl
                                 trace!("ARP: Searching for MAC address for IPv4 {ip}");
                                 if let Some((id, sender)) = down_sender.get_key_value(&id) {
                                     match id {
-                                        LinkLayerId::Ethernet(_, sha) => {
+                                        LinkLayerId::Ethernet(_, sha)
+                                        | LinkLayerId::Dot1q(_, _, sha)
+                                        | LinkLayerId::Bond(_, sha) => {
                                             let packet = ArpPacket::new_request(
                                                 1,
                                                 EtherType::IP_V4,
@@ -316,7 +397,7 @@ impl
                                                     .0
                                                     .read()
                                                     .await
-                                                    .addr
+                                                    .source_addr(id, ip)
                                                     .as_slice()
                                                     .to_vec(),
                                                 ip.as_slice().to_vec(),
@@ -331,6 +412,9 @@ impl
                                                 warn!("ARP: Error sending ARP request package: {e}")
                                             }
                                         }
+                                        LinkLayerId::Tunnel(_) => {
+                                            warn!("ARP: {id} has no link layer addresses")
+                                        }
                                     }
                                 }
                             }
@@ -353,8 +437,8 @@ impl
 }
 #[derive(Debug)]
 pub struct ArpHandle<Addr, HAddr> {
-    rx: Receiver<HAddr>,
-    tx: Sender<Addr>,
+    pub(super) rx: Receiver<HAddr>,
+    pub(super) tx: Sender<Addr>,
 }
 
 impl<Addr, HAddr> ArpHandle<Addr, HAddr>
@@ -381,7 +465,7 @@ where
     }
 }
 
-fn get_handle_pair<Addr, HAddr>() -> (ArpHandle<HAddr, Addr>, ArpHandle<Addr, HAddr>) {
+pub(super) fn get_handle_pair<Addr, HAddr>() -> (ArpHandle<HAddr, Addr>, ArpHandle<Addr, HAddr>) {
     let (tx1, rx1) = flume::unbounded();
     let (tx2, rx2) = flume::unbounded();
     (
@@ -400,6 +484,8 @@ pub struct GenericArpHandle {
         Sender<()>,
         Receiver<HashMap<(IpV4Addr, LinkLayerId), (Mac, DateTime<Local>)>>,
     ),
+
+    announce: Sender<LinkLayerId>,
 }
 
 impl GenericArpHandle {
@@ -414,4 +500,9 @@ impl GenericArpHandle {
         self.get_ipv4_table.0.send_async(()).await.ok()?;
         self.get_ipv4_table.1.recv_async().await.ok()
     }
+
+    /// Sends gratuitous ARP for the addresses of the interface
+    pub async fn announce(&self, iface: LinkLayerId) {
+        let _ = self.announce.send_async(iface).await;
+    }
 }
@@ -156,6 +156,30 @@ impl ArpPacket {
         })
     }
 
+    pub const fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub const fn protocol_type(&self) -> EtherType {
+        self.ptype
+    }
+
+    pub fn sender_hardware_address(&self) -> &[u8] {
+        &self.sender_harware_address
+    }
+
+    pub fn sender_protocol_address(&self) -> &[u8] {
+        &self.sender_protocol_address
+    }
+
+    pub fn target_hardware_address(&self) -> &[u8] {
+        &self.target_harware_address
+    }
+
+    pub fn target_protocol_address(&self) -> &[u8] {
+        &self.target_protocol_address
+    }
+
     pub fn to_vec(&self) -> Vec<u8> {
         let mut vec = Vec::with_capacity(8 + self.hlen as usize * 2 + self.plen as usize * 2);
         vec.extend_from_slice(&self.htype.to_be_bytes());
@@ -170,3 +194,28 @@ impl ArpPacket {
         vec
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{corruption::corruptions, link::ethernet::ethertype::EtherType};
+
+    use super::ArpPacket;
+
+    #[test]
+    fn survives_corrupted_packets() {
+        let packet = ArpPacket::new_request(
+            1,
+            EtherType::IP_V4,
+            vec![2, 0, 0, 0, 0, 1],
+            vec![10, 0, 0, 1],
+            vec![10, 0, 0, 2],
+        );
+        let data = packet.to_vec();
+        assert_eq!(ArpPacket::from_vec(&data), Some(packet));
+        for data in corruptions(&data) {
+            if let Some(packet) = ArpPacket::from_vec(&data) {
+                assert_eq!(packet.to_vec(), data);
+            }
+        }
+    }
+}
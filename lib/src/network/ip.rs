@@ -1,5 +1,50 @@
-use super::ipv4::addr::IpV4Addr;
+use std::{fmt::Display, str::FromStr};
+
+use super::{
+    ipv4::addr::{IPv4ParseError, IpV4Addr},
+    ipv6::addr::{IPv6ParseError, IpV6Addr},
+};
 
 pub trait Ip {}
 
 impl Ip for IpV4Addr {}
+
+impl Ip for IpV6Addr {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IpAddr {
+    V4(IpV4Addr),
+    V6(IpV6Addr),
+}
+
+#[derive(Debug, Clone)]
+pub struct IpParseError(pub IPv4ParseError, pub IPv6ParseError);
+
+impl Display for IpParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for IpParseError {}
+
+impl FromStr for IpAddr {
+    type Err = IpParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        IpV4Addr::from_str(s).map(Self::V4).or_else(|e4| {
+            IpV6Addr::from_str(s)
+                .map(Self::V6)
+                .map_err(|e6| IpParseError(e4, e6))
+        })
+    }
+}
+
+impl Display for IpAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V4(addr) => write!(f, "{addr}"),
+            Self::V6(addr) => write!(f, "{addr}"),
+        }
+    }
+}
@@ -1,37 +1,334 @@
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
 
 use flume::Sender;
+use tokio::task::JoinSet;
 use tracing::{trace, warn};
 
 use crate::{
     chassis::{
         LinkLayerId, LinkNetworkPayload, MidLevelProcess, NetworkLayerId, NetworkTransportMessage,
-        NetworkTransportPayload, ProcessMessage, TransportLayerId,
+        NetworkTransportPayload, ProcessMessage, ReceptionResult, TransportLayerId,
     },
-    mac::Mac,
+    either::ThreeWayEither,
+    mac::{self, Mac},
     network::arp::ArpHandle,
-    transport::icmp::packet::IcmpPacket,
+    transport::icmp::packet::{DestinationUnreachable, IcmpPacket, TimeExceeded},
 };
 
 use self::{
-    addr::IpV4Addr,
+    acl::Direction,
+    addr::{IpV4Addr, BROADCAST, DEFAULT},
     config::IpV4Config,
     packet::{IpV4Header, Ipv4Packet},
+    reassembly::ReassemblyBuffer,
+    tunnel::Tunnel,
 };
 
+pub mod acl;
 pub mod addr;
 pub mod config;
+pub mod conntrack;
+pub mod nat;
 pub mod packet;
 pub mod protocol;
+pub mod reassembly;
+pub mod tunnel;
+
+/// Levels of encapsulation before a packet is dropped, as a tunnel routed through itself would
+/// encapsulate its packets forever
+const MAX_TUNNEL_DEPTH: u8 = 4;
 
 pub struct IpV4Process {
     config: IpV4Config,
     arp: ArpHandle<(IpV4Addr, LinkLayerId), Mac>,
+    identification: u16,
+    reassembly: ReassemblyBuffer,
+    /// Tunnels the packet being sent is going through
+    tunnel_depth: u8,
+}
+
+/// IP header and first 8 bytes of the payload, as included in ICMP errors
+fn icmp_error_data(ip_packet: &Ipv4Packet) -> Vec<u8> {
+    let mut data = ip_packet.header.to_vec();
+    data.extend_from_slice(&ip_packet.payload[..(8.min(ip_packet.payload.len()))]);
+    data
+}
+
+/// ICMP errors are never sent about other ICMP errors or about fragments other than the first
+fn may_send_icmp_error(ip_packet: &Ipv4Packet) -> bool {
+    let icmp_error = ip_packet.header.protocol == protocol::ProtocolType::ICMP
+        && ip_packet
+            .payload
+            .first()
+            .is_some_and(|typ| matches!(typ, 3 | 4 | 5 | 11 | 12));
+    ip_packet.header.fragment_offset() == 0 && !icmp_error
+}
+
+/// Hash of the packet's flow: addresses, protocol and the TCP or UDP ports. Fragments only use
+/// the addresses and protocol, so they follow the same path as the rest of the packet
+fn flow_hash(ip_packet: &Ipv4Packet) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    ip_packet.header.source.hash(&mut hasher);
+    ip_packet.header.destination.hash(&mut hasher);
+    ip_packet.header.protocol.hash(&mut hasher);
+    let ports = matches!(
+        ip_packet.header.protocol,
+        protocol::ProtocolType::TCP | protocol::ProtocolType::UDP
+    ) && !ip_packet.header.is_fragment();
+    if ports {
+        ip_packet.payload.get(..4).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Reason a packet couldn't be forwarded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ForwardError {
+    /// No route to the destination
+    NetUnreachable,
+    /// The next hop didn't answer to ARP
+    HostUnreachable,
+    /// The packet doesn't fit in the interface MTU and has DF set
+    FragmentationNeeded(u16),
+    /// Denied by the outbound access list of the interface, with whether it's reported
+    Prohibited(bool),
+}
+
+impl ForwardError {
+    fn to_icmp(self, data: Vec<u8>) -> DestinationUnreachable {
+        match self {
+            Self::NetUnreachable => DestinationUnreachable::NetUnreachable { data },
+            Self::HostUnreachable => DestinationUnreachable::HostUnreachable { data },
+            Self::FragmentationNeeded(next_hop_mtu) => {
+                DestinationUnreachable::FragmentationNeeded { next_hop_mtu, data }
+            }
+            Self::Prohibited(_) => DestinationUnreachable::AdministrativelyProhibited { data },
+        }
+    }
 }
 
 impl IpV4Process {
     pub fn new(config: IpV4Config, arp: ArpHandle<(IpV4Addr, LinkLayerId), Mac>) -> Self {
-        Self { config, arp }
+        Self {
+            config,
+            arp,
+            identification: 0,
+            reassembly: Default::default(),
+            tunnel_depth: 0,
+        }
+    }
+
+    /// Routes the packet, fragmenting it if it doesn't fit in the interface MTU. Transit packets,
+    /// received on the ingress interface, go through the outbound access list and the address
+    /// translation of the interface, the packets sent are tracked
+    async fn forward(
+        &mut self,
+        mut ip_packet: Ipv4Packet,
+        ingress: Option<LinkLayerId>,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) -> Result<(), ForwardError> {
+        let destination = ip_packet.header.destination;
+        let routes = self.config.read().await.routing.get_routes(destination);
+        if routes.is_empty() {
+            warn!("Can't find route to {destination}");
+            return Err(ForwardError::NetUnreachable);
+        }
+        // Equal-cost routes are chosen per flow so its packets aren't reordered
+        let (gateway, iface) = routes[flow_hash(&ip_packet) as usize % routes.len()];
+        // Connected routes have no gateway, the destination is on the link
+        let next_hop = if gateway == DEFAULT {
+            destination
+        } else {
+            gateway
+        };
+        {
+            let mut config = self.config.write().await;
+            if ingress.is_some() {
+                if let Some(report) = config.acl_denies(iface, Direction::Out, &ip_packet) {
+                    trace!("Packet to {destination} denied out of {iface}");
+                    return Err(ForwardError::Prohibited(report));
+                }
+            }
+            config.conntrack.track(&ip_packet);
+            if let Some(ingress) = ingress {
+                if config.nat_out(ingress, iface, &mut ip_packet) {
+                    trace!(
+                        "Packet to {destination} translated from {}",
+                        ip_packet.header.source
+                    );
+                }
+            }
+        }
+        let (mtu, tunnel) = {
+            let config = self.config.read().await;
+            (config.get_mtu(&iface), config.tunnels.get(&iface).copied())
+        };
+        let fragments = ip_packet
+            .fragment(mtu)
+            .ok_or(ForwardError::FragmentationNeeded(mtu))?;
+        if let Some(tunnel) = tunnel {
+            return self
+                .send_through_tunnel(iface, tunnel, fragments, down_sender)
+                .await;
+        }
+        let Some(Ok(dest_mac)) = self
+            .arp
+            .get_haddr_timeout((next_hop, iface), std::time::Duration::from_secs(1))
+            .await
+        else {
+            warn!("Can't resolve next hop {next_hop} on {iface}");
+            return Err(ForwardError::HostUnreachable);
+        };
+        trace!("Sending IPv4 packet to interface: {iface} next_hop {next_hop} ({dest_mac})");
+        if let Some(sender) = down_sender.get(&iface) {
+            for fragment in fragments {
+                let _ = sender
+                    .send_async(ProcessMessage::Message(
+                        NetworkLayerId::Ipv4,
+                        (dest_mac, fragment.to_vec()),
+                    ))
+                    .await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the packets inside outer packets from the tunnel source to its destination, which are
+    /// routed again
+    async fn send_through_tunnel(
+        &mut self,
+        iface: LinkLayerId,
+        tunnel: Tunnel,
+        packets: Vec<Ipv4Packet>,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) -> Result<(), ForwardError> {
+        if self.tunnel_depth >= MAX_TUNNEL_DEPTH {
+            warn!("Too many levels of encapsulation through {iface}, is it routed through itself?");
+            return Err(ForwardError::HostUnreachable);
+        }
+        trace!(
+            "Sending IPv4 packet through {iface} towards {}",
+            tunnel.destination
+        );
+        self.tunnel_depth += 1;
+        let mut result = Ok(());
+        for packet in packets {
+            let payload = tunnel.encapsulate(packet.to_vec());
+            self.identification = self.identification.wrapping_add(1);
+            let outer = Ipv4Packet::new(
+                IpV4Header::new(
+                    0,
+                    packet::Ecn::NotECT,
+                    payload.len() as u16,
+                    self.identification,
+                    packet::Flags::empty(),
+                    0,
+                    255,
+                    tunnel.mode.protocol(),
+                    tunnel.destination,
+                    tunnel.source,
+                    vec![],
+                ),
+                payload,
+            );
+            result = Box::pin(self.forward(outer, None, down_sender)).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        self.tunnel_depth -= 1;
+        // The tunnel destination being unreachable is reported as the tunnel's next hop
+        result.map_err(|_| ForwardError::HostUnreachable)
+    }
+
+    /// Sends an ICMP destination unreachable about the packet back to its source
+    async fn send_unreachable(
+        &mut self,
+        ip_packet: &Ipv4Packet,
+        unreachable: impl FnOnce(Vec<u8>) -> DestinationUnreachable,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+        up_sender: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
+    ) {
+        if !may_send_icmp_error(ip_packet) {
+            return;
+        }
+        self.send_packet(
+            ip_packet.header.source,
+            None,
+            protocol::ProtocolType::ICMP,
+            IcmpPacket::DestinationUnreachable(unreachable(icmp_error_data(ip_packet))).to_vec(),
+            down_sender,
+            up_sender,
+        )
+        .await
+    }
+
+    async fn send_packet(
+        &mut self,
+        target_ip: IpV4Addr,
+        ttl: Option<u8>,
+        ptype: protocol::ProtocolType,
+        msg: Vec<u8>,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+        up_sender: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
+    ) {
+        let ip = self.config.read().await.source_addr_to(target_ip);
+        self.identification = self.identification.wrapping_add(1);
+        let ip_packet = Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                packet::Ecn::NotECT,
+                msg.len() as u16,
+                self.identification,
+                packet::Flags::empty(),
+                0,
+                ttl.unwrap_or(255),
+                ptype,
+                target_ip,
+                ip,
+                vec![],
+            ),
+            msg,
+        );
+        let data = icmp_error_data(&ip_packet);
+        let report = may_send_icmp_error(&ip_packet);
+        if let Err(e) = self.forward(ip_packet, None, down_sender).await {
+            warn!("Unable to send packet to {target_ip}: {e:?}");
+            // Locally originated packets are reported to the local ICMP process
+            if let (true, Some(sender)) = (report, up_sender.get(&TransportLayerId::Icmp)) {
+                let _ = sender
+                    .send_async(ProcessMessage::Message(
+                        NetworkLayerId::Ipv4,
+                        NetworkTransportMessage::IPv4(
+                            ip,
+                            None,
+                            IcmpPacket::DestinationUnreachable(e.to_icmp(data)).to_vec(),
+                        ),
+                    ))
+                    .await;
+            }
+        }
     }
 
     async fn send_message(
@@ -42,55 +339,135 @@ impl IpV4Process {
             LinkLayerId,
             Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
         >,
+        up_sender: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
     ) {
-        #[allow(irrefutable_let_patterns)]
-        if let NetworkTransportMessage::IPv4(target_ip, ttl, msg) = msg {
-            let ptype = match up_id {
-                TransportLayerId::Tcp => protocol::ProtocolType::TCP,
-                TransportLayerId::Udp => protocol::ProtocolType::UDP,
-                TransportLayerId::Icmp => protocol::ProtocolType::ICMP,
-            };
-            let ip = self.config.read().await.addr;
-            trace!(IP = ?ip, msg = ?msg, "Recieved packet from {up_id:?} towards {target_ip}");
-            if let Some((next_hop, iface)) = self.config.read().await.routing.get_route(target_ip) {
-                if let Some(Ok(dest_mac)) = self
-                    .arp
-                    .get_haddr_timeout((next_hop, iface), std::time::Duration::from_secs(1))
+        let ptype = match up_id {
+            TransportLayerId::Tcp => protocol::ProtocolType::TCP,
+            TransportLayerId::Udp => protocol::ProtocolType::UDP,
+            TransportLayerId::Icmp => protocol::ProtocolType::ICMP,
+            TransportLayerId::Ospf => protocol::ProtocolType::OSPF,
+            TransportLayerId::Vrrp => protocol::ProtocolType::VRRP,
+        };
+        match msg {
+            NetworkTransportMessage::IPv4(target_ip, ttl, msg) => {
+                trace!(msg = ?msg, "Recieved packet from {up_id:?} towards {target_ip}");
+                self.send_packet(target_ip, ttl, ptype, msg, down_sender, up_sender)
                     .await
-                {
-                    trace!(IP = ?ip, "Sending IPv4 packet to interface: {iface} next_hop {next_hop} ({dest_mac})");
-                    if let Some(sender) = down_sender.get(&iface) {
-                        sender
-                            .send_async(ProcessMessage::Message(
-                                NetworkLayerId::Ipv4,
-                                (
-                                    dest_mac,
-                                    Ipv4Packet::new(
-                                        IpV4Header::new(
-                                            0,
-                                            packet::Ecn::NotECT,
-                                            msg.len() as u16,
-                                            0,
-                                            packet::Flags::empty(),
-                                            0,
-                                            ttl.unwrap_or(255),
-                                            ptype,
-                                            target_ip,
-                                            ip,
-                                            vec![],
-                                        ),
-                                        msg,
-                                    )
-                                    .to_vec(),
-                                ),
-                            ))
-                            .await
-                            .unwrap();
-                    }
+            }
+            NetworkTransportMessage::IPv4PortUnreachable(source, data) => {
+                trace!("No socket for packet from {source}, sending icmp packet back");
+                self.send_packet(
+                    source,
+                    None,
+                    protocol::ProtocolType::ICMP,
+                    IcmpPacket::DestinationUnreachable(DestinationUnreachable::PortUnreachable {
+                        data,
+                    })
+                    .to_vec(),
+                    down_sender,
+                    up_sender,
+                )
+                .await
+            }
+            NetworkTransportMessage::IPv4Broadcast(iface, ttl, msg) => {
+                trace!(msg = ?msg, "Recieved broadcast from {up_id:?} towards {iface}");
+                self.send_on_link(iface, BROADCAST, ttl, ptype, msg, down_sender)
+                    .await
+            }
+            NetworkTransportMessage::IPv4Link(iface, destination, ttl, msg) => {
+                trace!(msg = ?msg, "Recieved packet from {up_id:?} towards {destination} on {iface}");
+                self.send_on_link(iface, destination, ttl, ptype, msg, down_sender)
+                    .await
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends the packet straight out of the interface to a neighbor, the limited broadcast or a
+    /// multicast group, it's never routed
+    async fn send_on_link(
+        &mut self,
+        iface: LinkLayerId,
+        destination: IpV4Addr,
+        ttl: Option<u8>,
+        ptype: protocol::ProtocolType,
+        msg: Vec<u8>,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) {
+        let (ip, mtu, tunnel) = {
+            let config = self.config.read().await;
+            let primary = config.iface_addrs(&iface).first();
+            (
+                primary.map_or(DEFAULT, |iface_addr| iface_addr.addr),
+                config.get_mtu(&iface),
+                config.tunnels.get(&iface).copied(),
+            )
+        };
+        self.identification = self.identification.wrapping_add(1);
+        let ip_packet = Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                packet::Ecn::NotECT,
+                msg.len() as u16,
+                self.identification,
+                packet::Flags::empty(),
+                0,
+                ttl.unwrap_or(255),
+                ptype,
+                destination,
+                ip,
+                vec![],
+            ),
+            msg,
+        );
+        let Some(fragments) = ip_packet.fragment(mtu) else {
+            warn!("Unable to send packet to {destination} on {iface}");
+            return;
+        };
+        if let Some(tunnel) = tunnel {
+            // Every destination on a tunnel is its far end
+            if let Err(e) = self
+                .send_through_tunnel(iface, tunnel, fragments, down_sender)
+                .await
+            {
+                warn!("Unable to send packet to {destination} on {iface}: {e:?}");
+            }
+            return;
+        }
+        let Some(sender) = down_sender.get(&iface) else {
+            warn!("Unable to send packet to {destination} on {iface}");
+            return;
+        };
+        let dest_mac = if destination == BROADCAST {
+            mac::BROADCAST
+        } else if destination.is_multicast() {
+            destination.multicast_mac()
+        } else {
+            match self
+                .arp
+                .get_haddr_timeout((destination, iface), std::time::Duration::from_secs(1))
+                .await
+            {
+                Some(Ok(dest_mac)) => dest_mac,
+                _ => {
+                    warn!("Can't resolve {destination} on {iface}");
+                    return;
                 }
-            } else {
-                warn!(IP = ?ip, "Can't find route to {target_ip}");
             }
+        };
+        for fragment in fragments {
+            let _ = sender
+                .send_async(ProcessMessage::Message(
+                    NetworkLayerId::Ipv4,
+                    (dest_mac, fragment.to_vec()),
+                ))
+                .await;
         }
     }
 }
@@ -119,83 +496,191 @@ impl
             Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
         >,
     ) {
-        let ip = self.config.read().await.addr;
-        trace!(IP = ?ip, "Recieved from {down_id} {source_mac}: {msg:?}");
+        trace!("Recieved from {down_id} {source_mac}: {msg:?}");
         if let Some(mut ip_packet) = Ipv4Packet::from_vec(&msg) {
-            trace!(IP = ?ip, "Recieved IP packet: {ip_packet:?}");
-            if ip_packet.header.time_to_live > 0 {
-                if ip_packet.header.destination == ip {
-                    // TODO fragmented packets`
-                    if let Some(up_id) = match ip_packet.header.protocol {
-                        protocol::ProtocolType::TCP => Some(TransportLayerId::Tcp),
-                        protocol::ProtocolType::UDP => Some(TransportLayerId::Udp),
-                        protocol::ProtocolType::ICMP => Some(TransportLayerId::Icmp),
-                        x => {
-                            warn!(IP = ?ip, "Unknown IP protocol: {x:?}");
-                            None
-                        }
-                    } {
-                        if let Some(sender) = up_sender.get(&up_id) {
-                            let _ = sender
-                                .send_async(ProcessMessage::Message(
-                                    NetworkLayerId::Ipv4,
-                                    NetworkTransportMessage::IPv4(
-                                        ip_packet.header.source,
-                                        Some(ip_packet.header.time_to_live),
-                                        ip_packet.payload,
-                                    ),
-                                ))
-                                .await;
-                        }
+            trace!("Recieved IP packet: {ip_packet:?}");
+            // The translation rewrites the transport header, which only the first fragment has
+            if ip_packet.header.is_fragment()
+                && self.config.read().await.nat.role(&down_id).is_some()
+            {
+                trace!(
+                    "Recieved fragment of packet {} to translate",
+                    ip_packet.header.identification()
+                );
+                match self.reassembly.add(ip_packet) {
+                    Some(packet) => ip_packet = packet,
+                    None => return,
+                }
+            }
+            if self.config.write().await.nat_in(down_id, &mut ip_packet) {
+                trace!("Packet translated to {}", ip_packet.header.destination);
+            }
+            let broadcast = ip_packet.header.destination == BROADCAST
+                || ip_packet.header.destination.is_link_local_multicast();
+            let denied = self
+                .config
+                .write()
+                .await
+                .acl_denies(down_id, Direction::In, &ip_packet);
+            if let Some(report) = denied {
+                trace!(
+                    "Packet from {} denied in {down_id}",
+                    ip_packet.header.source
+                );
+                if report && !broadcast && !ip_packet.header.destination.is_multicast() {
+                    self.send_unreachable(
+                        &ip_packet,
+                        |data| DestinationUnreachable::AdministrativelyProhibited { data },
+                        down_sender,
+                        up_sender,
+                    )
+                    .await
+                }
+                return;
+            }
+            let (local, virtual_addr) = {
+                let config = self.config.read().await;
+                let destination = ip_packet.header.destination;
+                (config.is_local(destination), config.is_virtual(destination))
+            };
+            if virtual_addr && !local {
+                // Only the owner of a virtual router's addresses accepts the packets to them
+                trace!(
+                    "Dropped packet to virtual address {}",
+                    ip_packet.header.destination
+                );
+                return;
+            }
+            if local {
+                self.config.write().await.conntrack.track(&ip_packet);
+            }
+            if local || broadcast {
+                if ip_packet.header.is_fragment() {
+                    trace!(
+                        "Recieved fragment of packet {}",
+                        ip_packet.header.identification()
+                    );
+                    match self.reassembly.add(ip_packet) {
+                        Some(packet) => ip_packet = packet,
+                        None => return,
                     }
-                } else {
-                    ip_packet.header.time_to_live -= 1;
-                    if let Some((next_hop, iface)) = self
-                        .config
-                        .read()
-                        .await
-                        .routing
-                        .get_route(ip_packet.header.destination)
-                    {
-                        if let Some(Ok(dest_mac)) = self
-                            .arp
-                            .get_haddr_timeout((next_hop, iface), std::time::Duration::from_secs(1))
+                }
+                let tunnel = self.config.read().await.tunnel_from(
+                    ip_packet.header.source,
+                    ip_packet.header.destination,
+                    ip_packet.header.protocol,
+                );
+                if let (true, Some((tunnel_id, tunnel))) = (local, tunnel) {
+                    // The packet is received again as if it came from the tunnel interface
+                    match tunnel.decapsulate(ip_packet.payload) {
+                        Some(inner) => {
+                            trace!("Decapsulated packet from {tunnel_id}");
+                            self.on_down_message(
+                                (source_mac, inner),
+                                tunnel_id,
+                                down_sender,
+                                up_sender,
+                            )
                             .await
-                        {
-                            trace!(IP = ?ip, "Sending IPv4 packet to interface: {iface} next_hop {next_hop} ({dest_mac})");
-                            if let Some(sender) = down_sender.get(&iface) {
-                                let _ = sender
-                                    .send_async(ProcessMessage::Message(
-                                        NetworkLayerId::Ipv4,
-                                        (dest_mac, ip_packet.to_vec()),
-                                    ))
-                                    .await;
-                            }
                         }
+                        None => warn!("Unable to decapsulate packet from {tunnel_id}"),
+                    }
+                    return;
+                }
+                let sender = match ip_packet.header.protocol {
+                    protocol::ProtocolType::TCP => up_sender.get(&TransportLayerId::Tcp),
+                    protocol::ProtocolType::UDP => up_sender.get(&TransportLayerId::Udp),
+                    protocol::ProtocolType::ICMP => up_sender.get(&TransportLayerId::Icmp),
+                    protocol::ProtocolType::OSPF => up_sender.get(&TransportLayerId::Ospf),
+                    protocol::ProtocolType::VRRP => up_sender.get(&TransportLayerId::Vrrp),
+                    _ => None,
+                };
+                let ttl = Some(ip_packet.header.time_to_live);
+                if let Some(sender) = sender {
+                    let msg = if matches!(
+                        ip_packet.header.protocol,
+                        protocol::ProtocolType::OSPF | protocol::ProtocolType::VRRP
+                    ) {
+                        // OSPF and VRRP talk to the neighbors on each link, whatever the destination
+                        NetworkTransportMessage::IPv4Link(
+                            down_id,
+                            ip_packet.header.source,
+                            ttl,
+                            ip_packet.payload,
+                        )
+                    } else if broadcast {
+                        NetworkTransportMessage::IPv4Broadcast(down_id, ttl, ip_packet.payload)
+                    } else if ip_packet.header.protocol == protocol::ProtocolType::UDP {
+                        // Quoted if no socket is bound to the port
+                        let data = icmp_error_data(&ip_packet);
+                        NetworkTransportMessage::IPv4Quoted(
+                            ip_packet.header.source,
+                            ttl,
+                            ip_packet.payload,
+                            data,
+                        )
                     } else {
-                        warn!(IP = ?ip, "Can't find route to {}", ip_packet.header.destination);
+                        NetworkTransportMessage::IPv4(
+                            ip_packet.header.source,
+                            ttl,
+                            ip_packet.payload,
+                        )
+                    };
+                    let _ = sender
+                        .send_async(ProcessMessage::Message(NetworkLayerId::Ipv4, msg))
+                        .await;
+                } else if broadcast {
+                    trace!(
+                        "Dropped broadcast for unknown protocol {:?}",
+                        ip_packet.header.protocol
+                    );
+                } else {
+                    warn!("Unknown IP protocol: {:?}", ip_packet.header.protocol);
+                    self.send_unreachable(
+                        &ip_packet,
+                        |data| DestinationUnreachable::ProtocolUnreachable { data },
+                        down_sender,
+                        up_sender,
+                    )
+                    .await
+                }
+            } else if ip_packet.header.destination.is_multicast() {
+                trace!("Dropped multicast packet, multicast isn't routed");
+            } else if ip_packet.header.time_to_live > 0 {
+                ip_packet.header.time_to_live -= 1;
+                let unreachable = ip_packet.clone();
+                match self.forward(ip_packet, Some(down_id), down_sender).await {
+                    Ok(()) | Err(ForwardError::Prohibited(false)) => {}
+                    Err(e) => {
+                        trace!("Unable to forward packet ({e:?}), sending icmp packet back");
+                        self.send_unreachable(
+                            &unreachable,
+                            |data| e.to_icmp(data),
+                            down_sender,
+                            up_sender,
+                        )
+                        .await
                     }
                 }
             } else {
-                trace!(IP = ?ip, "Dropped packet, sending icmp packet back");
-                let mut data = ip_packet.header.to_vec();
-                data.extend_from_slice(&ip_packet.payload[..(8.min(ip_packet.payload.len()))]);
+                trace!("Dropped packet, sending icmp packet back");
                 self.send_message(
                     NetworkTransportMessage::IPv4(
                         ip_packet.header.source,
                         None,
-                        IcmpPacket::TimeExceeded(
-                            crate::transport::icmp::packet::TimeExceeded::TtlTransit { data },
-                        )
+                        IcmpPacket::TimeExceeded(TimeExceeded::TtlTransit {
+                            data: icmp_error_data(&ip_packet),
+                        })
                         .to_vec(),
                     ),
                     TransportLayerId::Icmp,
                     down_sender,
+                    up_sender,
                 )
                 .await
             }
         } else {
-            warn!(IP = ?ip, "Unable to decode IP packet")
+            warn!("Unable to decode IP packet")
         }
     }
     async fn on_up_message(
@@ -206,11 +691,106 @@ impl
             LinkLayerId,
             Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
         >,
-        _: &HashMap<
+        up_sender: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
+    ) {
+        self.send_message(msg, up_id, down_sender, up_sender).await
+    }
+
+    async fn setup(
+        &mut self,
+        join_set: &mut JoinSet<
+            ThreeWayEither<
+                ReceptionResult<ProcessMessage<LinkLayerId, NetworkLayerId, LinkNetworkPayload>>,
+                ReceptionResult<
+                    ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportPayload>,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        join_set.spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            ThreeWayEither::C(())
+        });
+    }
+
+    /// Periodically drops the packets that couldn't be reassembled in time
+    async fn on_extra_message(
+        &mut self,
+        (): Self::Extra,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+        up_sender: &HashMap<
             TransportLayerId,
             Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
         >,
+        join_set: &mut JoinSet<
+            ThreeWayEither<
+                ReceptionResult<ProcessMessage<LinkLayerId, NetworkLayerId, LinkNetworkPayload>>,
+                ReceptionResult<
+                    ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportPayload>,
+                >,
+                Self::Extra,
+            >,
+        >,
     ) {
-        self.send_message(msg, up_id, down_sender).await
+        let timeout = self.config.read().await.reassembly_timeout;
+        for first_fragment in self.reassembly.expire(timeout) {
+            trace!("Fragment reassembly time exceeded, sending icmp packet back");
+            self.send_message(
+                NetworkTransportMessage::IPv4(
+                    first_fragment.header.source,
+                    None,
+                    IcmpPacket::TimeExceeded(TimeExceeded::FragmentReassembly {
+                        data: icmp_error_data(&first_fragment),
+                    })
+                    .to_vec(),
+                ),
+                TransportLayerId::Icmp,
+                down_sender,
+                up_sender,
+            )
+            .await
+        }
+        join_set.spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            ThreeWayEither::C(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        addr::IpV4Addr,
+        icmp_error_data,
+        packet::{Ecn, Flags, IpV4Header, Ipv4Packet},
+        protocol::ProtocolType,
+    };
+
+    #[test]
+    fn quotes_the_received_header() {
+        let payload = (0..20).collect::<Vec<u8>>();
+        let header = IpV4Header::new(
+            0,
+            Ecn::NotECT,
+            payload.len() as u16,
+            0x1234,
+            Flags::DF,
+            0,
+            3,
+            ProtocolType::UDP,
+            IpV4Addr::new([10, 0, 0, 2]),
+            IpV4Addr::new([10, 0, 0, 1]),
+            vec![],
+        );
+        let data = Ipv4Packet::new(header, payload).to_vec();
+        let packet = Ipv4Packet::from_vec(&data).unwrap();
+        assert_eq!(icmp_error_data(&packet), data[..28]);
     }
 }
@@ -0,0 +1,470 @@
+//! Stateless access lists, filtering the IPv4 packets received or sent by the interfaces
+
+use std::{fmt::Display, str::FromStr};
+
+use tracing::info;
+
+use super::{
+    addr::{IpV4Addr, IpV4Mask, DEFAULT},
+    conntrack::State,
+    packet::Ipv4Packet,
+    protocol::ProtocolType,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Packets received by the interface, before they're routed or delivered
+    In,
+    /// Packets routed out of the interface, the locally originated ones aren't filtered
+    Out,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::In => write!(f, "in"),
+            Self::Out => write!(f, "out"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Permit,
+    Deny,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Permit => write!(f, "permit"),
+            Self::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// Network matched by a rule, `any` is 0.0.0.0/0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    pub addr: IpV4Addr,
+    pub mask: IpV4Mask,
+}
+
+impl Prefix {
+    pub fn any() -> Self {
+        Self {
+            addr: DEFAULT,
+            mask: IpV4Mask::new(0),
+        }
+    }
+
+    pub fn contains(&self, addr: IpV4Addr) -> bool {
+        self.mask & addr == self.mask & self.addr
+    }
+
+    fn len(&self) -> u32 {
+        u32::from(self.mask.to_addr()).leading_ones()
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = String;
+
+    /// `any`, an address or a network as `<addr>/<len>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "any" {
+            return Ok(Self::any());
+        }
+        let (addr, len) = s.split_once('/').unwrap_or((s, "32"));
+        match (addr.parse(), len.parse::<u8>()) {
+            (Ok(addr), Ok(len)) if len <= 32 => Ok(Self {
+                addr,
+                mask: IpV4Mask::new(len),
+            }),
+            _ => Err(format!("Invalid prefix {s}")),
+        }
+    }
+}
+
+impl Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.len() {
+            0 => write!(f, "any"),
+            32 => write!(f, "{}", self.addr),
+            len => write!(f, "{}/{len}", self.mask & self.addr),
+        }
+    }
+}
+
+/// Inclusive range of TCP or UDP ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ports {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl Ports {
+    pub const fn contains(&self, port: u16) -> bool {
+        self.first <= port && port <= self.last
+    }
+}
+
+impl FromStr for Ports {
+    type Err = String;
+
+    /// A port or a range as `<first>-<last>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        match (first.parse(), last.parse()) {
+            (Ok(first), Ok(last)) if first <= last => Ok(Self { first, last }),
+            _ => Err(format!("Invalid port range {s}")),
+        }
+    }
+}
+
+impl Display for Ports {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// Rule of an access list, the fields not given match every packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub source: Prefix,
+    pub destination: Prefix,
+    pub protocol: Option<ProtocolType>,
+    /// Only match TCP and UDP packets, never the fragments after the first one
+    pub source_ports: Option<Ports>,
+    pub destination_ports: Option<Ports>,
+    /// Only matches ICMP packets
+    pub icmp_type: Option<u8>,
+    /// Connection tracking states, every packet matches when empty and untracked ones never
+    /// match otherwise
+    pub states: Vec<State>,
+    /// Logs the packets matching the rule
+    pub log: bool,
+    pub hits: u64,
+}
+
+impl Rule {
+    pub fn new(action: Action) -> Self {
+        Self {
+            action,
+            source: Prefix::any(),
+            destination: Prefix::any(),
+            protocol: None,
+            source_ports: None,
+            destination_ports: None,
+            icmp_type: None,
+            states: Vec::new(),
+            log: false,
+            hits: 0,
+        }
+    }
+
+    pub fn matches(&self, packet: &Ipv4Packet, state: Option<State>) -> bool {
+        let header = &packet.header;
+        if !self.states.is_empty() && !state.is_some_and(|state| self.states.contains(&state)) {
+            return false;
+        }
+        if !self.source.contains(header.source)
+            || !self.destination.contains(header.destination)
+            || self
+                .protocol
+                .is_some_and(|protocol| protocol != header.protocol)
+        {
+            return false;
+        }
+        // The fragments after the first one don't have the transport header
+        let transport = (header.fragment_offset() == 0).then_some(packet.payload.as_slice());
+        if self.source_ports.is_some() || self.destination_ports.is_some() {
+            let ports = transport
+                .filter(|_| matches!(header.protocol, ProtocolType::TCP | ProtocolType::UDP))
+                .and_then(|payload| payload.get(..4))
+                .map(|ports| {
+                    (
+                        u16::from_be_bytes([ports[0], ports[1]]),
+                        u16::from_be_bytes([ports[2], ports[3]]),
+                    )
+                });
+            let Some((source, destination)) = ports else {
+                return false;
+            };
+            if self
+                .source_ports
+                .is_some_and(|ports| !ports.contains(source))
+                || self
+                    .destination_ports
+                    .is_some_and(|ports| !ports.contains(destination))
+            {
+                return false;
+            }
+        }
+        self.icmp_type.is_none_or(|icmp_type| {
+            header.protocol == ProtocolType::ICMP
+                && transport.and_then(|payload| payload.first()) == Some(&icmp_type)
+        })
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.action)?;
+        match self.protocol {
+            Some(ProtocolType::ICMP) => write!(f, " icmp")?,
+            Some(ProtocolType::TCP) => write!(f, " tcp")?,
+            Some(ProtocolType::UDP) => write!(f, " udp")?,
+            Some(protocol) => write!(f, " {}", protocol.inner())?,
+            None => write!(f, " ip")?,
+        }
+        write!(f, " {}", self.source)?;
+        if let Some(ports) = self.source_ports {
+            write!(f, " port {ports}")?;
+        }
+        write!(f, " {}", self.destination)?;
+        if let Some(ports) = self.destination_ports {
+            write!(f, " port {ports}")?;
+        }
+        if let Some(icmp_type) = self.icmp_type {
+            write!(f, " type {icmp_type}")?;
+        }
+        if !self.states.is_empty() {
+            let states = self.states.iter().map(State::to_string).collect::<Vec<_>>();
+            write!(f, " state {}", states.join(","))?;
+        }
+        if self.log {
+            write!(f, " log")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rules checked in order, the first one matching decides. The packets matching none are denied
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AccessList {
+    pub rules: Vec<Rule>,
+    /// The denied packets are answered with ICMP communication administratively prohibited,
+    /// instead of being dropped silently
+    pub prohibited: bool,
+    /// Packets denied by the implicit rule at the end
+    pub implicit_hits: u64,
+}
+
+impl AccessList {
+    /// Counts the hit of the packet on the rule it matches
+    pub fn check(&mut self, name: &str, packet: &Ipv4Packet, state: Option<State>) -> Action {
+        match self
+            .rules
+            .iter_mut()
+            .find(|rule| rule.matches(packet, state))
+        {
+            Some(rule) => {
+                rule.hits += 1;
+                if rule.log {
+                    info!(
+                        "ACL {name} {rule}: {} > {} protocol {}",
+                        packet.header.source,
+                        packet.header.destination,
+                        packet.header.protocol.inner()
+                    );
+                }
+                rule.action
+            }
+            None => {
+                self.implicit_hits += 1;
+                Action::Deny
+            }
+        }
+    }
+
+    pub fn print(&self) -> prettytable::Table {
+        let mut table = prettytable::table!(["#", "rule", "hits"]);
+        for (i, rule) in self.rules.iter().enumerate() {
+            table.add_row(prettytable::row![i, rule, rule.hits]);
+        }
+        table.add_row(prettytable::row!["", "deny ip any any", self.implicit_hits]);
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        network::ipv4::{
+            addr::IpV4Addr,
+            conntrack::State,
+            packet::{Ecn, Flags, IpV4Header, Ipv4Packet},
+            protocol::ProtocolType,
+        },
+        transport::{icmp::packet::IcmpPacket, udp::packet::UdpPacket},
+    };
+
+    use super::{AccessList, Action, Ports, Prefix, Rule};
+
+    fn packet(protocol: ProtocolType, fragment_offset: u16, payload: Vec<u8>) -> Ipv4Packet {
+        Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                Ecn::NotECT,
+                payload.len() as u16,
+                1,
+                Flags::empty(),
+                fragment_offset,
+                64,
+                protocol,
+                IpV4Addr::new([10, 0, 1, 2]),
+                IpV4Addr::new([10, 0, 0, 2]),
+                vec![],
+            ),
+            payload,
+        )
+    }
+
+    fn udp(source_port: u16, destination_port: u16) -> Ipv4Packet {
+        let udp = UdpPacket {
+            source_port,
+            destination_port,
+            payload: vec![0; 8],
+        };
+        packet(ProtocolType::UDP, 0, udp.to_vec())
+    }
+
+    #[test]
+    fn parses_prefixes_and_ports() {
+        let prefix = "10.0.0.0/8".parse::<Prefix>().unwrap();
+        assert!(prefix.contains(IpV4Addr::new([10, 1, 2, 3])));
+        assert!(!prefix.contains(IpV4Addr::new([11, 0, 0, 0])));
+        assert_eq!(prefix.to_string(), "10.0.0.0/8");
+        assert_eq!("any".parse(), Ok(Prefix::any()));
+        assert_eq!(
+            "10.0.0.1".parse::<Prefix>().unwrap().to_string(),
+            "10.0.0.1"
+        );
+        assert!("10.0.0.0/33".parse::<Prefix>().is_err());
+
+        assert_eq!(
+            "80".parse(),
+            Ok(Ports {
+                first: 80,
+                last: 80
+            })
+        );
+        assert_eq!(
+            "1024-2048".parse(),
+            Ok(Ports {
+                first: 1024,
+                last: 2048
+            })
+        );
+        assert!("2048-1024".parse::<Ports>().is_err());
+    }
+
+    #[test]
+    fn matches_port_ranges() {
+        let rule = Rule {
+            protocol: Some(ProtocolType::UDP),
+            source_ports: Some(Ports {
+                first: 1024,
+                last: 2048,
+            }),
+            destination_ports: Some(Ports {
+                first: 53,
+                last: 53,
+            }),
+            ..Rule::new(Action::Permit)
+        };
+        assert!(rule.matches(&udp(1024, 53), None));
+        assert!(rule.matches(&udp(2048, 53), None));
+        assert!(!rule.matches(&udp(1023, 53), None));
+        assert!(!rule.matches(&udp(2049, 53), None));
+        assert!(!rule.matches(&udp(1500, 54), None));
+        // Packets without ports never match port rules
+        let icmp = IcmpPacket::EchoRequest { id: 1024, seq: 53 };
+        let rule = Rule {
+            protocol: None,
+            ..rule
+        };
+        assert!(!rule.matches(&packet(ProtocolType::ICMP, 0, icmp.to_vec()), None));
+    }
+
+    #[test]
+    fn never_matches_ports_of_later_fragments() {
+        let rule = Rule {
+            destination_ports: Some(Ports {
+                first: 53,
+                last: 53,
+            }),
+            ..Rule::new(Action::Deny)
+        };
+        let first = udp(1024, 53);
+        let later = packet(ProtocolType::UDP, 1, first.payload.clone());
+        assert!(rule.matches(&first, None));
+        assert!(!rule.matches(&later, None));
+        // The rules without ports match them
+        assert!(Rule::new(Action::Deny).matches(&later, None));
+    }
+
+    #[test]
+    fn matches_icmp_types() {
+        let rule = Rule {
+            icmp_type: Some(8),
+            ..Rule::new(Action::Permit)
+        };
+        let request = IcmpPacket::EchoRequest { id: 1, seq: 1 }.to_vec();
+        let reply = IcmpPacket::EchoReply { id: 1, seq: 1 }.to_vec();
+        assert!(rule.matches(&packet(ProtocolType::ICMP, 0, request.clone()), None));
+        assert!(!rule.matches(&packet(ProtocolType::ICMP, 0, reply), None));
+        assert!(!rule.matches(&packet(ProtocolType::ICMP, 1, request.clone()), None));
+        assert!(!rule.matches(&packet(ProtocolType::UDP, 0, request), None));
+    }
+
+    #[test]
+    fn matches_states() {
+        let rule = Rule {
+            states: vec![State::Established, State::Related],
+            ..Rule::new(Action::Permit)
+        };
+        let packet = udp(1024, 53);
+        assert!(rule.matches(&packet, Some(State::Established)));
+        assert!(rule.matches(&packet, Some(State::Related)));
+        assert!(!rule.matches(&packet, Some(State::New)));
+        assert!(!rule.matches(&packet, None));
+        assert!(Rule::new(Action::Permit).matches(&packet, None));
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let mut acl = AccessList {
+            rules: vec![
+                Rule {
+                    source: "10.0.0.0/24".parse().unwrap(),
+                    destination_ports: Some(Ports {
+                        first: 53,
+                        last: 53,
+                    }),
+                    ..Rule::new(Action::Deny)
+                },
+                Rule {
+                    destination: "10.0.1.0/24".parse().unwrap(),
+                    ..Rule::new(Action::Permit)
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(acl.check("test", &udp(1024, 53), None), Action::Deny);
+        assert_eq!(acl.check("test", &udp(1024, 80), None), Action::Permit);
+        assert_eq!(acl.check("test", &udp(1024, 80), None), Action::Permit);
+        let mut other = udp(1024, 80);
+        other.header.destination = IpV4Addr::new([10, 0, 2, 2]);
+        assert_eq!(acl.check("test", &other, None), Action::Deny);
+        let hits = acl.rules.iter().map(|rule| rule.hits).collect::<Vec<_>>();
+        assert_eq!(hits, [1, 2]);
+        assert_eq!(acl.implicit_hits, 1);
+    }
+}
@@ -8,7 +8,7 @@ use std::{
 
 use tracing::debug;
 
-use crate::route::AddrMask;
+use crate::{mac::Mac, route::AddrMask};
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct IpV4Addr {
@@ -57,6 +57,39 @@ impl IpV4Addr {
     pub const fn as_slice(&self) -> &[u8; 4] {
         &self.addr
     }
+
+    pub const fn is_multicast(&self) -> bool {
+        (self.addr[0] & 0xf0) == 0xe0
+    }
+
+    /// Local network control block (224.0.0.0/24), these groups are never routed
+    pub const fn is_link_local_multicast(&self) -> bool {
+        self.addr[0] == 224 && self.addr[1] == 0 && self.addr[2] == 0
+    }
+
+    /// Ethernet multicast address a packet sent to this (multicast) address is framed with
+    pub const fn multicast_mac(&self) -> Mac {
+        Mac::new([
+            0x01,
+            0x00,
+            0x5e,
+            self.addr[1] & 0x7f,
+            self.addr[2],
+            self.addr[3],
+        ])
+    }
+}
+
+impl From<u32> for IpV4Addr {
+    fn from(value: u32) -> Self {
+        Self::new(value.to_be_bytes())
+    }
+}
+
+impl From<IpV4Addr> for u32 {
+    fn from(value: IpV4Addr) -> Self {
+        Self::from_be_bytes(value.addr)
+    }
 }
 
 impl Debug for IpV4Addr {
@@ -84,6 +117,15 @@ impl IpV4Mask {
         Self(mask.min(32))
     }
 
+    /// Mask from its dotted form, the bits after the first zero are ignored
+    pub fn from_addr(addr: IpV4Addr) -> Self {
+        Self::new(u32::from(addr).leading_ones() as u8)
+    }
+
+    pub fn to_addr(self) -> IpV4Addr {
+        IpV4Addr::new(self.get_mask())
+    }
+
     fn get_mask(&self) -> [u8; 4] {
         let mut mask: u32 = 0;
         for _ in 0..self.0 {
@@ -120,9 +162,11 @@ impl BitAnd<IpV4Addr> for IpV4Mask {
 }
 
 impl AddrMask<IpV4Addr> for IpV4Mask {
-    type Specifity = u8;
-
-    fn specifity(&self) -> Self::Specifity {
+    fn prefix_len(&self) -> u8 {
         self.0
     }
+
+    fn from_prefix_len(len: u8) -> Self {
+        Self::new(len)
+    }
 }
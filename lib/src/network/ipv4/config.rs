@@ -1,26 +1,320 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use tokio::sync::RwLock;
 
-use crate::{chassis::LinkLayerId, route::RoutingTable};
+use crate::{
+    chassis::LinkLayerId,
+    mac::Mac,
+    route::{RouteSource, RoutingEntry, RoutingTable},
+};
 
-use super::addr::{IpV4Addr, IpV4Mask, DEFAULT};
+use super::{
+    acl::{AccessList, Action, Direction},
+    addr::{IpV4Addr, IpV4Mask, DEFAULT},
+    conntrack::ConnTrack,
+    nat::{Nat, Role},
+    packet::Ipv4Packet,
+    protocol::ProtocolType,
+    tunnel::Tunnel,
+};
+
+/// MTU used for interfaces without an explicit one
+pub const DEFAULT_MTU: u16 = 1500;
+
+/// Address bound to an interface with the prefix of its network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceAddr {
+    pub addr: IpV4Addr,
+    pub mask: IpV4Mask,
+}
+
+impl InterfaceAddr {
+    pub const fn new(addr: IpV4Addr, mask: IpV4Mask) -> Self {
+        Self { addr, mask }
+    }
+
+    pub fn network(&self) -> IpV4Addr {
+        self.mask & self.addr
+    }
+
+    pub fn contains(&self, addr: IpV4Addr) -> bool {
+        self.mask & addr == self.network()
+    }
+
+    /// Route to the network through the interface, the gateway is unspecified since the
+    /// destinations are on the link
+    fn connected_route(&self, iface: LinkLayerId) -> RoutingEntry<IpV4Addr, IpV4Mask, LinkLayerId> {
+        RoutingEntry::new(self.network(), DEFAULT, self.mask, iface)
+            .with_source(RouteSource::Connected)
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IpV4ConfigInner {
-    pub addr: IpV4Addr,
+    /// Addresses of every interface, the first one is the primary and the rest are secondary
+    pub addrs: HashMap<LinkLayerId, Vec<InterfaceAddr>>,
     pub routing: RoutingTable<IpV4Addr, IpV4Mask, LinkLayerId>,
     pub arp_ttl: chrono::Duration,
     pub dhcp_run: bool,
+    pub mtu: HashMap<LinkLayerId, u16>,
+    /// Time fragments are kept waiting for the rest of the packet
+    pub reassembly_timeout: chrono::Duration,
+    /// Access lists by name
+    pub acls: HashMap<String, AccessList>,
+    /// Access list filtering each direction of the interfaces
+    pub acl_bindings: HashMap<(LinkLayerId, Direction), String>,
+    pub conntrack: ConnTrack,
+    pub nat: Nat,
+    /// Addresses of the virtual routers the interfaces are VRRP master of, with their virtual MAC
+    pub virtual_addrs: HashMap<LinkLayerId, Vec<(IpV4Addr, Mac)>>,
+    /// Endpoints of the tunnel interfaces
+    pub tunnels: HashMap<LinkLayerId, Tunnel>,
+    /// Interfaces answering ARP requests for the addresses routed through other interfaces
+    pub proxy_arp: HashSet<LinkLayerId>,
+}
+
+impl IpV4ConfigInner {
+    /// Tunnels leave room for the encapsulation so their packets aren't fragmented by default
+    pub fn get_mtu(&self, iface: &LinkLayerId) -> u16 {
+        self.mtu.get(iface).copied().unwrap_or_else(|| {
+            self.tunnels
+                .get(iface)
+                .map_or(DEFAULT_MTU, |tunnel| DEFAULT_MTU - tunnel.mode.overhead())
+        })
+    }
+
+    /// Tunnel the packet received from the source to the destination comes out of
+    pub fn tunnel_from(
+        &self,
+        source: IpV4Addr,
+        destination: IpV4Addr,
+        protocol: ProtocolType,
+    ) -> Option<(LinkLayerId, Tunnel)> {
+        self.tunnels
+            .iter()
+            .find(|(_, tunnel)| {
+                tunnel.source == destination
+                    && tunnel.destination == source
+                    && tunnel.mode.protocol() == protocol
+            })
+            .map(|(iface, tunnel)| (*iface, *tunnel))
+    }
+
+    /// Checks the packet against the access list bound to the interface direction. `Some` if it's
+    /// denied, with whether it's answered with ICMP administratively prohibited. A missing access
+    /// list permits everything
+    pub fn acl_denies(
+        &mut self,
+        iface: LinkLayerId,
+        direction: Direction,
+        packet: &Ipv4Packet,
+    ) -> Option<bool> {
+        let name = self.acl_bindings.get(&(iface, direction))?;
+        let acl = self.acls.get_mut(name)?;
+        match acl.check(name, packet, self.conntrack.state(packet)) {
+            Action::Permit => None,
+            Action::Deny => Some(acl.prohibited),
+        }
+    }
+
+    /// Translates the packet received on the interface back to the inside host if it's an outside
+    /// interface, `true` if it's translated
+    pub fn nat_in(&mut self, iface: LinkLayerId, packet: &mut Ipv4Packet) -> bool {
+        if self.nat.role(&iface) != Some(Role::Outside) {
+            return false;
+        }
+        let addrs = self
+            .iface_addrs(&iface)
+            .iter()
+            .map(|addr| addr.addr)
+            .collect::<Vec<_>>();
+        self.nat.translate_in(packet, &addrs)
+    }
+
+    /// Translates the packet routed from an inside interface out of an outside one with the
+    /// primary address of the outside interface, `true` if it's translated
+    pub fn nat_out(
+        &mut self,
+        ingress: LinkLayerId,
+        egress: LinkLayerId,
+        packet: &mut Ipv4Packet,
+    ) -> bool {
+        if self.nat.role(&ingress) != Some(Role::Inside)
+            || self.nat.role(&egress) != Some(Role::Outside)
+        {
+            return false;
+        }
+        match self.iface_addrs(&egress).first() {
+            Some(addr) => self.nat.translate_out(packet, addr.addr),
+            None => false,
+        }
+    }
+
+    pub fn iface_addrs(&self, iface: &LinkLayerId) -> &[InterfaceAddr] {
+        self.addrs.get(iface).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether the address belongs to any interface
+    pub fn is_local(&self, addr: IpV4Addr) -> bool {
+        self.addrs
+            .values()
+            .flatten()
+            .any(|iface_addr| iface_addr.addr == addr)
+    }
+
+    /// Whether the address belongs to a virtual router an interface is master of
+    pub fn is_virtual(&self, addr: IpV4Addr) -> bool {
+        self.virtual_addrs
+            .values()
+            .flatten()
+            .any(|(virtual_addr, _)| *virtual_addr == addr)
+    }
+
+    /// Virtual MAC of the address if the interface is master of its virtual router
+    pub fn virtual_mac(&self, iface: &LinkLayerId, addr: IpV4Addr) -> Option<Mac> {
+        self.virtual_addrs
+            .get(iface)?
+            .iter()
+            .find(|(virtual_addr, _)| *virtual_addr == addr)
+            .map(|(_, mac)| *mac)
+    }
+
+    pub fn has_addr(&self, iface: &LinkLayerId, addr: IpV4Addr) -> bool {
+        self.iface_addrs(iface)
+            .iter()
+            .any(|iface_addr| iface_addr.addr == addr)
+    }
+
+    /// Address used as source towards a neighbor of the interface: the one in the same network
+    /// or the primary. Unspecified if the interface has none
+    pub fn source_addr(&self, iface: &LinkLayerId, neighbor: IpV4Addr) -> IpV4Addr {
+        let addrs = self.iface_addrs(iface);
+        addrs
+            .iter()
+            .find(|iface_addr| iface_addr.contains(neighbor))
+            .or(addrs.first())
+            .map_or(DEFAULT, |iface_addr| iface_addr.addr)
+    }
+
+    /// Address used as source towards the destination, based on the interface it's routed through
+    pub fn source_addr_to(&self, destination: IpV4Addr) -> IpV4Addr {
+        match self.routing.get_route(destination) {
+            Some((DEFAULT, iface)) => self.source_addr(&iface, destination),
+            Some((gateway, iface)) => self.source_addr(&iface, gateway),
+            None => DEFAULT,
+        }
+    }
+
+    /// Replaces the primary address of the interface
+    pub fn set_addr(&mut self, iface: LinkLayerId, addr: InterfaceAddr) {
+        if let Some(&primary) = self.iface_addrs(&iface).first() {
+            self.remove_addr(iface, primary.addr);
+        }
+        self.add_addr(iface, addr);
+        let addrs = self.addrs.entry(iface).or_default();
+        if let Some(i) = addrs.iter().position(|iface_addr| iface_addr == &addr) {
+            let addr = addrs.remove(i);
+            addrs.insert(0, addr);
+        }
+    }
+
+    /// Adds the address to the interface with its connected route, false if it already had it
+    pub fn add_addr(&mut self, iface: LinkLayerId, addr: InterfaceAddr) -> bool {
+        if self.has_addr(&iface, addr.addr) {
+            return false;
+        }
+        let addrs = self.addrs.entry(iface).or_default();
+        let connected = addrs
+            .iter()
+            .any(|other| other.network() == addr.network() && other.mask == addr.mask);
+        addrs.push(addr);
+        if !connected {
+            self.routing.add_route(addr.connected_route(iface));
+        }
+        true
+    }
+
+    /// Removes the address from the interface, and its connected route if no other address is in
+    /// the network. False if the interface didn't have it
+    pub fn remove_addr(&mut self, iface: LinkLayerId, addr: IpV4Addr) -> bool {
+        let Some(addrs) = self.addrs.get_mut(&iface) else {
+            return false;
+        };
+        let Some(i) = addrs.iter().position(|iface_addr| iface_addr.addr == addr) else {
+            return false;
+        };
+        let removed = addrs.remove(i);
+        let connected = addrs
+            .iter()
+            .any(|other| other.network() == removed.network() && other.mask == removed.mask);
+        if addrs.is_empty() {
+            self.addrs.remove(&iface);
+        }
+        if !connected {
+            self.routing.remove_route(&removed.connected_route(iface));
+        }
+        true
+    }
+
+    pub fn print_addrs(&self) -> prettytable::Table {
+        let mut table = prettytable::table!(["interface", "address", "mask", "primary"]);
+        if self.addrs.is_empty() {
+            table.add_empty_row();
+        }
+        let mut ifaces = self.addrs.iter().collect::<Vec<_>>();
+        ifaces.sort_by_key(|(iface, _)| iface.to_string());
+        for (iface, addrs) in ifaces {
+            for (i, iface_addr) in addrs.iter().enumerate() {
+                table.add_row(prettytable::row![
+                    iface,
+                    iface_addr.addr,
+                    iface_addr.mask,
+                    i == 0
+                ]);
+            }
+        }
+        table
+    }
+
+    pub fn print_tunnels(&self) -> prettytable::Table {
+        let mut table = prettytable::table!(["interface", "mode", "source", "destination", "mtu"]);
+        if self.tunnels.is_empty() {
+            table.add_empty_row();
+        }
+        let mut tunnels = self.tunnels.iter().collect::<Vec<_>>();
+        tunnels.sort_by_key(|(iface, _)| iface.to_string());
+        for (iface, tunnel) in tunnels {
+            table.add_row(prettytable::row![
+                iface,
+                tunnel.mode,
+                tunnel.source,
+                tunnel.destination,
+                self.get_mtu(iface)
+            ]);
+        }
+        table
+    }
 }
 
 impl Default for IpV4ConfigInner {
     fn default() -> Self {
         Self {
-            addr: DEFAULT,
+            addrs: Default::default(),
             routing: Default::default(),
             dhcp_run: Default::default(),
             arp_ttl: chrono::Duration::seconds(5),
+            mtu: Default::default(),
+            reassembly_timeout: chrono::Duration::seconds(30),
+            acls: Default::default(),
+            acl_bindings: Default::default(),
+            conntrack: Default::default(),
+            nat: Default::default(),
+            virtual_addrs: Default::default(),
+            tunnels: Default::default(),
+            proxy_arp: Default::default(),
         }
     }
 }
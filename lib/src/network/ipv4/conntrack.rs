@@ -0,0 +1,478 @@
+//! Connection tracking of the UDP, TCP and ICMP echo flows going through the IPv4 process, so the
+//! access lists can match on the state of the packets' connection
+//!
+//! Fragments after the first one don't carry the ports and are never tracked
+
+use std::{collections::HashMap, fmt::Display};
+
+use chrono::{DateTime, Local};
+
+use crate::transport::{
+    icmp::packet::{IcmpPacket, TimeExceeded},
+    tcp::packet::{Flags, TcpPacket},
+};
+
+use super::{addr::IpV4Addr, packet::Ipv4Packet, protocol::ProtocolType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum State {
+    /// Starts a connection, or belongs to one that hasn't seen a reply yet
+    New,
+    /// Belongs to a connection that has seen packets both ways
+    Established,
+    /// ICMP error about a packet of a connection
+    Related,
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::New => write!(f, "new"),
+            Self::Established => write!(f, "established"),
+            Self::Related => write!(f, "related"),
+        }
+    }
+}
+
+/// Identifies the packets of a flow in one direction. ICMP echoes use the identifier as the
+/// source port and the message type as the destination port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tuple {
+    pub protocol: ProtocolType,
+    pub source: IpV4Addr,
+    pub destination: IpV4Addr,
+    pub source_port: u16,
+    pub destination_port: u16,
+}
+
+const ECHO_REPLY: u16 = 0;
+const ECHO_REQUEST: u16 = 8;
+
+impl Tuple {
+    /// Tuple of the packets going the other way
+    pub fn reverse(&self) -> Self {
+        let (source_port, destination_port) = match self.protocol {
+            ProtocolType::ICMP if self.destination_port == ECHO_REQUEST => {
+                (self.source_port, ECHO_REPLY)
+            }
+            ProtocolType::ICMP => (self.source_port, ECHO_REQUEST),
+            _ => (self.destination_port, self.source_port),
+        };
+        Self {
+            protocol: self.protocol,
+            source: self.destination,
+            destination: self.source,
+            source_port,
+            destination_port,
+        }
+    }
+}
+
+impl Display for Tuple {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.protocol {
+            ProtocolType::ICMP => write!(
+                f,
+                "icmp {} > {} id {}",
+                self.source, self.destination, self.source_port
+            ),
+            protocol => write!(
+                f,
+                "{} {}:{} > {}:{}",
+                match protocol {
+                    ProtocolType::TCP => "tcp",
+                    _ => "udp",
+                },
+                self.source,
+                self.source_port,
+                self.destination,
+                self.destination_port
+            ),
+        }
+    }
+}
+
+/// What a packet is to the connection tracking
+enum Track {
+    /// Packet that may start a connection
+    Flow(Tuple, Option<Flags>),
+    /// ICMP echo reply, only belongs to a request already seen
+    Reply(Tuple),
+    /// ICMP error quoting a packet with the tuple
+    Error(Tuple),
+}
+
+fn classify(packet: &Ipv4Packet) -> Option<Track> {
+    if packet.header.fragment_offset() != 0 {
+        return None;
+    }
+    let tuple = |source_port, destination_port| Tuple {
+        protocol: packet.header.protocol,
+        source: packet.header.source,
+        destination: packet.header.destination,
+        source_port,
+        destination_port,
+    };
+    let ports = packet.payload.get(..4).map(|p| {
+        (
+            u16::from_be_bytes([p[0], p[1]]),
+            u16::from_be_bytes([p[2], p[3]]),
+        )
+    });
+    match packet.header.protocol {
+        ProtocolType::UDP => {
+            ports.map(|(source, destination)| Track::Flow(tuple(source, destination), None))
+        }
+        ProtocolType::TCP => {
+            let flags = TcpPacket::from_vec(&packet.payload).map(|tcp| tcp.flags);
+            ports.map(|(source, destination)| Track::Flow(tuple(source, destination), flags))
+        }
+        ProtocolType::ICMP => match IcmpPacket::from_vec(&packet.payload)? {
+            IcmpPacket::EchoRequest { id, .. } => Some(Track::Flow(tuple(id, ECHO_REQUEST), None)),
+            IcmpPacket::EchoReply { id, .. } => Some(Track::Reply(tuple(id, ECHO_REPLY))),
+            IcmpPacket::DestinationUnreachable(unreachable) => quoted(unreachable.data()),
+            IcmpPacket::TimeExceeded(
+                TimeExceeded::TtlTransit { data } | TimeExceeded::FragmentReassembly { data },
+            ) => quoted(&data),
+        },
+        _ => None,
+    }
+}
+
+/// Tuple of the packet quoted by an ICMP error
+fn quoted(data: &[u8]) -> Option<Track> {
+    let packet = Ipv4Packet::from_vec_truncated(data)?;
+    match classify(&packet)? {
+        Track::Flow(tuple, _) | Track::Reply(tuple) => Some(Track::Error(tuple)),
+        Track::Error(_) => None,
+    }
+}
+
+/// How long the connections are kept without packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub udp: chrono::Duration,
+    pub icmp: chrono::Duration,
+    pub tcp_established: chrono::Duration,
+    /// TCP connections opening or closing
+    pub tcp_transitory: chrono::Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            udp: chrono::Duration::seconds(30),
+            icmp: chrono::Duration::seconds(30),
+            tcp_established: chrono::Duration::hours(2),
+            tcp_transitory: chrono::Duration::seconds(120),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    /// Tuple of the packet that started the connection
+    pub tuple: Tuple,
+    /// A packet was seen the other way
+    pub replied: bool,
+    /// A TCP FIN or RST was seen
+    pub closing: bool,
+    /// Packets seen both ways
+    pub packets: u64,
+    pub expires: DateTime<Local>,
+}
+
+impl Connection {
+    fn refresh(&mut self, flags: Option<Flags>, timeouts: &Timeouts, now: DateTime<Local>) {
+        self.packets += 1;
+        if flags.is_some_and(|flags| flags.intersects(Flags::FIN | Flags::RST)) {
+            self.closing = true;
+        }
+        let timeout = match self.tuple.protocol {
+            ProtocolType::TCP if self.replied && !self.closing => timeouts.tcp_established,
+            ProtocolType::TCP => timeouts.tcp_transitory,
+            ProtocolType::ICMP => timeouts.icmp,
+            _ => timeouts.udp,
+        };
+        self.expires = now + timeout;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ConnTrack {
+    /// Connections by the tuple of the packet that started them
+    connections: HashMap<Tuple, Connection>,
+    /// Tuple of the connections by the tuple of their replies
+    replies: HashMap<Tuple, Tuple>,
+    pub timeouts: Timeouts,
+    /// Last time the expired connections were removed
+    expired: Option<DateTime<Local>>,
+}
+
+impl ConnTrack {
+    /// Connection of the tuple, and whether the tuple is the reply direction
+    fn find(&self, tuple: &Tuple, now: DateTime<Local>) -> Option<(&Connection, bool)> {
+        let (key, reply) = match self.replies.get(tuple) {
+            Some(key) => (key, true),
+            None => (tuple, false),
+        };
+        self.connections
+            .get(key)
+            .filter(|connection| connection.expires > now)
+            .map(|connection| (connection, reply))
+    }
+
+    /// State of the packet, `None` if it isn't tracked
+    pub fn state(&self, packet: &Ipv4Packet) -> Option<State> {
+        let now = Local::now();
+        match classify(packet)? {
+            Track::Flow(tuple, _) => match self.find(&tuple, now) {
+                Some((connection, reply)) if reply || connection.replied => {
+                    Some(State::Established)
+                }
+                _ => Some(State::New),
+            },
+            Track::Reply(tuple) => self
+                .find(&tuple, now)
+                .filter(|(_, reply)| *reply)
+                .map(|_| State::Established),
+            Track::Error(tuple) => self.find(&tuple, now).map(|_| State::Related),
+        }
+    }
+
+    /// Records the packet in its connection, starting one if it's new
+    pub fn track(&mut self, packet: &Ipv4Packet) {
+        let now = Local::now();
+        if self
+            .expired
+            .is_none_or(|expired| now - expired > chrono::Duration::seconds(1))
+        {
+            self.expire(now);
+        }
+        let (tuple, flags, starts) = match classify(packet) {
+            Some(Track::Flow(tuple, flags)) => (tuple, flags, true),
+            Some(Track::Reply(tuple)) => (tuple, None, false),
+            _ => return,
+        };
+        let stale = self.connections.contains_key(&tuple) || self.replies.contains_key(&tuple);
+        if stale && self.find(&tuple, now).is_none() {
+            self.expire(now);
+        }
+        let timeouts = self.timeouts;
+        if let Some(key) = self.replies.get(&tuple) {
+            if let Some(connection) = self.connections.get_mut(key) {
+                connection.replied = true;
+                connection.refresh(flags, &timeouts, now);
+            }
+        } else if let Some(connection) = self.connections.get_mut(&tuple) {
+            connection.refresh(flags, &timeouts, now);
+        } else if starts {
+            let mut connection = Connection {
+                tuple,
+                replied: false,
+                closing: false,
+                packets: 0,
+                expires: now,
+            };
+            connection.refresh(flags, &timeouts, now);
+            self.connections.insert(tuple, connection);
+            self.replies.insert(tuple.reverse(), tuple);
+        }
+    }
+
+    /// Removes the connections without packets for longer than their timeout
+    pub fn expire(&mut self, now: DateTime<Local>) {
+        self.expired = Some(now);
+        self.connections
+            .retain(|_, connection| connection.expires > now);
+        let connections = &self.connections;
+        self.replies.retain(|_, key| connections.contains_key(key));
+    }
+
+    pub fn flush(&mut self) {
+        self.connections.clear();
+        self.replies.clear();
+    }
+
+    /// Connections that haven't expired
+    pub fn connections(&self) -> impl Iterator<Item = &Connection> {
+        let now = Local::now();
+        self.connections
+            .values()
+            .filter(move |connection| connection.expires > now)
+    }
+
+    pub fn print(&self) -> prettytable::Table {
+        let now = Local::now();
+        let mut connections = self.connections().collect::<Vec<_>>();
+        connections.sort_by_key(|connection| connection.expires);
+        let mut table = prettytable::table!(["connection", "state", "packets", "expires in"]);
+        for connection in connections {
+            let state = match (connection.replied, connection.closing) {
+                (_, true) => "closing",
+                (true, false) => "established",
+                (false, false) => "unreplied",
+            };
+            table.add_row(prettytable::row![
+                connection.tuple,
+                state,
+                connection.packets,
+                format!("{}s", (connection.expires - now).num_seconds())
+            ]);
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use crate::{
+        network::ipv4::{
+            addr::IpV4Addr,
+            packet::{Ecn, Flags, IpV4Header, Ipv4Packet},
+            protocol::ProtocolType,
+        },
+        transport::{
+            icmp::packet::{DestinationUnreachable, IcmpPacket, TimeExceeded},
+            udp::packet::UdpPacket,
+        },
+    };
+
+    use super::{ConnTrack, State};
+
+    const CLIENT: IpV4Addr = IpV4Addr::new([10, 0, 0, 2]);
+    const SERVER: IpV4Addr = IpV4Addr::new([10, 0, 1, 2]);
+    const ROUTER: IpV4Addr = IpV4Addr::new([10, 0, 0, 1]);
+
+    fn packet(
+        protocol: ProtocolType,
+        source: IpV4Addr,
+        destination: IpV4Addr,
+        payload: Vec<u8>,
+    ) -> Ipv4Packet {
+        Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                Ecn::NotECT,
+                payload.len() as u16,
+                1,
+                Flags::empty(),
+                0,
+                64,
+                protocol,
+                destination,
+                source,
+                vec![],
+            ),
+            payload,
+        )
+    }
+
+    fn udp(
+        source: IpV4Addr,
+        source_port: u16,
+        destination: IpV4Addr,
+        destination_port: u16,
+    ) -> Ipv4Packet {
+        let udp = UdpPacket {
+            source_port,
+            destination_port,
+            payload: vec![0; 4],
+        };
+        packet(ProtocolType::UDP, source, destination, udp.to_vec())
+    }
+
+    fn icmp(source: IpV4Addr, destination: IpV4Addr, icmp: IcmpPacket) -> Ipv4Packet {
+        packet(ProtocolType::ICMP, source, destination, icmp.to_vec())
+    }
+
+    /// Header and first 8 bytes of the packet, as quoted in ICMP errors
+    fn quote(packet: &Ipv4Packet) -> Vec<u8> {
+        let mut data = packet.header.to_vec();
+        data.extend_from_slice(&packet.payload[..8]);
+        data
+    }
+
+    #[test]
+    fn establishes_on_reply() {
+        let mut conntrack = ConnTrack::default();
+        let request = udp(CLIENT, 5000, SERVER, 53);
+        let reply = udp(SERVER, 53, CLIENT, 5000);
+        assert_eq!(conntrack.state(&request), Some(State::New));
+        conntrack.track(&request);
+        // Still unreplied
+        assert_eq!(conntrack.state(&request), Some(State::New));
+        assert_eq!(conntrack.state(&reply), Some(State::Established));
+        conntrack.track(&reply);
+        assert_eq!(conntrack.state(&request), Some(State::Established));
+
+        let connection = conntrack.connections().next().unwrap();
+        assert!(connection.replied);
+        assert_eq!(connection.packets, 2);
+        // Other ports are another flow
+        assert_eq!(
+            conntrack.state(&udp(SERVER, 53, CLIENT, 5001)),
+            Some(State::New)
+        );
+    }
+
+    #[test]
+    fn matches_echo_replies_to_requests() {
+        let mut conntrack = ConnTrack::default();
+        let reply = icmp(SERVER, CLIENT, IcmpPacket::EchoReply { id: 7, seq: 1 });
+        // Replies never start a connection
+        assert_eq!(conntrack.state(&reply), None);
+        conntrack.track(&reply);
+        assert_eq!(conntrack.connections().count(), 0);
+
+        let request = icmp(CLIENT, SERVER, IcmpPacket::EchoRequest { id: 7, seq: 1 });
+        assert_eq!(conntrack.state(&request), Some(State::New));
+        conntrack.track(&request);
+        assert_eq!(conntrack.state(&reply), Some(State::Established));
+        let other = icmp(SERVER, CLIENT, IcmpPacket::EchoReply { id: 8, seq: 1 });
+        assert_eq!(conntrack.state(&other), None);
+        // A request the other way is a new connection
+        let request = icmp(SERVER, CLIENT, IcmpPacket::EchoRequest { id: 7, seq: 1 });
+        assert_eq!(conntrack.state(&request), Some(State::New));
+    }
+
+    #[test]
+    fn relates_errors_to_connections() {
+        let mut conntrack = ConnTrack::default();
+        let probe = udp(CLIENT, 5000, SERVER, 33434);
+        let exceeded = IcmpPacket::TimeExceeded(TimeExceeded::TtlTransit {
+            data: quote(&probe),
+        });
+        let error = icmp(ROUTER, CLIENT, exceeded);
+        assert_eq!(conntrack.state(&error), None);
+        conntrack.track(&probe);
+        assert_eq!(conntrack.state(&error), Some(State::Related));
+
+        let unreachable =
+            IcmpPacket::DestinationUnreachable(DestinationUnreachable::PortUnreachable {
+                data: quote(&udp(CLIENT, 5001, SERVER, 33434)),
+            });
+        assert_eq!(conntrack.state(&icmp(SERVER, CLIENT, unreachable)), None);
+    }
+
+    #[test]
+    fn expires_connections() {
+        let mut conntrack = ConnTrack::default();
+        let request = udp(CLIENT, 5000, SERVER, 53);
+        let reply = udp(SERVER, 53, CLIENT, 5000);
+        conntrack.track(&request);
+        conntrack.track(&reply);
+        conntrack.expire(Local::now() + conntrack.timeouts.udp - chrono::Duration::seconds(1));
+        assert_eq!(conntrack.state(&reply), Some(State::Established));
+        conntrack.expire(Local::now() + conntrack.timeouts.udp + chrono::Duration::seconds(1));
+        assert_eq!(conntrack.connections().count(), 0);
+        assert_eq!(conntrack.state(&reply), Some(State::New));
+
+        // The connections without packets for their timeout aren't found before they're removed
+        conntrack.timeouts.udp = chrono::Duration::zero();
+        conntrack.track(&request);
+        assert_eq!(conntrack.state(&reply), Some(State::New));
+    }
+}
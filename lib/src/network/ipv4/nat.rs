@@ -0,0 +1,774 @@
+//! Network address translation between inside and outside interfaces. The packets routed from an
+//! inside interface out of an outside one take the address of the outside interface, with their
+//! UDP or TCP port or ICMP echo identifier translated, and static port forwards send the packets
+//! to an outside address port to an inside host
+//!
+//! Other protocols aren't translated. The fragments received on inside and outside interfaces
+//! are reassembled before the translation, the ones reaching it aren't translated
+
+use std::{collections::HashMap, fmt::Display};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    chassis::LinkLayerId,
+    transport::icmp::packet::{DestinationUnreachable, IcmpPacket, TimeExceeded},
+};
+
+use super::{addr::IpV4Addr, packet::Ipv4Packet, protocol::ProtocolType};
+
+/// Ports and identifiers given to the translations
+const PORTS: std::ops::RangeInclusive<u16> = 1024..=65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Private side, its hosts are hidden behind the outside interfaces
+    Inside,
+    /// Public side, the translated packets leave with its address
+    Outside,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Inside => write!(f, "inside"),
+            Self::Outside => write!(f, "outside"),
+        }
+    }
+}
+
+/// Address and UDP or TCP port, or ICMP echo identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Endpoint {
+    pub addr: IpV4Addr,
+    pub port: u16,
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.addr, self.port)
+    }
+}
+
+/// Packets to the port of the outside interfaces' addresses go to the inside host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Forward {
+    pub protocol: ProtocolType,
+    pub port: u16,
+    pub inside: Endpoint,
+}
+
+impl Display for Forward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} port {} > {}",
+            protocol_name(self.protocol),
+            self.port,
+            self.inside
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    pub protocol: ProtocolType,
+    pub inside: Endpoint,
+    pub outside: Endpoint,
+    /// Packets translated both ways
+    pub packets: u64,
+    pub expires: DateTime<Local>,
+}
+
+/// How long the translations are kept without packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub udp: chrono::Duration,
+    pub icmp: chrono::Duration,
+    pub tcp: chrono::Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            udp: chrono::Duration::seconds(300),
+            icmp: chrono::Duration::seconds(60),
+            tcp: chrono::Duration::hours(2),
+        }
+    }
+}
+
+impl Timeouts {
+    fn get(&self, protocol: ProtocolType) -> chrono::Duration {
+        match protocol {
+            ProtocolType::TCP => self.tcp,
+            ProtocolType::ICMP => self.icmp,
+            _ => self.udp,
+        }
+    }
+}
+
+/// Port of the packet, as the source or the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Source,
+    Destination,
+}
+
+/// What a packet is to the translation
+enum Kind {
+    /// UDP or TCP, or ICMP echo request or reply with the identifier as both ports
+    Ports { source: u16, destination: u16 },
+    /// ICMP error quoting a packet
+    Error(Ipv4Packet),
+}
+
+fn kind(packet: &Ipv4Packet) -> Option<Kind> {
+    if packet.header.is_fragment() {
+        return None;
+    }
+    let payload = &packet.payload;
+    match packet.header.protocol {
+        ProtocolType::UDP | ProtocolType::TCP => payload.get(..4).map(|p| Kind::Ports {
+            source: u16::from_be_bytes([p[0], p[1]]),
+            destination: u16::from_be_bytes([p[2], p[3]]),
+        }),
+        ProtocolType::ICMP => match IcmpPacket::from_vec(payload)? {
+            IcmpPacket::EchoRequest { id, .. } | IcmpPacket::EchoReply { id, .. } => {
+                Some(Kind::Ports {
+                    source: id,
+                    destination: id,
+                })
+            }
+            IcmpPacket::DestinationUnreachable(unreachable) => {
+                Ipv4Packet::from_vec_truncated(unreachable.data()).map(Kind::Error)
+            }
+            IcmpPacket::TimeExceeded(
+                TimeExceeded::TtlTransit { data } | TimeExceeded::FragmentReassembly { data },
+            ) => Ipv4Packet::from_vec_truncated(&data).map(Kind::Error),
+        },
+        _ => None,
+    }
+}
+
+fn is_echo_request(packet: &Ipv4Packet) -> bool {
+    packet.header.protocol == ProtocolType::ICMP && packet.payload.first() == Some(&8)
+}
+
+fn is_echo_reply(packet: &Ipv4Packet) -> bool {
+    packet.header.protocol == ProtocolType::ICMP && packet.payload.first() == Some(&0)
+}
+
+/// Endpoint of the packet on the side
+fn endpoint(packet: &Ipv4Packet, side: Side) -> Option<Endpoint> {
+    let Some(Kind::Ports {
+        source,
+        destination,
+    }) = kind(packet)
+    else {
+        return None;
+    };
+    Some(match side {
+        Side::Source => Endpoint {
+            addr: packet.header.source,
+            port: source,
+        },
+        Side::Destination => Endpoint {
+            addr: packet.header.destination,
+            port: destination,
+        },
+    })
+}
+
+/// Rewrites the address and port of the side, the ICMP echoes' identifier is both ports
+fn rewrite(packet: &mut Ipv4Packet, side: Side, endpoint: Endpoint) {
+    let port = endpoint.port.to_be_bytes();
+    let offset = match (packet.header.protocol, side) {
+        (ProtocolType::ICMP, _) => 4,
+        (_, Side::Source) => 0,
+        (_, Side::Destination) => 2,
+    };
+    if let Some(bytes) = packet.payload.get_mut(offset..offset + 2) {
+        bytes.copy_from_slice(&port);
+    }
+    match side {
+        Side::Source => packet.header.source = endpoint.addr,
+        Side::Destination => packet.header.destination = endpoint.addr,
+    }
+}
+
+/// Rewrites the packet quoted by the ICMP error
+fn rewrite_quoted(packet: &mut Ipv4Packet, quoted: &Ipv4Packet) {
+    let mut data = quoted.header.to_vec();
+    data.extend_from_slice(&quoted.payload);
+    let icmp = match IcmpPacket::from_vec(&packet.payload) {
+        Some(IcmpPacket::DestinationUnreachable(unreachable)) => {
+            IcmpPacket::DestinationUnreachable(match unreachable {
+                DestinationUnreachable::NetUnreachable { .. } => {
+                    DestinationUnreachable::NetUnreachable { data }
+                }
+                DestinationUnreachable::HostUnreachable { .. } => {
+                    DestinationUnreachable::HostUnreachable { data }
+                }
+                DestinationUnreachable::ProtocolUnreachable { .. } => {
+                    DestinationUnreachable::ProtocolUnreachable { data }
+                }
+                DestinationUnreachable::PortUnreachable { .. } => {
+                    DestinationUnreachable::PortUnreachable { data }
+                }
+                DestinationUnreachable::FragmentationNeeded { next_hop_mtu, .. } => {
+                    DestinationUnreachable::FragmentationNeeded { next_hop_mtu, data }
+                }
+                DestinationUnreachable::AdministrativelyProhibited { .. } => {
+                    DestinationUnreachable::AdministrativelyProhibited { data }
+                }
+            })
+        }
+        Some(IcmpPacket::TimeExceeded(TimeExceeded::TtlTransit { .. })) => {
+            IcmpPacket::TimeExceeded(TimeExceeded::TtlTransit { data })
+        }
+        Some(IcmpPacket::TimeExceeded(TimeExceeded::FragmentReassembly { .. })) => {
+            IcmpPacket::TimeExceeded(TimeExceeded::FragmentReassembly { data })
+        }
+        _ => return,
+    };
+    // Same length, the quoted header and ports are rewritten in place
+    packet.payload = icmp.to_vec();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Nat {
+    pub roles: HashMap<LinkLayerId, Role>,
+    pub forwards: Vec<Forward>,
+    pub timeouts: Timeouts,
+    /// Translations by their protocol and outside endpoint
+    translations: HashMap<(ProtocolType, Endpoint), Translation>,
+    /// Outside endpoint of the translations by their protocol, inside endpoint and outside address
+    inside: HashMap<(ProtocolType, Endpoint, IpV4Addr), Endpoint>,
+    /// Last port or identifier given
+    next_port: u16,
+    /// Last time the expired translations were removed
+    expired: Option<DateTime<Local>>,
+}
+
+impl Nat {
+    pub fn role(&self, iface: &LinkLayerId) -> Option<Role> {
+        self.roles.get(iface).copied()
+    }
+
+    /// Translations that haven't expired
+    pub fn translations(&self) -> impl Iterator<Item = &Translation> {
+        let now = Local::now();
+        self.translations
+            .values()
+            .filter(move |translation| translation.expires > now)
+    }
+
+    pub fn flush(&mut self) {
+        self.translations.clear();
+        self.inside.clear();
+    }
+
+    /// Removes the translations without packets for longer than their timeout
+    pub fn expire(&mut self, now: DateTime<Local>) {
+        self.expired = Some(now);
+        self.translations
+            .retain(|_, translation| translation.expires > now);
+        let translations = &self.translations;
+        self.inside
+            .retain(|(protocol, ..), outside| translations.contains_key(&(*protocol, *outside)));
+    }
+
+    /// Removes the expired translations, at most once a second
+    fn expire_stale(&mut self, now: DateTime<Local>) {
+        if self
+            .expired
+            .is_none_or(|expired| now - expired > chrono::Duration::seconds(1))
+        {
+            self.expire(now);
+        }
+    }
+
+    fn forward_to(&self, protocol: ProtocolType, port: u16) -> Option<&Forward> {
+        self.forwards
+            .iter()
+            .find(|forward| forward.protocol == protocol && forward.port == port)
+    }
+
+    fn forward_from(&self, protocol: ProtocolType, inside: Endpoint) -> Option<&Forward> {
+        self.forwards
+            .iter()
+            .find(|forward| forward.protocol == protocol && forward.inside == inside)
+    }
+
+    /// Inside endpoint of the outside one, refreshing its translation
+    fn lookup_outside(
+        &mut self,
+        protocol: ProtocolType,
+        outside: Endpoint,
+        addrs: &[IpV4Addr],
+        now: DateTime<Local>,
+    ) -> Option<Endpoint> {
+        let timeout = self.timeouts.get(protocol);
+        if let Some(translation) = self
+            .translations
+            .get_mut(&(protocol, outside))
+            .filter(|translation| translation.expires > now)
+        {
+            translation.packets += 1;
+            translation.expires = now + timeout;
+            return Some(translation.inside);
+        }
+        addrs
+            .contains(&outside.addr)
+            .then(|| self.forward_to(protocol, outside.port))
+            .flatten()
+            .map(|forward| forward.inside)
+    }
+
+    /// Outside endpoint of the inside one, refreshing its translation
+    fn lookup_inside(
+        &mut self,
+        protocol: ProtocolType,
+        inside: Endpoint,
+        addr: IpV4Addr,
+        now: DateTime<Local>,
+    ) -> Option<Endpoint> {
+        if let Some(forward) = self.forward_from(protocol, inside) {
+            return Some(Endpoint {
+                addr,
+                port: forward.port,
+            });
+        }
+        let outside = *self.inside.get(&(protocol, inside, addr))?;
+        self.lookup_outside(protocol, outside, &[], now)?;
+        Some(outside)
+    }
+
+    /// Gives an outside endpoint to the inside one, keeping its port when it's free
+    fn allocate(
+        &mut self,
+        protocol: ProtocolType,
+        inside: Endpoint,
+        addr: IpV4Addr,
+        now: DateTime<Local>,
+    ) -> Option<Endpoint> {
+        let free = |nat: &Self, port| {
+            nat.forward_to(protocol, port).is_none()
+                && nat
+                    .translations
+                    .get(&(protocol, Endpoint { addr, port }))
+                    .is_none_or(|translation| translation.expires <= now)
+        };
+        let port = if PORTS.contains(&inside.port) && free(self, inside.port) {
+            inside.port
+        } else {
+            let mut port = None;
+            for _ in PORTS {
+                self.next_port = match self.next_port {
+                    u16::MAX => *PORTS.start(),
+                    port => port.max(*PORTS.start() - 1) + 1,
+                };
+                if free(self, self.next_port) {
+                    port = Some(self.next_port);
+                    break;
+                }
+            }
+            port?
+        };
+        let outside = Endpoint { addr, port };
+        self.translations.insert(
+            (protocol, outside),
+            Translation {
+                protocol,
+                inside,
+                outside,
+                packets: 1,
+                expires: now + self.timeouts.get(protocol),
+            },
+        );
+        self.inside.insert((protocol, inside, addr), outside);
+        Some(outside)
+    }
+
+    /// Translates the packet received on an outside interface with the addresses back to the
+    /// inside host, `false` if it isn't for a translation
+    pub fn translate_in(&mut self, packet: &mut Ipv4Packet, addrs: &[IpV4Addr]) -> bool {
+        let now = Local::now();
+        self.expire_stale(now);
+        let protocol = packet.header.protocol;
+        match kind(packet) {
+            // The identifiers of the echo requests received belong to their senders
+            Some(Kind::Ports { .. }) if is_echo_request(packet) => false,
+            Some(Kind::Ports { .. }) => {
+                let Some(outside) = endpoint(packet, Side::Destination) else {
+                    return false;
+                };
+                match self.lookup_outside(protocol, outside, addrs, now) {
+                    Some(inside) => {
+                        rewrite(packet, Side::Destination, inside);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            // Errors about a translated packet sent out
+            Some(Kind::Error(mut quoted)) => {
+                let Some(outside) = endpoint(&quoted, Side::Source) else {
+                    return false;
+                };
+                let Some(inside) = self.lookup_outside(quoted.header.protocol, outside, addrs, now)
+                else {
+                    return false;
+                };
+                rewrite(&mut quoted, Side::Source, inside);
+                rewrite_quoted(packet, &quoted);
+                packet.header.destination = inside.addr;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Translates the packet routed from an inside interface out of an outside interface with
+    /// the address, `false` if it's left as is
+    pub fn translate_out(&mut self, packet: &mut Ipv4Packet, addr: IpV4Addr) -> bool {
+        let now = Local::now();
+        self.expire_stale(now);
+        let protocol = packet.header.protocol;
+        match kind(packet) {
+            // The identifiers of the echo replies sent belong to the senders of the requests
+            Some(Kind::Ports { .. }) if is_echo_reply(packet) => false,
+            Some(Kind::Ports { .. }) => {
+                let Some(inside) = endpoint(packet, Side::Source) else {
+                    return false;
+                };
+                let outside = match self.lookup_inside(protocol, inside, addr, now) {
+                    Some(outside) => Some(outside),
+                    None => self.allocate(protocol, inside, addr, now),
+                };
+                match outside {
+                    Some(outside) => {
+                        rewrite(packet, Side::Source, outside);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            // Errors about a packet received through a translation
+            Some(Kind::Error(mut quoted)) => {
+                let Some(inside) = endpoint(&quoted, Side::Destination) else {
+                    return false;
+                };
+                let Some(outside) = self.lookup_inside(quoted.header.protocol, inside, addr, now)
+                else {
+                    return false;
+                };
+                rewrite(&mut quoted, Side::Destination, outside);
+                rewrite_quoted(packet, &quoted);
+                packet.header.source = addr;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn print(&self) -> prettytable::Table {
+        let now = Local::now();
+        let mut translations = self.translations().collect::<Vec<_>>();
+        translations.sort_by_key(|translation| translation.expires);
+        let mut table =
+            prettytable::table!(["protocol", "inside", "outside", "packets", "expires in"]);
+        for translation in translations {
+            table.add_row(prettytable::row![
+                protocol_name(translation.protocol),
+                translation.inside,
+                translation.outside,
+                translation.packets,
+                format!("{}s", (translation.expires - now).num_seconds())
+            ]);
+        }
+        table
+    }
+}
+
+fn protocol_name(protocol: ProtocolType) -> String {
+    match protocol {
+        ProtocolType::ICMP => "icmp".into(),
+        ProtocolType::TCP => "tcp".into(),
+        ProtocolType::UDP => "udp".into(),
+        protocol => protocol.inner().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        network::ipv4::{
+            addr::IpV4Addr,
+            packet::{Ecn, Flags, IpV4Header, Ipv4Packet},
+            protocol::ProtocolType,
+        },
+        transport::{
+            icmp::packet::{DestinationUnreachable, IcmpPacket, TimeExceeded},
+            udp::packet::UdpPacket,
+        },
+    };
+
+    use super::{Endpoint, Forward, Nat, PORTS};
+
+    const HOST: IpV4Addr = IpV4Addr::new([192, 168, 1, 2]);
+    const OTHER_HOST: IpV4Addr = IpV4Addr::new([192, 168, 1, 3]);
+    const PUBLIC: IpV4Addr = IpV4Addr::new([203, 0, 113, 1]);
+    const SERVER: IpV4Addr = IpV4Addr::new([198, 51, 100, 1]);
+
+    fn packet(
+        protocol: ProtocolType,
+        source: IpV4Addr,
+        destination: IpV4Addr,
+        payload: Vec<u8>,
+    ) -> Ipv4Packet {
+        Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                Ecn::NotECT,
+                payload.len() as u16,
+                1,
+                Flags::empty(),
+                0,
+                64,
+                protocol,
+                destination,
+                source,
+                vec![],
+            ),
+            payload,
+        )
+    }
+
+    fn udp(source: Endpoint, destination: Endpoint) -> Ipv4Packet {
+        let udp = UdpPacket {
+            source_port: source.port,
+            destination_port: destination.port,
+            payload: vec![0xa5; 4],
+        };
+        packet(
+            ProtocolType::UDP,
+            source.addr,
+            destination.addr,
+            udp.to_vec(),
+        )
+    }
+
+    fn icmp(source: IpV4Addr, destination: IpV4Addr, icmp: IcmpPacket) -> Ipv4Packet {
+        packet(ProtocolType::ICMP, source, destination, icmp.to_vec())
+    }
+
+    /// Endpoints of a UDP packet
+    fn endpoints(packet: &Ipv4Packet) -> (Endpoint, Endpoint) {
+        let udp = UdpPacket::from_vec(&packet.payload).unwrap();
+        (
+            Endpoint {
+                addr: packet.header.source,
+                port: udp.source_port,
+            },
+            Endpoint {
+                addr: packet.header.destination,
+                port: udp.destination_port,
+            },
+        )
+    }
+
+    /// Header and first 8 bytes of the packet, as quoted in ICMP errors
+    fn quote(packet: &Ipv4Packet) -> Vec<u8> {
+        let mut data = packet.header.to_vec();
+        data.extend_from_slice(&packet.payload[..8]);
+        data
+    }
+
+    fn endpoint(addr: IpV4Addr, port: u16) -> Endpoint {
+        Endpoint { addr, port }
+    }
+
+    #[test]
+    fn translates_out_and_back() {
+        let mut nat = Nat::default();
+        let mut request = udp(endpoint(HOST, 5000), endpoint(SERVER, 53));
+        assert!(nat.translate_out(&mut request, PUBLIC));
+        // The port is free, it's kept
+        assert_eq!(
+            endpoints(&request),
+            (endpoint(PUBLIC, 5000), endpoint(SERVER, 53))
+        );
+
+        let mut reply = udp(endpoint(SERVER, 53), endpoint(PUBLIC, 5000));
+        assert!(nat.translate_in(&mut reply, &[PUBLIC]));
+        assert_eq!(
+            endpoints(&reply),
+            (endpoint(SERVER, 53), endpoint(HOST, 5000))
+        );
+
+        let mut unsolicited = udp(endpoint(SERVER, 53), endpoint(PUBLIC, 5001));
+        assert!(!nat.translate_in(&mut unsolicited, &[PUBLIC]));
+        assert_eq!(nat.translations().count(), 1);
+    }
+
+    #[test]
+    fn gives_another_port_when_taken() {
+        let mut nat = Nat::default();
+        let mut first = udp(endpoint(HOST, 5000), endpoint(SERVER, 53));
+        let mut second = udp(endpoint(OTHER_HOST, 5000), endpoint(SERVER, 53));
+        assert!(nat.translate_out(&mut first, PUBLIC));
+        assert!(nat.translate_out(&mut second, PUBLIC));
+        let (outside, _) = endpoints(&second);
+        assert_ne!(outside.port, 5000);
+        assert!(PORTS.contains(&outside.port));
+
+        // The flow keeps its translation
+        let mut again = udp(endpoint(OTHER_HOST, 5000), endpoint(SERVER, 53));
+        assert!(nat.translate_out(&mut again, PUBLIC));
+        assert_eq!(endpoints(&again).0, outside);
+
+        let mut reply = udp(endpoint(SERVER, 53), outside);
+        assert!(nat.translate_in(&mut reply, &[PUBLIC]));
+        assert_eq!(endpoints(&reply).1, endpoint(OTHER_HOST, 5000));
+    }
+
+    #[test]
+    fn translates_echo_identifiers() {
+        let mut nat = Nat::default();
+        let mut request = icmp(HOST, SERVER, IcmpPacket::EchoRequest { id: 1, seq: 7 });
+        assert!(nat.translate_out(&mut request, PUBLIC));
+        assert_eq!(request.header.source, PUBLIC);
+        // Identifiers below the translation range are never kept
+        let Some(IcmpPacket::EchoRequest { id, seq: 7 }) = IcmpPacket::from_vec(&request.payload)
+        else {
+            panic!("Not an echo request: {request:?}");
+        };
+        assert!(PORTS.contains(&id));
+
+        let mut reply = icmp(SERVER, PUBLIC, IcmpPacket::EchoReply { id, seq: 7 });
+        assert!(nat.translate_in(&mut reply, &[PUBLIC]));
+        assert_eq!(reply.header.destination, HOST);
+        assert_eq!(
+            IcmpPacket::from_vec(&reply.payload),
+            Some(IcmpPacket::EchoReply { id: 1, seq: 7 })
+        );
+
+        // The requests received and the replies sent keep the identifier of their sender
+        let mut request = icmp(SERVER, PUBLIC, IcmpPacket::EchoRequest { id, seq: 1 });
+        assert!(!nat.translate_in(&mut request, &[PUBLIC]));
+        let mut reply = icmp(HOST, SERVER, IcmpPacket::EchoReply { id: 1, seq: 1 });
+        assert!(!nat.translate_out(&mut reply, PUBLIC));
+    }
+
+    #[test]
+    fn forwards_ports() {
+        let mut nat = Nat::default();
+        nat.forwards.push(Forward {
+            protocol: ProtocolType::UDP,
+            port: 8080,
+            inside: endpoint(HOST, 80),
+        });
+        let mut request = udp(endpoint(SERVER, 5000), endpoint(PUBLIC, 8080));
+        assert!(nat.translate_in(&mut request, &[PUBLIC]));
+        assert_eq!(
+            endpoints(&request),
+            (endpoint(SERVER, 5000), endpoint(HOST, 80))
+        );
+        // Only the packets to the outside addresses are forwarded
+        let mut other = udp(endpoint(SERVER, 5000), endpoint(SERVER, 8080));
+        assert!(!nat.translate_in(&mut other, &[PUBLIC]));
+
+        let mut reply = udp(endpoint(HOST, 80), endpoint(SERVER, 5000));
+        assert!(nat.translate_out(&mut reply, PUBLIC));
+        assert_eq!(
+            endpoints(&reply),
+            (endpoint(PUBLIC, 8080), endpoint(SERVER, 5000))
+        );
+    }
+
+    #[test]
+    fn translates_quoted_packets() {
+        let mut nat = Nat::default();
+        nat.forwards.push(Forward {
+            protocol: ProtocolType::UDP,
+            port: 8080,
+            inside: endpoint(HOST, 80),
+        });
+        let mut request = udp(endpoint(HOST, 5000), endpoint(SERVER, 33434));
+        assert!(nat.translate_out(&mut request, PUBLIC));
+
+        // A router on the way reports the translated packet
+        let router = IpV4Addr::new([198, 51, 100, 254]);
+        let data = quote(&request);
+        let exceeded = IcmpPacket::TimeExceeded(TimeExceeded::TtlTransit { data });
+        let mut error = icmp(router, PUBLIC, exceeded);
+        assert!(nat.translate_in(&mut error, &[PUBLIC]));
+        assert_eq!(error.header.destination, HOST);
+        let Some(IcmpPacket::TimeExceeded(TimeExceeded::TtlTransit { data })) =
+            IcmpPacket::from_vec(&error.payload)
+        else {
+            panic!("Not a time exceeded: {error:?}");
+        };
+        let quoted = Ipv4Packet::from_vec_truncated(&data).unwrap();
+        assert_eq!(quoted.header.source, HOST);
+        assert_eq!(quoted.payload[..2], 5000u16.to_be_bytes());
+
+        // The inside host reports a forwarded packet
+        let mut forwarded = udp(endpoint(SERVER, 5000), endpoint(PUBLIC, 8080));
+        assert!(nat.translate_in(&mut forwarded, &[PUBLIC]));
+        let unreachable =
+            IcmpPacket::DestinationUnreachable(DestinationUnreachable::PortUnreachable {
+                data: quote(&forwarded),
+            });
+        let mut error = icmp(HOST, SERVER, unreachable);
+        assert!(nat.translate_out(&mut error, PUBLIC));
+        assert_eq!(error.header.source, PUBLIC);
+        let Some(IcmpPacket::DestinationUnreachable(unreachable)) =
+            IcmpPacket::from_vec(&error.payload)
+        else {
+            panic!("Not a destination unreachable: {error:?}");
+        };
+        let quoted = Ipv4Packet::from_vec_truncated(unreachable.data()).unwrap();
+        assert_eq!(quoted.header.destination, PUBLIC);
+        assert_eq!(quoted.payload[2..4], 8080u16.to_be_bytes());
+    }
+
+    #[test]
+    fn expires_translations_both_ways() {
+        let mut nat = Nat::default();
+        nat.timeouts.udp = chrono::Duration::zero();
+        let mut request = udp(endpoint(HOST, 5000), endpoint(SERVER, 53));
+        assert!(nat.translate_out(&mut request, PUBLIC));
+        assert_eq!(nat.translations().count(), 0);
+
+        // The packets received remove the expired translations too
+        nat.expired = None;
+        let mut reply = udp(endpoint(SERVER, 53), endpoint(PUBLIC, 5000));
+        assert!(!nat.translate_in(&mut reply, &[PUBLIC]));
+        assert!(nat.translations.is_empty());
+        assert!(nat.inside.is_empty());
+    }
+
+    #[test]
+    fn leaves_fragments_alone() {
+        let mut nat = Nat::default();
+        let mut fragment = udp(endpoint(HOST, 5000), endpoint(SERVER, 53));
+        fragment.header = IpV4Header::new(
+            0,
+            Ecn::NotECT,
+            fragment.payload.len() as u16,
+            1,
+            Flags::MF,
+            0,
+            64,
+            ProtocolType::UDP,
+            SERVER,
+            HOST,
+            vec![],
+        );
+        assert!(!nat.translate_out(&mut fragment, PUBLIC));
+        assert_eq!(fragment.header.source, HOST);
+    }
+}
@@ -120,6 +120,44 @@ impl IpV4Header {
         }
     }
 
+    pub const fn identification(&self) -> u16 {
+        self.identification
+    }
+
+    pub const fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Offset in 8 byte blocks
+    pub const fn fragment_offset(&self) -> u16 {
+        self.fragment_offset
+    }
+
+    pub const fn total_length(&self) -> u16 {
+        self.total_length
+    }
+
+    pub fn header_length(&self) -> usize {
+        20 + self.options.len()
+    }
+
+    /// Header of the packet rebuilt from the fragments starting with this one
+    pub fn defragmented(&self, payload_length: u16) -> Self {
+        let mut flags = self.flags;
+        flags.remove(Flags::MF);
+        Self {
+            total_length: payload_length + self.header_length() as u16,
+            flags,
+            fragment_offset: 0,
+            ..self.clone()
+        }
+    }
+
+    /// Whether the packet is a fragment of a bigger one
+    pub fn is_fragment(&self) -> bool {
+        self.flags.contains(Flags::MF) || self.fragment_offset != 0
+    }
+
     fn get_checksum(&self, extra: u16) -> u16 {
         let bytes = self.to_vec_checksum(extra);
         if bytes.len() % 2 != 0 {
@@ -135,11 +173,28 @@ impl IpV4Header {
     }
 
     pub fn from_vec(data: &[u8]) -> Option<(Self, usize)> {
+        let (res, len) = Self::from_vec_truncated(data)?;
+        if data.len() < res.total_length as usize {
+            return None;
+        }
+        Some((res, len))
+    }
+
+    /// Decodes a header without requiring the whole payload, as quoted in ICMP errors
+    pub fn from_vec_truncated(data: &[u8]) -> Option<(Self, usize)> {
+        if data.len() < 20 {
+            warn!("IPv4 header: Not enough data");
+            return None;
+        }
         if data[0] >> 4 != 4 {
             warn!("IPv4 header version is not set correctly");
             return None;
         }
         let ihl = data[0] & 0x0f;
+        if ihl < 5 {
+            warn!("IPv4 header: Header length of {ihl} words, less than the minimum");
+            return None;
+        }
         if data.len() < ihl as usize * 4 {
             warn!("IPv4 header: Not enough data");
             return None;
@@ -147,9 +202,6 @@ impl IpV4Header {
         let dscp = data[1] >> 2;
         let ecn = Ecn::from_u8(data[1] & 0b11)?;
         let total_length = u16::from_be_bytes(data[2..4].try_into().unwrap());
-        if data.len() < total_length as usize {
-            return None;
-        }
         let identification = u16::from_be_bytes(data[4..6].try_into().unwrap());
         let fragment_and_flags = u16::from_be_bytes(data[6..8].try_into().unwrap());
         let fragment_offset = fragment_and_flags & 0x1fff;
@@ -221,8 +273,65 @@ impl Ipv4Packet {
 
     pub fn from_vec(data: &[u8]) -> Option<Self> {
         let (header, left) = IpV4Header::from_vec(data)?;
+        let end = (header.total_length as usize).max(left);
+
+        Some(Self::new(header, data[left..end].to_vec()))
+    }
+
+    /// Decodes a packet whose payload may be truncated, as quoted in ICMP errors
+    pub fn from_vec_truncated(data: &[u8]) -> Option<Self> {
+        let (header, left) = IpV4Header::from_vec_truncated(data)?;
+        let end = (header.total_length as usize).max(left).min(data.len());
 
-        Some(Self::new(header, data[left..].to_vec()))
+        Some(Self::new(header, data[left..end].to_vec()))
+    }
+
+    /// Splits the packet in fragments that fit in the MTU, `None` if it doesn't fit and DF is set
+    pub fn fragment(self, mtu: u16) -> Option<Vec<Self>> {
+        if self.header.total_length <= mtu {
+            return Some(vec![self]);
+        }
+        if self.header.flags.contains(Flags::DF) {
+            return None;
+        }
+        // Options aren't decoded, so they are only kept in the first fragment
+        let first_len = (mtu as usize).checked_sub(self.header.header_length())? / 8 * 8;
+        let rest_len = (mtu as usize - 20) / 8 * 8;
+        if first_len == 0 || rest_len == 0 {
+            return None;
+        }
+        let mut fragments = Vec::new();
+        let mut offset = 0;
+        while offset < self.payload.len() {
+            let first = offset == 0;
+            let len = if first { first_len } else { rest_len };
+            let end = (offset + len).min(self.payload.len());
+            let last = end == self.payload.len();
+            let mut flags = self.header.flags;
+            flags.set(Flags::MF, !last || self.header.flags.contains(Flags::MF));
+            fragments.push(Self::new(
+                IpV4Header::new(
+                    self.header.dscp,
+                    self.header.ecn,
+                    (end - offset) as u16,
+                    self.header.identification,
+                    flags,
+                    self.header.fragment_offset + (offset / 8) as u16,
+                    self.header.time_to_live,
+                    self.header.protocol,
+                    self.header.destination,
+                    self.header.source,
+                    if first {
+                        self.header.options.clone()
+                    } else {
+                        vec![]
+                    },
+                ),
+                self.payload[offset..end].to_vec(),
+            ));
+            offset = end;
+        }
+        Some(fragments)
     }
 
     pub fn to_vec(&self) -> Vec<u8> {
@@ -231,3 +340,75 @@ impl Ipv4Packet {
         vec
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        corruption::corruptions,
+        network::ipv4::{addr::IpV4Addr, protocol::ProtocolType},
+    };
+
+    use super::{Ecn, Flags, IpV4Header, Ipv4Packet};
+
+    fn header() -> IpV4Header {
+        IpV4Header::new(
+            0,
+            Ecn::NotECT,
+            0,
+            1,
+            Flags::empty(),
+            0,
+            64,
+            ProtocolType::UDP,
+            IpV4Addr::new([10, 0, 0, 2]),
+            IpV4Addr::new([10, 0, 0, 1]),
+            vec![],
+        )
+    }
+
+    #[test]
+    fn decodes_header() {
+        let header = header();
+        assert_eq!(IpV4Header::from_vec(&header.to_vec()), Some((header, 20)));
+    }
+
+    #[test]
+    fn rejects_short_header_length() {
+        let mut data = header().to_vec();
+        data[0] = 0x44;
+        assert_eq!(IpV4Header::from_vec(&data), None);
+        assert_eq!(IpV4Header::from_vec_truncated(&data), None);
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let header = IpV4Header {
+            total_length: 40,
+            ..header()
+        };
+        let packet = Ipv4Packet::new(header, vec![0; 20]).to_vec();
+        assert_eq!(Ipv4Packet::from_vec(&packet[..30]), None);
+        assert_eq!(Ipv4Packet::from_vec(&packet[..10]), None);
+        assert_eq!(Ipv4Packet::from_vec(&[]), None);
+    }
+
+    #[test]
+    fn survives_corrupted_packets() {
+        let header = IpV4Header {
+            total_length: 32,
+            options: vec![1; 4],
+            ..header()
+        };
+        let packet = Ipv4Packet::new(header, vec![0xa5; 8]);
+        let data = packet.to_vec();
+        assert_eq!(Ipv4Packet::from_vec(&data), Some(packet));
+        for data in corruptions(&data) {
+            if let Some(packet) = Ipv4Packet::from_vec(&data) {
+                packet.to_vec();
+            }
+            if let Some(packet) = Ipv4Packet::from_vec_truncated(&data) {
+                packet.to_vec();
+            }
+        }
+    }
+}
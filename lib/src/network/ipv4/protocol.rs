@@ -1,4 +1,4 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct ProtocolType(u8);
 
@@ -14,4 +14,9 @@ impl ProtocolType {
     pub const ICMP: Self = Self::new(0x01);
     pub const TCP: Self = Self::new(0x06);
     pub const UDP: Self = Self::new(0x11);
+    pub const OSPF: Self = Self::new(0x59);
+    pub const IP_IP: Self = Self::new(0x04);
+    pub const GRE: Self = Self::new(0x2f);
+    pub const VRRP: Self = Self::new(0x70);
+    pub const ICMP_V6: Self = Self::new(0x3a);
 }
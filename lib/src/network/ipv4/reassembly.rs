@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use tracing::warn;
+
+use super::{
+    addr::IpV4Addr,
+    packet::{Flags, IpV4Header, Ipv4Packet},
+    protocol::ProtocolType,
+};
+
+/// (source, destination, identification, protocol)
+pub type FragmentKey = (IpV4Addr, IpV4Addr, u16, ProtocolType);
+
+#[derive(Debug)]
+struct Reassembly {
+    /// Header of the fragment with offset 0
+    header: Option<IpV4Header>,
+    /// (offset in bytes, data)
+    fragments: Vec<(usize, Vec<u8>)>,
+    /// Payload length, known once the last fragment arrives
+    total: Option<usize>,
+    started: DateTime<Local>,
+}
+
+impl Reassembly {
+    fn new() -> Self {
+        Self {
+            header: None,
+            fragments: Vec::new(),
+            total: None,
+            started: Local::now(),
+        }
+    }
+
+    fn add(&mut self, packet: Ipv4Packet) {
+        let offset = packet.header.fragment_offset() as usize * 8;
+        if !packet.header.flags().contains(Flags::MF) {
+            self.total = Some(offset + packet.payload.len());
+        }
+        if offset == 0 {
+            self.header = Some(packet.header);
+        }
+        self.fragments.push((offset, packet.payload));
+    }
+
+    /// Whether the fragments would make a packet longer than the IPv4 maximum
+    fn oversized(&self) -> bool {
+        let header_length = self.header.as_ref().map_or(20, IpV4Header::header_length);
+        self.fragments
+            .iter()
+            .map(|(offset, data)| offset + data.len())
+            .chain(self.total)
+            .max()
+            .is_some_and(|length| length + header_length > u16::MAX as usize)
+    }
+
+    fn complete(&mut self) -> Option<Ipv4Packet> {
+        let total = self.total?;
+        let header = self.header.as_ref()?;
+        self.fragments.sort_by_key(|(offset, _)| *offset);
+        let mut payload = Vec::with_capacity(total);
+        for (offset, data) in self.fragments.iter() {
+            if *offset > payload.len() {
+                // There's a hole
+                return None;
+            }
+            let end = (offset + data.len()).min(total);
+            if end > payload.len() {
+                payload.extend_from_slice(&data[(payload.len() - offset)..(end - offset)]);
+            }
+        }
+        if payload.len() < total {
+            return None;
+        }
+        Some(Ipv4Packet::new(
+            header.defragmented(total as u16),
+            payload,
+        ))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReassemblyBuffer {
+    buffers: HashMap<FragmentKey, Reassembly>,
+}
+
+impl ReassemblyBuffer {
+    /// Stores the fragment, returning the whole packet once every fragment has arrived
+    pub fn add(&mut self, packet: Ipv4Packet) -> Option<Ipv4Packet> {
+        let key = (
+            packet.header.source,
+            packet.header.destination,
+            packet.header.identification(),
+            packet.header.protocol,
+        );
+        let reassembly = self.buffers.entry(key).or_insert_with(Reassembly::new);
+        reassembly.add(packet);
+        if reassembly.oversized() {
+            warn!("Dropped fragmented packet longer than the IPv4 maximum");
+            self.buffers.remove(&key);
+            return None;
+        }
+        let res = reassembly.complete();
+        if res.is_some() {
+            self.buffers.remove(&key);
+        }
+        res
+    }
+
+    /// Drops the packets waiting for longer than the timeout, returning the first fragment of
+    /// each one, if it arrived
+    pub fn expire(&mut self, timeout: chrono::Duration) -> Vec<Ipv4Packet> {
+        let now = Local::now();
+        let expired = self
+            .buffers
+            .iter()
+            .filter(|(_, reassembly)| now - reassembly.started > timeout)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .filter_map(|key| self.buffers.remove(&key))
+            .filter_map(|mut reassembly| {
+                let header = reassembly.header.take()?;
+                let (_, data) = reassembly
+                    .fragments
+                    .into_iter()
+                    .find(|(offset, _)| *offset == 0)?;
+                Some(Ipv4Packet::new(header, data))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::ipv4::{
+        addr::IpV4Addr,
+        packet::{Ecn, Flags, IpV4Header, Ipv4Packet},
+        protocol::ProtocolType,
+    };
+
+    use super::ReassemblyBuffer;
+
+    fn fragment(offset: u16, more: bool, payload: Vec<u8>) -> Ipv4Packet {
+        Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                Ecn::NotECT,
+                payload.len() as u16,
+                1,
+                if more { Flags::MF } else { Flags::empty() },
+                offset,
+                64,
+                ProtocolType::UDP,
+                IpV4Addr::new([10, 0, 0, 2]),
+                IpV4Addr::new([10, 0, 0, 1]),
+                vec![],
+            ),
+            payload,
+        )
+    }
+
+    #[test]
+    fn reassembles_fragments() {
+        let mut buffer = ReassemblyBuffer::default();
+        assert_eq!(buffer.add(fragment(1, false, vec![2; 4])), None);
+        let packet = buffer.add(fragment(0, true, vec![1; 8])).unwrap();
+        assert_eq!(packet.payload, [1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(packet.header.total_length(), 32);
+        assert!(!packet.header.is_fragment());
+    }
+
+    #[test]
+    fn drops_oversized_last_fragment() {
+        let mut buffer = ReassemblyBuffer::default();
+        assert_eq!(buffer.add(fragment(0, true, vec![0; 8])), None);
+        // Ends at 65612 bytes of payload
+        assert_eq!(buffer.add(fragment(8189, false, vec![0; 100])), None);
+        assert!(buffer.buffers.is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt::Display;
+
+use crate::link::ethernet::ethertype::EtherType;
+
+use super::{addr::IpV4Addr, protocol::ProtocolType};
+
+/// Encapsulation of the packets sent through a tunnel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    /// Generic Routing Encapsulation (RFC 2784) without checksum, key or sequence number
+    Gre,
+    /// IP in IP (RFC 2003), the packet is the payload of the outer one as is
+    IpIp,
+}
+
+impl Mode {
+    pub const fn protocol(self) -> ProtocolType {
+        match self {
+            Self::Gre => ProtocolType::GRE,
+            Self::IpIp => ProtocolType::IP_IP,
+        }
+    }
+
+    /// Bytes added to the packet, the outer header and the GRE header
+    pub const fn overhead(self) -> u16 {
+        match self {
+            Self::Gre => 24,
+            Self::IpIp => 20,
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Gre => write!(f, "gre"),
+            Self::IpIp => write!(f, "ipip"),
+        }
+    }
+}
+
+/// Point to point tunnel between two addresses, the packets routed through it are carried by
+/// outer IPv4 packets from the source to the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tunnel {
+    pub mode: Mode,
+    pub source: IpV4Addr,
+    pub destination: IpV4Addr,
+}
+
+impl Tunnel {
+    /// Payload of the outer packet carrying the IPv4 packet
+    pub fn encapsulate(&self, packet: Vec<u8>) -> Vec<u8> {
+        match self.mode {
+            Mode::Gre => {
+                let mut payload = vec![0, 0];
+                payload.extend_from_slice(&EtherType::IP_V4.to_u16().to_be_bytes());
+                payload.extend(packet);
+                payload
+            }
+            Mode::IpIp => packet,
+        }
+    }
+
+    /// IPv4 packet carried by the payload of the outer packet, `None` for GRE packets with
+    /// optional fields or other protocols
+    pub fn decapsulate(&self, payload: Vec<u8>) -> Option<Vec<u8>> {
+        match self.mode {
+            Mode::Gre => {
+                let (header, packet) = payload.split_at_checked(4)?;
+                (header[..2] == [0, 0] && header[2..] == EtherType::IP_V4.to_u16().to_be_bytes())
+                    .then(|| packet.to_vec())
+            }
+            Mode::IpIp => Some(payload),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use flume::Sender;
+use tracing::{trace, warn};
+
+use crate::{
+    chassis::{
+        LinkLayerId, LinkNetworkPayload, MidLevelProcess, NetworkLayerId, NetworkTransportMessage,
+        NetworkTransportPayload, ProcessMessage, TransportLayerId,
+    },
+    mac::Mac,
+    network::{ipv4::protocol::ProtocolType, ndp::NeighborHandle},
+    transport::icmp::{packet::TimeExceeded, packet_v6::Icmpv6Packet},
+};
+
+use self::{
+    addr::{IpV6Addr, ALL_NODES, UNSPECIFIED},
+    config::IpV6Config,
+    packet::{IpV6Header, Ipv6Packet},
+};
+
+pub mod addr;
+pub mod config;
+pub mod packet;
+
+pub struct Ipv6Process {
+    config: IpV6Config,
+    /// Neighbors are resolved through NDP
+    ndp: NeighborHandle,
+}
+
+impl Ipv6Process {
+    pub fn new(config: IpV6Config, ndp: NeighborHandle) -> Self {
+        Self { config, ndp }
+    }
+
+    async fn resolve(&self, next_hop: IpV6Addr, iface: LinkLayerId) -> Option<Mac> {
+        if next_hop.is_multicast() {
+            Some(next_hop.multicast_mac())
+        } else {
+            self.ndp
+                .get_haddr_timeout((next_hop, iface), std::time::Duration::from_secs(1))
+                .await
+                .and_then(Result::ok)
+        }
+    }
+
+    async fn forward(
+        &self,
+        packet: Ipv6Packet,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) {
+        let ip = self.config.read().await.addr;
+        let destination = packet.header.destination;
+        if let Some((next_hop, iface)) = self.config.read().await.routing.get_route(destination) {
+            // Connected routes have no next hop, the destination is on the link
+            let next_hop = if next_hop == UNSPECIFIED {
+                destination
+            } else {
+                next_hop
+            };
+            if let Some(dest_mac) = self.resolve(next_hop, iface).await {
+                trace!(IP = ?ip, "Sending IPv6 packet to interface: {iface} next_hop {next_hop} ({dest_mac})");
+                if let Some(sender) = down_sender.get(&iface) {
+                    let _ = sender
+                        .send_async(ProcessMessage::Message(
+                            NetworkLayerId::Ipv6,
+                            (dest_mac, packet.to_vec()),
+                        ))
+                        .await;
+                }
+            }
+        } else {
+            warn!(IP = ?ip, "Can't find route to {destination}");
+        }
+    }
+
+    async fn send_message(
+        &mut self,
+        msg: NetworkTransportPayload,
+        up_id: TransportLayerId,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) {
+        if let NetworkTransportMessage::IPv6(target_ip, hop_limit, msg) = msg {
+            let next_header = match up_id {
+                TransportLayerId::Tcp => ProtocolType::TCP,
+                TransportLayerId::Udp => ProtocolType::UDP,
+                TransportLayerId::Icmp => ProtocolType::ICMP_V6,
+                TransportLayerId::Ospf => ProtocolType::OSPF,
+                TransportLayerId::Vrrp => ProtocolType::VRRP,
+            };
+            let ip = self.config.read().await.addr;
+            trace!(IP = ?ip, msg = ?msg, "Recieved packet from {up_id:?} towards {target_ip}");
+            let mut packet = Ipv6Packet::new(
+                IpV6Header::new(
+                    0,
+                    0,
+                    msg.len() as u16,
+                    next_header,
+                    hop_limit.unwrap_or(255),
+                    target_ip,
+                    ip,
+                ),
+                msg,
+            );
+            if next_header == ProtocolType::ICMP_V6 {
+                packet.set_icmp_checksum();
+            }
+            self.forward(packet, down_sender).await
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl
+    MidLevelProcess<
+        NetworkLayerId,
+        TransportLayerId,
+        LinkLayerId,
+        LinkNetworkPayload,
+        NetworkTransportPayload,
+    > for Ipv6Process
+{
+    type Extra = ();
+    async fn on_down_message(
+        &mut self,
+        (source_mac, msg): LinkNetworkPayload,
+        down_id: LinkLayerId,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+        up_sender: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
+    ) {
+        let ip = self.config.read().await.addr;
+        trace!(IP = ?ip, "Recieved from {down_id} {source_mac}: {msg:?}");
+        if let Some(mut ip_packet) = Ipv6Packet::from_vec(&msg) {
+            trace!(IP = ?ip, "Recieved IP packet: {ip_packet:?}");
+            // Packets to this host are delivered whatever their hop limit
+            if ip_packet.header.destination == ip || ip_packet.header.destination == ALL_NODES {
+                if ip_packet.header.next_header == ProtocolType::ICMP_V6
+                    && !ip_packet.icmp_checksum_valid()
+                {
+                    warn!(IP = ?ip, "Dropped ICMPv6 packet with a wrong checksum");
+                    return;
+                }
+                if let Some(up_id) = match ip_packet.header.next_header {
+                    ProtocolType::TCP => Some(TransportLayerId::Tcp),
+                    ProtocolType::UDP => Some(TransportLayerId::Udp),
+                    ProtocolType::ICMP_V6 => Some(TransportLayerId::Icmp),
+                    x => {
+                        warn!(IP = ?ip, "Unknown IPv6 next header: {x:?}");
+                        None
+                    }
+                } {
+                    if let Some(sender) = up_sender.get(&up_id) {
+                        let _ = sender
+                            .send_async(ProcessMessage::Message(
+                                NetworkLayerId::Ipv6,
+                                NetworkTransportMessage::IPv6(
+                                    ip_packet.header.source,
+                                    Some(ip_packet.header.hop_limit),
+                                    ip_packet.payload,
+                                ),
+                            ))
+                            .await;
+                    }
+                }
+            } else if ip_packet.header.destination.is_multicast() {
+                trace!(IP = ?ip, "Ignoring IPv6 packet for multicast group {}", ip_packet.header.destination);
+            } else if ip_packet.header.hop_limit > 0 {
+                ip_packet.header.hop_limit -= 1;
+                self.forward(ip_packet, down_sender).await;
+            } else {
+                trace!(IP = ?ip, "Dropped packet, sending icmpv6 packet back");
+                // As much of the invoking packet as possible without exceeding the minimum MTU
+                let mut data = ip_packet.to_vec();
+                data.truncate(1280 - 48);
+                self.send_message(
+                    NetworkTransportMessage::IPv6(
+                        ip_packet.header.source,
+                        None,
+                        Icmpv6Packet::TimeExceeded(TimeExceeded::TtlTransit { data }).to_vec(),
+                    ),
+                    TransportLayerId::Icmp,
+                    down_sender,
+                )
+                .await
+            }
+        } else {
+            warn!(IP = ?ip, "Unable to decode IPv6 packet")
+        }
+    }
+    async fn on_up_message(
+        &mut self,
+        msg: NetworkTransportPayload,
+        up_id: TransportLayerId,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+        _: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
+    ) {
+        self.send_message(msg, up_id, down_sender).await
+    }
+}
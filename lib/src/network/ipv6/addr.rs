@@ -0,0 +1,254 @@
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    num::ParseIntError,
+    ops::BitAnd,
+    str::FromStr,
+};
+
+use crate::{mac::Mac, network::ipv4::addr::IpV4Addr, route::AddrMask};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpV6Addr {
+    addr: [u8; 16],
+}
+
+#[derive(Debug, Clone)]
+pub enum IPv6ParseError {
+    ParseGroupError(ParseIntError),
+    EmbeddedIpV4Error,
+    LengthError,
+}
+
+impl Display for IPv6ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl Error for IPv6ParseError {}
+
+fn parse_groups(s: &str) -> Result<Vec<u16>, IPv6ParseError> {
+    if s.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut groups = Vec::new();
+    let mut split = s.split(':').peekable();
+    while let Some(group) = split.next() {
+        if split.peek().is_none() && group.contains('.') {
+            // Trailing embedded IPv4 (::ffff:192.168.1.1)
+            let [a, b, c, d] = IpV4Addr::from_str(group)
+                .map_err(|_| IPv6ParseError::EmbeddedIpV4Error)?
+                .as_arr();
+            groups.push(u16::from_be_bytes([a, b]));
+            groups.push(u16::from_be_bytes([c, d]));
+        } else {
+            if group.len() > 4 {
+                return Err(IPv6ParseError::LengthError);
+            }
+            groups.push(u16::from_str_radix(group, 16).map_err(IPv6ParseError::ParseGroupError)?);
+        }
+    }
+    Ok(groups)
+}
+
+impl FromStr for IpV6Addr {
+    type Err = IPv6ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let groups = match s.split_once("::") {
+            Some((head, tail)) => {
+                let head = parse_groups(head)?;
+                let tail = parse_groups(tail)?;
+                if head.len() + tail.len() > 7 {
+                    return Err(IPv6ParseError::LengthError);
+                }
+                let mut groups = head;
+                groups.resize(8 - tail.len(), 0);
+                groups.extend(tail);
+                groups
+            }
+            None => parse_groups(s)?,
+        };
+        let groups: [u16; 8] = groups
+            .try_into()
+            .map_err(|_| IPv6ParseError::LengthError)?;
+        Ok(Self::from_groups(groups))
+    }
+}
+
+impl IpV6Addr {
+    pub const fn new(addr: [u8; 16]) -> Self {
+        Self { addr }
+    }
+
+    pub const fn from_groups(groups: [u16; 8]) -> Self {
+        let mut addr = [0; 16];
+        let mut i = 0;
+        while i < 8 {
+            let [a, b] = groups[i].to_be_bytes();
+            addr[i * 2] = a;
+            addr[i * 2 + 1] = b;
+            i += 1;
+        }
+        Self { addr }
+    }
+
+    pub const fn groups(&self) -> [u16; 8] {
+        let mut groups = [0; 8];
+        let mut i = 0;
+        while i < 8 {
+            groups[i] = u16::from_be_bytes([self.addr[i * 2], self.addr[i * 2 + 1]]);
+            i += 1;
+        }
+        groups
+    }
+
+    pub const fn as_arr(self) -> [u8; 16] {
+        self.addr
+    }
+
+    pub const fn as_slice(&self) -> &[u8; 16] {
+        &self.addr
+    }
+
+    pub const fn is_multicast(&self) -> bool {
+        self.addr[0] == 0xff
+    }
+
+    pub const fn is_link_local(&self) -> bool {
+        self.addr[0] == 0xfe && (self.addr[1] & 0xc0) == 0x80
+    }
+
+    /// Solicited-node multicast group (ff02::1:ffXX:XXXX) used by neighbor solicitations
+    pub const fn solicited_node(&self) -> Self {
+        Self::new([
+            0xff,
+            0x02,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0x01,
+            0xff,
+            self.addr[13],
+            self.addr[14],
+            self.addr[15],
+        ])
+    }
+
+    /// Ethernet multicast address a packet sent to this (multicast) address is framed with
+    pub const fn multicast_mac(&self) -> Mac {
+        Mac::new([
+            0x33,
+            0x33,
+            self.addr[12],
+            self.addr[13],
+            self.addr[14],
+            self.addr[15],
+        ])
+    }
+}
+
+impl Debug for IpV6Addr {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // RFC 5952: compress the longest run (len > 1) of zero groups, lowercase hex
+        let groups = self.groups();
+        let (mut best, mut curr) = ((0, 0), (0, 0));
+        for (i, g) in groups.iter().enumerate() {
+            if *g == 0 {
+                if curr.1 == 0 {
+                    curr.0 = i;
+                }
+                curr.1 += 1;
+                if curr.1 > best.1 {
+                    best = curr;
+                }
+            } else {
+                curr.1 = 0;
+            }
+        }
+        let write_groups = |fmt: &mut std::fmt::Formatter<'_>, groups: &[u16]| {
+            groups
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, g)| match i {
+                    0 => write!(fmt, "{g:x}"),
+                    _ => write!(fmt, ":{g:x}"),
+                })
+        };
+        if best.1 > 1 {
+            write_groups(fmt, &groups[..best.0])?;
+            write!(fmt, "::")?;
+            write_groups(fmt, &groups[(best.0 + best.1)..])
+        } else {
+            write_groups(fmt, &groups)
+        }
+    }
+}
+
+impl Display for IpV6Addr {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{self:?}")
+    }
+}
+
+pub const UNSPECIFIED: IpV6Addr = IpV6Addr::new([0; 16]);
+pub const LOOPBACK: IpV6Addr = IpV6Addr::from_groups([0, 0, 0, 0, 0, 0, 0, 1]); // Virtual
+pub const ALL_NODES: IpV6Addr = IpV6Addr::from_groups([0xff02, 0, 0, 0, 0, 0, 0, 1]);
+pub const ALL_ROUTERS: IpV6Addr = IpV6Addr::from_groups([0xff02, 0, 0, 0, 0, 0, 0, 2]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpV6Mask(u8);
+
+impl IpV6Mask {
+    pub fn new(mask: u8) -> Self {
+        Self(mask.min(128))
+    }
+
+    fn get_mask(&self) -> [u8; 16] {
+        u128::MAX
+            .checked_shl(128 - self.0 as u32)
+            .unwrap_or(0)
+            .to_be_bytes()
+    }
+}
+
+impl From<u8> for IpV6Mask {
+    fn from(value: u8) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Display for IpV6Mask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "/{}", self.0)
+    }
+}
+
+impl BitAnd<IpV6Addr> for IpV6Mask {
+    type Output = IpV6Addr;
+
+    fn bitand(self, rhs: IpV6Addr) -> Self::Output {
+        let mut res = rhs.addr;
+        for (real, mask) in res.iter_mut().zip(self.get_mask()) {
+            *real &= mask;
+        }
+        IpV6Addr::new(res)
+    }
+}
+
+impl AddrMask<IpV6Addr> for IpV6Mask {
+    fn prefix_len(&self) -> u8 {
+        self.0
+    }
+
+    fn from_prefix_len(len: u8) -> Self {
+        Self::new(len)
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+
+use crate::{
+    chassis::LinkLayerId,
+    route::{RouteSource, RoutingEntry, RoutingTable},
+};
+
+use super::addr::{IpV6Addr, IpV6Mask, UNSPECIFIED};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpV6ConfigInner {
+    pub addr: IpV6Addr,
+    /// Prefix length of the network of the address and the interface it's on
+    pub prefix: Option<(IpV6Mask, LinkLayerId)>,
+    pub routing: RoutingTable<IpV6Addr, IpV6Mask, LinkLayerId>,
+    /// Time a neighbor is considered reachable after a confirmation
+    pub reachable_time: chrono::Duration,
+    /// Time a neighbor waits in DELAY before it's probed
+    pub delay_first_probe_time: chrono::Duration,
+    /// Time between neighbor solicitations
+    pub retrans_timer: chrono::Duration,
+    /// Router lifetime advertised in router advertisements in seconds, 0 disables them
+    pub router_lifetime: u16,
+}
+
+impl Default for IpV6ConfigInner {
+    fn default() -> Self {
+        Self {
+            addr: UNSPECIFIED,
+            prefix: None,
+            routing: Default::default(),
+            reachable_time: chrono::Duration::seconds(30),
+            delay_first_probe_time: chrono::Duration::seconds(5),
+            retrans_timer: chrono::Duration::seconds(1),
+            router_lifetime: 0,
+        }
+    }
+}
+
+impl IpV6ConfigInner {
+    /// Route to the network of the address through its interface, the next hop is unspecified
+    /// since the destinations are on the link
+    fn connected_route(&self) -> Option<RoutingEntry<IpV6Addr, IpV6Mask, LinkLayerId>> {
+        let (mask, iface) = self.prefix?;
+        Some(
+            RoutingEntry::new(mask & self.addr, UNSPECIFIED, mask, iface)
+                .with_source(RouteSource::Connected),
+        )
+    }
+
+    /// Replaces the address and the connected route of its network
+    pub fn set_addr(&mut self, addr: IpV6Addr, mask: IpV6Mask, iface: LinkLayerId) {
+        if let Some(route) = self.connected_route() {
+            self.routing.remove_route(&route);
+        }
+        self.addr = addr;
+        self.prefix = Some((mask, iface));
+        if let Some(route) = self.connected_route() {
+            self.routing.add_route(route);
+        }
+    }
+}
+
+pub type IpV6Config = Arc<RwLock<IpV6ConfigInner>>;
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chassis::LinkLayerId,
+        mac::Mac,
+        network::ipv6::addr::{IpV6Addr, IpV6Mask, UNSPECIFIED},
+    };
+
+    use super::IpV6ConfigInner;
+
+    #[test]
+    fn set_addr_replaces_connected_route() {
+        let eth0 = LinkLayerId::Ethernet(0, Mac::new([0, 1, 0, 0, 0, 0]));
+        let eth1 = LinkLayerId::Ethernet(1, Mac::new([0, 1, 0, 0, 0, 1]));
+        let mut config = IpV6ConfigInner::default();
+        config.set_addr(
+            IpV6Addr::from_groups([0x2001, 0xdb8, 0, 1, 0, 0, 0, 1]),
+            IpV6Mask::new(64),
+            eth0,
+        );
+        let neighbor = IpV6Addr::from_groups([0x2001, 0xdb8, 0, 1, 0, 0, 0, 2]);
+        assert_eq!(
+            config.routing.get_route(neighbor),
+            Some((UNSPECIFIED, eth0))
+        );
+
+        config.set_addr(
+            IpV6Addr::from_groups([0x2001, 0xdb8, 0, 2, 0, 0, 0, 1]),
+            IpV6Mask::new(64),
+            eth1,
+        );
+        assert_eq!(config.routing.get_route(neighbor), None);
+        let neighbor = IpV6Addr::from_groups([0x2001, 0xdb8, 0, 2, 0, 0, 0, 2]);
+        assert_eq!(
+            config.routing.get_route(neighbor),
+            Some((UNSPECIFIED, eth1))
+        );
+    }
+}
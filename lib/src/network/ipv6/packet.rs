@@ -0,0 +1,171 @@
+use tracing::warn;
+
+use crate::network::ipv4::protocol::ProtocolType;
+
+use super::addr::IpV6Addr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpV6Header {
+    // version: u8, // 4 bit
+    pub traffic_class: u8,
+    flow_label: u32, // 20 bit
+
+    payload_length: u16,
+
+    // Extension headers are not decoded, they are treated as an upper layer protocol
+    pub next_header: ProtocolType,
+
+    pub hop_limit: u8,
+
+    // 128 bit each
+    pub source: IpV6Addr,
+    pub destination: IpV6Addr,
+}
+
+impl IpV6Header {
+    pub const fn new(
+        traffic_class: u8,
+        flow_label: u32,
+        payload_length: u16,
+        next_header: ProtocolType,
+        hop_limit: u8,
+        destination: IpV6Addr,
+        source: IpV6Addr,
+    ) -> Self {
+        Self {
+            traffic_class,
+            flow_label: flow_label & 0x000f_ffff,
+            payload_length,
+            next_header,
+            hop_limit,
+            source,
+            destination,
+        }
+    }
+
+    pub const fn payload_length(&self) -> u16 {
+        self.payload_length
+    }
+
+    pub fn from_vec(data: &[u8]) -> Option<(Self, usize)> {
+        if data.len() < 40 {
+            warn!("IPv6 header: Not enough data");
+            return None;
+        }
+        if data[0] >> 4 != 6 {
+            warn!("IPv6 header version is not set correctly");
+            return None;
+        }
+        let first = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let traffic_class = ((first >> 20) & 0xff) as u8;
+        let flow_label = first & 0x000f_ffff;
+        let payload_length = u16::from_be_bytes(data[4..6].try_into().unwrap());
+        if data.len() < 40 + payload_length as usize {
+            warn!("IPv6 header: Payload shorter than payload length");
+            return None;
+        }
+        let next_header = ProtocolType::new(data[6]);
+        let hop_limit = data[7];
+        let source = IpV6Addr::new(data[8..24].try_into().unwrap());
+        let destination = IpV6Addr::new(data[24..40].try_into().unwrap());
+        Some((
+            Self {
+                traffic_class,
+                flow_label,
+                payload_length,
+                next_header,
+                hop_limit,
+                source,
+                destination,
+            },
+            40,
+        ))
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(40);
+        let first = (6 << 28) | ((self.traffic_class as u32) << 20) | self.flow_label;
+        vec.extend_from_slice(&first.to_be_bytes());
+        vec.extend_from_slice(&self.payload_length.to_be_bytes());
+        vec.push(self.next_header.inner());
+        vec.push(self.hop_limit);
+        vec.extend_from_slice(self.source.as_slice());
+        vec.extend_from_slice(self.destination.as_slice());
+        vec
+    }
+}
+
+/// Internet checksum of the upper layer packet with the pseudo-header of its addresses, length and
+/// protocol (RFC 8200 8.1). It's 0 for a packet with the right checksum
+pub fn pseudo_header_checksum(
+    source: IpV6Addr,
+    destination: IpV6Addr,
+    next_header: ProtocolType,
+    payload: &[u8],
+) -> u16 {
+    let mut pseudo_header = Vec::with_capacity(40);
+    pseudo_header.extend_from_slice(source.as_slice());
+    pseudo_header.extend_from_slice(destination.as_slice());
+    pseudo_header.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    pseudo_header.extend_from_slice(&[0, 0, 0, next_header.inner()]);
+    let mut sum = pseudo_header
+        .chunks(2)
+        .chain(payload.chunks(2))
+        .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u64)
+        .sum::<u64>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv6Packet {
+    pub header: IpV6Header,
+
+    pub payload: Vec<u8>,
+}
+
+impl Ipv6Packet {
+    pub fn new(header: IpV6Header, payload: Vec<u8>) -> Self {
+        Self { header, payload }
+    }
+
+    pub fn from_vec(data: &[u8]) -> Option<Self> {
+        let (header, left) = IpV6Header::from_vec(data)?;
+        let end = left + header.payload_length as usize;
+        Some(Self::new(header, data[left..end].to_vec()))
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut vec = self.header.to_vec();
+        vec.extend_from_slice(&self.payload);
+        vec
+    }
+
+    /// Fills the checksum of the ICMPv6 payload, which covers the pseudo-header
+    pub fn set_icmp_checksum(&mut self) {
+        let Some(field) = self.payload.get_mut(2..4) else {
+            return;
+        };
+        field.fill(0);
+        let checksum = pseudo_header_checksum(
+            self.header.source,
+            self.header.destination,
+            ProtocolType::ICMP_V6,
+            &self.payload,
+        );
+        self.payload[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    /// Whether the checksum of the ICMPv6 payload is right
+    pub fn icmp_checksum_valid(&self) -> bool {
+        self.payload.len() >= 4
+            && pseudo_header_checksum(
+                self.header.source,
+                self.header.destination,
+                ProtocolType::ICMP_V6,
+                &self.payload,
+            ) == 0
+    }
+}
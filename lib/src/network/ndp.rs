@@ -0,0 +1,679 @@
+use chrono::{DateTime, Local};
+use flume::{Receiver, RecvError, Sender};
+use tokio::task::JoinSet;
+use tracing::{trace, warn};
+
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use super::{
+    arp::{get_handle_pair, ArpHandle},
+    ipv4::protocol::ProtocolType,
+    ipv6::{
+        addr::{IpV6Addr, IpV6Mask, ALL_NODES, ALL_ROUTERS, UNSPECIFIED},
+        config::IpV6Config,
+        packet::{IpV6Header, Ipv6Packet},
+    },
+};
+use crate::{
+    chassis::{
+        LinkLayerId, LinkNetworkPayload, MidLevelProcess, NetworkLayerId, NetworkTransportPayload,
+        ProcessMessage, ReceptionResult, TransportLayerId,
+    },
+    either::ThreeWayEither,
+    mac::Mac,
+    route::RoutingEntry,
+};
+use packet::{NdpOption, NdpPacket};
+
+pub mod packet;
+
+/// Multicast solicitations sent before giving up on an INCOMPLETE neighbor
+const MAX_MULTICAST_SOLICIT: u8 = 3;
+/// Unicast solicitations sent before giving up on a neighbor in PROBE
+const MAX_UNICAST_SOLICIT: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborState {
+    /// Resolution in progress, no link layer address yet
+    Incomplete,
+    /// Reachability confirmed recently
+    Reachable,
+    /// Reachability unknown, nothing is done until traffic is sent
+    Stale,
+    /// Traffic was sent to a stale neighbor, waiting before probing
+    Delay,
+    /// Unicast solicitations are sent to confirm reachability
+    Probe,
+}
+
+impl Display for NeighborState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Incomplete => write!(f, "INCOMPLETE"),
+            Self::Reachable => write!(f, "REACHABLE"),
+            Self::Stale => write!(f, "STALE"),
+            Self::Delay => write!(f, "DELAY"),
+            Self::Probe => write!(f, "PROBE"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NeighborEntry {
+    pub mac: Option<Mac>,
+    pub state: NeighborState,
+    pub router: bool,
+    pub updated: DateTime<Local>,
+    probes: u8,
+}
+
+impl NeighborEntry {
+    fn new(mac: Option<Mac>, state: NeighborState) -> Self {
+        Self {
+            mac,
+            state,
+            router: false,
+            updated: Local::now(),
+            probes: 0,
+        }
+    }
+
+    fn set_state(&mut self, state: NeighborState) {
+        self.state = state;
+        self.updated = Local::now();
+        self.probes = 0;
+    }
+
+    /// Applies the timer based transitions, REACHABLE -> STALE and DELAY -> PROBE
+    fn age(&mut self, reachable_time: chrono::Duration, delay_first_probe_time: chrono::Duration) {
+        let elapsed = Local::now() - self.updated;
+        match self.state {
+            NeighborState::Reachable if elapsed > reachable_time => {
+                self.set_state(NeighborState::Stale)
+            }
+            NeighborState::Delay if elapsed > delay_first_probe_time => {
+                self.set_state(NeighborState::Probe)
+            }
+            _ => (),
+        }
+    }
+}
+
+pub type NeighborTable = HashMap<(IpV6Addr, LinkLayerId), NeighborEntry>;
+
+pub type NeighborHandle = ArpHandle<(IpV6Addr, LinkLayerId), Mac>;
+
+/// Requests of the neighbor handles and the channel of their answers
+type NeighborHandleChannel = (Arc<Receiver<(IpV6Addr, LinkLayerId)>>, Sender<Mac>);
+
+#[derive(Debug)]
+pub struct NdpProcess {
+    config: IpV6Config,
+    table: NeighborTable,
+    handle: Option<NeighborHandleChannel>,
+    get_new_handle: (Arc<Receiver<()>>, Sender<NeighborHandle>),
+    get_table: (Arc<Receiver<()>>, Sender<NeighborTable>),
+    router_solicit: Arc<Receiver<LinkLayerId>>,
+}
+
+impl NdpProcess {
+    pub fn new(config: IpV6Config) -> (Self, GenericNdpHandle) {
+        let (new_handle_external_tx, new_handle_internal_rx) = flume::unbounded();
+        let (new_handle_internal_tx, new_handle_external_rx) = flume::unbounded();
+        let (get_table_external_tx, get_table_internal_rx) = flume::unbounded();
+        let (get_table_internal_tx, get_table_external_rx) = flume::unbounded();
+        let (router_solicit_tx, router_solicit_rx) = flume::unbounded();
+        (
+            Self {
+                config,
+                table: HashMap::new(),
+                handle: None,
+                get_new_handle: (Arc::new(new_handle_internal_rx), new_handle_internal_tx),
+                get_table: (Arc::new(get_table_internal_rx), get_table_internal_tx),
+                router_solicit: Arc::new(router_solicit_rx),
+            },
+            GenericNdpHandle {
+                get_new_handle: (new_handle_external_tx, new_handle_external_rx),
+                get_table: (get_table_external_tx, get_table_external_rx),
+                router_solicit: router_solicit_tx,
+            },
+        )
+    }
+
+    pub fn new_handle(&mut self) -> NeighborHandle {
+        let (inner, ext) = get_handle_pair();
+        self.handle = Some((Arc::new(inner.rx), inner.tx));
+        ext
+    }
+
+    async fn send(
+        &self,
+        packet: NdpPacket,
+        destination: IpV6Addr,
+        dest_mac: Mac,
+        iface: LinkLayerId,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) {
+        let ip = self.config.read().await.addr;
+        let payload = packet.to_vec();
+        trace!(NDP = ?ip, "Sending {packet:?} to {destination} ({dest_mac}) through {iface}");
+        let mut packet = Ipv6Packet::new(
+            IpV6Header::new(
+                0,
+                0,
+                payload.len() as u16,
+                ProtocolType::ICMP_V6,
+                255,
+                destination,
+                ip,
+            ),
+            payload,
+        );
+        packet.set_icmp_checksum();
+        if let Some(sender) = down_sender.get(&iface) {
+            if let Err(e) = sender
+                .send_async(ProcessMessage::Message(
+                    NetworkLayerId::Ndp,
+                    (dest_mac, packet.to_vec()),
+                ))
+                .await
+            {
+                warn!("NDP: Error sending packet: {e}")
+            }
+        }
+    }
+
+    async fn solicit(
+        &self,
+        target: IpV6Addr,
+        unicast: Option<Mac>,
+        iface: LinkLayerId,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) {
+        // Interfaces from the routing table don't carry the MAC address
+        let Some(mac) = down_sender.get_key_value(&iface).map(|(iface, _)| iface.mac()) else {
+            warn!("NDP: Unknown interface {iface}");
+            return;
+        };
+        let (destination, dest_mac) = match unicast {
+            Some(dest_mac) => (target, dest_mac),
+            None => (target.solicited_node(), target.solicited_node().multicast_mac()),
+        };
+        self.send(
+            NdpPacket::NeighborSolicitation {
+                target,
+                options: vec![NdpOption::SourceLinkLayerAddress(mac)],
+            },
+            destination,
+            dest_mac,
+            iface,
+            down_sender,
+        )
+        .await
+    }
+
+    /// Resolves a neighbor, answering through the handle if the address is already known
+    async fn resolve(
+        &mut self,
+        ip: IpV6Addr,
+        iface: LinkLayerId,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+    ) -> Option<Mac> {
+        let (reachable_time, delay_first_probe_time, retrans_timer) = {
+            let config = self.config.read().await;
+            (
+                config.reachable_time,
+                config.delay_first_probe_time,
+                config.retrans_timer,
+            )
+        };
+        let Some(entry) = self.table.get_mut(&(ip, iface)) else {
+            trace!("NDP: Searching for MAC address for {ip}");
+            self.table.insert(
+                (ip, iface),
+                NeighborEntry {
+                    probes: 1,
+                    ..NeighborEntry::new(None, NeighborState::Incomplete)
+                },
+            );
+            self.solicit(ip, None, iface, down_sender).await;
+            return None;
+        };
+        entry.age(reachable_time, delay_first_probe_time);
+        let elapsed = Local::now() - entry.updated;
+        match entry.state {
+            NeighborState::Incomplete => {
+                if entry.probes < MAX_MULTICAST_SOLICIT {
+                    entry.probes += 1;
+                    self.solicit(ip, None, iface, down_sender).await;
+                } else if elapsed > retrans_timer * MAX_MULTICAST_SOLICIT as i32 {
+                    trace!("NDP: Resolution of {ip} failed, starting again");
+                    *entry = NeighborEntry {
+                        probes: 1,
+                        ..NeighborEntry::new(None, NeighborState::Incomplete)
+                    };
+                    self.solicit(ip, None, iface, down_sender).await;
+                }
+                None
+            }
+            NeighborState::Reachable | NeighborState::Delay => entry.mac,
+            NeighborState::Stale => {
+                entry.set_state(NeighborState::Delay);
+                entry.mac
+            }
+            NeighborState::Probe => {
+                let mac = entry.mac;
+                if entry.probes == 0 || elapsed > retrans_timer {
+                    if entry.probes >= MAX_UNICAST_SOLICIT {
+                        trace!("NDP: Neighbor {ip} is unreachable");
+                        *entry = NeighborEntry {
+                            probes: 1,
+                            ..NeighborEntry::new(None, NeighborState::Incomplete)
+                        };
+                        self.solicit(ip, None, iface, down_sender).await;
+                        return None;
+                    }
+                    entry.probes += 1;
+                    entry.updated = Local::now();
+                    self.solicit(ip, mac, iface, down_sender).await;
+                }
+                mac
+            }
+        }
+    }
+
+    /// Records the link layer address of a neighbor that sent us a packet
+    async fn update_from_sender(&mut self, ip: IpV6Addr, mac: Mac, iface: LinkLayerId) {
+        match self.table.get_mut(&(ip, iface)) {
+            Some(entry) if entry.mac == Some(mac) => (),
+            Some(entry) => {
+                let pending = entry.state == NeighborState::Incomplete;
+                entry.mac = Some(mac);
+                entry.set_state(NeighborState::Stale);
+                if pending {
+                    self.answer(mac).await;
+                }
+            }
+            None => {
+                self.table
+                    .insert((ip, iface), NeighborEntry::new(Some(mac), NeighborState::Stale));
+            }
+        }
+    }
+
+    async fn answer(&self, mac: Mac) {
+        if let Some((_, tx)) = self.handle.as_ref() {
+            let _ = tx.send_async(mac).await;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExtraMessage {
+    GetIpV6(Result<(IpV6Addr, LinkLayerId), RecvError>),
+    NewIpV6(Result<(), RecvError>),
+    GetCurrentTable(Result<(), RecvError>),
+    RouterSolicit(Result<LinkLayerId, RecvError>),
+}
+
+#[async_trait::async_trait]
+impl
+    MidLevelProcess<
+        NetworkLayerId,
+        TransportLayerId,
+        LinkLayerId,
+        LinkNetworkPayload,
+        NetworkTransportPayload,
+    > for NdpProcess
+{
+    async fn on_down_message(
+        &mut self,
+        (source_mac, msg): LinkNetworkPayload,
+        down_id: LinkLayerId,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+        _: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
+    ) {
+        let Some(ip_packet) = Ipv6Packet::from_vec(&msg) else {
+            warn!("NDP: Unable to decode IPv6 packet");
+            return;
+        };
+        // Packets that could have been forwarded by a router are not trusted
+        if ip_packet.header.hop_limit != 255 {
+            warn!("NDP: Dropped packet with hop limit {}", ip_packet.header.hop_limit);
+            return;
+        }
+        if !ip_packet.icmp_checksum_valid() {
+            warn!("NDP: Dropped packet with a wrong checksum");
+            return;
+        }
+        let Some(packet) = NdpPacket::from_vec(&ip_packet.payload) else {
+            warn!("NDP: Unable to decode NDP packet");
+            return;
+        };
+        let (ip, router_lifetime) = {
+            let config = self.config.read().await;
+            (config.addr, config.router_lifetime)
+        };
+        let source = ip_packet.header.source;
+        let mac = down_id.mac();
+        trace!(NDP = ?ip, "Recieved from {down_id} {source_mac}: {packet:?}");
+        match &packet {
+            NdpPacket::NeighborSolicitation { target, .. } => {
+                if *target != ip {
+                    return;
+                }
+                if let Some(sha) = packet.source_link_layer_address() {
+                    if source != UNSPECIFIED {
+                        self.update_from_sender(source, sha, down_id).await;
+                    }
+                }
+                let (destination, dest_mac) = if source == UNSPECIFIED {
+                    (ALL_NODES, ALL_NODES.multicast_mac())
+                } else {
+                    (
+                        source,
+                        packet.source_link_layer_address().unwrap_or(source_mac),
+                    )
+                };
+                self.send(
+                    NdpPacket::NeighborAdvertisement {
+                        // Only set when this host advertises itself as a router
+                        router: router_lifetime != 0,
+                        solicited: source != UNSPECIFIED,
+                        override_flag: true,
+                        target: ip,
+                        options: vec![NdpOption::TargetLinkLayerAddress(mac)],
+                    },
+                    destination,
+                    dest_mac,
+                    down_id,
+                    down_sender,
+                )
+                .await
+            }
+            NdpPacket::NeighborAdvertisement {
+                router,
+                solicited,
+                override_flag,
+                target,
+                ..
+            } => {
+                let tha = packet.target_link_layer_address();
+                if let Some(entry) = self.table.get_mut(&(*target, down_id)) {
+                    if entry.state == NeighborState::Incomplete {
+                        if let Some(tha) = tha {
+                            entry.mac = Some(tha);
+                            entry.router = *router;
+                            entry.set_state(if *solicited {
+                                NeighborState::Reachable
+                            } else {
+                                NeighborState::Stale
+                            });
+                            self.answer(tha).await;
+                        }
+                    } else {
+                        let changed = tha.is_some_and(|tha| entry.mac != Some(tha));
+                        entry.router = *router;
+                        if !override_flag && changed {
+                            if entry.state == NeighborState::Reachable {
+                                entry.set_state(NeighborState::Stale);
+                            }
+                        } else {
+                            if changed {
+                                entry.mac = tha;
+                            }
+                            if *solicited {
+                                entry.set_state(NeighborState::Reachable);
+                            } else if changed {
+                                entry.set_state(NeighborState::Stale);
+                            }
+                        }
+                    }
+                }
+            }
+            NdpPacket::RouterSolicitation { .. } => {
+                if router_lifetime == 0 {
+                    return;
+                }
+                if let Some(sha) = packet.source_link_layer_address() {
+                    if source != UNSPECIFIED {
+                        self.update_from_sender(source, sha, down_id).await;
+                    }
+                }
+                let reachable_time = self.config.read().await.reachable_time;
+                self.send(
+                    NdpPacket::RouterAdvertisement {
+                        cur_hop_limit: 255,
+                        managed: false,
+                        other: false,
+                        router_lifetime,
+                        reachable_time: reachable_time.num_milliseconds() as u32,
+                        retrans_timer: 0,
+                        options: vec![NdpOption::SourceLinkLayerAddress(mac)],
+                    },
+                    ALL_NODES,
+                    ALL_NODES.multicast_mac(),
+                    down_id,
+                    down_sender,
+                )
+                .await
+            }
+            NdpPacket::RouterAdvertisement {
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+                ..
+            } => {
+                if let Some(sha) = packet.source_link_layer_address() {
+                    self.update_from_sender(source, sha, down_id).await;
+                }
+                if let Some(entry) = self.table.get_mut(&(source, down_id)) {
+                    entry.router = true;
+                }
+                let mut config = self.config.write().await;
+                let default_route =
+                    RoutingEntry::new(UNSPECIFIED, source, IpV6Mask::new(0), down_id);
+                config.routing.remove_route(&default_route);
+                if *router_lifetime != 0 {
+                    trace!(NDP = ?ip, "Adding default router {source} through {down_id}");
+                    config.routing.add_route(default_route);
+                }
+                if *reachable_time != 0 {
+                    config.reachable_time = chrono::Duration::milliseconds(*reachable_time as i64);
+                }
+                if *retrans_timer != 0 {
+                    config.retrans_timer = chrono::Duration::milliseconds(*retrans_timer as i64);
+                }
+            }
+        }
+    }
+    async fn on_up_message(
+        &mut self,
+        _: NetworkTransportPayload,
+        up_id: TransportLayerId,
+        _: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+        _: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
+    ) {
+        warn!(NDP = ?self, "Recieved packet from {up_id:?}");
+    }
+    type Extra = ExtraMessage;
+
+    async fn setup(
+        &mut self,
+        join_set: &mut JoinSet<
+            ThreeWayEither<
+                ReceptionResult<ProcessMessage<LinkLayerId, NetworkLayerId, LinkNetworkPayload>>,
+                ReceptionResult<
+                    ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportPayload>,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        let new_rx = self.get_new_handle.0.clone();
+        join_set.spawn(async move {
+            ThreeWayEither::C(ExtraMessage::NewIpV6(new_rx.recv_async().await))
+        });
+        let rx = self.get_table.0.clone();
+        join_set.spawn(async move {
+            ThreeWayEither::C(ExtraMessage::GetCurrentTable(rx.recv_async().await))
+        });
+        let rx = self.router_solicit.clone();
+        join_set.spawn(async move {
+            ThreeWayEither::C(ExtraMessage::RouterSolicit(rx.recv_async().await))
+        });
+        if let Some((rx, _)) = self.handle.as_ref() {
+            let rx = rx.clone();
+            join_set.spawn(async move {
+                ThreeWayEither::C(ExtraMessage::GetIpV6(rx.recv_async().await))
+            });
+        }
+    }
+    async fn on_extra_message(
+        &mut self,
+        msg: Self::Extra,
+        down_sender: &HashMap<
+            LinkLayerId,
+            Sender<ProcessMessage<NetworkLayerId, LinkLayerId, LinkNetworkPayload>>,
+        >,
+        _: &HashMap<
+            TransportLayerId,
+            Sender<ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportPayload>>,
+        >,
+        join_set: &mut JoinSet<
+            ThreeWayEither<
+                ReceptionResult<ProcessMessage<LinkLayerId, NetworkLayerId, LinkNetworkPayload>>,
+                ReceptionResult<
+                    ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportPayload>,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        match msg {
+            ExtraMessage::GetCurrentTable(r) => match r {
+                Ok(()) => {
+                    let (reachable_time, delay_first_probe_time) = {
+                        let config = self.config.read().await;
+                        (config.reachable_time, config.delay_first_probe_time)
+                    };
+                    for entry in self.table.values_mut() {
+                        entry.age(reachable_time, delay_first_probe_time);
+                    }
+                    let _ = self.get_table.1.send_async(self.table.clone()).await;
+                    let rx = self.get_table.0.clone();
+                    join_set.spawn(async move {
+                        ThreeWayEither::C(ExtraMessage::GetCurrentTable(rx.recv_async().await))
+                    });
+                }
+                Err(RecvError::Disconnected) => warn!("NDP: Disconnected get neighbor table"),
+            },
+            ExtraMessage::RouterSolicit(r) => match r {
+                Ok(iface) => {
+                    if let Some(mac) =
+                        down_sender.get_key_value(&iface).map(|(iface, _)| iface.mac())
+                    {
+                        self.send(
+                            NdpPacket::RouterSolicitation {
+                                options: vec![NdpOption::SourceLinkLayerAddress(mac)],
+                            },
+                            ALL_ROUTERS,
+                            ALL_ROUTERS.multicast_mac(),
+                            iface,
+                            down_sender,
+                        )
+                        .await;
+                    } else {
+                        warn!("NDP: Unknown interface {iface}");
+                    }
+                    let rx = self.router_solicit.clone();
+                    join_set.spawn(async move {
+                        ThreeWayEither::C(ExtraMessage::RouterSolicit(rx.recv_async().await))
+                    });
+                }
+                Err(RecvError::Disconnected) => warn!("NDP: Disconnected router solicitation"),
+            },
+            ExtraMessage::NewIpV6(r) => {
+                match r {
+                    Ok(()) => {
+                        let handle = self.new_handle();
+                        let _ = self.get_new_handle.1.send_async(handle).await;
+                        if let Some((rx, _)) = self.handle.as_ref() {
+                            let rx = rx.clone();
+                            join_set.spawn(async move {
+                                ThreeWayEither::C(ExtraMessage::GetIpV6(rx.recv_async().await))
+                            });
+                        }
+                    }
+                    Err(e) => warn!(NDP = ?self, "Error receiving extra message: {e:?}"),
+                }
+                let new_rx = self.get_new_handle.0.clone();
+                join_set.spawn(async move {
+                    ThreeWayEither::C(ExtraMessage::NewIpV6(new_rx.recv_async().await))
+                });
+            }
+            ExtraMessage::GetIpV6(ip) => match ip {
+                Ok((ip, id)) => {
+                    if let Some(mac) = self.resolve(ip, id, down_sender).await {
+                        trace!("NDP: Sending known MAC address ({mac}) for {ip}");
+                        self.answer(mac).await;
+                    }
+                    if let Some((rx, _)) = self.handle.as_ref() {
+                        let rx = rx.clone();
+                        join_set.spawn(async move {
+                            ThreeWayEither::C(ExtraMessage::GetIpV6(rx.recv_async().await))
+                        });
+                    }
+                }
+                Err(RecvError::Disconnected) => {
+                    warn!(NDP = ?self, "Disconnected IPv6 handle");
+                    self.handle = None;
+                }
+            },
+        }
+    }
+}
+
+pub struct GenericNdpHandle {
+    get_new_handle: (Sender<()>, Receiver<NeighborHandle>),
+    get_table: (Sender<()>, Receiver<NeighborTable>),
+    router_solicit: Sender<LinkLayerId>,
+}
+
+impl GenericNdpHandle {
+    pub async fn get_new_handle(&self) -> Option<NeighborHandle> {
+        self.get_new_handle.0.send_async(()).await.ok()?;
+        self.get_new_handle.1.recv_async().await.ok()
+    }
+
+    pub async fn get_table(&self) -> Option<NeighborTable> {
+        self.get_table.0.send_async(()).await.ok()?;
+        self.get_table.1.recv_async().await.ok()
+    }
+
+    /// Sends a router solicitation through the interface
+    pub async fn router_solicit(&self, iface: LinkLayerId) -> Option<()> {
+        self.router_solicit.send_async(iface).await.ok()
+    }
+}
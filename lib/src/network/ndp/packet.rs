@@ -0,0 +1,226 @@
+use tracing::warn;
+
+use crate::{mac::Mac, network::ipv6::addr::IpV6Addr};
+
+/// NDP option, represents type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NdpOption {
+    /// 1
+    SourceLinkLayerAddress(Mac),
+    /// 2
+    TargetLinkLayerAddress(Mac),
+    /// 5
+    Mtu(u32),
+    Unknown(u8, Vec<u8>),
+}
+
+impl NdpOption {
+    /// Decodes a list of options, options are padded to multiples of 8 bytes
+    fn from_vec(mut data: &[u8]) -> Option<Vec<Self>> {
+        let mut options = Vec::new();
+        while !data.is_empty() {
+            if data.len() < 2 {
+                warn!("NDP option: Not enough data");
+                return None;
+            }
+            let typ = data[0];
+            let len = data[1] as usize * 8;
+            if len == 0 || data.len() < len {
+                warn!("NDP option: Invalid length {len}");
+                return None;
+            }
+            options.push(match typ {
+                1 => Self::SourceLinkLayerAddress(Mac::new(data[2..8].try_into().ok()?)),
+                2 => Self::TargetLinkLayerAddress(Mac::new(data[2..8].try_into().ok()?)),
+                5 if len == 8 => Self::Mtu(u32::from_be_bytes(data[4..8].try_into().ok()?)),
+                x => Self::Unknown(x, data[2..len].to_vec()),
+            });
+            data = &data[len..];
+        }
+        Some(options)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        let (typ, mut data) = match self {
+            Self::SourceLinkLayerAddress(mac) => (1, mac.as_slice().to_vec()),
+            Self::TargetLinkLayerAddress(mac) => (2, mac.as_slice().to_vec()),
+            Self::Mtu(mtu) => (5, {
+                let mut data = vec![0; 2];
+                data.extend_from_slice(&mtu.to_be_bytes());
+                data
+            }),
+            Self::Unknown(typ, data) => (*typ, data.clone()),
+        };
+        // Pad type, length and data to a multiple of 8 bytes
+        data.resize((data.len() + 2).div_ceil(8) * 8 - 2, 0);
+        let mut res = Vec::with_capacity(data.len() + 2);
+        res.push(typ);
+        res.push(((data.len() + 2) / 8) as u8);
+        res.extend_from_slice(&data);
+        res
+    }
+}
+
+/// NDP message carried over ICMPv6, represents type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NdpPacket {
+    /// 133
+    RouterSolicitation { options: Vec<NdpOption> },
+    /// 134
+    RouterAdvertisement {
+        cur_hop_limit: u8,
+        managed: bool,
+        other: bool,
+        /// Seconds
+        router_lifetime: u16,
+        /// Milliseconds
+        reachable_time: u32,
+        /// Milliseconds
+        retrans_timer: u32,
+        options: Vec<NdpOption>,
+    },
+    /// 135
+    NeighborSolicitation {
+        target: IpV6Addr,
+        options: Vec<NdpOption>,
+    },
+    /// 136
+    NeighborAdvertisement {
+        router: bool,
+        solicited: bool,
+        override_flag: bool,
+        target: IpV6Addr,
+        options: Vec<NdpOption>,
+    },
+}
+
+/// Whether an IPv6 packet (header included) carries an NDP message
+pub fn is_ndp_packet(data: &[u8]) -> bool {
+    data.len() > 40 && data[6] == 58 && (133..=137).contains(&data[40])
+}
+
+impl NdpPacket {
+    pub fn from_vec(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            warn!("NDP packet: Not enough data");
+            return None;
+        }
+        let typ = data[0];
+        let code = data[1];
+        // The checksum covers the IPv6 pseudo-header, it's checked with the IPv6 packet
+        if code != 0 {
+            warn!("NDP packet: Invalid code {code}");
+            return None;
+        }
+        match typ {
+            133 => Some(Self::RouterSolicitation {
+                options: NdpOption::from_vec(&data[8..])?,
+            }),
+            134 if data.len() >= 16 => Some(Self::RouterAdvertisement {
+                cur_hop_limit: data[4],
+                managed: data[5] & 0x80 != 0,
+                other: data[5] & 0x40 != 0,
+                router_lifetime: u16::from_be_bytes(data[6..8].try_into().ok()?),
+                reachable_time: u32::from_be_bytes(data[8..12].try_into().ok()?),
+                retrans_timer: u32::from_be_bytes(data[12..16].try_into().ok()?),
+                options: NdpOption::from_vec(&data[16..])?,
+            }),
+            135 if data.len() >= 24 => Some(Self::NeighborSolicitation {
+                target: IpV6Addr::new(data[8..24].try_into().ok()?),
+                options: NdpOption::from_vec(&data[24..])?,
+            }),
+            136 if data.len() >= 24 => Some(Self::NeighborAdvertisement {
+                router: data[4] & 0x80 != 0,
+                solicited: data[4] & 0x40 != 0,
+                override_flag: data[4] & 0x20 != 0,
+                target: IpV6Addr::new(data[8..24].try_into().ok()?),
+                options: NdpOption::from_vec(&data[24..])?,
+            }),
+            x => {
+                warn!("Unknown NDP type: {x}");
+                None
+            }
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let (typ, body, options) = match self {
+            Self::RouterSolicitation { options } => (133, vec![0; 4], options),
+            Self::RouterAdvertisement {
+                cur_hop_limit,
+                managed,
+                other,
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+                options,
+            } => (134, {
+                let mut body = vec![
+                    *cur_hop_limit,
+                    (u8::from(*managed) << 7) | (u8::from(*other) << 6),
+                ];
+                body.extend_from_slice(&router_lifetime.to_be_bytes());
+                body.extend_from_slice(&reachable_time.to_be_bytes());
+                body.extend_from_slice(&retrans_timer.to_be_bytes());
+                body
+            }, options),
+            Self::NeighborSolicitation { target, options } => (135, {
+                let mut body = vec![0; 4];
+                body.extend_from_slice(target.as_slice());
+                body
+            }, options),
+            Self::NeighborAdvertisement {
+                router,
+                solicited,
+                override_flag,
+                target,
+                options,
+            } => (136, {
+                let mut body = vec![
+                    (u8::from(*router) << 7)
+                        | (u8::from(*solicited) << 6)
+                        | (u8::from(*override_flag) << 5),
+                    0,
+                    0,
+                    0,
+                ];
+                body.extend_from_slice(target.as_slice());
+                body
+            }, options),
+        };
+        let mut res = Vec::with_capacity(4 + body.len());
+        res.push(typ);
+        res.push(0);
+        res.extend_from_slice(&0u16.to_be_bytes()); // Checksum, set with the IPv6 packet
+        res.extend_from_slice(&body);
+        for option in options {
+            res.extend_from_slice(&option.to_vec());
+        }
+        res
+    }
+
+    pub fn options(&self) -> &[NdpOption] {
+        match self {
+            Self::RouterSolicitation { options }
+            | Self::RouterAdvertisement { options, .. }
+            | Self::NeighborSolicitation { options, .. }
+            | Self::NeighborAdvertisement { options, .. } => options,
+        }
+    }
+
+    /// Link layer address of the sender, if included
+    pub fn source_link_layer_address(&self) -> Option<Mac> {
+        self.options().iter().find_map(|option| match option {
+            NdpOption::SourceLinkLayerAddress(mac) => Some(*mac),
+            _ => None,
+        })
+    }
+
+    /// Link layer address of the target, if included
+    pub fn target_link_layer_address(&self) -> Option<Mac> {
+        self.options().iter().find_map(|option| match option {
+            NdpOption::TargetLinkLayerAddress(mac) => Some(*mac),
+            _ => None,
+        })
+    }
+}
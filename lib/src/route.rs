@@ -2,9 +2,83 @@
 
 use std::{fmt::Display, ops::BitAnd};
 
+/// Prefix mask of an address family, the mask of length `n` keeps the first `n` bits
 pub trait AddrMask<Addr>: BitAnd<Addr, Output = Addr> {
-    type Specifity: Ord;
-    fn specifity(&self) -> Self::Specifity;
+    fn prefix_len(&self) -> u8;
+    fn from_prefix_len(len: u8) -> Self;
+}
+
+/// Whether the bit at `i` (from the most significant) of the address is set
+fn bit<Addr, Mask>(addr: &Addr, i: u8) -> bool
+where
+    Mask: AddrMask<Addr>,
+    Addr: Clone + Eq,
+{
+    Mask::from_prefix_len(i + 1) & addr.clone() != Mask::from_prefix_len(i) & addr.clone()
+}
+
+/// Length of the prefix shared by both addresses, up to `max`
+fn common_prefix_len<Addr, Mask>(a: &Addr, b: &Addr, max: u8) -> u8
+where
+    Mask: AddrMask<Addr>,
+    Addr: Clone + Eq,
+{
+    // Sharing a prefix of length n implies sharing the shorter ones, so it can be bisected
+    let (mut low, mut high) = (0, max);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        let mask = Mask::from_prefix_len(mid);
+        if mask & a.clone() == Mask::from_prefix_len(mid) & b.clone() {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low
+}
+
+/// Where a route was learned from, it ranks routes to the same prefix before their metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteSource {
+    Connected,
+    Static,
+    /// Learned from a peer in another AS
+    Ebgp,
+    Ospf,
+    Rip,
+    /// Learned from a peer in the same AS
+    Ibgp,
+    /// Default route given by a DHCP server
+    Dhcp,
+}
+
+impl RouteSource {
+    /// Administrative distance, lower is preferred
+    pub const fn distance(self) -> u8 {
+        match self {
+            Self::Connected => 0,
+            Self::Static => 1,
+            Self::Ebgp => 20,
+            Self::Ospf => 110,
+            Self::Rip => 120,
+            Self::Ibgp => 200,
+            Self::Dhcp => 254,
+        }
+    }
+}
+
+impl Display for RouteSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connected => write!(f, "connected"),
+            Self::Static => write!(f, "static"),
+            Self::Ebgp => write!(f, "ebgp"),
+            Self::Ospf => write!(f, "ospf"),
+            Self::Rip => write!(f, "rip"),
+            Self::Ibgp => write!(f, "ibgp"),
+            Self::Dhcp => write!(f, "dhcp"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -13,54 +87,229 @@ pub struct RoutingEntry<Addr, AddrMask, Iface> {
     gateway: Addr,
     mask: AddrMask,
     iface: Iface,
+    source: RouteSource,
+    metric: u32,
 }
 
 impl<Addr, AddrMask, Iface> RoutingEntry<Addr, AddrMask, Iface> {
+    /// Static route with no metric
     pub const fn new(destination: Addr, gateway: Addr, mask: AddrMask, iface: Iface) -> Self {
         Self {
             destination,
             gateway,
             mask,
             iface,
+            source: RouteSource::Static,
+            metric: 0,
         }
     }
+
+    pub fn with_source(mut self, source: RouteSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn with_metric(mut self, metric: u32) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub const fn destination(&self) -> &Addr {
+        &self.destination
+    }
+
+    pub const fn gateway(&self) -> &Addr {
+        &self.gateway
+    }
+
+    pub const fn mask(&self) -> &AddrMask {
+        &self.mask
+    }
+
+    pub const fn iface(&self) -> &Iface {
+        &self.iface
+    }
+
+    pub const fn source(&self) -> RouteSource {
+        self.source
+    }
+
+    pub const fn metric(&self) -> u32 {
+        self.metric
+    }
+
+    /// Ranking among the routes to the same prefix, lower is preferred
+    const fn preference(&self) -> (u8, u32) {
+        (self.source.distance(), self.metric)
+    }
 }
 
+/// Node of the Patricia trie, only the prefixes with routes and the branching points are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Node<Addr, Mask, Iface> {
+    prefix: Addr,
+    mask: Mask,
+    /// Sorted by preference, the ones as good as the first are the selected ones
+    routes: Vec<RoutingEntry<Addr, Mask, Iface>>,
+    /// Longer prefixes, by the value of their bit after this prefix
+    children: [Option<Box<Node<Addr, Mask, Iface>>>; 2],
+}
+
+impl<Addr, Mask, Iface> Node<Addr, Mask, Iface> {
+    /// Routes with the best preference, the equal-cost next hops of the prefix
+    fn selected(&self) -> &[RoutingEntry<Addr, Mask, Iface>] {
+        let best = self.routes.first().map(RoutingEntry::preference);
+        let count = self
+            .routes
+            .iter()
+            .take_while(|route| Some(route.preference()) == best)
+            .count();
+        &self.routes[..count]
+    }
+
+    fn add(&mut self, route: RoutingEntry<Addr, Mask, Iface>) {
+        let i = self
+            .routes
+            .partition_point(|other| other.preference() <= route.preference());
+        self.routes.insert(i, route);
+    }
+}
+
+impl<Addr, Mask, Iface> Node<Addr, Mask, Iface>
+where
+    Mask: AddrMask<Addr> + Clone,
+    Addr: Clone + Eq,
+{
+    fn new(prefix: Addr, mask: Mask) -> Self {
+        Self {
+            prefix,
+            mask,
+            routes: Vec::new(),
+            children: [None, None],
+        }
+    }
+
+    fn insert(&mut self, prefix: Addr, mask: Mask, route: RoutingEntry<Addr, Mask, Iface>) {
+        let (len, own_len) = (mask.prefix_len(), self.mask.prefix_len());
+        let common = common_prefix_len::<Addr, Mask>(&self.prefix, &prefix, len.min(own_len));
+        if common < own_len {
+            // The new prefix branches off above this node, which moves down
+            let branch_mask = Mask::from_prefix_len(common);
+            let branch = Self::new(branch_mask.clone() & prefix.clone(), branch_mask);
+            let old = std::mem::replace(self, branch);
+            let side = bit::<Addr, Mask>(&old.prefix, common) as usize;
+            self.children[side] = Some(Box::new(old));
+        }
+        if common == len {
+            self.add(route);
+            return;
+        }
+        let side = bit::<Addr, Mask>(&prefix, common) as usize;
+        match &mut self.children[side] {
+            Some(child) => child.insert(prefix, mask, route),
+            None => {
+                let mut leaf = Self::new(prefix, mask);
+                leaf.add(route);
+                self.children[side] = Some(Box::new(leaf));
+            }
+        }
+    }
+
+    /// Removes the route from the subtree in the slot, returns whether it was found. Nodes left
+    /// without routes are only kept while they branch
+    fn remove(
+        slot: &mut Option<Box<Self>>,
+        prefix: &Addr,
+        len: u8,
+        route: &RoutingEntry<Addr, Mask, Iface>,
+    ) -> bool
+    where
+        Mask: Eq,
+        Iface: Eq,
+    {
+        let Some(node) = slot else {
+            return false;
+        };
+        let own_len = node.mask.prefix_len();
+        if own_len > len || node.mask.clone() & prefix.clone() != node.prefix {
+            return false;
+        }
+        let removed = if own_len == len {
+            let i = node.routes.iter().position(|entry| entry == route);
+            i.map(|i| node.routes.remove(i)).is_some()
+        } else {
+            let side = bit::<Addr, Mask>(prefix, own_len) as usize;
+            Self::remove(&mut node.children[side], prefix, len, route)
+        };
+        if removed && node.routes.is_empty() {
+            match std::mem::take(&mut node.children) {
+                [Some(a), Some(b)] => node.children = [Some(a), Some(b)],
+                [Some(only), None] | [None, Some(only)] => *slot = Some(only),
+                [None, None] => *slot = None,
+            }
+        }
+        removed
+    }
+}
+
+/// Routes indexed by destination prefix in a Patricia trie, lookups take as many steps as the
+/// address has bits at most
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RoutingTable<Addr, Mask, Iface> {
-    data: Vec<RoutingEntry<Addr, Mask, Iface>>,
+    root: Option<Box<Node<Addr, Mask, Iface>>>,
 }
 
 impl<Addr, Mask, Iface> RoutingTable<Addr, Mask, Iface> {
     pub const fn new() -> Self {
-        Self { data: Vec::new() }
+        Self { root: None }
     }
 
     pub fn add_route(&mut self, route: RoutingEntry<Addr, Mask, Iface>)
     where
-        Mask: AddrMask<Addr>,
+        Mask: AddrMask<Addr> + Clone,
+        Addr: Clone + Eq,
     {
-        let i = self
-            .data
-            .binary_search_by_key(&route.mask.specifity(), |entry| entry.mask.specifity())
-            .map_or_else(|i| i, |i| i);
-        self.data.insert(i, route);
+        let prefix = route.mask.clone() & route.destination.clone();
+        let mask = route.mask.clone();
+        match &mut self.root {
+            Some(root) => root.insert(prefix, mask, route),
+            None => {
+                let mut root = Node::new(prefix, mask);
+                root.add(route);
+                self.root = Some(Box::new(root));
+            }
+        }
     }
 
     pub fn remove_route(&mut self, route: &RoutingEntry<Addr, Mask, Iface>)
     where
-        Mask: AddrMask<Addr> + Eq,
-        Addr: Eq,
+        Mask: AddrMask<Addr> + Clone + Eq,
+        Addr: Clone + Eq,
         Iface: Eq,
     {
-        if let Some(i) =
-            self.data
-                .iter()
-                .enumerate()
-                .find_map(|(i, entry)| if entry == route { Some(i) } else { None })
-        {
-            self.data.remove(i);
+        let prefix = route.mask.clone() & route.destination.clone();
+        Node::remove(&mut self.root, &prefix, route.mask.prefix_len(), route);
+    }
+
+    /// Selected routes of the longest prefix containing the address
+    pub fn lookup(&self, addr: &Addr) -> &[RoutingEntry<Addr, Mask, Iface>]
+    where
+        Mask: AddrMask<Addr> + Clone,
+        Addr: Clone + Eq,
+    {
+        let mut best: &[_] = &[];
+        let mut node = self.root.as_deref();
+        while let Some(current) = node {
+            if current.mask.clone() & addr.clone() != current.prefix {
+                break;
+            }
+            if !current.routes.is_empty() {
+                best = current.selected();
+            }
+            let side = bit::<Addr, Mask>(addr, current.mask.prefix_len()) as usize;
+            node = current.children[side].as_deref();
         }
+        best
     }
 
     pub fn get_route(&self, addr: Addr) -> Option<(Addr, Iface)>
@@ -69,25 +318,38 @@ impl<Addr, Mask, Iface> RoutingTable<Addr, Mask, Iface> {
         Addr: Clone + Eq,
         Iface: Clone,
     {
-        self.data
+        self.lookup(&addr)
+            .first()
+            .map(|route| (route.gateway.clone(), route.iface.clone()))
+    }
+
+    /// Equal-cost next hops towards the address
+    pub fn get_routes(&self, addr: Addr) -> Vec<(Addr, Iface)>
+    where
+        Mask: AddrMask<Addr> + Clone,
+        Addr: Clone + Eq,
+        Iface: Clone,
+    {
+        self.lookup(&addr)
             .iter()
-            .rev()
-            .find(
-                |RoutingEntry {
-                     destination: dest,
-                     gateway: _,
-                     mask,
-                     iface: _,
-                 }| (mask.clone() & dest.clone()) == (mask.clone() & addr.clone()),
-            )
-            .map(
-                |RoutingEntry {
-                     destination: _,
-                     gateway,
-                     mask: _,
-                     iface,
-                 }| (gateway.clone(), iface.clone()),
-            )
+            .map(|route| (route.gateway.clone(), route.iface.clone()))
+            .collect()
+    }
+
+    /// Nodes with routes ordered by prefix, shorter prefixes first when one contains the other
+    fn nodes(&self) -> impl Iterator<Item = &Node<Addr, Mask, Iface>> {
+        let mut stack = self.root.as_deref().into_iter().collect::<Vec<_>>();
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev().filter_map(Option::as_deref));
+            Some(node)
+        })
+        .filter(|node| !node.routes.is_empty())
+    }
+
+    /// Routes ordered by prefix, and by preference for the same prefix
+    pub fn iter(&self) -> impl Iterator<Item = &RoutingEntry<Addr, Mask, Iface>> {
+        self.nodes().flat_map(|node| node.routes.iter())
     }
 }
 
@@ -98,18 +360,33 @@ where
     Iface: Display,
 {
     pub fn print(&self) -> prettytable::Table {
-        let mut table = prettytable::table!(["destination", "mask", "gateway", "interface"]);
-        if self.data.is_empty() {
+        let mut table = prettytable::table!([
+            "destination",
+            "mask",
+            "gateway",
+            "interface",
+            "source",
+            "distance",
+            "metric",
+            "selected"
+        ]);
+        if self.root.is_none() {
             table.add_empty_row();
         }
-        for RoutingEntry {
-            destination,
-            gateway,
-            mask,
-            iface,
-        } in self.data.iter()
-        {
-            table.add_row(prettytable::row![destination, mask, gateway, iface]);
+        for node in self.nodes() {
+            let selected = node.selected().len();
+            for (i, route) in node.routes.iter().enumerate() {
+                table.add_row(prettytable::row![
+                    route.destination,
+                    route.mask,
+                    route.gateway,
+                    route.iface,
+                    route.source,
+                    route.source.distance(),
+                    route.metric,
+                    i < selected
+                ]);
+            }
         }
         table
     }
@@ -120,3 +397,192 @@ impl<Addr, AddrMask, Iface> Default for RoutingTable<Addr, AddrMask, Iface> {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::network::{
+        ipv4::addr::{IpV4Addr, IpV4Mask},
+        ipv6::addr::{IpV6Addr, IpV6Mask},
+    };
+
+    use super::{AddrMask, RouteSource, RoutingEntry, RoutingTable};
+
+    type V4Route = RoutingEntry<IpV4Addr, IpV4Mask, u8>;
+
+    fn v4(destination: &str, len: u8, gateway: &str, iface: u8) -> V4Route {
+        RoutingEntry::new(
+            destination.parse().unwrap(),
+            gateway.parse().unwrap(),
+            IpV4Mask::new(len),
+            iface,
+        )
+    }
+
+    fn v6(destination: &str, len: u8, iface: u8) -> RoutingEntry<IpV6Addr, IpV6Mask, u8> {
+        RoutingEntry::new(
+            destination.parse().unwrap(),
+            "::".parse().unwrap(),
+            IpV6Mask::new(len),
+            iface,
+        )
+    }
+
+    fn ifaces<Addr, Mask>(routes: &[RoutingEntry<Addr, Mask, u8>]) -> Vec<u8> {
+        routes.iter().map(|route| *route.iface()).collect()
+    }
+
+    #[test]
+    fn insert_splits_node() {
+        let mut table = RoutingTable::new();
+        table.add_route(v4("10.0.0.0", 24, "1.1.1.1", 0));
+        table.add_route(v4("10.0.1.0", 24, "1.1.1.1", 1));
+
+        // The two prefixes branch off a /23 without routes
+        let root = table.root.as_deref().unwrap();
+        assert_eq!(root.prefix, "10.0.0.0".parse().unwrap());
+        assert_eq!(root.mask.prefix_len(), 23);
+        assert!(root.routes.is_empty());
+        let children = root.children.each_ref().map(|child| {
+            let child = child.as_deref().unwrap();
+            (child.prefix, child.mask.prefix_len())
+        });
+        assert_eq!(
+            children,
+            [
+                ("10.0.0.0".parse().unwrap(), 24),
+                ("10.0.1.0".parse().unwrap(), 24)
+            ]
+        );
+
+        // A prefix containing the root goes above it
+        table.add_route(v4("10.0.0.0", 8, "1.1.1.1", 2));
+        let root = table.root.as_deref().unwrap();
+        assert_eq!(root.mask.prefix_len(), 8);
+        assert_eq!(ifaces(&root.routes), vec![2]);
+    }
+
+    #[test]
+    fn remove_collapses_nodes() {
+        let mut table = RoutingTable::new();
+        let routes = [
+            v4("10.0.0.0", 24, "1.1.1.1", 0),
+            v4("10.0.1.0", 24, "1.1.1.1", 1),
+            v4("10.0.1.128", 25, "1.1.1.1", 2),
+        ];
+        for route in routes.iter() {
+            table.add_route(*route);
+        }
+
+        // The /24 left without routes is replaced by the /25 under it
+        table.remove_route(&routes[1]);
+        let mut only = table.clone();
+        only.remove_route(&routes[0]);
+        let root = only.root.as_deref().unwrap();
+        assert_eq!(root.mask.prefix_len(), 25);
+        assert_eq!(root.children, [None, None]);
+
+        // The branching /23 goes away with one of its sides
+        table.remove_route(&routes[2]);
+        let root = table.root.as_deref().unwrap();
+        assert_eq!(root.mask.prefix_len(), 24);
+        assert_eq!(ifaces(&root.routes), vec![0]);
+        assert_eq!(root.children, [None, None]);
+
+        // Removing a missing route changes nothing
+        table.remove_route(&routes[1]);
+        assert_eq!(table.iter().count(), 1);
+        table.remove_route(&routes[0]);
+        assert!(table.root.is_none());
+    }
+
+    #[test]
+    fn lookup_longest_prefix_v4() {
+        let mut table = RoutingTable::new();
+        table.add_route(v4("0.0.0.0", 0, "1.1.1.1", 0));
+        table.add_route(v4("10.0.0.0", 8, "1.1.1.1", 1));
+        table.add_route(v4("10.1.0.0", 16, "1.1.1.1", 2));
+        table.add_route(v4("10.1.2.3", 32, "1.1.1.1", 3));
+
+        let lookup = |addr: &str| ifaces(table.lookup(&addr.parse().unwrap()));
+        assert_eq!(lookup("192.168.0.1"), vec![0]);
+        assert_eq!(lookup("10.2.0.1"), vec![1]);
+        assert_eq!(lookup("10.1.2.4"), vec![2]);
+        assert_eq!(lookup("10.1.2.3"), vec![3]);
+
+        table.remove_route(&v4("0.0.0.0", 0, "1.1.1.1", 0));
+        let lookup = |addr: &str| ifaces(table.lookup(&addr.parse().unwrap()));
+        assert_eq!(lookup("192.168.0.1"), Vec::<u8>::new());
+        assert_eq!(lookup("10.1.2.3"), vec![3]);
+    }
+
+    #[test]
+    fn lookup_longest_prefix_v6() {
+        let mut table = RoutingTable::new();
+        table.add_route(v6("::", 0, 0));
+        table.add_route(v6("2001:db8::", 32, 1));
+        table.add_route(v6("2001:db8:1::", 48, 2));
+        table.add_route(v6("2001:db8:1::1", 128, 3));
+
+        let lookup = |addr: &str| ifaces(table.lookup(&addr.parse().unwrap()));
+        assert_eq!(lookup("fe80::1"), vec![0]);
+        assert_eq!(lookup("2001:db8:2::1"), vec![1]);
+        assert_eq!(lookup("2001:db8:1::2"), vec![2]);
+        assert_eq!(lookup("2001:db8:1::1"), vec![3]);
+    }
+
+    #[test]
+    fn selects_by_distance_then_metric() {
+        let mut table = RoutingTable::new();
+        let rip = v4("10.0.0.0", 8, "1.1.1.1", 0).with_source(RouteSource::Rip);
+        let ospf = v4("10.0.0.0", 8, "2.2.2.2", 1)
+            .with_source(RouteSource::Ospf)
+            .with_metric(20);
+        let ecmp = v4("10.0.0.0", 8, "3.3.3.3", 2)
+            .with_source(RouteSource::Ospf)
+            .with_metric(20);
+        let worse = v4("10.0.0.0", 8, "4.4.4.4", 3)
+            .with_source(RouteSource::Ospf)
+            .with_metric(30);
+        for route in [rip, worse, ospf, ecmp] {
+            table.add_route(route);
+        }
+
+        // Equal-cost routes keep their insertion order
+        let addr = "10.0.0.1".parse().unwrap();
+        assert_eq!(ifaces(table.lookup(&addr)), vec![1, 2]);
+        assert_eq!(
+            table.get_routes(addr),
+            vec![
+                ("2.2.2.2".parse().unwrap(), 1),
+                ("3.3.3.3".parse().unwrap(), 2)
+            ]
+        );
+        let order = table.iter().map(|route| *route.iface()).collect::<Vec<_>>();
+        assert_eq!(order, vec![1, 2, 3, 0]);
+
+        let static_route = v4("10.0.0.0", 8, "5.5.5.5", 4).with_source(RouteSource::Static);
+        table.add_route(static_route);
+        assert_eq!(table.get_route(addr), Some(("5.5.5.5".parse().unwrap(), 4)));
+        table.remove_route(&static_route);
+        table.remove_route(&ospf);
+        assert_eq!(ifaces(table.lookup(&addr)), vec![2]);
+        assert!(table.iter().any(|route| *route == rip));
+    }
+
+    #[test]
+    fn iterates_by_prefix() {
+        let mut table = RoutingTable::new();
+        for (destination, len, iface) in [
+            ("192.168.1.0", 24, 0),
+            ("10.0.0.0", 8, 1),
+            ("0.0.0.0", 0, 2),
+            ("10.128.0.0", 9, 3),
+            ("10.0.0.0", 16, 4),
+            ("192.168.0.0", 16, 5),
+        ] {
+            table.add_route(v4(destination, len, "1.1.1.1", iface));
+        }
+        let order = table.iter().map(|route| *route.iface()).collect::<Vec<_>>();
+        assert_eq!(order, vec![2, 1, 4, 3, 5, 0]);
+    }
+}
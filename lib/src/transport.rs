@@ -1,2 +1,5 @@
 pub mod icmp;
+pub mod ospf;
+pub mod tcp;
 pub mod udp;
+pub mod vrrp;
@@ -1,7 +1,7 @@
 use std::{collections::HashMap, sync::Arc};
 
 use either::Either;
-use flume::{Receiver, RecvError, SendError, Sender};
+use flume::{Receiver, RecvError, Sender};
 use tokio::task::JoinSet;
 use tracing::{trace, warn};
 
@@ -10,28 +10,46 @@ use crate::{
         NetworkLayerId, NetworkTransportMessage, ProcessMessage, TransportLayerId,
         TransportLevelProcess,
     },
-    network::ipv4::addr::IpV4Addr,
+    network::{
+        ipv4::{addr::IpV4Addr, packet::Ipv4Packet, protocol::ProtocolType},
+        ipv6::addr::IpV6Addr,
+    },
 };
 
-use self::packet::{IcmpPacket, TimeExceeded};
+use self::{
+    packet::{DestinationUnreachable, IcmpPacket, TimeExceeded},
+    packet_v6::Icmpv6Packet,
+};
 
 pub mod packet;
+pub mod packet_v6;
 
 type Duplex<Tx, Rx> = (Sender<Tx>, Arc<Receiver<Rx>>);
 
+/// (id, seq, destination)
+type EchoRequest<Addr> = (u16, u16, Addr);
+/// (id, seq, source, ttl)
+type EchoReply<Addr> = (u16, u16, Addr, u8);
+/// (source, error)
+type Unreachable<Addr> = (Addr, DestinationUnreachable);
+type EchoResult<Addr> = Result<EchoReply<Addr>, Unreachable<Addr>>;
+
 #[derive(Debug, Clone)]
 pub struct IcmpApi {
-    echo_ip_v4: Duplex<(u16, u16, IpV4Addr), Receiver<(u16, u16, IpV4Addr, u8)>>,
+    echo_ip_v4: Duplex<(u16, u16, IpV4Addr), Receiver<EchoResult<IpV4Addr>>>,
+    echo_ip_v6: Duplex<EchoRequest<IpV6Addr>, Receiver<EchoReply<IpV6Addr>>>,
     handler_ttl_ip_v4: Duplex<(), Receiver<(IpV4Addr, Vec<u8>)>>,
+    handler_unreachable_ip_v4: Duplex<(), Receiver<Unreachable<IpV4Addr>>>,
 }
 
 impl IcmpApi {
+    /// Sends an echo request, resolves to the reply or to the destination unreachable error
     pub async fn echo_ip_v4(
         &self,
         id: u16,
         seq: u16,
         ip: IpV4Addr,
-    ) -> Option<(u16, u16, IpV4Addr, u8)> {
+    ) -> Option<EchoResult<IpV4Addr>> {
         self.echo_ip_v4
             .0
             .send_async((id, seq, ip))
@@ -51,43 +69,90 @@ impl IcmpApi {
             .ok()
     }
 
+    pub async fn echo_ip_v6(&self, id: u16, seq: u16, ip: IpV6Addr) -> Option<EchoReply<IpV6Addr>> {
+        self.echo_ip_v6
+            .0
+            .send_async((id, seq, ip))
+            .await
+            .map_err(|e| warn!("Echo send err: {e}"))
+            .ok()?;
+        let rx = self
+            .echo_ip_v6
+            .1
+            .recv_async()
+            .await
+            .map_err(|e| warn!("Echo recv reciever err: {e}"))
+            .ok()?;
+        rx.recv_async()
+            .await
+            .map_err(|e| warn!("Echo recv err: {e}"))
+            .ok()
+    }
+
     pub async fn get_ttl_handler(&self) -> Option<Receiver<(IpV4Addr, Vec<u8>)>> {
         self.handler_ttl_ip_v4.0.send_async(()).await.ok()?;
         self.handler_ttl_ip_v4.1.recv_async().await.ok()
     }
+
+    pub async fn get_unreachable_handler(&self) -> Option<Receiver<Unreachable<IpV4Addr>>> {
+        self.handler_unreachable_ip_v4.0.send_async(()).await.ok()?;
+        self.handler_unreachable_ip_v4.1.recv_async().await.ok()
+    }
 }
 
 pub struct IcmpProcess {
-    echo_ip_v4: Duplex<Receiver<(u16, u16, IpV4Addr, u8)>, (u16, u16, IpV4Addr)>,
-    echo_data_ip_v4: HashMap<(u16, u16, IpV4Addr), Sender<(u16, u16, IpV4Addr, u8)>>,
+    echo_ip_v4: Duplex<Receiver<EchoResult<IpV4Addr>>, (u16, u16, IpV4Addr)>,
+    echo_data_ip_v4: HashMap<(u16, u16, IpV4Addr), Sender<EchoResult<IpV4Addr>>>,
+    echo_ip_v6: Duplex<Receiver<EchoReply<IpV6Addr>>, EchoRequest<IpV6Addr>>,
+    echo_data_ip_v6: HashMap<EchoRequest<IpV6Addr>, Sender<EchoReply<IpV6Addr>>>,
     get_ttl_handler_ip_v4: Duplex<Receiver<(IpV4Addr, Vec<u8>)>, ()>,
     ttl_handler_ip_v4: Option<Sender<(IpV4Addr, Vec<u8>)>>,
+    get_unreachable_handler_ip_v4: Duplex<Receiver<Unreachable<IpV4Addr>>, ()>,
+    unreachable_handler_ip_v4: Option<Sender<Unreachable<IpV4Addr>>>,
 }
 
 impl IcmpProcess {
     pub fn new() -> (Self, IcmpApi) {
         let (echo_ip_v4_internal_tx, echo_ip_v4_external_rx) = flume::unbounded();
         let (echo_ip_v4_external_tx, echo_ip_v4_internal_rx) = flume::unbounded();
+        let (echo_ip_v6_internal_tx, echo_ip_v6_external_rx) = flume::unbounded();
+        let (echo_ip_v6_external_tx, echo_ip_v6_internal_rx) = flume::unbounded();
         let (get_ttl_handler_ip_v4_internal_tx, get_ttl_handler_ip_v4_external_rx) =
             flume::unbounded();
         let (get_ttl_handler_ip_v4_external_tx, get_ttl_handler_ip_v4_internal_rx) =
             flume::unbounded();
+        let (get_unreachable_handler_ip_v4_internal_tx, get_unreachable_handler_ip_v4_external_rx) =
+            flume::unbounded();
+        let (get_unreachable_handler_ip_v4_external_tx, get_unreachable_handler_ip_v4_internal_rx) =
+            flume::unbounded();
         (
             Self {
                 echo_ip_v4: (echo_ip_v4_internal_tx, Arc::new(echo_ip_v4_internal_rx)),
                 echo_data_ip_v4: HashMap::new(),
+                echo_ip_v6: (echo_ip_v6_internal_tx, Arc::new(echo_ip_v6_internal_rx)),
+                echo_data_ip_v6: HashMap::new(),
                 get_ttl_handler_ip_v4: (
                     get_ttl_handler_ip_v4_internal_tx,
                     Arc::new(get_ttl_handler_ip_v4_internal_rx),
                 ),
                 ttl_handler_ip_v4: None,
+                get_unreachable_handler_ip_v4: (
+                    get_unreachable_handler_ip_v4_internal_tx,
+                    Arc::new(get_unreachable_handler_ip_v4_internal_rx),
+                ),
+                unreachable_handler_ip_v4: None,
             },
             IcmpApi {
                 echo_ip_v4: (echo_ip_v4_external_tx, Arc::new(echo_ip_v4_external_rx)),
+                echo_ip_v6: (echo_ip_v6_external_tx, Arc::new(echo_ip_v6_external_rx)),
                 handler_ttl_ip_v4: (
                     get_ttl_handler_ip_v4_external_tx,
                     Arc::new(get_ttl_handler_ip_v4_external_rx),
                 ),
+                handler_unreachable_ip_v4: (
+                    get_unreachable_handler_ip_v4_external_tx,
+                    Arc::new(get_unreachable_handler_ip_v4_external_rx),
+                ),
             },
         )
     }
@@ -95,7 +160,9 @@ impl IcmpProcess {
 
 pub enum ExtraMessage {
     EchoIpV4(Result<(u16, u16, IpV4Addr), RecvError>),
+    EchoIpV6(Result<EchoRequest<IpV6Addr>, RecvError>),
     SetTtlHandler(Result<(), RecvError>),
+    SetUnreachableHandler(Result<(), RecvError>),
 }
 
 #[async_trait::async_trait]
@@ -129,7 +196,8 @@ impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMes
                         }
                         IcmpPacket::EchoReply { id, seq } => {
                             if let Some(tx) = self.echo_data_ip_v4.remove(&(id, seq, addr)) {
-                                let _ = tx.send_async((id, seq, addr, ttl.unwrap_or(255))).await;
+                                let _ =
+                                    tx.send_async(Ok((id, seq, addr, ttl.unwrap_or(255)))).await;
                             }
                         }
                         IcmpPacket::TimeExceeded(t) => match t {
@@ -141,10 +209,68 @@ impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMes
                                     }
                                 }
                             }
+                            TimeExceeded::FragmentReassembly { data } => {
+                                trace!(data = ?data, "Fragment reassembly time exceeded: source {addr}");
+                            }
                         },
+                        IcmpPacket::DestinationUnreachable(d) => {
+                            trace!(error = ?d, "Destination unreachable: source {addr}");
+                            // Echo requests are matched by the quoted header and id, seq
+                            if let Some(quoted) = Ipv4Packet::from_vec_truncated(d.data())
+                                .filter(|quoted| quoted.header.protocol == ProtocolType::ICMP)
+                            {
+                                if let Some(IcmpPacket::EchoRequest { id, seq }) =
+                                    IcmpPacket::from_vec(&quoted.payload)
+                                {
+                                    let key = (id, seq, quoted.header.destination);
+                                    if let Some(tx) = self.echo_data_ip_v4.remove(&key) {
+                                        let _ = tx.send_async(Err((addr, d.clone()))).await;
+                                    }
+                                }
+                            }
+                            if let Some(h) = self.unreachable_handler_ip_v4.as_ref().cloned() {
+                                if h.send_async((addr, d)).await.is_err() {
+                                    self.unreachable_handler_ip_v4 = None
+                                }
+                            }
+                        }
                     }
                 }
             }
+            NetworkTransportMessage::IPv6(addr, hop_limit, payload) => {
+                if let Some(msg) = Icmpv6Packet::from_vec(&payload) {
+                    match msg {
+                        Icmpv6Packet::EchoRequest { id, seq } => {
+                            let _ = down_sender[&down_id]
+                                .send_async(ProcessMessage::Message(
+                                    TransportLayerId::Icmp,
+                                    NetworkTransportMessage::IPv6(
+                                        addr,
+                                        None,
+                                        Icmpv6Packet::EchoReply { id, seq }.to_vec(),
+                                    ),
+                                ))
+                                .await;
+                        }
+                        Icmpv6Packet::EchoReply { id, seq } => {
+                            if let Some(tx) = self.echo_data_ip_v6.remove(&(id, seq, addr)) {
+                                let _ = tx
+                                    .send_async((id, seq, addr, hop_limit.unwrap_or(255)))
+                                    .await;
+                            }
+                        }
+                        Icmpv6Packet::TimeExceeded(t) => match t {
+                            TimeExceeded::TtlTransit { data } => {
+                                trace!(data = ?data, "Hop limit exceeded: source {addr} (hop_limit={hop_limit:?})");
+                            }
+                            TimeExceeded::FragmentReassembly { data } => {
+                                trace!(data = ?data, "Fragment reassembly time exceeded: source {addr}");
+                            }
+                        },
+                    }
+                }
+            }
+            _ => {}
         }
     }
     async fn setup(
@@ -161,10 +287,16 @@ impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMes
     ) {
         let rx = self.echo_ip_v4.1.clone();
         join_set.spawn(async move { Either::Right(ExtraMessage::EchoIpV4(rx.recv_async().await)) });
+        let rx = self.echo_ip_v6.1.clone();
+        join_set.spawn(async move { Either::Right(ExtraMessage::EchoIpV6(rx.recv_async().await)) });
         let rx = self.get_ttl_handler_ip_v4.1.clone();
         join_set.spawn(
             async move { Either::Right(ExtraMessage::SetTtlHandler(rx.recv_async().await)) },
         );
+        let rx = self.get_unreachable_handler_ip_v4.1.clone();
+        join_set.spawn(async move {
+            Either::Right(ExtraMessage::SetUnreachableHandler(rx.recv_async().await))
+        });
     }
     type Extra = ExtraMessage;
     async fn on_extra_message(
@@ -199,6 +331,21 @@ impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMes
 
                 Err(RecvError::Disconnected) => warn!("Handler set ttl handler ip v4 disconnected"),
             },
+            ExtraMessage::SetUnreachableHandler(msg) => match msg {
+                Ok(()) => {
+                    let (tx, rx) = flume::unbounded();
+                    self.unreachable_handler_ip_v4 = Some(tx);
+                    let _ = self.get_unreachable_handler_ip_v4.0.send_async(rx).await;
+
+                    let rx = self.get_unreachable_handler_ip_v4.1.clone();
+                    join_set.spawn(async move {
+                        Either::Right(ExtraMessage::SetUnreachableHandler(rx.recv_async().await))
+                    });
+                }
+                Err(RecvError::Disconnected) => {
+                    warn!("Handler set unreachable handler ip v4 disconnected")
+                }
+            },
             ExtraMessage::EchoIpV4(msg) => match msg {
                 Ok(msg) => {
                     let (tx, rx) = flume::bounded(1);
@@ -227,6 +374,34 @@ impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMes
                 }
                 Err(RecvError::Disconnected) => warn!("Handler echo ip v4 disconnected"),
             },
+            ExtraMessage::EchoIpV6(msg) => match msg {
+                Ok(msg) => {
+                    let (tx, rx) = flume::bounded(1);
+                    trace!(msg = ?msg, "Adding echo sender");
+                    self.echo_data_ip_v6.insert(msg, tx);
+                    let _ = self.echo_ip_v6.0.send_async(rx).await;
+
+                    if let Some(sender) = down_sender.get(&NetworkLayerId::Ipv6) {
+                        let (id, seq, addr) = msg;
+                        let _ = sender
+                            .send_async(ProcessMessage::Message(
+                                TransportLayerId::Icmp,
+                                NetworkTransportMessage::IPv6(
+                                    addr,
+                                    None,
+                                    Icmpv6Packet::EchoRequest { id, seq }.to_vec(),
+                                ),
+                            ))
+                            .await;
+                    }
+
+                    let rx = self.echo_ip_v6.1.clone();
+                    join_set.spawn(async move {
+                        Either::Right(ExtraMessage::EchoIpV6(rx.recv_async().await))
+                    });
+                }
+                Err(RecvError::Disconnected) => warn!("Handler echo ip v6 disconnected"),
+            },
         }
     }
 }
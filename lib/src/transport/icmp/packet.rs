@@ -6,8 +6,8 @@ pub enum IcmpPacket {
     /// 0
     EchoReply { id: u16, seq: u16 },
 
-    // /// 3
-    // DestinationUnreachable(u8), // TODO
+    /// 3
+    DestinationUnreachable(DestinationUnreachable),
     // /// 4
     // SourceQuench,
 
@@ -20,6 +20,55 @@ pub enum IcmpPacket {
     TimeExceeded(TimeExceeded),
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestinationUnreachable {
+    /// 0 Destination network unreachable
+    NetUnreachable {
+        // IP header and first 8 bytes
+        data: Vec<u8>,
+    },
+    /// 1 Destination host unreachable
+    HostUnreachable {
+        // IP header and first 8 bytes
+        data: Vec<u8>,
+    },
+    /// 2 Destination protocol unreachable
+    ProtocolUnreachable {
+        // IP header and first 8 bytes
+        data: Vec<u8>,
+    },
+    /// 3 Destination port unreachable
+    PortUnreachable {
+        // IP header and first 8 bytes
+        data: Vec<u8>,
+    },
+    /// 4 Fragmentation required, and DF flag set
+    FragmentationNeeded {
+        next_hop_mtu: u16,
+        // IP header and first 8 bytes
+        data: Vec<u8>,
+    },
+    /// 13 Communication administratively prohibited, by a filter
+    AdministrativelyProhibited {
+        // IP header and first 8 bytes
+        data: Vec<u8>,
+    },
+}
+
+impl DestinationUnreachable {
+    /// IP header and first 8 bytes of the packet that couldn't be delivered
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Self::NetUnreachable { data }
+            | Self::HostUnreachable { data }
+            | Self::ProtocolUnreachable { data }
+            | Self::PortUnreachable { data }
+            | Self::FragmentationNeeded { data, .. }
+            | Self::AdministrativelyProhibited { data } => data,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TimeExceeded {
     /// 0 TTL exceeded in transit
@@ -27,6 +76,11 @@ pub enum TimeExceeded {
         // IP header and first 8 bytes
         data: Vec<u8>,
     },
+    /// 1 Fragment reassembly time exceeded
+    FragmentReassembly {
+        // IP header and first 8 bytes
+        data: Vec<u8>,
+    },
 }
 
 impl IcmpPacket {
@@ -42,6 +96,43 @@ impl IcmpPacket {
                 id: u16::from_be_bytes(data[4..6].try_into().ok()?),
                 seq: u16::from_be_bytes(data[6..8].try_into().ok()?),
             }),
+            3 if data.len() >= 8 => match code {
+                0 => Some(Self::DestinationUnreachable(
+                    DestinationUnreachable::NetUnreachable {
+                        data: data[8..].to_vec(),
+                    },
+                )),
+                1 => Some(Self::DestinationUnreachable(
+                    DestinationUnreachable::HostUnreachable {
+                        data: data[8..].to_vec(),
+                    },
+                )),
+                2 => Some(Self::DestinationUnreachable(
+                    DestinationUnreachable::ProtocolUnreachable {
+                        data: data[8..].to_vec(),
+                    },
+                )),
+                3 => Some(Self::DestinationUnreachable(
+                    DestinationUnreachable::PortUnreachable {
+                        data: data[8..].to_vec(),
+                    },
+                )),
+                4 if data.len() >= 8 => Some(Self::DestinationUnreachable(
+                    DestinationUnreachable::FragmentationNeeded {
+                        next_hop_mtu: u16::from_be_bytes(data[6..8].try_into().ok()?),
+                        data: data[8..].to_vec(),
+                    },
+                )),
+                13 => Some(Self::DestinationUnreachable(
+                    DestinationUnreachable::AdministrativelyProhibited {
+                        data: data[8..].to_vec(),
+                    },
+                )),
+                x => {
+                    warn!("Unknown ICMP destination unreachable code: {x}");
+                    None
+                }
+            },
             8 if data.len() == 8 => Some(Self::EchoRequest {
                 id: u16::from_be_bytes(data[4..6].try_into().ok()?),
                 seq: u16::from_be_bytes(data[6..8].try_into().ok()?),
@@ -50,6 +141,9 @@ impl IcmpPacket {
                 0 => Some(Self::TimeExceeded(TimeExceeded::TtlTransit {
                     data: data[4..].to_vec(),
                 })),
+                1 => Some(Self::TimeExceeded(TimeExceeded::FragmentReassembly {
+                    data: data[4..].to_vec(),
+                })),
                 x => {
                     warn!("Unknown ICMP time exceeded code: {x}");
                     None
@@ -68,12 +162,28 @@ impl IcmpPacket {
                 let ([a, b], [c, d]) = (id.to_be_bytes(), seq.to_be_bytes());
                 vec![a, b, c, d]
             }),
+            Self::DestinationUnreachable(d) => match d {
+                DestinationUnreachable::NetUnreachable { data } => (3, 0, unused(data)),
+                DestinationUnreachable::HostUnreachable { data } => (3, 1, unused(data)),
+                DestinationUnreachable::ProtocolUnreachable { data } => (3, 2, unused(data)),
+                DestinationUnreachable::PortUnreachable { data } => (3, 3, unused(data)),
+                DestinationUnreachable::FragmentationNeeded { next_hop_mtu, data } => (3, 4, {
+                    let mut extra = vec![0; 2];
+                    extra.extend_from_slice(&next_hop_mtu.to_be_bytes());
+                    extra.extend_from_slice(data);
+                    extra
+                }),
+                DestinationUnreachable::AdministrativelyProhibited { data } => {
+                    (3, 13, unused(data))
+                }
+            },
             Self::EchoRequest { id, seq } => (8, 0, {
                 let ([a, b], [c, d]) = (id.to_be_bytes(), seq.to_be_bytes());
                 vec![a, b, c, d]
             }),
             Self::TimeExceeded(t) => match t {
                 TimeExceeded::TtlTransit { data } => (11, 0, data.clone()),
+                TimeExceeded::FragmentReassembly { data } => (11, 1, data.clone()),
             },
         };
         let mut res = Vec::with_capacity(4 + extra.len());
@@ -84,3 +194,62 @@ impl IcmpPacket {
         res
     }
 }
+
+/// Prefixes the data with the 4 unused bytes of the header
+fn unused(data: &[u8]) -> Vec<u8> {
+    let mut extra = vec![0; 4];
+    extra.extend_from_slice(data);
+    extra
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        corruption::corruptions,
+        network::ipv4::{
+            addr::IpV4Addr,
+            packet::{Ecn, Flags, IpV4Header, Ipv4Packet},
+            protocol::ProtocolType,
+        },
+    };
+
+    use super::{DestinationUnreachable, IcmpPacket};
+
+    #[test]
+    fn survives_corrupted_packets() {
+        let quoted = Ipv4Packet::new(
+            IpV4Header::new(
+                0,
+                Ecn::NotECT,
+                8,
+                1,
+                Flags::empty(),
+                0,
+                64,
+                ProtocolType::UDP,
+                IpV4Addr::new([10, 0, 0, 2]),
+                IpV4Addr::new([10, 0, 0, 1]),
+                vec![],
+            ),
+            vec![0xa5; 8],
+        );
+        let packets = [
+            IcmpPacket::EchoRequest { id: 7, seq: 1 },
+            IcmpPacket::DestinationUnreachable(DestinationUnreachable::FragmentationNeeded {
+                next_hop_mtu: 576,
+                data: quoted.to_vec(),
+            }),
+        ];
+        for packet in packets {
+            let data = packet.to_vec();
+            assert_eq!(IcmpPacket::from_vec(&data), Some(packet));
+            for data in corruptions(&data) {
+                if let Some(IcmpPacket::DestinationUnreachable(unreachable)) =
+                    IcmpPacket::from_vec(&data)
+                {
+                    Ipv4Packet::from_vec_truncated(unreachable.data());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use tracing::warn;
+
+use super::packet::TimeExceeded;
+
+/// ICMPv6 message, represents type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Icmpv6Packet {
+    /// 3
+    TimeExceeded(TimeExceeded),
+
+    /// 128
+    EchoRequest { id: u16, seq: u16 },
+    /// 129
+    EchoReply { id: u16, seq: u16 },
+}
+
+impl Icmpv6Packet {
+    pub fn from_vec(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        let typ = data[0];
+        let code = data[1];
+        // The checksum covers the IPv6 pseudo-header, it's checked by the IPv6 process
+        match typ {
+            3 => match code {
+                0 if data.len() >= 8 => Some(Self::TimeExceeded(TimeExceeded::TtlTransit {
+                    data: data[8..].to_vec(),
+                })),
+                1 if data.len() >= 8 => Some(Self::TimeExceeded(TimeExceeded::FragmentReassembly {
+                    data: data[8..].to_vec(),
+                })),
+                x => {
+                    warn!("Unknown ICMPv6 time exceeded code: {x}");
+                    None
+                }
+            },
+            128 if data.len() >= 8 => Some(Self::EchoRequest {
+                id: u16::from_be_bytes(data[4..6].try_into().ok()?),
+                seq: u16::from_be_bytes(data[6..8].try_into().ok()?),
+            }),
+            129 if data.len() >= 8 => Some(Self::EchoReply {
+                id: u16::from_be_bytes(data[4..6].try_into().ok()?),
+                seq: u16::from_be_bytes(data[6..8].try_into().ok()?),
+            }),
+            x => {
+                warn!("Unknown ICMPv6 type: {x}");
+                None
+            }
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let (typ, code, extra) = match self {
+            Self::TimeExceeded(t) => match t {
+                TimeExceeded::TtlTransit { data } => (3, 0, {
+                    let mut extra = vec![0; 4];
+                    extra.extend_from_slice(data);
+                    extra
+                }),
+                TimeExceeded::FragmentReassembly { data } => (3, 1, {
+                    let mut extra = vec![0; 4];
+                    extra.extend_from_slice(data);
+                    extra
+                }),
+            },
+            Self::EchoRequest { id, seq } => (128, 0, {
+                let ([a, b], [c, d]) = (id.to_be_bytes(), seq.to_be_bytes());
+                vec![a, b, c, d]
+            }),
+            Self::EchoReply { id, seq } => (129, 0, {
+                let ([a, b], [c, d]) = (id.to_be_bytes(), seq.to_be_bytes());
+                vec![a, b, c, d]
+            }),
+        };
+        let mut res = Vec::with_capacity(4 + extra.len());
+        res.push(typ);
+        res.push(code);
+        res.extend_from_slice(&0u16.to_be_bytes()); // Checksum, set by the IPv6 process
+        res.extend_from_slice(&extra);
+        res
+    }
+}
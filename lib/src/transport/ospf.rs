@@ -0,0 +1,188 @@
+use std::{collections::HashMap, sync::Arc};
+
+use either::Either;
+use flume::{Receiver, RecvError, Sender};
+use tokio::{sync::oneshot, task::JoinSet};
+use tracing::{trace, warn};
+
+use crate::{
+    chassis::{
+        LinkLayerId, NetworkLayerId, NetworkTransportMessage, ProcessMessage, TransportLayerId,
+        TransportLevelProcess,
+    },
+    network::ipv4::addr::IpV4Addr,
+};
+
+type Duplex<Tx, Rx> = (Sender<Tx>, Arc<Receiver<Rx>>);
+
+/// (iface, neighbor or group, payload)
+type Data = (LinkLayerId, IpV4Addr, Vec<u8>);
+
+/// OSPF packets are only exchanged between neighbors
+const TTL: u8 = 1;
+
+/// Endpoint of IP protocol 89, it only carries the packets, the protocol itself is run by the OSPF
+/// router that owns the socket
+pub struct OspfSocket {
+    duplex: Duplex<Data, Data>,
+}
+
+impl OspfSocket {
+    /// Sends the packet out of the interface to the neighbor or multicast group
+    pub async fn send(&self, iface: LinkLayerId, dest: IpV4Addr, payload: Vec<u8>) {
+        if self
+            .duplex
+            .0
+            .send_async((iface, dest, payload))
+            .await
+            .is_err()
+        {
+            warn!("OSPF Packet not sent");
+        }
+    }
+
+    /// Next packet as (iface, source, payload)
+    pub async fn recv(&self) -> Result<Data, RecvError> {
+        self.duplex.1.recv_async().await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OspfHandle {
+    get_socket: Sender<oneshot::Sender<OspfSocket>>,
+}
+
+impl OspfHandle {
+    /// Takes over the protocol, the previous socket stops receiving packets
+    pub async fn get_socket(&self) -> Result<OspfSocket, ()> {
+        let (tx, rx) = oneshot::channel();
+        self.get_socket.send_async(tx).await.map_err(|_| ())?;
+        rx.await.map_err(|_| ())
+    }
+}
+
+pub struct OspfProcess {
+    get_socket: Arc<Receiver<oneshot::Sender<OspfSocket>>>,
+    /// Packets are dropped while no router owns the socket
+    socket: Option<Duplex<Data, Data>>,
+}
+
+impl OspfProcess {
+    pub fn new() -> (Self, OspfHandle) {
+        let (tx, rx) = flume::unbounded();
+        (
+            Self {
+                get_socket: Arc::new(rx),
+                socket: None,
+            },
+            OspfHandle { get_socket: tx },
+        )
+    }
+}
+
+pub enum ExtraMessage {
+    GetSocket(Result<oneshot::Sender<OspfSocket>, RecvError>),
+    Send(Arc<Receiver<Data>>, Result<Data, RecvError>),
+}
+
+#[async_trait::async_trait]
+impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMessage>
+    for OspfProcess
+{
+    async fn on_down_message(
+        &mut self,
+        msg: NetworkTransportMessage,
+        _: NetworkLayerId,
+        _: &HashMap<
+            NetworkLayerId,
+            Sender<ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportMessage>>,
+        >,
+    ) {
+        match msg {
+            NetworkTransportMessage::IPv4Link(iface, source, _, payload) => match &self.socket {
+                Some((tx, _)) => {
+                    if tx.send_async((iface, source, payload)).await.is_err() {
+                        self.socket = None;
+                    }
+                }
+                None => trace!("OSPF: No router, dropped packet from {source} on {iface}"),
+            },
+            msg => warn!("OSPF: Unexpected message {msg:?}"),
+        }
+    }
+
+    async fn setup(
+        &mut self,
+        join_set: &mut JoinSet<
+            Either<
+                Result<
+                    ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportMessage>,
+                    RecvError,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        let rx = self.get_socket.clone();
+        join_set
+            .spawn(async move { Either::Right(ExtraMessage::GetSocket(rx.recv_async().await)) });
+    }
+
+    type Extra = ExtraMessage;
+    async fn on_extra_message(
+        &mut self,
+        msg: Self::Extra,
+        down_sender: &HashMap<
+            NetworkLayerId,
+            Sender<ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportMessage>>,
+        >,
+        join_set: &mut JoinSet<
+            Either<
+                Result<
+                    ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportMessage>,
+                    RecvError,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        match msg {
+            ExtraMessage::GetSocket(Ok(reply)) => {
+                let (internal_tx, external_rx) = flume::unbounded();
+                let (external_tx, internal_rx) = flume::unbounded();
+                let internal_rx = Arc::new(internal_rx);
+                self.socket = Some((internal_tx, internal_rx.clone()));
+                let _ = reply.send(OspfSocket {
+                    duplex: (external_tx, Arc::new(external_rx)),
+                });
+                join_set.spawn(async move {
+                    let msg = internal_rx.recv_async().await;
+                    Either::Right(ExtraMessage::Send(internal_rx, msg))
+                });
+                let rx = self.get_socket.clone();
+                join_set.spawn(async move {
+                    Either::Right(ExtraMessage::GetSocket(rx.recv_async().await))
+                });
+            }
+            ExtraMessage::GetSocket(Err(RecvError::Disconnected)) => {
+                warn!("OSPF: Handle disconnected")
+            }
+            ExtraMessage::Send(rx, Ok((iface, dest, payload))) => {
+                if let Some(sender) = down_sender.get(&NetworkLayerId::Ipv4) {
+                    let _ = sender
+                        .send_async(ProcessMessage::Message(
+                            TransportLayerId::Ospf,
+                            NetworkTransportMessage::IPv4Link(iface, dest, Some(TTL), payload),
+                        ))
+                        .await;
+                }
+                join_set.spawn(async move {
+                    let msg = rx.recv_async().await;
+                    Either::Right(ExtraMessage::Send(rx, msg))
+                });
+            }
+            // The router owning the socket stopped
+            ExtraMessage::Send(_, Err(RecvError::Disconnected)) => {}
+        }
+    }
+}
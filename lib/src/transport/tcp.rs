@@ -0,0 +1,552 @@
+use std::{
+    collections::HashMap, fmt::Debug, future::Future, hash::Hash, pin::Pin, sync::Arc,
+    time::Instant,
+};
+
+use either::Either;
+use flume::{Receiver, RecvError, Sender};
+use futures::FutureExt;
+use tokio::{sync::oneshot, task::JoinSet};
+use tracing::{trace, warn};
+
+use crate::{
+    chassis::{
+        NetworkLayerId, NetworkTransportMessage, ProcessMessage, TransportLayerId,
+        TransportLevelProcess,
+    },
+    network::{ipv4::addr::IpV4Addr, ipv6::addr::IpV6Addr},
+};
+
+use self::{
+    connection::{Connection, Event, State},
+    packet::{Flags, TcpPacket},
+};
+
+pub mod connection;
+pub mod packet;
+
+/// Interval of the retransmission and TIME-WAIT timers
+const TICK: std::time::Duration = std::time::Duration::from_millis(100);
+const EPHEMERAL_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+type BoxedFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// (remote addr, remote port, local port)
+type ConnectionId<Addr> = (Addr, u16, u16);
+type CommandChannel<Addr> = (
+    Sender<(ConnectionId<Addr>, StreamCommand)>,
+    Arc<Receiver<(ConnectionId<Addr>, StreamCommand)>>,
+);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamCommand {
+    Write(Vec<u8>),
+    Close,
+}
+
+/// Connected socket, closed when dropped
+pub struct Stream<Addr: Copy> {
+    id: ConnectionId<Addr>,
+    commands: Sender<(ConnectionId<Addr>, StreamCommand)>,
+    data: Receiver<Vec<u8>>,
+}
+
+impl<Addr: Copy> Stream<Addr> {
+    pub const fn peer(&self) -> (Addr, u16) {
+        (self.id.0, self.id.1)
+    }
+
+    pub const fn local_port(&self) -> u16 {
+        self.id.2
+    }
+
+    /// Queues data to be sent
+    pub async fn write(&self, data: Vec<u8>) -> Result<(), ()> {
+        self.commands
+            .send_async((self.id, StreamCommand::Write(data)))
+            .await
+            .map_err(|_| ())
+    }
+
+    /// Next received data, `None` once the peer closed or the connection was reset
+    pub async fn read(&self) -> Option<Vec<u8>> {
+        self.data.recv_async().await.ok()
+    }
+
+    /// Sends a FIN after the queued data, data can still be read until the peer closes
+    pub async fn close(&self) {
+        let _ = self
+            .commands
+            .send_async((self.id, StreamCommand::Close))
+            .await;
+    }
+}
+
+impl<Addr: Copy> Drop for Stream<Addr> {
+    fn drop(&mut self) {
+        let _ = self.commands.send((self.id, StreamCommand::Close));
+    }
+}
+
+pub struct Listener<Addr: Copy> {
+    port: u16,
+    accept: Receiver<Stream<Addr>>,
+}
+
+impl<Addr: Copy> Listener<Addr> {
+    pub const fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Next established connection
+    pub async fn accept(&self) -> Result<Stream<Addr>, RecvError> {
+        self.accept.recv_async().await
+    }
+}
+
+type ListenRequest<Addr> = (u16, oneshot::Sender<Result<Listener<Addr>, ()>>);
+type ConnectRequest<Addr> = ((Addr, u16), oneshot::Sender<Result<Stream<Addr>, ()>>);
+
+#[derive(Clone)]
+pub struct TcpHandleGeneric<Addr: Copy> {
+    listen: Sender<ListenRequest<Addr>>,
+    connect: Sender<ConnectRequest<Addr>>,
+}
+
+impl<Addr: Copy + Send> TcpHandleGeneric<Addr> {
+    /// Fails if the port is already listening
+    pub async fn listen(&self, port: u16) -> Result<Listener<Addr>, ()> {
+        let (tx, rx) = oneshot::channel();
+        self.listen.send_async((port, tx)).await.map_err(|_| ())?;
+        rx.await.map_err(|_| ())?
+    }
+
+    /// Resolves once the handshake completes, fails if it's reset or times out
+    pub async fn connect(&self, dest: (Addr, u16)) -> Result<Stream<Addr>, ()> {
+        let (tx, rx) = oneshot::channel();
+        self.connect.send_async((dest, tx)).await.map_err(|_| ())?;
+        rx.await.map_err(|_| ())?
+    }
+}
+
+/// Stream waiting for the handshake to be handed to its owner
+enum Pending<Addr: Copy> {
+    Connect(oneshot::Sender<Result<Stream<Addr>, ()>>, Stream<Addr>),
+    Accept(Sender<Stream<Addr>>, Stream<Addr>),
+}
+
+struct Socket<Addr: Copy> {
+    connection: Connection,
+    pending: Option<Pending<Addr>>,
+    data: Option<Sender<Vec<u8>>>,
+}
+
+pub struct TcpProcessGeneric<Addr: Copy> {
+    listeners: HashMap<u16, Sender<Stream<Addr>>>,
+    sockets: HashMap<ConnectionId<Addr>, Socket<Addr>>,
+    listen: Arc<Receiver<ListenRequest<Addr>>>,
+    connect: Arc<Receiver<ConnectRequest<Addr>>>,
+    commands: CommandChannel<Addr>,
+    next_port: u16,
+    next_iss: u32,
+    /// Segments to send down
+    outgoing: Vec<(Addr, Vec<u8>)>,
+}
+
+impl<Addr: Copy> TcpProcessGeneric<Addr> {
+    pub fn new() -> (Self, TcpHandleGeneric<Addr>) {
+        let (listen_tx, listen_rx) = flume::unbounded();
+        let (connect_tx, connect_rx) = flume::unbounded();
+        let (commands_tx, commands_rx) = flume::unbounded();
+        (
+            Self {
+                listeners: HashMap::new(),
+                sockets: HashMap::new(),
+                listen: Arc::new(listen_rx),
+                connect: Arc::new(connect_rx),
+                commands: (commands_tx, Arc::new(commands_rx)),
+                next_port: *EPHEMERAL_PORTS.start(),
+                next_iss: 0,
+                outgoing: Vec::new(),
+            },
+            TcpHandleGeneric {
+                listen: listen_tx,
+                connect: connect_tx,
+            },
+        )
+    }
+}
+
+pub enum ExtraMessageGeneric<Addr: Copy> {
+    Listen(Result<ListenRequest<Addr>, RecvError>),
+    Connect(Result<ConnectRequest<Addr>, RecvError>),
+    Command(Result<(ConnectionId<Addr>, StreamCommand), RecvError>),
+    Tick,
+}
+
+impl<Addr> TcpProcessGeneric<Addr>
+where
+    Addr: Copy + Eq + Hash + Debug + Send + Sync + 'static,
+{
+    fn recv_listen(&self) -> BoxedFuture<ExtraMessageGeneric<Addr>> {
+        let rx = self.listen.clone();
+        async move { ExtraMessageGeneric::Listen(rx.recv_async().await) }.boxed()
+    }
+
+    fn recv_connect(&self) -> BoxedFuture<ExtraMessageGeneric<Addr>> {
+        let rx = self.connect.clone();
+        async move { ExtraMessageGeneric::Connect(rx.recv_async().await) }.boxed()
+    }
+
+    fn recv_command(&self) -> BoxedFuture<ExtraMessageGeneric<Addr>> {
+        let rx = self.commands.1.clone();
+        async move { ExtraMessageGeneric::Command(rx.recv_async().await) }.boxed()
+    }
+
+    fn tick() -> BoxedFuture<ExtraMessageGeneric<Addr>> {
+        async move {
+            tokio::time::sleep(TICK).await;
+            ExtraMessageGeneric::Tick
+        }
+        .boxed()
+    }
+
+    fn setup(&self) -> Vec<BoxedFuture<ExtraMessageGeneric<Addr>>> {
+        vec![
+            self.recv_listen(),
+            self.recv_connect(),
+            self.recv_command(),
+            Self::tick(),
+        ]
+    }
+
+    pub fn take_outgoing(&mut self) -> Vec<(Addr, Vec<u8>)> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Initial sequence number, spread so reused connections don't overlap
+    fn new_iss(&mut self) -> u32 {
+        self.next_iss = self.next_iss.wrapping_add(64000);
+        self.next_iss
+    }
+
+    fn new_port(&mut self, (addr, port): (Addr, u16)) -> Option<u16> {
+        for _ in EPHEMERAL_PORTS {
+            let candidate = self.next_port;
+            self.next_port = if candidate == *EPHEMERAL_PORTS.end() {
+                *EPHEMERAL_PORTS.start()
+            } else {
+                candidate + 1
+            };
+            if !self.listeners.contains_key(&candidate)
+                && !self.sockets.contains_key(&(addr, port, candidate))
+            {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    fn new_socket(
+        &self,
+        id: ConnectionId<Addr>,
+        connection: Connection,
+    ) -> (Socket<Addr>, Stream<Addr>) {
+        let (tx, rx) = flume::unbounded();
+        (
+            Socket {
+                connection,
+                pending: None,
+                data: Some(tx),
+            },
+            Stream {
+                id,
+                commands: self.commands.0.clone(),
+                data: rx,
+            },
+        )
+    }
+
+    /// Sends the queued segments and hands the events to the socket owner
+    fn flush(&mut self, id: ConnectionId<Addr>) {
+        let Some(socket) = self.sockets.get_mut(&id) else {
+            return;
+        };
+        for event in socket.connection.take_events() {
+            trace!("TCP {id:?}: {event:?}");
+            match event {
+                Event::Established => {
+                    let delivered = match socket.pending.take() {
+                        Some(Pending::Connect(tx, stream)) => tx.send(Ok(stream)).is_ok(),
+                        Some(Pending::Accept(tx, stream)) => tx.send(stream).is_ok(),
+                        None => true,
+                    };
+                    if !delivered {
+                        socket.connection.abort();
+                    }
+                }
+                Event::Data(data) => {
+                    if let Some(tx) = &socket.data {
+                        let _ = tx.send(data);
+                    }
+                }
+                Event::Eof | Event::Reset => socket.data = None,
+            }
+        }
+        self.outgoing.extend(
+            socket
+                .connection
+                .take_outgoing()
+                .into_iter()
+                .map(|segment| (id.0, segment.to_vec())),
+        );
+        if socket.connection.state() == State::Closed {
+            trace!("TCP {id:?}: Closed");
+            if let Some(Pending::Connect(tx, _)) = self.sockets.remove(&id).and_then(|s| s.pending)
+            {
+                let _ = tx.send(Err(()));
+            }
+        }
+    }
+
+    fn on_segment(&mut self, addr: Addr, payload: Vec<u8>) {
+        let Some(segment) = TcpPacket::from_vec(&payload) else {
+            return;
+        };
+        trace!("TCP received from {addr:?}: {segment:?}");
+        let id = (addr, segment.source_port, segment.destination_port);
+        let now = Instant::now();
+        if let Some(socket) = self.sockets.get_mut(&id) {
+            socket.connection.on_segment(&segment, now);
+            self.flush(id);
+            return;
+        }
+        if segment.flags & (Flags::SYN | Flags::ACK | Flags::RST) == Flags::SYN {
+            if let Some(listener) = self.listeners.get(&segment.destination_port).cloned() {
+                if !listener.is_disconnected() {
+                    let iss = self.new_iss();
+                    let connection = Connection::accept(id.2, &segment, iss, now);
+                    let (mut socket, stream) = self.new_socket(id, connection);
+                    socket.pending = Some(Pending::Accept(listener, stream));
+                    self.sockets.insert(id, socket);
+                    self.flush(id);
+                    return;
+                }
+                self.listeners.remove(&segment.destination_port);
+            }
+        }
+        if let Some(rst) = Connection::reset_for(&segment) {
+            self.outgoing.push((addr, rst.to_vec()));
+        }
+    }
+
+    fn on_extra(
+        &mut self,
+        msg: ExtraMessageGeneric<Addr>,
+    ) -> Vec<BoxedFuture<ExtraMessageGeneric<Addr>>> {
+        let now = Instant::now();
+        match msg {
+            ExtraMessageGeneric::Listen(Ok((port, tx))) => {
+                let res = if self
+                    .listeners
+                    .get(&port)
+                    .is_some_and(|listener| !listener.is_disconnected())
+                {
+                    warn!("TCP port {port} already listening");
+                    Err(())
+                } else {
+                    let (accept_tx, accept_rx) = flume::unbounded();
+                    self.listeners.insert(port, accept_tx);
+                    Ok(Listener {
+                        port,
+                        accept: accept_rx,
+                    })
+                };
+                let _ = tx.send(res);
+                vec![self.recv_listen()]
+            }
+            ExtraMessageGeneric::Connect(Ok((dest, tx))) => {
+                let Some(port) = self.new_port(dest) else {
+                    warn!("TCP: No free ports to connect to {dest:?}");
+                    let _ = tx.send(Err(()));
+                    return vec![self.recv_connect()];
+                };
+                let id = (dest.0, dest.1, port);
+                let iss = self.new_iss();
+                let (mut socket, stream) =
+                    self.new_socket(id, Connection::connect(port, dest.1, iss, now));
+                socket.pending = Some(Pending::Connect(tx, stream));
+                self.sockets.insert(id, socket);
+                self.flush(id);
+                vec![self.recv_connect()]
+            }
+            ExtraMessageGeneric::Command(Ok((id, command))) => {
+                if let Some(socket) = self.sockets.get_mut(&id) {
+                    match command {
+                        StreamCommand::Write(data) => {
+                            if !socket.connection.send(&data, now) {
+                                warn!(
+                                    "TCP {id:?}: Can't send in state {:?}",
+                                    socket.connection.state()
+                                );
+                            }
+                        }
+                        StreamCommand::Close => socket.connection.close(now),
+                    }
+                    self.flush(id);
+                }
+                vec![self.recv_command()]
+            }
+            ExtraMessageGeneric::Tick => {
+                let ids: Vec<_> = self.sockets.keys().copied().collect();
+                for id in ids {
+                    if let Some(socket) = self.sockets.get_mut(&id) {
+                        socket.connection.on_tick(now);
+                    }
+                    self.flush(id);
+                }
+                vec![Self::tick()]
+            }
+            ExtraMessageGeneric::Listen(Err(RecvError::Disconnected))
+            | ExtraMessageGeneric::Connect(Err(RecvError::Disconnected)) => {
+                warn!("TCP handle disconnected");
+                vec![]
+            }
+            // The process holds a sender
+            ExtraMessageGeneric::Command(Err(RecvError::Disconnected)) => vec![],
+        }
+    }
+}
+
+pub struct TcpProcess {
+    ip_v4: TcpProcessGeneric<IpV4Addr>,
+    ip_v6: TcpProcessGeneric<IpV6Addr>,
+}
+
+impl TcpProcess {
+    pub const fn new(
+        ip_v4: TcpProcessGeneric<IpV4Addr>,
+        ip_v6: TcpProcessGeneric<IpV6Addr>,
+    ) -> Self {
+        Self { ip_v4, ip_v6 }
+    }
+
+    async fn send_down(
+        &mut self,
+        down_sender: &HashMap<
+            NetworkLayerId,
+            Sender<ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportMessage>>,
+        >,
+    ) {
+        let messages = self
+            .ip_v4
+            .take_outgoing()
+            .into_iter()
+            .map(|(addr, payload)| {
+                (
+                    NetworkLayerId::Ipv4,
+                    NetworkTransportMessage::IPv4(addr, None, payload),
+                )
+            })
+            .chain(
+                self.ip_v6
+                    .take_outgoing()
+                    .into_iter()
+                    .map(|(addr, payload)| {
+                        (
+                            NetworkLayerId::Ipv6,
+                            NetworkTransportMessage::IPv6(addr, None, payload),
+                        )
+                    }),
+            );
+        for (id, msg) in messages {
+            if let Some(tx) = down_sender.get(&id) {
+                let _ = tx
+                    .send_async(ProcessMessage::Message(TransportLayerId::Tcp, msg))
+                    .await;
+            }
+        }
+    }
+}
+
+pub enum ExtraMessage {
+    IPv4(ExtraMessageGeneric<IpV4Addr>),
+    IPv6(ExtraMessageGeneric<IpV6Addr>),
+}
+
+#[async_trait::async_trait]
+impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMessage>
+    for TcpProcess
+{
+    async fn on_down_message(
+        &mut self,
+        msg: NetworkTransportMessage,
+        down_id: NetworkLayerId,
+        down_sender: &HashMap<
+            NetworkLayerId,
+            Sender<ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportMessage>>,
+        >,
+    ) {
+        match (down_id, msg) {
+            (NetworkLayerId::Ipv4, NetworkTransportMessage::IPv4(addr, _, payload)) => {
+                self.ip_v4.on_segment(addr, payload)
+            }
+            (NetworkLayerId::Ipv6, NetworkTransportMessage::IPv6(addr, _, payload)) => {
+                self.ip_v6.on_segment(addr, payload)
+            }
+            (id, _) => warn!("TCP: Unexpected message from {id:?}"),
+        }
+        self.send_down(down_sender).await;
+    }
+    async fn setup(
+        &mut self,
+        join_set: &mut JoinSet<
+            Either<
+                Result<
+                    ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportMessage>,
+                    RecvError,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        for fut in self.ip_v4.setup() {
+            join_set.spawn(async move { Either::Right(ExtraMessage::IPv4(fut.await)) });
+        }
+        for fut in self.ip_v6.setup() {
+            join_set.spawn(async move { Either::Right(ExtraMessage::IPv6(fut.await)) });
+        }
+    }
+    type Extra = ExtraMessage;
+    async fn on_extra_message(
+        &mut self,
+        msg: Self::Extra,
+        down_sender: &HashMap<
+            NetworkLayerId,
+            Sender<ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportMessage>>,
+        >,
+        join_set: &mut JoinSet<
+            Either<
+                Result<
+                    ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportMessage>,
+                    RecvError,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        match msg {
+            ExtraMessage::IPv4(msg) => {
+                for fut in self.ip_v4.on_extra(msg) {
+                    join_set.spawn(async move { Either::Right(ExtraMessage::IPv4(fut.await)) });
+                }
+            }
+            ExtraMessage::IPv6(msg) => {
+                for fut in self.ip_v6.on_extra(msg) {
+                    join_set.spawn(async move { Either::Right(ExtraMessage::IPv6(fut.await)) });
+                }
+            }
+        }
+        self.send_down(down_sender).await;
+    }
+}
@@ -0,0 +1,704 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::packet::{Flags, TcpPacket};
+
+/// Window advertised to the peer, received data is handed to the socket right away
+const RECEIVE_WINDOW: u16 = u16::MAX;
+/// MSS advertised to the peer
+pub const MSS: u16 = 1460;
+/// MSS assumed when the peer doesn't advertise one
+const DEFAULT_PEER_MSS: u16 = 536;
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MAX_RTO: Duration = Duration::from_secs(60);
+/// Retransmissions of a segment before the connection is dropped
+const MAX_RETRANSMISSIONS: u8 = 8;
+/// Twice the maximum segment lifetime, short as packets don't linger in the simulator
+const TIME_WAIT: Duration = Duration::from_secs(2);
+
+/// `a < b` in sequence space
+const fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// `a <= b` in sequence space
+const fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// Things the socket owner has to be told about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Established,
+    Data(Vec<u8>),
+    /// The peer won't send more data
+    Eof,
+    /// The connection was reset or timed out
+    Reset,
+}
+
+/// Transmission control block of a connection, segments to send and events are queued
+/// until taken
+#[derive(Debug)]
+pub struct Connection {
+    state: State,
+    local_port: u16,
+    remote_port: u16,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    snd_wl1: u32,
+    snd_wl2: u32,
+    /// Sequence number of the first byte of `send_buffer`
+    buffer_seq: u32,
+    /// Data not yet acknowledged
+    send_buffer: VecDeque<u8>,
+    close_requested: bool,
+    /// Sequence number of our FIN while it's sent
+    fin_seq: Option<u32>,
+    mss: u16,
+
+    rcv_nxt: u32,
+    /// Segments received ahead of `rcv_nxt`
+    out_of_order: Vec<(u32, Vec<u8>)>,
+
+    rto: Duration,
+    retransmit_at: Option<Instant>,
+    retransmissions: u8,
+    /// A zero window probe is sent, the peer answering it doesn't count as a retransmission
+    probing: bool,
+    time_wait_until: Option<Instant>,
+
+    outgoing: Vec<TcpPacket>,
+    events: Vec<Event>,
+}
+
+impl Connection {
+    fn new(local_port: u16, remote_port: u16, iss: u32, state: State) -> Self {
+        Self {
+            state,
+            local_port,
+            remote_port,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            buffer_seq: iss.wrapping_add(1),
+            send_buffer: VecDeque::new(),
+            close_requested: false,
+            fin_seq: None,
+            mss: DEFAULT_PEER_MSS,
+            rcv_nxt: 0,
+            out_of_order: Vec::new(),
+            rto: INITIAL_RTO,
+            retransmit_at: None,
+            retransmissions: 0,
+            probing: false,
+            time_wait_until: None,
+            outgoing: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Active open, sends a SYN
+    pub fn connect(local_port: u16, remote_port: u16, iss: u32, now: Instant) -> Self {
+        let mut res = Self::new(local_port, remote_port, iss, State::SynSent);
+        res.send_syn(now);
+        res
+    }
+
+    /// Passive open from a SYN received by a listener, sends a SYN ACK
+    pub fn accept(local_port: u16, syn: &TcpPacket, iss: u32, now: Instant) -> Self {
+        let mut res = Self::new(local_port, syn.source_port, iss, State::SynReceived);
+        res.rcv_nxt = syn.seq.wrapping_add(1);
+        res.mss = syn.mss.unwrap_or(DEFAULT_PEER_MSS).min(MSS);
+        res.update_window(syn);
+        res.send_syn(now);
+        res
+    }
+
+    /// Reset answering a segment that doesn't belong to any connection
+    pub fn reset_for(segment: &TcpPacket) -> Option<TcpPacket> {
+        if segment.flags.contains(Flags::RST) {
+            None
+        } else if segment.flags.contains(Flags::ACK) {
+            Some(TcpPacket::new(
+                segment.destination_port,
+                segment.source_port,
+                segment.ack,
+                0,
+                Flags::RST,
+                0,
+                vec![],
+            ))
+        } else {
+            Some(TcpPacket::new(
+                segment.destination_port,
+                segment.source_port,
+                0,
+                segment.seq.wrapping_add(segment.len()),
+                Flags::RST | Flags::ACK,
+                0,
+                vec![],
+            ))
+        }
+    }
+
+    pub const fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn take_outgoing(&mut self) -> Vec<TcpPacket> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn segment(&self, flags: Flags, seq: u32, payload: Vec<u8>) -> TcpPacket {
+        let ack = if flags.contains(Flags::ACK) {
+            self.rcv_nxt
+        } else {
+            0
+        };
+        let mut res = TcpPacket::new(
+            self.local_port,
+            self.remote_port,
+            seq,
+            ack,
+            flags,
+            RECEIVE_WINDOW,
+            payload,
+        );
+        if flags.contains(Flags::SYN) {
+            res.mss = Some(MSS);
+        }
+        res
+    }
+
+    fn send_syn(&mut self, now: Instant) {
+        let flags = match self.state {
+            State::SynReceived => Flags::SYN | Flags::ACK,
+            _ => Flags::SYN,
+        };
+        self.outgoing.push(self.segment(flags, self.iss, vec![]));
+        self.retransmit_at = Some(now + self.rto);
+    }
+
+    fn send_ack(&mut self) {
+        self.outgoing
+            .push(self.segment(Flags::ACK, self.snd_nxt, vec![]));
+    }
+
+    fn reset(&mut self) {
+        self.state = State::Closed;
+        self.retransmit_at = None;
+        self.events.push(Event::Reset);
+    }
+
+    fn enter_time_wait(&mut self, now: Instant) {
+        self.state = State::TimeWait;
+        self.retransmit_at = None;
+        self.time_wait_until = Some(now + TIME_WAIT);
+    }
+
+    /// Queues data to be sent, returns false if the connection can't send anymore
+    pub fn send(&mut self, data: &[u8], now: Instant) -> bool {
+        if self.close_requested
+            || !matches!(
+                self.state,
+                State::SynSent | State::SynReceived | State::Established | State::CloseWait
+            )
+        {
+            return false;
+        }
+        self.send_buffer.extend(data);
+        self.transmit(now);
+        true
+    }
+
+    /// Sends a FIN once all the queued data is sent
+    pub fn close(&mut self, now: Instant) {
+        match self.state {
+            State::SynSent => self.state = State::Closed,
+            State::SynReceived | State::Established | State::CloseWait => {
+                self.close_requested = true;
+                self.transmit(now);
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops the connection, telling the peer if it's synchronized
+    pub fn abort(&mut self) {
+        if !matches!(self.state, State::SynSent | State::TimeWait | State::Closed) {
+            self.outgoing
+                .push(self.segment(Flags::RST, self.snd_nxt, vec![]));
+        }
+        self.state = State::Closed;
+        self.retransmit_at = None;
+    }
+
+    /// Sends as much data as the peer window allows, and the FIN if requested
+    fn transmit(&mut self, now: Instant) {
+        if !matches!(
+            self.state,
+            State::Established
+                | State::CloseWait
+                | State::FinWait1
+                | State::Closing
+                | State::LastAck
+        ) {
+            return;
+        }
+        let window_end = self.snd_una.wrapping_add(self.snd_wnd);
+        loop {
+            let offset = self.snd_nxt.wrapping_sub(self.buffer_seq) as usize;
+            let available = self.send_buffer.len().saturating_sub(offset);
+            let usable = (window_end.wrapping_sub(self.snd_nxt) as i32).max(0) as usize;
+            let len = available.min(self.mss as usize).min(usable);
+            if len == 0 {
+                break;
+            }
+            let payload = self
+                .send_buffer
+                .range(offset..(offset + len))
+                .copied()
+                .collect();
+            self.outgoing
+                .push(self.segment(Flags::ACK | Flags::PSH, self.snd_nxt, payload));
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+        }
+        let all_sent =
+            self.snd_nxt.wrapping_sub(self.buffer_seq) as usize >= self.send_buffer.len();
+        if self.close_requested && self.fin_seq.is_none() && all_sent {
+            self.outgoing
+                .push(self.segment(Flags::FIN | Flags::ACK, self.snd_nxt, vec![]));
+            self.fin_seq = Some(self.snd_nxt);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.state = match self.state {
+                State::Established => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                s => s,
+            };
+        }
+        // Data waiting on a closed window also needs the timer to probe it
+        if self.retransmit_at.is_none() && (self.snd_nxt != self.snd_una || !all_sent) {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+
+    fn update_window(&mut self, segment: &TcpPacket) {
+        self.snd_wnd = segment.window as u32;
+        self.snd_wl1 = segment.seq;
+        self.snd_wl2 = segment.ack;
+    }
+
+    fn process_ack(&mut self, ack: u32, now: Instant) {
+        if !(seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt)) {
+            return;
+        }
+        let acked = ack.wrapping_sub(self.buffer_seq) as i32;
+        if acked > 0 {
+            let acked = (acked as usize).min(self.send_buffer.len());
+            self.send_buffer.drain(..acked);
+            self.buffer_seq = self.buffer_seq.wrapping_add(acked as u32);
+        }
+        self.snd_una = ack;
+        self.retransmissions = 0;
+        self.probing = false;
+        self.rto = INITIAL_RTO;
+        self.retransmit_at = (self.snd_una != self.snd_nxt).then(|| now + self.rto);
+    }
+
+    /// Delivers the data in order, keeping what arrives ahead of time
+    fn receive(&mut self, seq: u32, payload: &[u8]) {
+        let skip = self.rcv_nxt.wrapping_sub(seq) as i32;
+        if skip > 0 {
+            if skip as usize >= payload.len() {
+                return;
+            }
+            return self.receive(self.rcv_nxt, &payload[(skip as usize)..]);
+        }
+        if seq != self.rcv_nxt {
+            self.out_of_order.push((seq, payload.to_vec()));
+            return;
+        }
+        let mut data = payload.to_vec();
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(payload.len() as u32);
+        while let Some(i) = self
+            .out_of_order
+            .iter()
+            .position(|(seq, _)| seq_le(*seq, self.rcv_nxt))
+        {
+            let (seq, payload) = self.out_of_order.swap_remove(i);
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if skip < payload.len() {
+                data.extend_from_slice(&payload[skip..]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add((payload.len() - skip) as u32);
+            }
+        }
+        self.events.push(Event::Data(data));
+    }
+
+    fn on_segment_syn_sent(&mut self, segment: &TcpPacket, now: Instant) {
+        let ack = segment.flags.contains(Flags::ACK);
+        if ack && (seq_le(segment.ack, self.iss) || seq_lt(self.snd_nxt, segment.ack)) {
+            if let Some(rst) = Self::reset_for(segment) {
+                self.outgoing.push(rst);
+            }
+            return;
+        }
+        if segment.flags.contains(Flags::RST) {
+            if ack {
+                self.reset();
+            }
+            return;
+        }
+        if !segment.flags.contains(Flags::SYN) {
+            return;
+        }
+        self.rcv_nxt = segment.seq.wrapping_add(1);
+        self.mss = segment.mss.unwrap_or(DEFAULT_PEER_MSS).min(MSS);
+        if ack {
+            self.process_ack(segment.ack, now);
+            self.update_window(segment);
+            self.state = State::Established;
+            self.events.push(Event::Established);
+            self.send_ack();
+            self.transmit(now);
+        } else {
+            // Simultaneous open
+            self.state = State::SynReceived;
+            self.send_syn(now);
+        }
+    }
+
+    pub fn on_segment(&mut self, segment: &TcpPacket, now: Instant) {
+        match self.state {
+            State::Closed => return,
+            State::SynSent => return self.on_segment_syn_sent(segment, now),
+            _ => {}
+        }
+
+        let flags = segment.flags;
+        let len = segment.len();
+        let window_end = self.rcv_nxt.wrapping_add(RECEIVE_WINDOW as u32);
+        let in_window = |seq| seq_le(self.rcv_nxt, seq) && seq_lt(seq, window_end);
+        let acceptable = if len == 0 {
+            segment.seq == self.rcv_nxt || in_window(segment.seq)
+        } else {
+            in_window(segment.seq) || in_window(segment.seq.wrapping_add(len - 1))
+        };
+        if !acceptable {
+            if self.state == State::SynReceived && flags.contains(Flags::SYN) {
+                // Our SYN ACK was lost
+                self.send_syn(now);
+            } else if !flags.contains(Flags::RST) {
+                self.send_ack();
+            }
+            return;
+        }
+        if flags.contains(Flags::RST) {
+            self.reset();
+            return;
+        }
+        if flags.contains(Flags::SYN) {
+            self.outgoing
+                .push(self.segment(Flags::RST, self.snd_nxt, vec![]));
+            self.reset();
+            return;
+        }
+        if !flags.contains(Flags::ACK) {
+            return;
+        }
+
+        if self.state == State::SynReceived {
+            if !(seq_lt(self.snd_una, segment.ack) && seq_le(segment.ack, self.snd_nxt)) {
+                if let Some(rst) = Self::reset_for(segment) {
+                    self.outgoing.push(rst);
+                }
+                return;
+            }
+            self.state = State::Established;
+            self.events.push(Event::Established);
+            self.update_window(segment);
+        }
+        if seq_lt(self.snd_nxt, segment.ack) {
+            // Acknowledges something not sent yet
+            self.send_ack();
+            return;
+        }
+        self.process_ack(segment.ack, now);
+        if seq_lt(self.snd_wl1, segment.seq)
+            || (self.snd_wl1 == segment.seq && seq_le(self.snd_wl2, segment.ack))
+        {
+            self.update_window(segment);
+        }
+        if self.probing && segment.ack == self.snd_una {
+            // The peer is still there, the probe is only refused by its window
+            self.retransmissions = 0;
+            if self.snd_wnd > 0 {
+                // The probed byte is sent again with the rest
+                self.probing = false;
+                self.snd_nxt = self.snd_una;
+            }
+        }
+        let fin_acked = self.fin_seq.is_some_and(|fin| seq_lt(fin, self.snd_una));
+        match self.state {
+            State::FinWait1 if fin_acked => self.state = State::FinWait2,
+            State::Closing if fin_acked => self.enter_time_wait(now),
+            State::LastAck if fin_acked => {
+                self.state = State::Closed;
+                self.retransmit_at = None;
+                return;
+            }
+            _ => {}
+        }
+
+        let mut need_ack = false;
+        if !segment.payload.is_empty()
+            && matches!(
+                self.state,
+                State::Established | State::FinWait1 | State::FinWait2
+            )
+        {
+            self.receive(segment.seq, &segment.payload);
+            need_ack = true;
+        }
+        if flags.contains(Flags::FIN) {
+            need_ack = true;
+            let fin = segment.seq.wrapping_add(segment.payload.len() as u32);
+            match self.state {
+                State::Established | State::FinWait1 | State::FinWait2 if fin == self.rcv_nxt => {
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                    self.out_of_order.clear();
+                    self.events.push(Event::Eof);
+                    match self.state {
+                        State::Established => self.state = State::CloseWait,
+                        State::FinWait1 if !fin_acked => self.state = State::Closing,
+                        _ => self.enter_time_wait(now),
+                    }
+                }
+                // Our ACK of the FIN was lost
+                State::TimeWait => self.enter_time_wait(now),
+                _ => {}
+            }
+        }
+        if need_ack {
+            self.send_ack();
+        }
+        self.transmit(now);
+    }
+
+    /// Handles the retransmission and TIME-WAIT timers
+    pub fn on_tick(&mut self, now: Instant) {
+        if let Some(until) = self.time_wait_until {
+            if now >= until {
+                self.state = State::Closed;
+            }
+            return;
+        }
+        if self.retransmit_at.is_none_or(|at| now < at) {
+            return;
+        }
+        self.retransmissions += 1;
+        if self.retransmissions > MAX_RETRANSMISSIONS {
+            self.abort();
+            self.events.push(Event::Reset);
+            return;
+        }
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        self.retransmit_at = None;
+        match self.state {
+            State::SynSent | State::SynReceived => self.send_syn(now),
+            _ => {
+                // Go back N, resending everything not acknowledged
+                self.snd_nxt = self.snd_una;
+                if self.fin_seq.is_some_and(|fin| seq_le(self.snd_una, fin)) {
+                    self.fin_seq = None;
+                }
+                self.transmit(now);
+                let offset = self.snd_nxt.wrapping_sub(self.buffer_seq) as usize;
+                if self.snd_nxt == self.snd_una && offset < self.send_buffer.len() {
+                    // Zero window probe
+                    let byte = self.send_buffer[offset];
+                    self.outgoing
+                        .push(self.segment(Flags::ACK, self.snd_nxt, vec![byte]));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    self.probing = true;
+                }
+                self.retransmit_at = Some(now + self.rto);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::transport::tcp::packet::{Flags, TcpPacket};
+
+    use super::{Connection, Event, State, MAX_RETRANSMISSIONS, MAX_RTO, TIME_WAIT};
+
+    /// Delivers the segments queued by `from` to `to`, returns how many there were
+    fn deliver(from: &mut Connection, to: &mut Connection, now: Instant) -> usize {
+        let segments = from.take_outgoing();
+        for segment in segments.iter() {
+            to.on_segment(segment, now);
+        }
+        segments.len()
+    }
+
+    /// Client on port 1000 with ISS 100 connected to a server on port 80 with ISS 5000
+    fn open(now: Instant) -> (Connection, Connection) {
+        let mut client = Connection::connect(1000, 80, 100, now);
+        let syn = client.take_outgoing().remove(0);
+        let mut server = Connection::accept(80, &syn, 5000, now);
+        deliver(&mut server, &mut client, now);
+        deliver(&mut client, &mut server, now);
+        (client, server)
+    }
+
+    #[test]
+    fn handshake() {
+        let now = Instant::now();
+        let (mut client, mut server) = open(now);
+        assert_eq!(client.state(), State::Established);
+        assert_eq!(server.state(), State::Established);
+        assert_eq!(client.take_events(), vec![Event::Established]);
+        assert_eq!(server.take_events(), vec![Event::Established]);
+
+        assert!(client.send(b"hello", now));
+        deliver(&mut client, &mut server, now);
+        assert_eq!(server.take_events(), vec![Event::Data(b"hello".to_vec())]);
+    }
+
+    #[test]
+    fn simultaneous_close() {
+        let now = Instant::now();
+        let (mut client, mut server) = open(now);
+        client.close(now);
+        server.close(now);
+        assert_eq!(client.state(), State::FinWait1);
+        assert_eq!(server.state(), State::FinWait1);
+
+        // The FINs cross
+        let client_fin = client.take_outgoing();
+        deliver(&mut server, &mut client, now);
+        for segment in client_fin.iter() {
+            server.on_segment(segment, now);
+        }
+        assert_eq!(client.state(), State::Closing);
+        assert_eq!(server.state(), State::Closing);
+
+        deliver(&mut client, &mut server, now);
+        deliver(&mut server, &mut client, now);
+        assert_eq!(client.state(), State::TimeWait);
+        assert_eq!(server.state(), State::TimeWait);
+        assert!(client.take_events().contains(&Event::Eof));
+        assert!(server.take_events().contains(&Event::Eof));
+
+        client.on_tick(now + TIME_WAIT);
+        server.on_tick(now + TIME_WAIT);
+        assert_eq!(client.state(), State::Closed);
+        assert_eq!(server.state(), State::Closed);
+    }
+
+    #[test]
+    fn retransmits_dropped_segment() {
+        let mut now = Instant::now();
+        let (mut client, mut server) = open(now);
+        server.take_events();
+        assert!(client.send(b"hello", now));
+        assert_eq!(client.take_outgoing().len(), 1);
+
+        // Nothing is resent before the timeout
+        client.on_tick(now);
+        assert!(client.take_outgoing().is_empty());
+
+        now += Duration::from_secs(1);
+        client.on_tick(now);
+        assert_eq!(deliver(&mut client, &mut server, now), 1);
+        assert_eq!(server.take_events(), vec![Event::Data(b"hello".to_vec())]);
+        deliver(&mut server, &mut client, now);
+
+        // Acknowledged, so never resent
+        client.on_tick(now + MAX_RTO);
+        assert!(client.take_outgoing().is_empty());
+    }
+
+    #[test]
+    fn resets_after_max_retransmissions() {
+        let mut now = Instant::now();
+        let (mut client, _) = open(now);
+        client.take_events();
+        assert!(client.send(b"hello", now));
+        client.take_outgoing();
+
+        let mut retransmissions = 0;
+        while client.state() != State::Closed {
+            now += MAX_RTO;
+            client.on_tick(now);
+            retransmissions += client
+                .take_outgoing()
+                .iter()
+                .filter(|segment| segment.payload == b"hello")
+                .count();
+        }
+        assert_eq!(retransmissions, MAX_RETRANSMISSIONS as usize);
+        assert_eq!(client.take_events(), vec![Event::Reset]);
+    }
+
+    #[test]
+    fn probes_zero_window() {
+        let mut now = Instant::now();
+        let (mut client, _) = open(now);
+        // The server's window is full
+        let window = |window| TcpPacket::new(80, 1000, 5001, 101, Flags::ACK, window, vec![]);
+        client.on_segment(&window(0), now);
+        assert!(client.send(b"hello", now));
+        assert!(client.take_outgoing().is_empty());
+
+        // Answered probes keep the connection up
+        for _ in 0..(MAX_RETRANSMISSIONS * 2) {
+            now += MAX_RTO;
+            client.on_tick(now);
+            let probes = client.take_outgoing();
+            assert_eq!(probes.len(), 1);
+            assert_eq!(probes[0].seq, 101);
+            assert_eq!(probes[0].payload, b"h");
+            client.on_segment(&window(0), now);
+        }
+        assert_eq!(client.state(), State::Established);
+
+        // All the data is sent once the window opens
+        client.on_segment(&window(1000), now);
+        let segments = client.take_outgoing();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].seq, 101);
+        assert_eq!(segments[0].payload, b"hello");
+    }
+}
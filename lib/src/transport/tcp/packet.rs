@@ -0,0 +1,167 @@
+use tracing::warn;
+
+bitflags::bitflags! {
+    pub struct Flags: u8 {
+        /// No more data from sender
+        const FIN = 0b0000_0001;
+        /// Synchronize sequence numbers
+        const SYN = 0b0000_0010;
+        /// Reset the connection
+        const RST = 0b0000_0100;
+        /// Push function
+        const PSH = 0b0000_1000;
+        /// Acknowledgment field significant
+        const ACK = 0b0001_0000;
+        /// Urgent pointer field significant
+        const URG = 0b0010_0000;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpPacket {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: Flags,
+    pub window: u16,
+    pub urgent: u16,
+    /// Maximum segment size option, only valid in SYN segments
+    pub mss: Option<u16>,
+    pub payload: Vec<u8>,
+}
+
+impl TcpPacket {
+    pub fn new(
+        source_port: u16,
+        destination_port: u16,
+        seq: u32,
+        ack: u32,
+        flags: Flags,
+        window: u16,
+        payload: Vec<u8>,
+    ) -> Self {
+        Self {
+            source_port,
+            destination_port,
+            seq,
+            ack,
+            flags,
+            window,
+            urgent: 0,
+            mss: None,
+            payload,
+        }
+    }
+
+    /// Sequence space used by the segment, SYN and FIN count as one
+    pub fn len(&self) -> u32 {
+        self.payload.len() as u32
+            + u32::from(self.flags.contains(Flags::SYN))
+            + u32::from(self.flags.contains(Flags::FIN))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn from_vec(data: &[u8]) -> Option<Self> {
+        if data.len() < 20 {
+            warn!("TCP packet: Not enough data");
+            return None;
+        }
+        let source_port = u16::from_be_bytes(data[0..2].try_into().ok()?);
+        let destination_port = u16::from_be_bytes(data[2..4].try_into().ok()?);
+        let seq = u32::from_be_bytes(data[4..8].try_into().ok()?);
+        let ack = u32::from_be_bytes(data[8..12].try_into().ok()?);
+        let data_offset = (data[12] >> 4) as usize * 4;
+        if data_offset < 20 || data.len() < data_offset {
+            warn!("TCP packet: Invalid data offset {data_offset}");
+            return None;
+        }
+        let flags = Flags::from_bits_truncate(data[13]);
+        let window = u16::from_be_bytes(data[14..16].try_into().ok()?);
+        let _checksum = u16::from_be_bytes(data[16..18].try_into().ok()?); // TODO
+        let urgent = u16::from_be_bytes(data[18..20].try_into().ok()?);
+        Some(Self {
+            source_port,
+            destination_port,
+            seq,
+            ack,
+            flags,
+            window,
+            urgent,
+            mss: Self::decode_mss(&data[20..data_offset]),
+            payload: data[data_offset..].to_vec(),
+        })
+    }
+
+    /// Finds the MSS in the options, the rest of the options are ignored
+    fn decode_mss(mut options: &[u8]) -> Option<u16> {
+        while let Some(&kind) = options.first() {
+            match kind {
+                // End of option list
+                0 => break,
+                // No operation
+                1 => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || options.len() < len {
+                        warn!("TCP option: Invalid length {len}");
+                        return None;
+                    }
+                    if kind == 2 && len == 4 {
+                        return Some(u16::from_be_bytes(options[2..4].try_into().ok()?));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        None
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let options = match self.mss {
+            Some(mss) => {
+                let [a, b] = mss.to_be_bytes();
+                vec![2, 4, a, b]
+            }
+            None => vec![],
+        };
+        let mut res = Vec::with_capacity(20 + options.len() + self.payload.len());
+        res.extend_from_slice(&self.source_port.to_be_bytes());
+        res.extend_from_slice(&self.destination_port.to_be_bytes());
+        res.extend_from_slice(&self.seq.to_be_bytes());
+        res.extend_from_slice(&self.ack.to_be_bytes());
+        res.push((((20 + options.len()) / 4) as u8) << 4);
+        res.push(self.flags.bits());
+        res.extend_from_slice(&self.window.to_be_bytes());
+        res.extend_from_slice(&0u16.to_be_bytes()); // TODO Checksum
+        res.extend_from_slice(&self.urgent.to_be_bytes());
+        res.extend_from_slice(&options);
+        res.extend_from_slice(&self.payload);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::corruption::corruptions;
+
+    use super::{Flags, TcpPacket};
+
+    #[test]
+    fn survives_corrupted_packets() {
+        let packet = TcpPacket {
+            mss: Some(1460),
+            ..TcpPacket::new(1024, 80, 1, 0, Flags::SYN, 65535, vec![0xa5; 8])
+        };
+        let data = packet.to_vec();
+        assert_eq!(TcpPacket::from_vec(&data), Some(packet));
+        for data in corruptions(&data) {
+            if let Some(packet) = TcpPacket::from_vec(&data) {
+                packet.to_vec();
+            }
+        }
+    }
+}
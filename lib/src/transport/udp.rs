@@ -9,10 +9,10 @@ use tracing::{trace, warn};
 
 use crate::{
     chassis::{
-        NetworkLayerId, NetworkTransportMessage, ProcessMessage, TransportLayerId,
+        LinkLayerId, NetworkLayerId, NetworkTransportMessage, ProcessMessage, TransportLayerId,
         TransportLevelProcess,
     },
-    network::ipv4::addr::IpV4Addr,
+    network::{ipv4::addr::IpV4Addr, ipv6::addr::IpV6Addr},
 };
 
 use self::packet::UdpPacket;
@@ -161,7 +161,7 @@ trait TransportLevelComposableProcess {
         id: Self::DownId,
         msg: Self::DownPayload,
         send_down: F,
-    );
+    ) -> bool;
 }
 
 #[async_trait::async_trait]
@@ -257,33 +257,51 @@ where
         _id: Self::DownId,
         (addr, msg, ttl): Self::DownPayload,
         _send_down: F,
-    ) {
+    ) -> bool {
         if let Some(packet) = UdpPacket::from_vec(&msg) {
-            if let Some((tx, _)) = self.sockets.map.get(&packet.destination_port).cloned() {
-                if tx
-                    .send_async((addr, packet.source_port, packet.payload, ttl))
-                    .await
-                    .is_err()
-                {
-                    self.sockets.map.remove(&packet.destination_port);
+            match self.sockets.map.get(&packet.destination_port).cloned() {
+                Some((tx, _)) => {
+                    if tx
+                        .send_async((addr, packet.source_port, packet.payload, ttl))
+                        .await
+                        .is_err()
+                    {
+                        self.sockets.map.remove(&packet.destination_port);
+                        return false;
+                    }
                 }
+                None => return false,
             }
         }
+        true
     }
 }
 
 pub struct UdpProcess {
     ip_v4: UdpProcessGeneric<IpV4Addr>,
+    ip_v6: UdpProcessGeneric<IpV6Addr>,
+    /// IPv4 limited broadcasts, addressed by interface instead of by IP
+    broadcast: UdpProcessGeneric<LinkLayerId>,
 }
 
 impl UdpProcess {
-    pub const fn new(ip_v4: UdpProcessGeneric<IpV4Addr>) -> Self {
-        Self { ip_v4 }
+    pub const fn new(
+        ip_v4: UdpProcessGeneric<IpV4Addr>,
+        ip_v6: UdpProcessGeneric<IpV6Addr>,
+        broadcast: UdpProcessGeneric<LinkLayerId>,
+    ) -> Self {
+        Self {
+            ip_v4,
+            ip_v6,
+            broadcast,
+        }
     }
 }
 
 pub enum ExtraMessage {
     IPv4(ExtraMessageGeneric<IpV4Addr>),
+    IPv6(ExtraMessageGeneric<IpV6Addr>),
+    Broadcast(ExtraMessageGeneric<LinkLayerId>),
 }
 
 #[async_trait::async_trait]
@@ -299,21 +317,57 @@ impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMes
             Sender<ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportMessage>>,
         >,
     ) {
-        if let (NetworkLayerId::Ipv4, NetworkTransportMessage::IPv4(addr, ttl, payload)) =
-            (down_id, msg)
-        {
-            self.ip_v4
-                .on_down_message((), (addr, payload, ttl), |addr, payload, ttl| async move {
+        match (down_id, msg) {
+            (NetworkLayerId::Ipv4, NetworkTransportMessage::IPv4Quoted(addr, ttl, payload, data)) => {
+                let delivered = self
+                    .ip_v4
+                    .on_down_message(
+                        (),
+                        (addr, payload, ttl),
+                        |addr, payload, ttl| async move {
+                            if let Some(tx) = down_sender.get(&NetworkLayerId::Ipv4) {
+                                let _ = tx
+                                    .send_async(ProcessMessage::Message(
+                                        TransportLayerId::Udp,
+                                        NetworkTransportMessage::IPv4(addr, ttl, payload),
+                                    ))
+                                    .await;
+                            }
+                        },
+                    )
+                    .await;
+                if !delivered {
                     if let Some(tx) = down_sender.get(&NetworkLayerId::Ipv4) {
                         let _ = tx
                             .send_async(ProcessMessage::Message(
                                 TransportLayerId::Udp,
-                                NetworkTransportMessage::IPv4(addr, ttl, payload),
+                                NetworkTransportMessage::IPv4PortUnreachable(addr, data),
                             ))
                             .await;
                     }
-                })
-                .await;
+                }
+            }
+            (NetworkLayerId::Ipv6, NetworkTransportMessage::IPv6(addr, ttl, payload)) => {
+                self.ip_v6
+                    .on_down_message((), (addr, payload, ttl), |addr, payload, ttl| async move {
+                        if let Some(tx) = down_sender.get(&NetworkLayerId::Ipv6) {
+                            let _ = tx
+                                .send_async(ProcessMessage::Message(
+                                    TransportLayerId::Udp,
+                                    NetworkTransportMessage::IPv6(addr, ttl, payload),
+                                ))
+                                .await;
+                        }
+                    })
+                    .await;
+            }
+            (NetworkLayerId::Ipv4, NetworkTransportMessage::IPv4Broadcast(iface, ttl, payload)) => {
+                // Broadcasts without a socket are dropped silently
+                self.broadcast
+                    .on_down_message((), (iface, payload, ttl), |_, _, _| async {})
+                    .await;
+            }
+            (id, _) => warn!("UDP: Unexpected message from {id:?}"),
         }
     }
     async fn setup(
@@ -333,6 +387,16 @@ impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMes
                 join_set.spawn(async move { Either::Right(ExtraMessage::IPv4(fut.await)) });
             })
             .await;
+        self.ip_v6
+            .setup(|fut| {
+                join_set.spawn(async move { Either::Right(ExtraMessage::IPv6(fut.await)) });
+            })
+            .await;
+        self.broadcast
+            .setup(|fut| {
+                join_set.spawn(async move { Either::Right(ExtraMessage::Broadcast(fut.await)) });
+            })
+            .await;
     }
     type Extra = ExtraMessage;
     async fn on_extra_message(
@@ -371,6 +435,42 @@ impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMes
                     join_set.spawn(async move { Either::Right(ExtraMessage::IPv4(r.await)) });
                 }
             }
+            ExtraMessage::IPv6(msg) => {
+                for r in self
+                    .ip_v6
+                    .on_extra(msg, |addr, payload, ttl| async move {
+                        if let Some(tx) = down_sender.get(&NetworkLayerId::Ipv6) {
+                            let _ = tx
+                                .send_async(ProcessMessage::Message(
+                                    TransportLayerId::Udp,
+                                    NetworkTransportMessage::IPv6(addr, ttl, payload),
+                                ))
+                                .await;
+                        }
+                    })
+                    .await
+                {
+                    join_set.spawn(async move { Either::Right(ExtraMessage::IPv6(r.await)) });
+                }
+            }
+            ExtraMessage::Broadcast(msg) => {
+                for r in self
+                    .broadcast
+                    .on_extra(msg, |iface, payload, ttl| async move {
+                        if let Some(tx) = down_sender.get(&NetworkLayerId::Ipv4) {
+                            let _ = tx
+                                .send_async(ProcessMessage::Message(
+                                    TransportLayerId::Udp,
+                                    NetworkTransportMessage::IPv4Broadcast(iface, ttl, payload),
+                                ))
+                                .await;
+                        }
+                    })
+                    .await
+                {
+                    join_set.spawn(async move { Either::Right(ExtraMessage::Broadcast(r.await)) });
+                }
+            }
         }
     }
 }
@@ -6,6 +6,9 @@ pub struct UdpPacket {
 
 impl UdpPacket {
     pub fn from_vec(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
         let source_port = u16::from_be_bytes(data[0..2].try_into().ok()?);
         let destination_port = u16::from_be_bytes(data[2..4].try_into().ok()?);
         let _length = u16::from_be_bytes(data[4..6].try_into().ok()?);
@@ -24,6 +27,7 @@ impl UdpPacket {
         res.extend_from_slice(&self.destination_port.to_be_bytes());
         res.extend_from_slice(&(self.payload.len() as u16 + 8).to_be_bytes());
         res.extend_from_slice(&(0u16).to_be_bytes()); // TODO
+        res.extend_from_slice(&self.payload);
         res
     }
 }
@@ -0,0 +1,156 @@
+use std::{collections::HashMap, sync::Arc};
+
+use either::Either;
+use flume::{Receiver, RecvError, Sender};
+use tokio::{sync::oneshot, task::JoinSet};
+use tracing::{trace, warn};
+
+use crate::{
+    chassis::{
+        LinkLayerId, NetworkLayerId, NetworkTransportMessage, ProcessMessage, TransportLayerId,
+        TransportLevelProcess,
+    },
+    network::ipv4::addr::IpV4Addr,
+};
+
+/// (iface, source, payload)
+type Data = (LinkLayerId, IpV4Addr, Vec<u8>);
+
+/// Advertisements are sent with it, the ones received with any other were routed and are dropped
+pub const TTL: u8 = 255;
+
+/// Endpoint of IP protocol 112. It only receives the advertisements, the VRRP router sends them
+/// straight on the links since they come from the virtual MAC
+pub struct VrrpSocket {
+    rx: Receiver<Data>,
+}
+
+impl VrrpSocket {
+    /// Next advertisement as (iface, source, payload)
+    pub async fn recv(&self) -> Result<Data, RecvError> {
+        self.rx.recv_async().await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VrrpHandle {
+    get_socket: Sender<oneshot::Sender<VrrpSocket>>,
+}
+
+impl VrrpHandle {
+    /// Takes over the protocol, the previous socket stops receiving advertisements
+    pub async fn get_socket(&self) -> Result<VrrpSocket, ()> {
+        let (tx, rx) = oneshot::channel();
+        self.get_socket.send_async(tx).await.map_err(|_| ())?;
+        rx.await.map_err(|_| ())
+    }
+}
+
+pub struct VrrpProcess {
+    get_socket: Arc<Receiver<oneshot::Sender<VrrpSocket>>>,
+    /// Advertisements are dropped while no router owns the socket
+    socket: Option<Sender<Data>>,
+}
+
+impl VrrpProcess {
+    pub fn new() -> (Self, VrrpHandle) {
+        let (tx, rx) = flume::unbounded();
+        (
+            Self {
+                get_socket: Arc::new(rx),
+                socket: None,
+            },
+            VrrpHandle { get_socket: tx },
+        )
+    }
+}
+
+pub enum ExtraMessage {
+    GetSocket(Result<oneshot::Sender<VrrpSocket>, RecvError>),
+}
+
+#[async_trait::async_trait]
+impl TransportLevelProcess<TransportLayerId, NetworkLayerId, NetworkTransportMessage>
+    for VrrpProcess
+{
+    async fn on_down_message(
+        &mut self,
+        msg: NetworkTransportMessage,
+        _: NetworkLayerId,
+        _: &HashMap<
+            NetworkLayerId,
+            Sender<ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportMessage>>,
+        >,
+    ) {
+        match msg {
+            NetworkTransportMessage::IPv4Link(iface, source, ttl, payload) => {
+                if ttl != Some(TTL) {
+                    trace!("VRRP: Dropped advertisement from {source} on {iface} with TTL {ttl:?}");
+                    return;
+                }
+                match &self.socket {
+                    Some(tx) => {
+                        if tx.send_async((iface, source, payload)).await.is_err() {
+                            self.socket = None;
+                        }
+                    }
+                    None => {
+                        trace!("VRRP: No router, dropped advertisement from {source} on {iface}")
+                    }
+                }
+            }
+            msg => warn!("VRRP: Unexpected message {msg:?}"),
+        }
+    }
+
+    async fn setup(
+        &mut self,
+        join_set: &mut JoinSet<
+            Either<
+                Result<
+                    ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportMessage>,
+                    RecvError,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        let rx = self.get_socket.clone();
+        join_set
+            .spawn(async move { Either::Right(ExtraMessage::GetSocket(rx.recv_async().await)) });
+    }
+
+    type Extra = ExtraMessage;
+    async fn on_extra_message(
+        &mut self,
+        msg: Self::Extra,
+        _: &HashMap<
+            NetworkLayerId,
+            Sender<ProcessMessage<TransportLayerId, NetworkLayerId, NetworkTransportMessage>>,
+        >,
+        join_set: &mut JoinSet<
+            Either<
+                Result<
+                    ProcessMessage<NetworkLayerId, TransportLayerId, NetworkTransportMessage>,
+                    RecvError,
+                >,
+                Self::Extra,
+            >,
+        >,
+    ) {
+        match msg {
+            ExtraMessage::GetSocket(Ok(reply)) => {
+                let (tx, rx) = flume::unbounded();
+                self.socket = Some(tx);
+                let _ = reply.send(VrrpSocket { rx });
+                let rx = self.get_socket.clone();
+                join_set.spawn(async move {
+                    Either::Right(ExtraMessage::GetSocket(rx.recv_async().await))
+                });
+            }
+            ExtraMessage::GetSocket(Err(RecvError::Disconnected)) => {
+                warn!("VRRP: Handle disconnected")
+            }
+        }
+    }
+}